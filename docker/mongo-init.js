@@ -5,4 +5,43 @@ db.createUser({
     { role: 'readWrite', db: 'drift' },
   ],
 });
-db.accounts.createIndex({ "address": "hashed "}); // this is unsupported.., TODO: make address a string
\ No newline at end of file
+db.accounts.createIndex({ "address": "hashed "}); // this is unsupported.., TODO: make address a string
+db.account_snapshots.createIndex({ "pubkey": 1, "slot": 1 }, { unique: true });
+db.account_diffs.createIndex({ "pubkey": 1, "slot": 1 });
+db.dynamic_events.createIndex({ "name": 1, "slot": 1 });
+db.order_action_records.createIndex({ "maker": 1, "ts": -1 });
+db.order_action_records.createIndex({ "taker": 1, "ts": -1 });
+db.order_records.createIndex({ "user": 1, "ts": -1 });
+db.orders.createIndex({ "user": 1, "orderId": 1 }, { unique: true });
+db.orders.createIndex({ "user": 1, "status": 1 });
+db.position_events.createIndex({ "user": 1, "marketIndex": 1, "slot": 1 });
+db.position_events.createIndex({ "signature": 1, "logIndex": 1, "user": 1 }, { unique: true });
+db.positions.createIndex({ "user": 1, "marketIndex": 1 }, { unique: true });
+db.candles.createIndex({ "marketType": 1, "marketIndex": 1, "resolution": 1, "start": 1 }, { unique: true });
+db.applied_events.createIndex({ "view": 1, "account": 1, "signature": 1, "logIndex": 1 }, { unique: true });
+db.account_stats.createIndex({ "account": 1, "marketType": 1, "marketIndex": 1, "bucket": 1 }, { unique: true });
+db.account_stats.createIndex({ "account": 1, "bucket": 1 });
+db.funding_rates.createIndex({ "marketIndex": 1, "recordId": 1 }, { unique: true });
+db.funding_rates.createIndex({ "marketIndex": 1, "ts": 1 });
+db.funding_payments.createIndex({ "signature": 1, "logIndex": 1 }, { unique: true });
+db.funding_payments.createIndex({ "user": 1, "marketIndex": 1, "ts": 1 });
+db.ledger.createIndex({ "user": 1, "signature": 1, "logIndex": 1 }, { unique: true });
+db.ledger.createIndex({ "user": 1, "marketIndex": 1, "slot": 1 });
+db.ledger.createIndex({ "user": 1, "marketIndex": 1, "ts": 1 });
+db.spot_interest.createIndex({ "marketIndex": 1, "signature": 1 }, { unique: true });
+db.spot_interest.createIndex({ "marketIndex": 1, "ts": -1 });
+db.if_stake_events.createIndex({ "signature": 1, "logIndex": 1 }, { unique: true });
+db.if_stake_events.createIndex({ "userAuthority": 1, "marketIndex": 1, "slot": 1 });
+db.if_vaults.createIndex({ "signature": 1, "logIndex": 1 }, { unique: true });
+db.if_vaults.createIndex({ "marketIndex": 1, "ts": 1 });
+db.lp_events.createIndex({ "signature": 1, "logIndex": 1 }, { unique: true });
+db.lp_events.createIndex({ "user": 1, "marketIndex": 1, "slot": 1 });
+db.swaps.createIndex({ "signature": 1, "logIndex": 1 }, { unique: true });
+db.swaps.createIndex({ "user": 1, "slot": -1 });
+db.watchlist.createIndex({ "pubkey": 1 }, { unique: true });
+db.poll_status.createIndex({ "pubkey": 1 }, { unique: true });
+db.pending_txs.createIndex({ "signature": 1 }, { unique: true });
+db.pending_txs.createIndex({ "slot": 1 });
+db.order_action_records.createIndex({ "signature": 1 });
+db.order_records.createIndex({ "signature": 1 });
+db.dynamic_events.createIndex({ "signature": 1 });
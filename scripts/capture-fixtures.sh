@@ -0,0 +1,23 @@
+#!/usr/bin/env bash
+# Capture mainnet drift accounts as test fixtures (res/fixtures/accounts/<name>.json)
+#   RPC_URL=https://api.mainnet-beta.solana.com ./scripts/capture-fixtures.sh
+set -euo pipefail
+
+RPC_URL=${RPC_URL:-https://api.mainnet-beta.solana.com}
+OUT=$(dirname "$0")/../res/fixtures/accounts
+mkdir -p "$OUT"
+
+capture() {
+    local name=$1 pubkey=$2
+    curl -sf "$RPC_URL" -X POST -H 'Content-Type: application/json' -d "{
+        \"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"getAccountInfo\",
+        \"params\": [\"$pubkey\", { \"encoding\": \"base64\" }]
+    }" | jq --arg pubkey "$pubkey" '{ pubkey: $pubkey, slot: .result.context.slot, account: .result.value }' \
+        > "$OUT/$name.json"
+    echo "captured $name: $pubkey"
+}
+
+capture state 5zpq7DvB6UdFFvpmBPspGPNfUGoBRRCE2HHg5u3gxcsN
+capture perp_market_0 8UJgxaiQx5nTrdDgph5FiahMmzduuLTLf5WmsPegYA6W
+capture spot_market_0 6gMq3mRCKf8aP3ttTyYhuijVZ2LGi14oDsBbkgubfLB3
+capture user "${USER_ACCOUNT:-BTDXiRzG1QBP7bfK4A33RcSP5mmZx8mGJ9YC5maetoD6}"
@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+# Fetch a drift IDL version into res/ and list the slots of the program's upgrades,
+# to register the version in `KNOWN_VERSIONS` with the slot of the upgrade which deployed it
+#   ./scripts/fetch-idl.sh 2.57.0
+set -euo pipefail
+
+VERSION=${1:?usage: fetch-idl.sh <version>}
+RPC_URL=${RPC_URL:-https://api.mainnet-beta.solana.com}
+# ProgramData account of dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH, written by every upgrade
+PROGRAM_DATA=${PROGRAM_DATA:-$(solana program show dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH -u "$RPC_URL" \
+    | awk '/ProgramData Address/ { print $3 }')}
+OUT=$(dirname "$0")/../res/drift-$VERSION.json
+
+curl -sf "https://raw.githubusercontent.com/drift-labs/protocol-v2/v$VERSION/sdk/src/idl/drift.json" \
+    | jq --arg version "$VERSION" '.version = $version' > "$OUT"
+echo "fetched IDL v$VERSION: $OUT"
+
+echo "program upgrades (slot, time, signature):"
+curl -sf "$RPC_URL" -X POST -H 'Content-Type: application/json' -d "{
+    \"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"getSignaturesForAddress\",
+    \"params\": [\"$PROGRAM_DATA\", { \"limit\": 1000 }]
+}" | jq -r '.result[] | select(.err == null) | "\(.slot) \(.blockTime | todate) \(.signature)"'
@@ -2,40 +2,165 @@
 use anchor_syn::idl::{
     EnumFields, Idl, IdlEvent, IdlField, IdlType, IdlTypeDefinition, IdlTypeDefinitionTy,
 };
+use heck::SnakeCase;
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use syn::parse_macro_input;
+use sha2::{Digest, Sha256};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Token,
+};
+
+mod zero_copy;
+use zero_copy::{gen_zero_copy_struct, zero_copy_closure, TypeDefs};
+
+/// `gen_idl_types!` arguments
+struct GenIdlTypesArgs {
+    /// path to the IDL json file, relative to the crate manifest
+    path: syn::LitStr,
+    /// names of zero-copy accounts
+    zero_copy: Vec<String>,
+    /// path of the program descriptor trait to implement
+    program: Option<syn::Path>,
+    /// the program ID (base58), if not in the IDL metadata
+    program_id: Option<syn::LitStr>,
+}
+
+impl Parse for GenIdlTypesArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut zero_copy = Vec::new();
+        let mut program = None;
+        let mut program_id = None;
+        while input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let ident: syn::Ident = input.parse()?;
+            let content;
+            syn::parenthesized!(content in input);
+            if ident == "zero_copy" {
+                zero_copy = Punctuated::<syn::Ident, Token![,]>::parse_terminated(&content)?
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect();
+            } else if ident == "program" {
+                program = Some(content.parse()?);
+            } else if ident == "program_id" {
+                program_id = Some(content.parse()?);
+            } else {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "expected `zero_copy(..)`, `program(..)` or `program_id(..)`",
+                ));
+            }
+        }
+
+        Ok(Self {
+            path,
+            zero_copy,
+            program,
+            program_id,
+        })
+    }
+}
 
 /// generate program types from given IDL json file
+///
+/// zero-copy accounts (and the types they contain) are optionally listed to generate their onchain `#[repr(C)]` layout
+///
+/// `program(..)` optionally names a program descriptor trait to implement for the generated `{Name}Program`
+///
+/// `program_id(..)` sets the `PROGRAM_ID` constant (owner of the accounts) if the IDL has no `metadata.address`,
+/// defaulting to `crate::ID`
+///
+/// Types are generated into a module named after the program with `types`, `accounts`, `events`, `instructions`
+/// and `errors` submodules
+/// ```ignore
+/// gen_idl_types!(
+///     "../rel/path/to/idl.json",
+///     zero_copy(User, PerpMarket),
+///     program(crate::AnchorProgram),
+///     program_id("dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH")
+/// );
+/// ```
 #[proc_macro]
 pub fn gen_idl_types(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let path_str = parse_macro_input!(input as syn::LitStr);
+    let GenIdlTypesArgs {
+        path: path_str,
+        zero_copy,
+        program,
+        program_id,
+    } = parse_macro_input!(input as GenIdlTypesArgs);
     let cargo_manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
     let path = std::path::PathBuf::from(cargo_manifest_dir).join(path_str.value());
     let idl_json = std::fs::read_to_string(path).expect("file found");
     let idef: Idl = serde_json::from_str(idl_json.as_str()).expect("valid IDL");
 
-    let mut output = quote! {
-        use anchor_attribute_event::event;
-        use anchor_lang::{prelude::*, AccountDeserialize, Discriminator};
-    };
+    let type_defs: TypeDefs = idef
+        .types
+        .iter()
+        .chain(idef.accounts.iter())
+        .map(|t| (t.name.as_str(), t))
+        .collect();
+    let zero_copy_types = zero_copy_closure(zero_copy.as_slice(), &type_defs);
+
+    let program_name = idef.name.as_str();
+    let program_mod = syn::Ident::new(program_name, Span::call_site());
+    let pascal_name = to_pascal_case(program_name);
+    let program_event_name = syn::Ident::new(&format!("{pascal_name}Event"), Span::call_site());
+    let program_account_name = syn::Ident::new(&format!("{pascal_name}Account"), Span::call_site());
+    let program_instruction_name =
+        syn::Ident::new(&format!("{pascal_name}Instruction"), Span::call_site());
+    let program_error_name = syn::Ident::new(&format!("{pascal_name}Error"), Span::call_site());
+    let program_descriptor_name =
+        syn::Ident::new(&format!("{pascal_name}Program"), Span::call_site());
 
-    // TODO: namespace the types, modules, events for better discoverability/dx
+    let mut types = TokenStream::new();
     idef.types.iter().for_each(|e| {
-        let type_struct = gen_type_struct(e);
-        output.extend(vec![type_struct]);
+        let type_struct = if zero_copy_types.contains(&e.name) {
+            gen_zero_copy_type(e, &type_defs, false)
+        } else {
+            gen_type_struct(e, false, false)
+        };
+        types.extend(vec![type_struct]);
     });
 
+    let mut accounts = TokenStream::new();
+    let mut outer_account_types = TokenStream::new();
+    let mut outer_account_impl = TokenStream::new();
+    let mut outer_account_names = TokenStream::new();
     idef.accounts.iter().for_each(|x| {
-        let type_struct = gen_type_struct(x);
-        output.extend(vec![type_struct]);
+        let type_struct = if zero_copy_types.contains(&x.name) {
+            gen_zero_copy_type(x, &type_defs, true)
+        } else {
+            gen_type_struct(x, false, true)
+        };
+        accounts.extend(vec![type_struct]);
+
+        let account_name = syn::Ident::new(x.name.as_str(), Span::call_site());
+        let account_name_str = x.name.as_str();
+        outer_account_types = quote! {
+            #outer_account_types
+            #account_name(#account_name),
+        };
+        outer_account_impl = quote! {
+            #outer_account_impl
+            #account_name::DISCRIMINATOR => Self::#account_name(AnchorDeserialize::deserialize(data).ok()?),
+        };
+        outer_account_names = quote! {
+            #outer_account_names
+            Self::#account_name(_) => #account_name_str,
+        };
     });
 
+    let mut events = TokenStream::new();
     let mut outer_event_types = TokenStream::new();
     let mut outer_event_impl = TokenStream::new();
-    if let Some(events) = idef.events {
-        events.iter().for_each(|event| {
+    let mut outer_event_names = TokenStream::new();
+    if let Some(ref idl_events) = idef.events {
+        idl_events.iter().for_each(|event| {
             let event_name = syn::Ident::new(event.name.as_str(), Span::call_site());
+            let event_name_str = event.name.as_str();
             outer_event_types = quote! {
                 #outer_event_types
                 #event_name(#event_name),
@@ -44,46 +169,454 @@ pub fn gen_idl_types(input: proc_macro::TokenStream) -> proc_macro::TokenStream
                 #outer_event_impl
                 #event_name::DISCRIMINATOR => Self::#event_name(AnchorDeserialize::deserialize(data).ok()?),
             };
-
-            let event_struct = gen_event_struct(event);
-            output = quote! {
-                #output
-                #event_struct
+            outer_event_names = quote! {
+                #outer_event_names
+                Self::#event_name(_) => #event_name_str,
             };
+            events.extend(vec![gen_event_struct(event)]);
         });
     }
 
-    let program_event_name = syn::Ident::new(
-        format!(
-            "{}{}Event",
-            (idef.name[..1].to_string()).to_uppercase(),
-            &idef.name[1..]
-        )
-        .as_str(),
-        Span::call_site(),
-    );
+    let mut instructions = TokenStream::new();
+    let mut outer_instruction_types = TokenStream::new();
+    let mut outer_instruction_impl = TokenStream::new();
+    let mut outer_instruction_names = TokenStream::new();
+    idef.instructions.iter().for_each(|ix| {
+        let ix_name = syn::Ident::new(to_pascal_case(&ix.name).as_str(), Span::call_site());
+        let ix_name_str = ix.name.as_str();
+        let discriminator = discriminator_tokens("global", ix.name.to_snake_case().as_str());
+        let fields: Vec<TokenStream> = ix
+            .args
+            .iter()
+            .map(|f| field_to_token_stream(f, true))
+            .collect();
+        instructions.extend(quote! {
+            #[derive(Clone, Debug, PartialEq, AnchorSerialize, AnchorDeserialize, Serialize, Deserialize)]
+            pub struct #ix_name {
+                #(#fields)*
+            }
+
+            impl Discriminator for #ix_name {
+                const DISCRIMINATOR: [u8; 8] = #discriminator;
+            }
+        });
+        outer_instruction_types = quote! {
+            #outer_instruction_types
+            #ix_name(#ix_name),
+        };
+        outer_instruction_impl = quote! {
+            #outer_instruction_impl
+            #ix_name::DISCRIMINATOR => Self::#ix_name(AnchorDeserialize::deserialize(data).ok()?),
+        };
+        outer_instruction_names = quote! {
+            #outer_instruction_names
+            Self::#ix_name(_) => #ix_name_str,
+        };
+    });
+
+    let mut error_variants = TokenStream::new();
+    let mut error_codes = TokenStream::new();
+    let mut error_msgs = TokenStream::new();
+    idef.errors.iter().flatten().for_each(|e| {
+        let error_name = syn::Ident::new(e.name.as_str(), Span::call_site());
+        let code = e.code;
+        let msg = e.msg.as_deref().unwrap_or(e.name.as_str());
+        error_variants = quote! {
+            #error_variants
+            #error_name = #code,
+        };
+        error_codes = quote! {
+            #error_codes
+            #code => Self::#error_name,
+        };
+        error_msgs = quote! {
+            #error_msgs
+            Self::#error_name => #msg,
+        };
+    });
+
+    let idl_version = idef.version.as_str();
+    let idl_json = idl_json.as_str();
+    let program_id_address = program_id
+        .map(|p| p.value())
+        .or_else(|| {
+            idef.metadata
+                .as_ref()
+                .and_then(|m| m.get("address"))
+                .and_then(|a| a.as_str())
+                .map(|a| a.to_string())
+        })
+        .map(|address| {
+            let bytes = bs58::decode(address.as_str())
+                .into_vec()
+                .expect("valid program ID");
+            assert_eq!(bytes.len(), 32, "valid program ID");
+            bytes
+        });
+    let (program_id_const, program_id_expr) = match program_id_address {
+        Some(bytes) => (
+            quote! {
+                /// The program ID
+                pub const PROGRAM_ID: Pubkey = Pubkey::new_from_array([#(#bytes),*]);
+            },
+            quote! { PROGRAM_ID },
+        ),
+        None => (
+            quote! {
+                /// The program ID (the crate's, the IDL has no address)
+                pub const PROGRAM_ID: Pubkey = crate::ID;
+            },
+            quote! { PROGRAM_ID },
+        ),
+    };
+
+    let descriptor = match program {
+        Some(program_trait) => quote! {
+            /// Descriptor of the program, for indexing its events
+            #[derive(Clone, Copy, Debug, Default)]
+            pub struct #program_descriptor_name;
+
+            impl #program_trait for #program_descriptor_name {
+                type Event = events::#program_event_name;
+                const NAME: &'static str = #program_name;
+                const IDL_VERSION: &'static str = IDL_VERSION;
+                fn program_id() -> Pubkey {
+                    #program_id_expr
+                }
+                fn decode_event(disc: [u8; 8], data: &mut &[u8]) -> Option<Self::Event> {
+                    events::#program_event_name::from_discriminant(disc, data)
+                }
+                fn event_name(event: &Self::Event) -> &'static str {
+                    event.name()
+                }
+            }
+        },
+        None => TokenStream::new(),
+    };
 
     quote! {
-        #output
+        /// Types of the program IDL
+        pub mod #program_mod {
+            use super::*;
+            use anchor_attribute_event::event;
+            use anchor_lang::{prelude::*, AccountDeserialize, Discriminator};
 
-        #[derive(Debug, PartialEq)]
-        pub enum #program_event_name {
-            #outer_event_types
-        }
+            /// Version of the IDL these types were generated from
+            pub const IDL_VERSION: &str = #idl_version;
+            /// The IDL (json) these types were generated from
+            pub const IDL_JSON: &str = #idl_json;
+            #program_id_const
+
+            /// default value for padding fields
+            fn zeroed<const N: usize>() -> [u8; N] {
+                [0; N]
+            }
+
+            /// (De)serializes 128-bit integers as decimal strings
+            mod int_string {
+                use std::{fmt::Display, str::FromStr};
+
+                use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+                pub fn serialize<T: Display, S: Serializer>(
+                    value: &T,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(value)
+                }
+
+                pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+                where
+                    T: FromStr,
+                    T::Err: Display,
+                    D: Deserializer<'de>,
+                {
+                    String::deserialize(deserializer)?
+                        .parse()
+                        .map_err(D::Error::custom)
+                }
+
+                /// `Option` of 128-bit integers
+                pub mod option {
+                    use super::*;
+
+                    pub fn serialize<T: Display, S: Serializer>(
+                        value: &Option<T>,
+                        serializer: S,
+                    ) -> Result<S::Ok, S::Error> {
+                        match value {
+                            Some(value) => serializer.collect_str(value),
+                            None => serializer.serialize_none(),
+                        }
+                    }
+
+                    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+                    where
+                        T: FromStr,
+                        T::Err: Display,
+                        D: Deserializer<'de>,
+                    {
+                        Option::<String>::deserialize(deserializer)?
+                            .map(|s| s.parse().map_err(D::Error::custom))
+                            .transpose()
+                    }
+                }
+
+                /// Arrays of 128-bit integers
+                pub mod array {
+                    use serde::ser::SerializeSeq;
+
+                    use super::*;
+
+                    pub fn serialize<T: Display, S: Serializer, const N: usize>(
+                        values: &[T; N],
+                        serializer: S,
+                    ) -> Result<S::Ok, S::Error> {
+                        let mut seq = serializer.serialize_seq(Some(N))?;
+                        for value in values {
+                            seq.serialize_element(&value.to_string())?;
+                        }
+                        seq.end()
+                    }
+
+                    pub fn deserialize<'de, T, D, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+                    where
+                        T: FromStr,
+                        T::Err: Display,
+                        D: Deserializer<'de>,
+                    {
+                        let values = Vec::<String>::deserialize(deserializer)?
+                            .iter()
+                            .map(|s| s.parse().map_err(D::Error::custom))
+                            .collect::<Result<Vec<T>, D::Error>>()?;
+                        let len = values.len();
+                        values
+                            .try_into()
+                            .map_err(|_| D::Error::invalid_length(len, &"an array of N integers"))
+                    }
+                }
+            }
+
+            #descriptor
+
+            /// User defined types
+            pub mod types {
+                use super::*;
+
+                #types
+            }
+
+            /// Program accounts
+            pub mod accounts {
+                use super::*;
+                use super::types::*;
+
+                #accounts
+
+                #[derive(Debug, PartialEq, Serialize)]
+                #[serde(untagged)]
+                pub enum #program_account_name {
+                    #outer_account_types
+                }
+
+                impl #program_account_name {
+                    /// Deserialize an account from its discriminant and data (excluding discriminant)
+                    pub fn from_discriminant(disc: [u8; 8], data: &mut &[u8]) -> Option<Self> {
+                        let account = match disc {
+                            #outer_account_impl
+                            _ => return None,
+                        };
+                        Some(account)
+                    }
+                    /// Deserialize an account from raw account data (including discriminant)
+                    pub fn try_from_bytes(data: &[u8]) -> Option<Self> {
+                        if data.len() < 8 {
+                            return None;
+                        }
+                        let (disc, mut data) = data.split_at(8);
+                        Self::from_discriminant(disc.try_into().unwrap(), &mut data)
+                    }
+                    /// The IDL name of the account type
+                    pub fn name(&self) -> &'static str {
+                        match self {
+                            #outer_account_names
+                        }
+                    }
+                }
+            }
+
+            /// Program events
+            pub mod events {
+                use super::*;
+                use super::types::*;
+
+                #events
+
+                #[derive(Debug, PartialEq, Serialize)]
+                #[serde(untagged)]
+                pub enum #program_event_name {
+                    #outer_event_types
+                }
 
-        impl #program_event_name {
-            fn from_discriminant(disc: [u8; 8], data: &mut &[u8]) -> Option<Self> {
-                let event = match disc {
-                    #outer_event_impl
-                    _ => return None,
-                };
-                Some(event)
+                impl #program_event_name {
+                    /// Deserialize an event from its discriminant and data (excluding discriminant)
+                    pub fn from_discriminant(disc: [u8; 8], data: &mut &[u8]) -> Option<Self> {
+                        let event = match disc {
+                            #outer_event_impl
+                            _ => return None,
+                        };
+                        Some(event)
+                    }
+                    /// The IDL name of the event type
+                    pub fn name(&self) -> &'static str {
+                        match self {
+                            #outer_event_names
+                        }
+                    }
+                }
+            }
+
+            /// Program instruction arguments
+            pub mod instructions {
+                use super::*;
+                use super::types::*;
+
+                #instructions
+
+                #[derive(Debug, PartialEq, Serialize)]
+                #[serde(untagged)]
+                pub enum #program_instruction_name {
+                    #outer_instruction_types
+                }
+
+                impl #program_instruction_name {
+                    /// Deserialize instruction arguments from instruction data (including discriminant)
+                    pub fn try_from_bytes(data: &[u8]) -> Option<Self> {
+                        if data.len() < 8 {
+                            return None;
+                        }
+                        let (disc, mut data) = data.split_at(8);
+                        let data = &mut data;
+                        let ix = match <[u8; 8]>::try_from(disc).unwrap() {
+                            #outer_instruction_impl
+                            _ => return None,
+                        };
+                        Some(ix)
+                    }
+                    /// The IDL name of the instruction
+                    pub fn name(&self) -> &'static str {
+                        match self {
+                            #outer_instruction_names
+                        }
+                    }
+                }
+            }
+
+            /// Program errors
+            pub mod errors {
+                #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+                #[repr(u32)]
+                pub enum #program_error_name {
+                    #error_variants
+                }
+
+                impl #program_error_name {
+                    /// Return the error with (custom program error) `code`
+                    pub fn from_code(code: u32) -> Option<Self> {
+                        let err = match code {
+                            #error_codes
+                            _ => return None,
+                        };
+                        Some(err)
+                    }
+                    /// The error message
+                    pub fn msg(&self) -> &'static str {
+                        match self {
+                            #error_msgs
+                        }
+                    }
+                }
+
+                impl std::fmt::Display for #program_error_name {
+                    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(f, "{}", self.msg())
+                    }
+                }
             }
         }
     }
     .into()
 }
 
+/// Convert an IDL (camelCase) name to PascalCase
+fn to_pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Return the anchor discriminator of `name` in `namespace` as a `[u8; 8]` literal
+fn discriminator_tokens(namespace: &str, name: &str) -> TokenStream {
+    format!(
+        "{:?}",
+        &Sha256::digest(format!("{namespace}:{name}").as_bytes())[..8]
+    )
+    .parse()
+    .unwrap()
+}
+
+/// Generate the anchor account traits of `type_def`, owned by the IDL's program (`PROGRAM_ID`)
+///
+/// NB: not `#[account]` which assumes the account is owned by the crate's program (`crate::ID`)
+pub(crate) fn gen_account_traits(type_def: &IdlTypeDefinition) -> TokenStream {
+    let type_name = syn::Ident::new(type_def.name.as_str(), Span::call_site());
+    let type_name_str = type_def.name.as_str();
+    let discriminator = discriminator_tokens("account", type_name_str);
+    quote! {
+        impl Discriminator for #type_name {
+            const DISCRIMINATOR: [u8; 8] = #discriminator;
+        }
+
+        impl Owner for #type_name {
+            fn owner() -> Pubkey {
+                PROGRAM_ID
+            }
+        }
+
+        impl AccountSerialize for #type_name {
+            fn try_serialize<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+                writer
+                    .write_all(&Self::DISCRIMINATOR)
+                    .and_then(|_| AnchorSerialize::serialize(self, writer))
+                    .map_err(|_| ErrorCode::AccountDidNotSerialize.into())
+            }
+        }
+
+        impl AccountDeserialize for #type_name {
+            fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+                if buf.len() < 8 {
+                    return Err(ErrorCode::AccountDiscriminatorNotFound.into());
+                }
+                if buf[..8] != Self::DISCRIMINATOR {
+                    return Err(error!(ErrorCode::AccountDiscriminatorMismatch).with_account_name(#type_name_str));
+                }
+                Self::try_deserialize_unchecked(buf)
+            }
+            fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+                let mut data: &[u8] = &buf[8..];
+                AnchorDeserialize::deserialize(&mut data)
+                    .map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+            }
+        }
+    }
+}
+
 fn gen_event_struct(event: &IdlEvent) -> TokenStream {
     let event_name = syn::Ident::new(event.name.as_str(), Span::call_site());
     let event_fields: Vec<TokenStream> = event
@@ -93,7 +626,9 @@ fn gen_event_struct(event: &IdlEvent) -> TokenStream {
             let f_name = syn::Ident::new(f.name.as_str().trim(), Span::call_site());
             let f_ty: syn::Type =
                 syn::parse_str(idl_ty_to_rust_ty(&f.ty).as_str()).expect("valid type");
+            let serde_attr = int_string_attr(&f.ty);
             quote! {
+                #serde_attr
                 pub #f_name: #f_ty,
             }
         })
@@ -108,7 +643,22 @@ fn gen_event_struct(event: &IdlEvent) -> TokenStream {
     }
 }
 
-fn gen_type_struct(type_def: &IdlTypeDefinition) -> TokenStream {
+/// Generate a type nested in or being a zero-copy account
+fn gen_zero_copy_type(
+    type_def: &IdlTypeDefinition,
+    type_defs: &TypeDefs,
+    is_account: bool,
+) -> TokenStream {
+    match type_def.ty {
+        IdlTypeDefinitionTy::Struct { .. } => gen_zero_copy_struct(type_def, type_defs, is_account),
+        IdlTypeDefinitionTy::Enum { .. } => gen_type_struct(type_def, true, false),
+    }
+}
+
+/// Generate a (borsh) type
+/// - `is_copy` derive `Copy` e.g. for enums nested in zero-copy types
+/// - `is_account` additionally generate the anchor account traits
+fn gen_type_struct(type_def: &IdlTypeDefinition, is_copy: bool, is_account: bool) -> TokenStream {
     let type_name = syn::Ident::new(type_def.name.as_str(), Span::call_site());
 
     let res: TokenStream = match type_def.ty {
@@ -118,8 +668,10 @@ fn gen_type_struct(type_def: &IdlTypeDefinition) -> TokenStream {
                 let variant_name = syn::Ident::new(v.name.as_str(), Span::call_site());
                 match v.fields {
                     Some(EnumFields::Named(ref named)) => {
-                        let fields: Vec<TokenStream> =
-                            named.iter().map(field_to_token_stream).collect();
+                        let fields: Vec<TokenStream> = named
+                            .iter()
+                            .map(|f| field_to_token_stream(f, false))
+                            .collect();
                         variant_ts = quote! {
                             #variant_ts
                             #variant_name{#(#fields)*},
@@ -148,21 +700,39 @@ fn gen_type_struct(type_def: &IdlTypeDefinition) -> TokenStream {
                     }
                 }
             }
+            let copy = if is_copy {
+                quote! { Copy, }
+            } else {
+                quote! {}
+            };
             quote! {
-                #[derive(Clone, Debug, PartialEq, AnchorDeserialize, AnchorSerialize, Serialize, Deserialize)]
+                #[derive(Clone, #copy Debug, PartialEq, AnchorDeserialize, AnchorSerialize, Serialize, Deserialize)]
                 pub enum #type_name {
                     #variant_ts
                 }
             }
         }
         IdlTypeDefinitionTy::Struct { ref fields } => {
-            let fields: Vec<TokenStream> = fields.iter().map(field_to_token_stream).collect();
-            // TODO: #[account] for accounts only
-            quote! {
-                #[derive(Debug, PartialEq, Serialize, Deserialize)]
-                #[account]
-                pub struct #type_name  {
-                    #(#fields)*
+            let fields: Vec<TokenStream> = fields
+                .iter()
+                .map(|f| field_to_token_stream(f, true))
+                .collect();
+            if is_account {
+                let traits = gen_account_traits(type_def);
+                quote! {
+                    #[derive(Clone, Debug, PartialEq, AnchorDeserialize, AnchorSerialize, Serialize, Deserialize)]
+                    pub struct #type_name  {
+                        #(#fields)*
+                    }
+
+                    #traits
+                }
+            } else {
+                quote! {
+                    #[derive(Clone, Debug, PartialEq, AnchorDeserialize, AnchorSerialize, Serialize, Deserialize)]
+                    pub struct #type_name  {
+                        #(#fields)*
+                    }
                 }
             }
         }
@@ -200,20 +770,49 @@ fn idl_ty_to_rust_ty(ty: &IdlType) -> String {
     }
 }
 
-fn field_to_token_stream(f: &IdlField) -> TokenStream {
+/// Generate a struct or enum variant field
+/// - `is_pub` make the field public (struct fields)
+fn field_to_token_stream(f: &IdlField, is_pub: bool) -> TokenStream {
     let name = syn::Ident::new(f.name.as_str(), Span::call_site());
     let ty_str = idl_ty_to_rust_ty(&f.ty);
     let ty: syn::Type = syn::parse_str(ty_str.as_str()).unwrap();
+    let vis = if is_pub {
+        quote! { pub }
+    } else {
+        quote! {}
+    };
 
-    // arrays with len > 32 do not implement important traits e.g PartialEq, Serialize, etc.
-    // in solana programs this is typically inconsequential 'padding' and can be safely ignored from the struct
-    if let IdlType::Array(ty, size) = &f.ty {
-        if ty.as_ref() == &IdlType::U8 && *size > 32 {
-            return TokenStream::new();
+    // arrays with len > 32 do not implement serde traits
+    // in solana programs this is typically inconsequential 'padding', it is kept for the layout but not (de)serialized
+    if let IdlType::Array(inner, size) = &f.ty {
+        if inner.as_ref() == &IdlType::U8 && *size > 32 {
+            return quote! {
+                #[serde(skip, default = "zeroed")]
+                #vis #name: #ty,
+            };
         }
     }
+    let serde_attr = int_string_attr(&f.ty);
 
     quote! {
-        #name: #ty,
+        #serde_attr
+        #vis #name: #ty,
+    }
+}
+
+/// Return the serde attribute of a field of type `ty` containing 128-bit integers, if any
+///
+/// serde_json (and bson) have no 128-bit integers, they are (de)serialized as decimal strings
+fn int_string_attr(ty: &IdlType) -> TokenStream {
+    let is_int128 = |ty: &IdlType| matches!(ty, IdlType::U128 | IdlType::I128);
+    match ty {
+        ty if is_int128(ty) => quote! { #[serde(with = "int_string")] },
+        IdlType::Option(inner) if is_int128(inner) => {
+            quote! { #[serde(with = "int_string::option")] }
+        }
+        IdlType::Array(inner, _) if is_int128(inner) => {
+            quote! { #[serde(with = "int_string::array")] }
+        }
+        _ => quote! {},
     }
 }
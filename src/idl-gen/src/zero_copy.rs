@@ -0,0 +1,306 @@
+//! zero-copy (`#[account(zero_copy)]`) type generation
+//!
+//! zero-copy accounts are `#[repr(C)]` structs cast directly from account data onchain.
+//! Their layout is reproduced as a packed struct with any implicit (alignment) padding made explicit,
+//! so the generated type has the exact onchain size regardless of host alignment rules
+//! (e.g. `u128` is 8 byte aligned on sbf but 16 byte aligned on x86_64).
+use std::collections::{HashMap, HashSet};
+
+use anchor_syn::idl::{IdlField, IdlType, IdlTypeDefinition, IdlTypeDefinitionTy};
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+
+use crate::{field_to_token_stream, gen_account_traits};
+
+/// Onchain memory layout of a type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// IDL type definitions by name
+pub type TypeDefs<'a> = HashMap<&'a str, &'a IdlTypeDefinition>;
+
+/// Return `roots` and all types they (transitively) contain
+///
+/// Types nested in a zero-copy type must also be zero-copy (i.e. `Copy` with a fixed layout)
+pub fn zero_copy_closure(roots: &[String], defs: &TypeDefs) -> HashSet<String> {
+    let mut closure = HashSet::<String>::new();
+    let mut stack: Vec<String> = roots.to_vec();
+    while let Some(name) = stack.pop() {
+        if !closure.insert(name.clone()) {
+            continue;
+        }
+        let def = defs
+            .get(name.as_str())
+            .unwrap_or_else(|| panic!("zero copy type not found: {name}"));
+        if let IdlTypeDefinitionTy::Struct { ref fields } = def.ty {
+            stack.extend(fields.iter().filter_map(|f| defined_name(&f.ty)));
+        }
+    }
+
+    closure
+}
+
+/// Return the name of the user defined type within `ty`, if any
+fn defined_name(ty: &IdlType) -> Option<String> {
+    match ty {
+        IdlType::Defined(name) => Some(name.clone()),
+        IdlType::Array(inner, _) | IdlType::Option(inner) | IdlType::Vec(inner) => {
+            defined_name(inner)
+        }
+        _ => None,
+    }
+}
+
+/// Calculate the onchain (sbf, `#[repr(C)]`) layout of `ty`
+pub fn layout_of(ty: &IdlType, defs: &TypeDefs) -> Layout {
+    let primitive = |size: usize| Layout { size, align: size };
+    match ty {
+        IdlType::Bool | IdlType::U8 | IdlType::I8 => primitive(1),
+        IdlType::U16 | IdlType::I16 => primitive(2),
+        IdlType::U32 | IdlType::I32 | IdlType::F32 => primitive(4),
+        IdlType::U64 | IdlType::I64 | IdlType::F64 => primitive(8),
+        // 8 byte aligned on sbf
+        IdlType::U128 | IdlType::I128 => Layout { size: 16, align: 8 },
+        IdlType::PublicKey => Layout { size: 32, align: 1 },
+        IdlType::Array(inner, len) => {
+            let inner = layout_of(inner, defs);
+            Layout {
+                size: inner.size * len,
+                align: inner.align,
+            }
+        }
+        IdlType::Defined(name) => {
+            let def = defs
+                .get(name.as_str())
+                .unwrap_or_else(|| panic!("type not found: {name}"));
+            match def.ty {
+                IdlTypeDefinitionTy::Struct { ref fields } => struct_layout(fields, defs).0,
+                IdlTypeDefinitionTy::Enum { ref variants } => {
+                    assert!(
+                        variants.iter().all(|v| v.fields.is_none()),
+                        "zero copy enum with fields is unsupported: {name}"
+                    );
+                    primitive(1)
+                }
+            }
+        }
+        _ => panic!("zero copy type has no fixed layout: {ty:?}"),
+    }
+}
+
+/// Calculate the layout of a struct with `fields`
+///
+/// Returns the struct layout and the implicit padding required before each field (and at the end)
+fn struct_layout(fields: &[IdlField], defs: &TypeDefs) -> (Layout, Vec<usize>) {
+    let mut offset = 0;
+    let mut align = 1;
+    let mut padding = Vec::with_capacity(fields.len() + 1);
+    for f in fields {
+        let field = layout_of(&f.ty, defs);
+        let pad = (field.align - offset % field.align) % field.align;
+        padding.push(pad);
+        offset += pad + field.size;
+        align = align.max(field.align);
+    }
+    let pad = (align - offset % align) % align;
+    padding.push(pad);
+
+    (
+        Layout {
+            size: offset + pad,
+            align,
+        },
+        padding,
+    )
+}
+
+/// True if `ty` is valid for any bit pattern i.e. can implement `bytemuck::Pod`
+fn is_pod(ty: &IdlType, defs: &TypeDefs) -> bool {
+    match ty {
+        IdlType::U8
+        | IdlType::I8
+        | IdlType::U16
+        | IdlType::I16
+        | IdlType::U32
+        | IdlType::I32
+        | IdlType::F32
+        | IdlType::U64
+        | IdlType::I64
+        | IdlType::F64
+        | IdlType::U128
+        | IdlType::I128
+        | IdlType::PublicKey => true,
+        IdlType::Array(inner, _) => is_pod(inner, defs),
+        IdlType::Defined(name) => match defs.get(name.as_str()).map(|d| &d.ty) {
+            Some(IdlTypeDefinitionTy::Struct { fields }) => {
+                fields.iter().all(|f| is_pod(&f.ty, defs))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Generate a zero-copy struct for `type_def`
+/// - `is_account` additionally generate the anchor account traits
+pub fn gen_zero_copy_struct(
+    type_def: &IdlTypeDefinition,
+    defs: &TypeDefs,
+    is_account: bool,
+) -> TokenStream {
+    let IdlTypeDefinitionTy::Struct { ref fields } = type_def.ty else {
+        unreachable!("zero copy struct");
+    };
+    let type_name = syn::Ident::new(type_def.name.as_str(), Span::call_site());
+    let (layout, padding) = struct_layout(fields, defs);
+    let size = layout.size;
+
+    let implicit_padding = |idx: usize, pad: usize| {
+        let name = syn::Ident::new(format!("_padding{idx}").as_str(), Span::call_site());
+        (
+            quote! {
+                #[serde(skip, default = "zeroed")]
+                #name: [u8; #pad],
+            },
+            name,
+        )
+    };
+    let mut field_ts = Vec::<TokenStream>::with_capacity(fields.len());
+    let mut field_names = Vec::<syn::Ident>::with_capacity(fields.len());
+    for (idx, (f, pad)) in fields.iter().zip(padding.iter()).enumerate() {
+        if *pad > 0 {
+            let (ts, name) = implicit_padding(idx, *pad);
+            field_ts.push(ts);
+            field_names.push(name);
+        }
+        field_ts.push(field_to_token_stream(f, true));
+        field_names.push(syn::Ident::new(f.name.as_str(), Span::call_site()));
+    }
+    if let Some(pad) = padding.last().filter(|p| **p > 0) {
+        let (ts, name) = implicit_padding(fields.len(), *pad);
+        field_ts.push(ts);
+        field_names.push(name);
+    }
+
+    let mut output = quote! {
+        #[derive(Clone, Copy, Debug, PartialEq, AnchorDeserialize, Serialize, Deserialize)]
+        #[repr(C, packed)]
+        pub struct #type_name {
+            #(#field_ts)*
+        }
+
+        const _: () = assert!(std::mem::size_of::<#type_name>() == #size);
+
+        impl AnchorSerialize for #type_name {
+            fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+                // copy out of the packed struct, references to its fields may be unaligned
+                #(AnchorSerialize::serialize(&{ self.#field_names }, writer)?;)*
+                Ok(())
+            }
+        }
+    };
+
+    if fields.iter().all(|f| is_pod(&f.ty, defs)) {
+        output.extend(quote! {
+            // SAFETY: packed with no uninit bytes, all fields are `Pod`
+            unsafe impl anchor_lang::__private::bytemuck::Zeroable for #type_name {}
+            unsafe impl anchor_lang::__private::bytemuck::Pod for #type_name {}
+        });
+    }
+
+    if is_account {
+        output.extend(gen_account_traits(type_def));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use anchor_syn::idl::IdlTypeDefinition;
+
+    use super::*;
+
+    fn field(name: &str, ty: IdlType) -> IdlField {
+        IdlField {
+            name: name.to_string(),
+            docs: None,
+            ty,
+        }
+    }
+
+    fn struct_def(name: &str, fields: Vec<IdlField>) -> IdlTypeDefinition {
+        IdlTypeDefinition {
+            name: name.to_string(),
+            docs: None,
+            ty: IdlTypeDefinitionTy::Struct { fields },
+        }
+    }
+
+    #[test]
+    fn layout_with_implicit_padding() {
+        let inner = struct_def(
+            "Inner",
+            vec![field("a", IdlType::U128), field("b", IdlType::U8)],
+        );
+        let outer = struct_def(
+            "Outer",
+            vec![
+                field("flag", IdlType::Bool),
+                field("inner", IdlType::Defined("Inner".to_string())),
+                field(
+                    "nested",
+                    IdlType::Array(Box::new(IdlType::Array(Box::new(IdlType::U16), 3)), 2),
+                ),
+                field("padding", IdlType::Array(Box::new(IdlType::U8), 40)),
+            ],
+        );
+        let defs = TypeDefs::from([("Inner", &inner), ("Outer", &outer)]);
+
+        // u128 is 8 byte aligned, trailing padding rounds to the alignment
+        assert_eq!(
+            layout_of(&IdlType::Defined("Inner".to_string()), &defs),
+            Layout { size: 24, align: 8 }
+        );
+        let IdlTypeDefinitionTy::Struct { ref fields } = outer.ty else {
+            unreachable!();
+        };
+        let (layout, padding) = struct_layout(fields, &defs);
+        assert_eq!(padding, vec![0, 7, 0, 0, 4]);
+        assert_eq!(layout, Layout { size: 88, align: 8 });
+    }
+
+    #[test]
+    fn closure_and_pod() {
+        let inner = struct_def("Inner", vec![field("a", IdlType::U64)]);
+        let outer = struct_def(
+            "Outer",
+            vec![field(
+                "inner",
+                IdlType::Array(Box::new(IdlType::Defined("Inner".to_string())), 2),
+            )],
+        );
+        let other = struct_def("Other", vec![field("flag", IdlType::Bool)]);
+        let defs = TypeDefs::from([("Inner", &inner), ("Outer", &outer), ("Other", &other)]);
+
+        assert_eq!(
+            zero_copy_closure(&["Outer".to_string()], &defs),
+            HashSet::from(["Outer".to_string(), "Inner".to_string()])
+        );
+        assert!(is_pod(&IdlType::Defined("Outer".to_string()), &defs));
+        assert!(!is_pod(&IdlType::Defined("Other".to_string()), &defs));
+    }
+
+    #[test]
+    fn account_owned_by_idl_program() {
+        let account = struct_def("Account", vec![field("a", IdlType::U64)]);
+        let defs = TypeDefs::from([("Account", &account)]);
+
+        let tokens = gen_zero_copy_struct(&account, &defs, true).to_string();
+        assert!(tokens.contains("fn owner () -> Pubkey { PROGRAM_ID }"));
+        assert!(!tokens.contains("crate :: ID"));
+    }
+}
@@ -0,0 +1,679 @@
+//! Liquidation alerts
+//!
+//! Raises alerts on `LiquidationRecord`s of monitored accounts (liquidations and bankruptcies), on liquidation
+//! spikes per market and on `User` accounts entering liquidation, delivered to webhook, Slack and stdout targets
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_sdk::pubkey::{ParsePubkeyError, Pubkey};
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    markets::MarketRegistry,
+    types::drift::{
+        accounts::User,
+        events::LiquidationRecord,
+        types::{LiquidationType, MarketType},
+    },
+};
+
+/// `User.status` flag of accounts being liquidated
+const USER_STATUS_BEING_LIQUIDATED: u8 = 0b01;
+/// `User.status` flag of bankrupt accounts
+const USER_STATUS_BANKRUPT: u8 = 0b10;
+
+/// Alert rules and targets
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AlertConfig {
+    /// Alert liquidations of these `User` accounts, all accounts if empty
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    /// Only alert on these markets (symbols e.g. 'SOL-PERP'), all markets if empty
+    #[serde(default)]
+    pub markets: Vec<String>,
+    /// Alert when liquidations on a market spike
+    pub spike: Option<SpikeRule>,
+    /// Where to deliver alerts
+    #[serde(default)]
+    pub targets: Vec<AlertTarget>,
+}
+
+/// Liquidation spike threshold
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct SpikeRule {
+    /// Number of liquidations
+    pub count: usize,
+    /// within this many seconds
+    pub window_s: i64,
+}
+
+/// An alert delivery target
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AlertTarget {
+    /// POST the alert as json
+    Webhook { url: String },
+    /// POST a Slack style `{"text": ..}` message
+    Slack { url: String },
+    /// Print the alert as json
+    Stdout,
+}
+
+/// A raised alert
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Alert {
+    /// A monitored account was liquidated
+    #[serde(rename_all = "camelCase")]
+    Liquidated {
+        user: String,
+        liquidation_id: u16,
+        liquidation_type: LiquidationType,
+        market: String,
+        ts: i64,
+    },
+    /// A monitored account went bankrupt
+    #[serde(rename_all = "camelCase")]
+    Bankrupt {
+        user: String,
+        liquidation_id: u16,
+        market: String,
+        ts: i64,
+    },
+    /// A monitored `User` account is being liquidated, or bankrupt, as of `slot`
+    #[serde(rename_all = "camelCase")]
+    UserStatus {
+        user: String,
+        being_liquidated: bool,
+        bankrupt: bool,
+        slot: u64,
+    },
+    /// Liquidations on a market reached the spike threshold
+    #[serde(rename_all = "camelCase")]
+    LiquidationSpike {
+        market: String,
+        count: usize,
+        window_s: i64,
+        ts: i64,
+    },
+    /// An alerted liquidation was retracted i.e. its tx was in a skipped slot
+    #[serde(rename_all = "camelCase")]
+    Retracted {
+        user: String,
+        liquidation_id: u16,
+        market: String,
+        ts: i64,
+    },
+}
+
+impl Alert {
+    /// Human readable alert message
+    pub fn message(&self) -> String {
+        match self {
+            Self::Liquidated {
+                user,
+                liquidation_id,
+                liquidation_type,
+                market,
+                ..
+            } => format!(
+                "🚨 {user} liquidated on {market} ({liquidation_type:?}, id: {liquidation_id})"
+            ),
+            Self::Bankrupt {
+                user,
+                liquidation_id,
+                market,
+                ..
+            } => format!("💀 {user} bankrupt on {market} (id: {liquidation_id})"),
+            Self::UserStatus {
+                user,
+                being_liquidated,
+                bankrupt,
+                slot,
+            } => format!(
+                "⚠️ {user} status @ {slot}: being liquidated: {being_liquidated}, bankrupt: {bankrupt}"
+            ),
+            Self::LiquidationSpike {
+                market,
+                count,
+                window_s,
+                ..
+            } => format!("📈 {count} liquidations on {market} within {window_s}s"),
+            Self::Retracted {
+                user,
+                liquidation_id,
+                market,
+                ..
+            } => format!("↩️ {user} liquidation on {market} retracted (id: {liquidation_id})"),
+        }
+    }
+}
+
+/// Mutable alerting state
+#[derive(Default)]
+struct AlertState {
+    /// Recent liquidation timestamps by market
+    liquidations: HashMap<String, Vec<i64>>,
+    /// Timestamp of the last spike alert by market
+    last_spike: HashMap<String, i64>,
+    /// Last seen `User.status` by account
+    user_status: HashMap<Pubkey, u8>,
+}
+
+/// Evaluates alert rules and delivers alerts
+///
+/// Cheap to clone, all clones share the same underlying state
+#[derive(Clone)]
+pub struct Alerter {
+    accounts: Arc<HashSet<Pubkey>>,
+    markets: Arc<Vec<String>>,
+    spike: Option<SpikeRule>,
+    targets: Arc<Vec<AlertTarget>>,
+    client: reqwest::Client,
+    state: Arc<Mutex<AlertState>>,
+}
+
+impl std::fmt::Debug for Alerter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Alerter")
+            .field("accounts", &self.accounts)
+            .field("markets", &self.markets)
+            .field("spike", &self.spike)
+            .field("targets", &self.targets)
+            .finish()
+    }
+}
+
+impl Alerter {
+    /// Create a new `Alerter` from its `config`
+    pub fn new(config: AlertConfig) -> Result<Self, ParsePubkeyError> {
+        let accounts = config
+            .accounts
+            .iter()
+            .map(|a| Pubkey::try_from(a.as_str()))
+            .collect::<Result<HashSet<Pubkey>, _>>()?;
+
+        Ok(Self {
+            accounts: Arc::new(accounts),
+            markets: Arc::new(config.markets),
+            spike: config.spike,
+            targets: Arc::new(config.targets),
+            client: reqwest::Client::new(),
+            state: Default::default(),
+        })
+    }
+
+    /// Create a new `Alerter` from a json config file at `path`
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let config: AlertConfig =
+            serde_json::from_str(json.as_str()).map_err(|err| err.to_string())?;
+        Self::new(config).map_err(|err| err.to_string())
+    }
+
+    /// True if alerts on `account` are enabled
+    fn is_monitored(&self, account: &Pubkey) -> bool {
+        self.accounts.is_empty() || self.accounts.contains(account)
+    }
+
+    /// True if alerts on `market` are enabled
+    fn is_monitored_market(&self, market: &str) -> bool {
+        self.markets.is_empty() || self.markets.iter().any(|m| m == market)
+    }
+
+    /// Evaluate the rules on a `LiquidationRecord`, delivering and returning any alerts
+    ///
+    /// A liquidation alerts once, even if it spans several records or is indexed repeatedly
+    pub async fn on_liquidation<T: IndexerBackend>(
+        &self,
+        db: &T,
+        record: &LiquidationRecord,
+        markets: Option<&MarketRegistry>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let (market_type, market_index) = liquidated_market(record);
+        let market = market_name(market_type, market_index, markets);
+        if !self.is_monitored_market(market.as_str()) {
+            return Ok(Default::default());
+        }
+        let user = record.user;
+        let bankrupt = record.bankrupt
+            || matches!(
+                record.liquidationType,
+                LiquidationType::PerpBankruptcy | LiquidationType::SpotBankruptcy
+            );
+
+        // keyed by liquidation ID rather than tx
+        let mut alerts = Vec::new();
+        let liquidation_id = record.liquidationId as u32;
+        if db
+            .mark_applied("liquidation_alerts", &user, "", liquidation_id)
+            .await?
+        {
+            if let Some(spike) = self.spike(market.as_str(), record.ts) {
+                alerts.push(spike);
+            }
+            if self.is_monitored(&user) {
+                alerts.push(Alert::Liquidated {
+                    user: user.to_string(),
+                    liquidation_id: record.liquidationId,
+                    liquidation_type: record.liquidationType.clone(),
+                    market: market.clone(),
+                    ts: record.ts,
+                });
+            }
+        }
+        if bankrupt
+            && self.is_monitored(&user)
+            && db
+                .mark_applied("bankruptcy_alerts", &user, "", liquidation_id)
+                .await?
+        {
+            alerts.push(Alert::Bankrupt {
+                user: user.to_string(),
+                liquidation_id: record.liquidationId,
+                market,
+                ts: record.ts,
+            });
+        }
+
+        self.deliver(alerts.as_slice());
+        Ok(alerts)
+    }
+
+    /// Undo the alerting state of a retracted `LiquidationRecord`, delivering and returning a retraction alert if
+    /// the liquidation was alerted
+    pub async fn on_retracted_liquidation<T: IndexerBackend>(
+        &self,
+        db: &T,
+        record: &LiquidationRecord,
+        markets: Option<&MarketRegistry>,
+    ) -> Result<Vec<Alert>, DbError> {
+        let (market_type, market_index) = liquidated_market(record);
+        let market = market_name(market_type, market_index, markets);
+        if !self.is_monitored_market(market.as_str()) {
+            return Ok(Default::default());
+        }
+        let user = record.user;
+        let liquidation_id = Some(record.liquidationId as u32);
+        db.unmark_applied("bankruptcy_alerts", &user, "", liquidation_id)
+            .await?;
+        if db
+            .unmark_applied("liquidation_alerts", &user, "", liquidation_id)
+            .await?
+            == 0
+        {
+            return Ok(Default::default());
+        }
+        if let Some(recent) = self.state.lock().unwrap().liquidations.get_mut(&market) {
+            if let Some(i) = recent.iter().position(|ts| *ts == record.ts) {
+                recent.remove(i);
+            }
+        }
+
+        let mut alerts = Vec::new();
+        if self.is_monitored(&user) {
+            alerts.push(Alert::Retracted {
+                user: user.to_string(),
+                liquidation_id: record.liquidationId,
+                market,
+                ts: record.ts,
+            });
+        }
+        self.deliver(alerts.as_slice());
+        Ok(alerts)
+    }
+
+    /// Count a liquidation on `market` at `ts`, returning a spike alert if the threshold is reached
+    ///
+    /// Alerts at most once per window and market
+    fn spike(&self, market: &str, ts: i64) -> Option<Alert> {
+        let rule = self.spike?;
+        let mut state = self.state.lock().unwrap();
+        let recent = state.liquidations.entry(market.to_string()).or_default();
+        recent.push(ts);
+        recent.retain(|t| *t > ts - rule.window_s);
+        let count = recent.len();
+        if count < rule.count {
+            return None;
+        }
+        match state.last_spike.get(market) {
+            Some(last) if ts - last < rule.window_s => None,
+            _ => {
+                state.last_spike.insert(market.to_string(), ts);
+                Some(Alert::LiquidationSpike {
+                    market: market.to_string(),
+                    count,
+                    window_s: rule.window_s,
+                    ts,
+                })
+            }
+        }
+    }
+
+    /// Evaluate the rules on a `User` account update, delivering and returning any alert
+    ///
+    /// Alerts when a monitored account enters liquidation or bankruptcy
+    pub fn on_user(&self, pubkey: Pubkey, slot: u64, user: &User) -> Option<Alert> {
+        if !self.is_monitored(&pubkey) {
+            return None;
+        }
+        let status = user.status & (USER_STATUS_BEING_LIQUIDATED | USER_STATUS_BANKRUPT);
+        let previous = self
+            .state
+            .lock()
+            .unwrap()
+            .user_status
+            .insert(pubkey, status)
+            .unwrap_or_default();
+        // only newly set flags alert
+        if status & !previous == 0 {
+            return None;
+        }
+        let alert = Alert::UserStatus {
+            user: pubkey.to_string(),
+            being_liquidated: status & USER_STATUS_BEING_LIQUIDATED != 0,
+            bankrupt: status & USER_STATUS_BANKRUPT != 0,
+            slot,
+        };
+        self.deliver(std::slice::from_ref(&alert));
+        Some(alert)
+    }
+
+    /// Deliver `alerts` to all targets (in the background)
+    fn deliver(&self, alerts: &[Alert]) {
+        for alert in alerts {
+            info!("alert: {}", alert.message());
+            for target in self.targets.iter() {
+                match target {
+                    AlertTarget::Stdout => println!("{}", json!(alert)),
+                    AlertTarget::Webhook { url } => self.post(url, json!(alert)),
+                    AlertTarget::Slack { url } => {
+                        self.post(url, json!({ "text": alert.message() }))
+                    }
+                }
+            }
+        }
+    }
+
+    /// POST `body` to `url` (in the background)
+    fn post(&self, url: &str, body: serde_json::Value) {
+        let request = self.client.post(url).json(&body);
+        let url = url.to_string();
+        tokio::spawn(async move {
+            match request.send().await.and_then(|res| res.error_for_status()) {
+                Ok(_) => (),
+                Err(err) => warn!("failed delivering alert to {url}: {err:?}"),
+            }
+        });
+    }
+}
+
+/// Return the (liability) market of a liquidation
+fn liquidated_market(record: &LiquidationRecord) -> (MarketType, u16) {
+    match record.liquidationType {
+        LiquidationType::LiquidatePerp => (MarketType::Perp, record.liquidatePerp.marketIndex),
+        LiquidationType::LiquidateSpot => {
+            (MarketType::Spot, record.liquidateSpot.liabilityMarketIndex)
+        }
+        LiquidationType::LiquidateBorrowForPerpPnl => (
+            MarketType::Spot,
+            record.liquidateBorrowForPerpPnl.liabilityMarketIndex,
+        ),
+        LiquidationType::LiquidatePerpPnlForDeposit => (
+            MarketType::Perp,
+            record.liquidatePerpPnlForDeposit.perpMarketIndex,
+        ),
+        LiquidationType::PerpBankruptcy => (MarketType::Perp, record.perpBankruptcy.marketIndex),
+        LiquidationType::SpotBankruptcy => (MarketType::Spot, record.spotBankruptcy.marketIndex),
+    }
+}
+
+/// Return the symbol of a market, or its type and index if unknown
+fn market_name(
+    market_type: MarketType,
+    market_index: u16,
+    markets: Option<&MarketRegistry>,
+) -> String {
+    markets
+        .and_then(|m| m.get(market_type, market_index))
+        .map(|m| m.symbol)
+        .unwrap_or_else(|| format!("{market_type:?}-{market_index}"))
+}
+
+#[cfg(test)]
+mod test {
+    use anchor_lang::Discriminator;
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        drift::accounts::DriftAccount,
+        drift::types::{
+            LiquidateBorrowForPerpPnlRecord, LiquidatePerpPnlForDepositRecord, LiquidatePerpRecord,
+            LiquidateSpotRecord, PerpBankruptcyRecord, SpotBankruptcyRecord,
+        },
+    };
+
+    fn liquidation(user: Pubkey, liquidation_id: u16, ts: i64) -> LiquidationRecord {
+        LiquidationRecord {
+            ts,
+            liquidationType: LiquidationType::LiquidatePerp,
+            user,
+            liquidator: Pubkey::new_unique(),
+            marginRequirement: 0,
+            totalCollateral: 0,
+            marginFreed: 0,
+            liquidationId: liquidation_id,
+            bankrupt: false,
+            canceledOrderIds: vec![],
+            liquidatePerp: LiquidatePerpRecord {
+                marketIndex: 1,
+                oraclePrice: 0,
+                baseAssetAmount: 0,
+                quoteAssetAmount: 0,
+                lpShares: 0,
+                fillRecordId: 0,
+                userOrderId: 0,
+                liquidatorOrderId: 0,
+                liquidatorFee: 0,
+                ifFee: 0,
+            },
+            liquidateSpot: LiquidateSpotRecord {
+                assetMarketIndex: 0,
+                assetPrice: 0,
+                assetTransfer: 0,
+                liabilityMarketIndex: 0,
+                liabilityPrice: 0,
+                liabilityTransfer: 0,
+                ifFee: 0,
+            },
+            liquidateBorrowForPerpPnl: LiquidateBorrowForPerpPnlRecord {
+                perpMarketIndex: 0,
+                marketOraclePrice: 0,
+                pnlTransfer: 0,
+                liabilityMarketIndex: 0,
+                liabilityPrice: 0,
+                liabilityTransfer: 0,
+            },
+            liquidatePerpPnlForDeposit: LiquidatePerpPnlForDepositRecord {
+                perpMarketIndex: 0,
+                marketOraclePrice: 0,
+                pnlTransfer: 0,
+                assetMarketIndex: 0,
+                assetPrice: 0,
+                assetTransfer: 0,
+            },
+            perpBankruptcy: PerpBankruptcyRecord {
+                marketIndex: 1,
+                pnl: 0,
+                ifPayment: 0,
+                clawbackUser: None,
+                clawbackUserPayment: None,
+                cumulativeFundingRateDelta: 0,
+            },
+            spotBankruptcy: SpotBankruptcyRecord {
+                marketIndex: 0,
+                borrowAmount: 0,
+                ifPayment: 0,
+                cumulativeDepositInterestDelta: 0,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn liquidation_alerts() {
+        let db = MockBackend::init("mockdb").await;
+        let monitored = Pubkey::new_unique();
+        let config: AlertConfig = serde_json::from_value(json!({
+            "accounts": [monitored.to_string()],
+            "markets": ["Perp-1"],
+            "spike": { "count": 3, "window_s": 60 },
+            "targets": [{ "type": "stdout" }],
+        }))
+        .unwrap();
+        let alerter = Alerter::new(config).unwrap();
+
+        // one alert per liquidation, repeated records are ignored
+        let record = liquidation(monitored, 1, 1_000);
+        let alerts = alerter.on_liquidation(&db, &record, None).await.unwrap();
+        assert_eq!(
+            alerts,
+            vec![Alert::Liquidated {
+                user: monitored.to_string(),
+                liquidation_id: 1,
+                liquidation_type: LiquidationType::LiquidatePerp,
+                market: "Perp-1".to_string(),
+                ts: 1_000,
+            }]
+        );
+        assert!(alerter
+            .on_liquidation(&db, &record, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // bankruptcy of the same liquidation
+        let bankruptcy = LiquidationRecord {
+            liquidationType: LiquidationType::PerpBankruptcy,
+            bankrupt: true,
+            ..liquidation(monitored, 1, 1_010)
+        };
+        let alerts = alerter
+            .on_liquidation(&db, &bankruptcy, None)
+            .await
+            .unwrap();
+        assert!(matches!(alerts.as_slice(), [Alert::Bankrupt { .. }]));
+
+        // other accounts only count towards spikes
+        let other = Pubkey::new_unique();
+        assert!(alerter
+            .on_liquidation(&db, &liquidation(other, 1, 1_020), None)
+            .await
+            .unwrap()
+            .is_empty());
+        let alerts = alerter
+            .on_liquidation(&db, &liquidation(Pubkey::new_unique(), 1, 1_030), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            alerts,
+            vec![Alert::LiquidationSpike {
+                market: "Perp-1".to_string(),
+                count: 3,
+                window_s: 60,
+                ts: 1_030,
+            }]
+        );
+        // once per window
+        assert!(alerter
+            .on_liquidation(&db, &liquidation(other, 2, 1_040), None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // unmonitored markets
+        let spot = LiquidationRecord {
+            liquidationType: LiquidationType::LiquidateSpot,
+            ..liquidation(monitored, 2, 1_050)
+        };
+        assert!(alerter
+            .on_liquidation(&db, &spot, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // retracted (skipped slot), alerts again if re-indexed
+        let retracted = alerter
+            .on_retracted_liquidation(&db, &record, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            retracted,
+            vec![Alert::Retracted {
+                user: monitored.to_string(),
+                liquidation_id: 1,
+                market: "Perp-1".to_string(),
+                ts: 1_000,
+            }]
+        );
+        assert!(alerter
+            .on_retracted_liquidation(&db, &record, None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            alerter
+                .on_liquidation(&db, &record, None)
+                .await
+                .unwrap()
+                .as_slice(),
+            [Alert::Liquidated { .. }]
+        ));
+
+        assert_eq!(
+            retracted[0].message(),
+            format!("↩️ {monitored} liquidation on Perp-1 retracted (id: 1)")
+        );
+        assert_eq!(
+            alerts[0].message(),
+            "📈 3 liquidations on Perp-1 within 60s"
+        );
+    }
+
+    #[test]
+    fn user_status_alerts() {
+        let monitored = Pubkey::new_unique();
+        let alerter = Alerter::new(AlertConfig {
+            accounts: vec![monitored.to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let mut data = vec![0_u8; 4_376];
+        data[..8].copy_from_slice(&User::DISCRIMINATOR);
+        let Some(DriftAccount::User(mut user)) = DriftAccount::try_from_bytes(data.as_slice())
+        else {
+            panic!("it decodes");
+        };
+
+        assert!(alerter.on_user(monitored, 1, &user).is_none());
+        user.status = USER_STATUS_BEING_LIQUIDATED;
+        assert!(matches!(
+            alerter.on_user(monitored, 2, &user),
+            Some(Alert::UserStatus {
+                being_liquidated: true,
+                bankrupt: false,
+                ..
+            })
+        ));
+        // unchanged
+        assert!(alerter.on_user(monitored, 3, &user).is_none());
+        user.status = USER_STATUS_BEING_LIQUIDATED | USER_STATUS_BANKRUPT;
+        assert!(alerter.on_user(monitored, 4, &user).is_some());
+        assert!(alerter.on_user(Pubkey::new_unique(), 5, &user).is_none());
+    }
+}
@@ -0,0 +1,338 @@
+//! Per account trading analytics
+//!
+//! `OrderActionRecord`s of monitored accounts are aggregated into daily per market buckets (`account_stats`),
+//! rolling (last n days) and lifetime figures are sums over the buckets
+use std::collections::BTreeMap;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    program::EventContext,
+    types::drift::{
+        events::OrderActionRecord,
+        types::{MarketType, OrderAction},
+    },
+};
+
+/// Length of a stats bucket (seconds)
+pub const STATS_BUCKET_S: i64 = 24 * 60 * 60;
+
+/// Trading figures, all amounts are `QUOTE_PRECISION`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradingStats {
+    /// Notional volume as taker
+    pub taker_volume: u64,
+    /// Notional volume as maker
+    pub maker_volume: u64,
+    pub taker_fills: u32,
+    pub maker_fills: u32,
+    /// Fees paid as taker
+    pub taker_fees: u64,
+    /// Fees paid as maker (positive `makerFee`)
+    pub maker_fees: u64,
+    /// Rebates earned as maker (negative `makerFee`)
+    pub maker_rebates: u64,
+    /// Rewards earned filling (and triggering) orders
+    pub filler_rewards: u64,
+    /// Rewards paid to the account's referrer out of its taker fees
+    pub referrer_rewards: u64,
+}
+
+impl TradingStats {
+    /// Total notional volume
+    pub fn volume(&self) -> u64 {
+        self.taker_volume + self.maker_volume
+    }
+
+    /// Add `other` to the stats
+    pub fn add(&mut self, other: &Self) {
+        self.taker_volume += other.taker_volume;
+        self.maker_volume += other.maker_volume;
+        self.taker_fills += other.taker_fills;
+        self.maker_fills += other.maker_fills;
+        self.taker_fees += other.taker_fees;
+        self.maker_fees += other.maker_fees;
+        self.maker_rebates += other.maker_rebates;
+        self.filler_rewards += other.filler_rewards;
+        self.referrer_rewards += other.referrer_rewards;
+    }
+
+    /// Subtract `other` from the stats
+    pub fn sub(&mut self, other: &Self) {
+        self.taker_volume = self.taker_volume.saturating_sub(other.taker_volume);
+        self.maker_volume = self.maker_volume.saturating_sub(other.maker_volume);
+        self.taker_fills = self.taker_fills.saturating_sub(other.taker_fills);
+        self.maker_fills = self.maker_fills.saturating_sub(other.maker_fills);
+        self.taker_fees = self.taker_fees.saturating_sub(other.taker_fees);
+        self.maker_fees = self.maker_fees.saturating_sub(other.maker_fees);
+        self.maker_rebates = self.maker_rebates.saturating_sub(other.maker_rebates);
+        self.filler_rewards = self.filler_rewards.saturating_sub(other.filler_rewards);
+        self.referrer_rewards = self.referrer_rewards.saturating_sub(other.referrer_rewards);
+    }
+
+    /// Return the stats of `account` from `record`, if the account took part
+    pub fn from_record(account: &Pubkey, record: &OrderActionRecord) -> Option<Self> {
+        let mut stats = Self::default();
+        if record.action == OrderAction::Fill {
+            let quote = record.quoteAssetAmountFilled.unwrap_or_default();
+            if record.taker.as_ref() == Some(account) {
+                stats.taker_volume = quote;
+                stats.taker_fills = 1;
+                stats.taker_fees = record.takerFee.unwrap_or_default();
+                stats.referrer_rewards = record.referrerReward.unwrap_or_default() as u64;
+            }
+            if record.maker.as_ref() == Some(account) {
+                let fee = record.makerFee.unwrap_or_default();
+                stats.maker_volume = quote;
+                stats.maker_fills = 1;
+                stats.maker_fees = fee.max(0) as u64;
+                stats.maker_rebates = fee.min(0).unsigned_abs();
+            }
+        }
+        if record.filler.as_ref() == Some(account) {
+            stats.filler_rewards = record.fillerReward.unwrap_or_default();
+        }
+
+        (stats != Self::default()).then_some(stats)
+    }
+}
+
+/// Trading stats of an account in a market over one bucket
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountStats {
+    pub account: Pubkey,
+    pub market_type: MarketType,
+    pub market_index: u16,
+    /// Unix timestamp of the bucket start
+    pub bucket: i64,
+    #[serde(flatten)]
+    pub stats: TradingStats,
+}
+
+/// Trading stats of an account in a market
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketStats {
+    pub market_type: MarketType,
+    pub market_index: u16,
+    #[serde(flatten)]
+    pub stats: TradingStats,
+}
+
+/// Trading stats of an account over a window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsSummary {
+    pub account: Pubkey,
+    /// Start of the window (unix timestamp), unset for lifetime stats
+    pub since: Option<i64>,
+    /// Notional volume over all markets
+    pub volume: u64,
+    /// Stats over all markets
+    pub total: TradingStats,
+    /// Stats by market
+    pub markets: Vec<MarketStats>,
+}
+
+impl StatsSummary {
+    /// Sum `buckets` of `account` starting at or after `since`
+    pub fn new(account: Pubkey, since: Option<i64>, buckets: &[AccountStats]) -> Self {
+        let mut total = TradingStats::default();
+        let mut markets = BTreeMap::<(u8, u16), MarketStats>::new();
+        for bucket in buckets {
+            total.add(&bucket.stats);
+            let market_type = match bucket.market_type {
+                MarketType::Perp => 0,
+                MarketType::Spot => 1,
+            };
+            markets
+                .entry((market_type, bucket.market_index))
+                .or_insert_with(|| MarketStats {
+                    market_type: bucket.market_type,
+                    market_index: bucket.market_index,
+                    stats: Default::default(),
+                })
+                .stats
+                .add(&bucket.stats);
+        }
+
+        Self {
+            account,
+            since,
+            volume: total.volume(),
+            total,
+            markets: markets.into_values().collect(),
+        }
+    }
+}
+
+/// Start of the stats bucket containing unix timestamp `ts`
+pub fn stats_bucket(ts: i64) -> i64 {
+    ts - ts.rem_euclid(STATS_BUCKET_S)
+}
+
+/// Add `record` to the stats of the indexed accounts (`ctx.accounts`)
+pub async fn track_account_stats<T: IndexerBackend>(
+    db: &T,
+    record: &OrderActionRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    for account in ctx.accounts.iter() {
+        track_stats(db, *account, record, ctx).await?;
+    }
+    Ok(())
+}
+
+/// Add `record` to the stats of `account`
+async fn track_stats<T: IndexerBackend>(
+    db: &T,
+    account: Pubkey,
+    record: &OrderActionRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    let Some(stats) = TradingStats::from_record(&account, record) else {
+        return Ok(());
+    };
+    if !db
+        .mark_applied("account_stats", &account, &ctx.signature, ctx.log_index)
+        .await?
+    {
+        debug!("stats of {account} already include {}", ctx.signature);
+        return Ok(());
+    }
+
+    let bucket = stats_bucket(record.ts);
+    let mut entry = db
+        .account_stats(&account, record.marketType, record.marketIndex, bucket)
+        .await?
+        .unwrap_or_else(|| AccountStats {
+            account,
+            market_type: record.marketType,
+            market_index: record.marketIndex,
+            bucket,
+            stats: Default::default(),
+        });
+    entry.stats.add(&stats);
+    db.upsert_account_stats(entry).await
+}
+
+/// Remove the `records` of retracted tx `ctx` from the stats of the indexed accounts (`ctx.accounts`)
+pub async fn untrack_account_stats<T: IndexerBackend>(
+    db: &T,
+    records: &[OrderActionRecord],
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    for account in ctx.accounts.iter() {
+        if db
+            .unmark_applied("account_stats", account, &ctx.signature, None)
+            .await?
+            == 0
+        {
+            continue;
+        }
+        for record in records {
+            let Some(stats) = TradingStats::from_record(account, record) else {
+                continue;
+            };
+            let bucket = stats_bucket(record.ts);
+            let Some(mut entry) = db
+                .account_stats(account, record.marketType, record.marketIndex, bucket)
+                .await?
+            else {
+                continue;
+            };
+            entry.stats.sub(&stats);
+            db.upsert_account_stats(entry).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the stats of `account` over the last `days` (incl. the current day) as of `now`, or lifetime stats
+pub async fn account_summary<T: IndexerBackend>(
+    db: &T,
+    account: Pubkey,
+    days: Option<u32>,
+    now: i64,
+) -> Result<StatsSummary, DbError> {
+    let since = days.map(|days| stats_bucket(now) - (days.max(1) as i64 - 1) * STATS_BUCKET_S);
+    let buckets = db.account_stats_of(&account, since).await?;
+    Ok(StatsSummary::new(account, since, buckets.as_slice()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::{fill, tx_ctx, SLOT},
+    };
+
+    fn ctx(account: Pubkey, signature: &str) -> EventContext {
+        EventContext {
+            accounts: vec![account],
+            ..tx_ctx(signature, SLOT, 0)
+        }
+    }
+
+    #[tokio::test]
+    async fn account_stats() {
+        let db = MockBackend::init("mockdb").await;
+        let fill = fill();
+        let maker = Pubkey::from_str("4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV").unwrap();
+        let taker = Pubkey::from_str("H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG").unwrap();
+
+        // the maker's fills, one a week earlier and one in another market, and the same fill twice
+        let earlier = OrderActionRecord {
+            ts: fill.ts - 7 * STATS_BUCKET_S,
+            ..fill.clone()
+        };
+        let spot = OrderActionRecord {
+            marketType: MarketType::Spot,
+            marketIndex: 1,
+            ..fill.clone()
+        };
+        for (record, signature) in [(&fill, "a"), (&fill, "a"), (&earlier, "b"), (&spot, "c")] {
+            track_account_stats(&db, record, &ctx(maker, signature))
+                .await
+                .unwrap();
+        }
+        // a taker that is not monitored
+        track_account_stats(
+            &db,
+            &fill,
+            &EventContext {
+                accounts: Vec::new(),
+                ..ctx(taker, "a")
+            },
+        )
+        .await
+        .unwrap();
+        assert!(db.account_stats_of(&taker, None).await.unwrap().is_empty());
+
+        let lifetime = account_summary(&db, maker, None, fill.ts).await.unwrap();
+        assert_eq!(lifetime.total.maker_fills, 3);
+        assert_eq!(lifetime.total.maker_volume, 3 * 41_526_450);
+        assert_eq!(lifetime.total.maker_rebates, 3 * 8305);
+        assert_eq!(lifetime.total.taker_volume, 0);
+        assert_eq!(lifetime.markets.len(), 2);
+        assert_eq!(lifetime.markets[0].market_type, MarketType::Perp);
+        assert_eq!(lifetime.markets[0].stats.maker_fills, 2);
+        assert_eq!(lifetime.markets[1].stats.maker_fills, 1);
+
+        let week = account_summary(&db, maker, Some(7), fill.ts).await.unwrap();
+        assert_eq!(week.total.maker_fills, 2);
+        assert_eq!(week.volume, 2 * 41_526_450);
+
+        let taker_stats = TradingStats::from_record(&taker, &fill).unwrap();
+        assert_eq!(taker_stats.taker_fees, 12458);
+        assert_eq!(taker_stats.taker_volume, 41_526_450);
+    }
+}
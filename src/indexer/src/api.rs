@@ -0,0 +1,946 @@
+//! HTTP query API
+//!
+//! Serves indexed records as json, each with the decimal values of its fixed-point fields
+//! and its market symbols (`normalized`)
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    analytics::{account_summary, StatsSummary},
+    candles::{Candle, Resolution},
+    db::{DbError, IndexerBackend},
+    funding::{funding_summary, FundingRateEntry, FundingSummary},
+    insurance::{
+        if_apy, if_stake_pda, stake_history, IfApy, IfStakeSnapshot, IfVault, StakeHistory,
+    },
+    ledger::{balance_at, Balance, LedgerEntry},
+    lp::{lp_position, LpPosition},
+    markets::MarketInfo,
+    orders::{LifecycleStatus, OrderLifecycle},
+    positions::{self, Position, PositionEntry},
+    precision::{NormalizedRecord, Normalizer},
+    scheduler::PollStatus,
+    swaps::Swap,
+    types::{
+        drift::{
+            events::{OrderActionRecord, OrderRecord},
+            types::MarketType,
+        },
+        IndexerError,
+    },
+    watchlist::{set_watch_status, watch, WatchStatus, WatchedAccount},
+};
+
+/// Default number of records per response
+const DEFAULT_LIMIT: usize = 100;
+/// Max. number of records per response
+const MAX_LIMIT: usize = 1_000;
+
+/// Query of an account's records
+#[derive(Debug, Deserialize)]
+pub struct RecordsQuery {
+    /// The account (pubkey)
+    account: String,
+    /// Max. number of records to return (newest first)
+    limit: Option<usize>,
+}
+
+impl RecordsQuery {
+    fn account(&self) -> Result<Pubkey, ApiError> {
+        Pubkey::try_from(self.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)
+    }
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+}
+
+/// Query filtering records by finality
+#[derive(Debug, Default, Deserialize)]
+pub struct FinalityQuery {
+    /// Exclude provisional records i.e. of txs not finalized yet
+    #[serde(default)]
+    finalized: bool,
+}
+
+/// Query of an account's orders
+#[derive(Debug, Deserialize)]
+pub struct OrdersQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// Only return orders with this status
+    status: Option<LifecycleStatus>,
+    /// Max. number of orders to return (newest first)
+    limit: Option<usize>,
+}
+
+/// Query of a `User` account's position in a perp market
+#[derive(Debug, Deserialize)]
+pub struct PositionQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// The perp market index
+    market: u16,
+}
+
+/// Query of a market's candles
+#[derive(Debug, Deserialize)]
+pub struct CandlesQuery {
+    /// The market index
+    market: u16,
+    /// The market type (default: perp)
+    market_type: Option<MarketType>,
+    /// Candle resolution: 1m|5m|1h|1d
+    resolution: Resolution,
+    /// Only candles starting at or after this unix timestamp
+    from: Option<i64>,
+    /// Only candles starting at or before this unix timestamp
+    to: Option<i64>,
+    /// Max. number of candles to return (oldest first)
+    limit: Option<usize>,
+}
+
+/// Query of an account's trading stats
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    /// The account (pubkey)
+    account: String,
+    /// Only the last n days (incl. today), lifetime if unset
+    days: Option<u32>,
+}
+
+/// Query of a perp market's funding rate history
+#[derive(Debug, Deserialize)]
+pub struct FundingRatesQuery {
+    /// The perp market index
+    market: u16,
+    /// Only updates at or after this unix timestamp
+    from: Option<i64>,
+    /// Only updates at or before this unix timestamp
+    to: Option<i64>,
+    /// Max. number of updates to return (oldest first)
+    limit: Option<usize>,
+}
+
+/// Query of a `User` account's funding over a window
+#[derive(Debug, Deserialize)]
+pub struct FundingQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// Only this perp market, all markets if unset
+    market: Option<u16>,
+    /// Only payments at or after this unix timestamp
+    from: Option<i64>,
+    /// Only payments at or before this unix timestamp
+    to: Option<i64>,
+}
+
+/// Query of a `User` account's ledger in a spot market
+#[derive(Debug, Deserialize)]
+pub struct LedgerQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// The spot market index
+    market: u16,
+    /// Only entries at or after this unix timestamp
+    from: Option<i64>,
+    /// Only entries at or before this unix timestamp
+    to: Option<i64>,
+    /// Max. number of entries to return (newest first)
+    limit: Option<usize>,
+}
+
+/// Query of a `User` account's spot balance at a point in time
+#[derive(Debug, Deserialize)]
+pub struct BalanceQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// The spot market index
+    market: u16,
+    /// Unix timestamp of the balance, now if unset
+    ts: Option<i64>,
+}
+
+/// Query of an authority's insurance fund stake in a spot market
+#[derive(Debug, Deserialize)]
+pub struct StakeQuery {
+    /// The staker's authority (pubkey)
+    authority: String,
+    /// The spot market index
+    market: u16,
+}
+
+/// Query of a spot market's insurance vault
+#[derive(Debug, Deserialize)]
+pub struct VaultQuery {
+    /// The spot market index
+    market: u16,
+    /// Only updates at or after this unix timestamp
+    from: Option<i64>,
+    /// Only updates at or before this unix timestamp
+    to: Option<i64>,
+}
+
+/// Query of a spot market's insurance fund staking yield
+#[derive(Debug, Deserialize)]
+pub struct ApyQuery {
+    /// The spot market index
+    market: u16,
+    /// Estimate over the last n days (default: 30)
+    days: Option<u32>,
+}
+
+/// Query of a `User` account's LP position in a perp market
+#[derive(Debug, Deserialize)]
+pub struct LpQuery {
+    /// The `User` account (pubkey)
+    account: String,
+    /// The perp market index
+    market: u16,
+}
+
+/// Request to add an account to the watchlist (or relabel it)
+#[derive(Debug, Deserialize)]
+pub struct WatchRequest {
+    /// The account (pubkey)
+    account: String,
+    /// Labels stamped onto the account's stored events
+    #[serde(default)]
+    labels: Vec<String>,
+    /// The program indexed for the account (pubkey), drift if unset
+    #[serde(default)]
+    program: Option<String>,
+}
+
+/// API request failure
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request has an invalid pubkey
+    InvalidPublicKey,
+    /// The account is not on the watchlist
+    NotWatched,
+    /// Failure reading the db
+    Db(DbError),
+}
+
+impl From<DbError> for ApiError {
+    fn from(err: DbError) -> Self {
+        Self::Db(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidPublicKey => (StatusCode::BAD_REQUEST, "invalid pubkey").into_response(),
+            Self::NotWatched => (StatusCode::NOT_FOUND, "account not watched").into_response(),
+            Self::Db(err) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:?}")).into_response()
+            }
+        }
+    }
+}
+
+/// Shared state of the API handlers
+struct ApiState<T> {
+    /// Db client
+    db: Arc<T>,
+    /// Normalizes records with the latest market metadata
+    normalizer: Normalizer,
+}
+
+impl<T> Clone for ApiState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            db: Arc::clone(&self.db),
+            normalizer: self.normalizer.clone(),
+        }
+    }
+}
+
+impl<T> ApiState<T> {
+    /// Re-normalize `records` (named `name`), so they carry the latest market symbols
+    fn normalize<R: Serialize>(&self, name: &str, records: &mut [NormalizedRecord<R>]) {
+        for record in records.iter_mut() {
+            if let Some(normalized) = self.normalizer.normalize_event(name, &record.record) {
+                record.normalized = Some(normalized);
+            }
+        }
+    }
+
+    /// Return `records` (named `name`) with their normalized fields
+    fn normalized<R: Serialize>(&self, name: &str, records: Vec<R>) -> Vec<NormalizedRecord<R>> {
+        let mut records: Vec<NormalizedRecord<R>> = records
+            .into_iter()
+            .map(|record| NormalizedRecord {
+                record,
+                normalized: None,
+                labels: Vec::new(),
+                signature: None,
+                slot: None,
+                provisional: false,
+                partial: false,
+            })
+            .collect();
+        self.normalize(name, records.as_mut_slice());
+        records
+    }
+}
+
+/// Provides the HTTP query API over the indexer backend
+pub struct ApiServer<T: IndexerBackend> {
+    state: ApiState<T>,
+}
+
+impl<T: IndexerBackend + 'static> ApiServer<T> {
+    /// Create a new `ApiServer`
+    /// - `normalizer` normalizes records with its (live) market metadata
+    pub fn new(db: Arc<T>, normalizer: Normalizer) -> Self {
+        Self {
+            state: ApiState { db, normalizer },
+        }
+    }
+
+    /// Return the API routes
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/markets", get(markets::<T>))
+            .route("/order_action_records", get(order_action_records::<T>))
+            .route("/order_records", get(order_records::<T>))
+            .route("/orders", get(orders::<T>))
+            .route("/positions", get(positions::<T>))
+            .route("/positions/history", get(position_history::<T>))
+            .route("/candles", get(candles::<T>))
+            .route("/stats", get(stats::<T>))
+            .route("/funding/rates", get(funding_rates::<T>))
+            .route("/funding/payments", get(funding_payments::<T>))
+            .route("/ledger", get(ledger::<T>))
+            .route("/ledger/balance", get(balance::<T>))
+            .route("/insurance/stakes", get(if_stakes::<T>))
+            .route("/insurance/stake_accounts", get(if_stake_accounts::<T>))
+            .route("/insurance/vault", get(if_vault::<T>))
+            .route("/insurance/apy", get(if_yield::<T>))
+            .route("/lp", get(lp::<T>))
+            .route("/swaps", get(swaps::<T>))
+            .with_state(self.state.clone())
+    }
+
+    /// Return the admin API routes (watchlist management)
+    pub fn admin_router(&self) -> Router {
+        Router::new()
+            .route("/watchlist", get(watchlist::<T>).post(watch_account::<T>))
+            .route("/metrics", get(metrics::<T>))
+            .route("/watchlist/:account", delete(unwatch_account::<T>))
+            .route("/watchlist/:account/pause", post(pause_account::<T>))
+            .route("/watchlist/:account/resume", post(resume_account::<T>))
+            .with_state(self.state.clone())
+    }
+
+    /// Serve the API on `addr`
+    pub async fn run(self, addr: SocketAddr) -> Result<(), IndexerError> {
+        info!("serving API on: {addr}");
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await
+            .map_err(|err| IndexerError::Api(err.to_string()))
+    }
+
+    /// Serve the admin API on `addr`
+    pub async fn run_admin(self, addr: SocketAddr) -> Result<(), IndexerError> {
+        info!("serving admin API on: {addr}");
+        axum::Server::bind(&addr)
+            .serve(self.admin_router().into_make_service())
+            .await
+            .map_err(|err| IndexerError::Api(err.to_string()))
+    }
+}
+
+/// `GET /markets`
+///
+/// Metadata of all known markets
+async fn markets<T>(State(state): State<ApiState<T>>) -> Json<Vec<MarketInfo>> {
+    Json(state.normalizer.markets().all())
+}
+
+/// `GET /order_action_records?account=<pubkey>&limit=<n>&finalized=<bool>`
+///
+/// Fills, cancels etc. where `account` is the maker or taker
+async fn order_action_records<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<RecordsQuery>,
+    Query(finality): Query<FinalityQuery>,
+) -> Result<Json<Vec<NormalizedRecord<OrderActionRecord>>>, ApiError> {
+    let mut records = state
+        .db
+        .order_action_records_of(&query.account()?, query.limit(), finality.finalized)
+        .await?;
+    state.normalize("OrderActionRecord", records.as_mut_slice());
+    Ok(Json(records))
+}
+
+/// `GET /order_records?account=<pubkey>&limit=<n>&finalized=<bool>`
+///
+/// Orders placed by `account`
+async fn order_records<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<RecordsQuery>,
+    Query(finality): Query<FinalityQuery>,
+) -> Result<Json<Vec<NormalizedRecord<OrderRecord>>>, ApiError> {
+    let mut records = state
+        .db
+        .order_records_of(&query.account()?, query.limit(), finality.finalized)
+        .await?;
+    state.normalize("OrderRecord", records.as_mut_slice());
+    Ok(Json(records))
+}
+
+/// `GET /orders?account=<pubkey>&status=<status>&limit=<n>`
+///
+/// Lifecycle of orders placed by `account` e.g. `status=PartiallyFilled`
+async fn orders<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<OrdersQuery>,
+) -> Result<Json<Vec<OrderLifecycle>>, ApiError> {
+    let records = RecordsQuery {
+        account: query.account,
+        limit: query.limit,
+    };
+    let orders = state
+        .db
+        .orders_of(&records.account()?, query.status, records.limit())
+        .await?;
+    Ok(Json(orders))
+}
+
+/// `GET /positions?account=<pubkey>`
+///
+/// Perp positions and PnL of `account`
+async fn positions<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<RecordsQuery>,
+) -> Result<Json<Vec<NormalizedRecord<Position>>>, ApiError> {
+    let positions = state.db.positions_of(&query.account()?).await?;
+    Ok(Json(state.normalized("Position", positions)))
+}
+
+/// `GET /positions/history?account=<pubkey>&market=<index>`
+///
+/// The position events of `account` in a perp market, each with the position after it (chain order)
+async fn position_history<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<PositionQuery>,
+) -> Result<Json<Vec<PositionEntry>>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let events = state.db.position_events(&account, query.market).await?;
+    Ok(Json(positions::position_history(events)))
+}
+
+/// `GET /candles?market=<index>&market_type=<Perp|Spot>&resolution=<1m|5m|1h|1d>&from=<ts>&to=<ts>&limit=<n>`
+///
+/// OHLCV candles of a market
+async fn candles<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<CandlesQuery>,
+) -> Result<Json<Vec<NormalizedRecord<Candle>>>, ApiError> {
+    let candles = state
+        .db
+        .candles(
+            query.market_type.unwrap_or(MarketType::Perp),
+            query.market,
+            query.resolution,
+            query.from.unwrap_or(i64::MIN),
+            query.to.unwrap_or(i64::MAX),
+            query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        )
+        .await?;
+    Ok(Json(state.normalized("Candle", candles)))
+}
+
+/// `GET /stats?account=<pubkey>&days=<n>`
+///
+/// Trading stats of `account` (`QUOTE_PRECISION`) over the last `days`, or lifetime
+async fn stats<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<StatsSummary>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let summary = account_summary(state.db.as_ref(), account, query.days, now).await?;
+    Ok(Json(summary))
+}
+
+/// `GET /funding/rates?market=<index>&from=<ts>&to=<ts>&limit=<n>`
+///
+/// Funding rate updates of a perp market, each with its period and annualized rate
+async fn funding_rates<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<FundingRatesQuery>,
+) -> Result<Json<Vec<NormalizedRecord<FundingRateEntry>>>, ApiError> {
+    let rates = state
+        .db
+        .funding_rates(
+            query.market,
+            query.from.unwrap_or(i64::MIN),
+            query.to.unwrap_or(i64::MAX),
+            query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        )
+        .await?;
+    let entries = rates.into_iter().map(FundingRateEntry::from).collect();
+    Ok(Json(state.normalized("FundingRateRecord", entries)))
+}
+
+/// `GET /funding/payments?account=<pubkey>&market=<index>&from=<ts>&to=<ts>`
+///
+/// Cumulative funding of `account` (`QUOTE_PRECISION`) per perp market over the window
+async fn funding_payments<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<FundingQuery>,
+) -> Result<Json<FundingSummary>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let summary = funding_summary(
+        state.db.as_ref(),
+        account,
+        query.market,
+        query.from,
+        query.to,
+    )
+    .await?;
+    Ok(Json(summary))
+}
+
+/// `GET /ledger?account=<pubkey>&market=<index>&from=<ts>&to=<ts>&limit=<n>`
+///
+/// Deposits, withdrawals, transfers and borrows of `account` in a spot market, each with the balance after it
+async fn ledger<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<LedgerQuery>,
+) -> Result<Json<Vec<NormalizedRecord<LedgerEntry>>>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let (from, to) = (query.from.unwrap_or(i64::MIN), query.to.unwrap_or(i64::MAX));
+    let entries: Vec<LedgerEntry> = state
+        .db
+        .ledger(&account, query.market)
+        .await?
+        .into_iter()
+        .rev()
+        .filter(|e| (from..=to).contains(&e.ts))
+        .take(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT))
+        .collect();
+    Ok(Json(state.normalized("LedgerEntry", entries)))
+}
+
+/// `GET /ledger/balance?account=<pubkey>&market=<index>&ts=<ts>`
+///
+/// Token balance of `account` in a spot market at `ts`, incl. interest accrued since its last ledger entry
+async fn balance<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<BalanceQuery>,
+) -> Result<Json<Option<NormalizedRecord<Balance>>>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let ts = query.ts.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    });
+    let balance = balance_at(state.db.as_ref(), account, query.market, ts).await?;
+    Ok(Json(
+        balance.and_then(|b| state.normalized("Balance", vec![b]).pop()),
+    ))
+}
+
+/// `GET /insurance/stakes?authority=<pubkey>&market=<index>`
+///
+/// Stake events of `authority` in a spot market's insurance fund with its shares and their value
+async fn if_stakes<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<StakeQuery>,
+) -> Result<Json<StakeHistory>, ApiError> {
+    let authority =
+        Pubkey::try_from(query.authority.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let history = stake_history(state.db.as_ref(), authority, query.market).await?;
+    Ok(Json(history))
+}
+
+/// `GET /insurance/stake_accounts?authority=<pubkey>&market=<index>`
+///
+/// Snapshots of the `InsuranceFundStake` account of `authority` in a spot market (see `--snapshot-if-stakes`)
+async fn if_stake_accounts<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<StakeQuery>,
+) -> Result<Json<Vec<IfStakeSnapshot>>, ApiError> {
+    let authority =
+        Pubkey::try_from(query.authority.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let snapshots = state
+        .db
+        .account_snapshots(&if_stake_pda(&authority, query.market))
+        .await?;
+    Ok(Json(
+        snapshots
+            .iter()
+            .filter_map(IfStakeSnapshot::decode)
+            .collect(),
+    ))
+}
+
+/// `GET /insurance/vault?market=<index>&from=<ts>&to=<ts>`
+///
+/// Insurance vault amount and total shares of a spot market after each update
+async fn if_vault<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<VaultQuery>,
+) -> Result<Json<Vec<IfVault>>, ApiError> {
+    let vaults = state
+        .db
+        .if_vaults(
+            query.market,
+            query.from.unwrap_or(i64::MIN),
+            query.to.unwrap_or(i64::MAX),
+        )
+        .await?;
+    Ok(Json(vaults))
+}
+
+/// `GET /insurance/apy?market=<index>&days=<n>`
+///
+/// Staking yield of a spot market's insurance fund estimated from the share price over the last `days`
+async fn if_yield<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<ApyQuery>,
+) -> Result<Json<Option<IfApy>>, ApiError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let apy = if_apy(
+        state.db.as_ref(),
+        query.market,
+        query.days.unwrap_or(30),
+        now,
+    )
+    .await?;
+    Ok(Json(apy))
+}
+
+/// `GET /lp?account=<pubkey>&market=<index>`
+///
+/// LP shares, settled base/quote and PnL of `account` in a perp market after each LP event, with the pool
+/// totals of the market's latest `PerpMarket` snapshot (see `--snapshot-accounts`)
+async fn lp<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<LpQuery>,
+) -> Result<Json<LpPosition>, ApiError> {
+    let account =
+        Pubkey::try_from(query.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let market = state
+        .normalizer
+        .markets()
+        .get(MarketType::Perp, query.market)
+        .map(|m| m.pubkey);
+    let position = lp_position(state.db.as_ref(), account, query.market, market).await?;
+    Ok(Json(position))
+}
+
+/// `GET /swaps?account=<pubkey>&limit=<n>`
+///
+/// Swaps of `account` with the programs and known venues they routed through
+async fn swaps<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Query(query): Query<RecordsQuery>,
+) -> Result<Json<Vec<NormalizedRecord<Swap>>>, ApiError> {
+    let swaps = state.db.swaps(&query.account()?, query.limit()).await?;
+    Ok(Json(state.normalized("SwapRecord", swaps)))
+}
+
+/// A watched account and its polling status
+#[derive(Serialize)]
+struct WatchlistEntry {
+    #[serde(flatten)]
+    account: WatchedAccount,
+    /// Unset until polled
+    poll: Option<PollStatus>,
+}
+
+/// `GET /watchlist`
+///
+/// All watched accounts with their status, labels and effective poll interval
+async fn watchlist<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+) -> Result<Json<Vec<WatchlistEntry>>, ApiError> {
+    let mut statuses: HashMap<Pubkey, PollStatus> = state
+        .db
+        .poll_statuses()
+        .await?
+        .into_iter()
+        .map(|s| (s.pubkey, s))
+        .collect();
+    Ok(Json(
+        state
+            .db
+            .watched_accounts()
+            .await?
+            .into_iter()
+            .map(|account| WatchlistEntry {
+                poll: statuses.remove(&account.pubkey),
+                account,
+            })
+            .collect(),
+    ))
+}
+
+/// Reads a gauge value from a poll status
+type PollGauge = fn(&PollStatus) -> String;
+
+/// `GET /metrics`
+///
+/// Polling metrics of the active watched accounts (Prometheus text format)
+async fn metrics<T: IndexerBackend>(State(state): State<ApiState<T>>) -> Result<String, ApiError> {
+    let active: Vec<Pubkey> = state
+        .db
+        .watched_accounts()
+        .await?
+        .into_iter()
+        .filter(|a| a.status == WatchStatus::Active)
+        .map(|a| a.pubkey)
+        .collect();
+    let statuses: Vec<PollStatus> = state
+        .db
+        .poll_statuses()
+        .await?
+        .into_iter()
+        .filter(|s| active.contains(&s.pubkey))
+        .collect();
+
+    let mut metrics = String::new();
+    let gauges: [(&str, &str, PollGauge); 3] = [
+        (
+            "indexer_poll_interval_seconds",
+            "Effective poll interval of a watched account",
+            |s| (s.interval_ms as f64 / 1_000.0).to_string(),
+        ),
+        (
+            "indexer_last_poll_timestamp_seconds",
+            "Unix timestamp of the last poll of a watched account",
+            |s| s.polled_at.to_string(),
+        ),
+        (
+            "indexer_poll_new_signatures",
+            "New signatures found by the last poll of a watched account",
+            |s| s.new_signatures.to_string(),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        metrics.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n"));
+        for status in statuses.iter() {
+            metrics.push_str(&format!(
+                "{name}{{account=\"{}\"}} {}\n",
+                status.pubkey,
+                value(status)
+            ));
+        }
+    }
+    Ok(metrics)
+}
+
+/// `POST /watchlist` `{"account": <pubkey>, "labels": [..], "program": <pubkey>}`
+///
+/// Watch an account, or replace the labels of a watched account
+async fn watch_account<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<WatchRequest>,
+) -> Result<Json<WatchedAccount>, ApiError> {
+    let account =
+        Pubkey::try_from(request.account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    let program = request
+        .program
+        .map(|program| Pubkey::try_from(program.as_str()))
+        .transpose()
+        .map_err(|_| ApiError::InvalidPublicKey)?;
+    Ok(Json(
+        watch(state.db.as_ref(), account, request.labels, program).await?,
+    ))
+}
+
+/// `DELETE /watchlist/<pubkey>`
+///
+/// Stop watching an account
+async fn unwatch_account<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Path(account): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let account = Pubkey::try_from(account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    if state.db.remove_watched_account(&account).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotWatched)
+    }
+}
+
+/// `POST /watchlist/<pubkey>/pause`
+///
+/// Stop indexing a watched account, keeping it on the watchlist
+async fn pause_account<T: IndexerBackend>(
+    state: State<ApiState<T>>,
+    account: Path<String>,
+) -> Result<Json<WatchedAccount>, ApiError> {
+    set_status(state, account, WatchStatus::Paused).await
+}
+
+/// `POST /watchlist/<pubkey>/resume`
+///
+/// Resume indexing a paused account
+async fn resume_account<T: IndexerBackend>(
+    state: State<ApiState<T>>,
+    account: Path<String>,
+) -> Result<Json<WatchedAccount>, ApiError> {
+    set_status(state, account, WatchStatus::Active).await
+}
+
+/// Set the status of a watched account
+async fn set_status<T: IndexerBackend>(
+    State(state): State<ApiState<T>>,
+    Path(account): Path<String>,
+    status: WatchStatus,
+) -> Result<Json<WatchedAccount>, ApiError> {
+    let account = Pubkey::try_from(account.as_str()).map_err(|_| ApiError::InvalidPublicKey)?;
+    set_watch_status(state.db.as_ref(), &account, status)
+        .await?
+        .map(Json)
+        .ok_or(ApiError::NotWatched)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        drift::types::{MarketStatus, MarketType},
+        markets::MarketRegistry,
+        test_utils::fill,
+    };
+
+    #[tokio::test]
+    async fn serve_normalized_records() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let record = fill();
+        let maker = record.maker.unwrap().to_string();
+        let normalized = Normalizer::default().normalize_event("OrderActionRecord", &record);
+        db.insert_order_action_record(NormalizedRecord {
+            record,
+            normalized,
+            labels: Vec::new(),
+            signature: None,
+            slot: None,
+            provisional: false,
+            partial: false,
+        })
+        .await
+        .unwrap();
+
+        // the market becomes known after the record was stored
+        let markets = MarketRegistry::default();
+        let state = ApiState {
+            db,
+            normalizer: Normalizer::new(markets.clone()),
+        };
+        markets.insert(MarketInfo {
+            market_type: MarketType::Perp,
+            market_index: 1,
+            pubkey: Pubkey::new_unique(),
+            symbol: "BTC-PERP".to_string(),
+            oracle: Pubkey::new_unique(),
+            decimals: 9,
+            tick_size: 1_000_000,
+            step_size: 1_000_000,
+            status: MarketStatus::Active,
+        });
+
+        let Json(records) = order_action_records(
+            State(state.clone()),
+            Query(RecordsQuery {
+                account: maker,
+                limit: None,
+            }),
+            Query(FinalityQuery::default()),
+        )
+        .await
+        .unwrap();
+        let json = serde_json::to_value(&records).unwrap();
+        // raw and decimal values side by side
+        assert_eq!(json[0]["quoteAssetAmountFilled"], 41526450);
+        assert_eq!(json[0]["normalized"]["quoteAssetAmountFilled"], 41.52645);
+        assert_eq!(json[0]["normalized"]["marketIndex"], "BTC-PERP");
+
+        let Json(all) = super::markets(State(state.clone())).await;
+        assert_eq!(all[0].symbol, "BTC-PERP");
+
+        let res = order_records(
+            State(state),
+            Query(RecordsQuery {
+                account: "not a pubkey".to_string(),
+                limit: None,
+            }),
+            Query(FinalityQuery::default()),
+        )
+        .await;
+        assert!(matches!(res, Err(ApiError::InvalidPublicKey)));
+    }
+
+    #[tokio::test]
+    async fn serve_poll_metrics() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let (active, paused) = (Pubkey::new_unique(), Pubkey::new_unique());
+        watch(db.as_ref(), active, Vec::new(), None).await.unwrap();
+        watch(db.as_ref(), paused, Vec::new(), None).await.unwrap();
+        set_watch_status(db.as_ref(), &paused, WatchStatus::Paused)
+            .await
+            .unwrap();
+        for pubkey in [active, paused] {
+            db.upsert_poll_status(PollStatus {
+                pubkey,
+                interval_ms: 1_500,
+                polled_at: 1_700_000_000,
+                new_signatures: 2,
+            })
+            .await
+            .unwrap();
+        }
+        let state = ApiState {
+            db,
+            normalizer: Normalizer::default(),
+        };
+
+        let Json(entries) = watchlist(State(state.clone())).await.unwrap();
+        let json = serde_json::to_value(&entries).unwrap();
+        assert_eq!(json[0]["status"], "Active");
+        assert_eq!(json[0]["poll"]["intervalMs"], 1_500);
+
+        let metrics = metrics(State(state)).await.unwrap();
+        assert!(metrics.contains(&format!(
+            "indexer_poll_interval_seconds{{account=\"{active}\"}} 1.5\n"
+        )));
+        assert!(!metrics.contains(paused.to_string().as_str()));
+    }
+}
@@ -0,0 +1,353 @@
+//! OHLCV candles
+//!
+//! Aggregates `OrderActionRecord` fills into per market candles at several resolutions, fills may arrive
+//! late or out of order (backfills), open/close are decided by fill order rather than arrival order
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    markets::{base_decimals, MarketRegistry},
+    types::drift::{
+        events::OrderActionRecord,
+        types::{MarketType, OrderAction},
+    },
+};
+
+/// Candle resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Resolution {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl Resolution {
+    /// All supported resolutions
+    pub const ALL: [Resolution; 4] = [
+        Self::OneMinute,
+        Self::FiveMinutes,
+        Self::OneHour,
+        Self::OneDay,
+    ];
+
+    /// Length of the candle (seconds)
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Self::OneMinute => 60,
+            Self::FiveMinutes => 5 * 60,
+            Self::OneHour => 60 * 60,
+            Self::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the candle containing unix timestamp `ts`
+    pub fn start(&self, ts: i64) -> i64 {
+        ts - ts.rem_euclid(self.seconds())
+    }
+}
+
+/// A fill's position in chain order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FillKey {
+    pub ts: i64,
+    pub fill_record_id: u64,
+}
+
+/// A fill of a market
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fill {
+    pub key: FillKey,
+    /// Fill price (`PRICE_PRECISION`)
+    pub price: u64,
+    pub base_asset_amount: u64,
+    pub quote_asset_amount: u64,
+}
+
+impl Fill {
+    /// Return the fill of `record`, if it is one
+    /// - `base_decimals` decimals of the market's base amounts
+    pub fn from_record(record: &OrderActionRecord, base_decimals: u32) -> Option<Self> {
+        if record.action != OrderAction::Fill {
+            return None;
+        }
+        let base = record.baseAssetAmountFilled.filter(|b| *b > 0)?;
+        let quote = record.quoteAssetAmountFilled?;
+        Some(Self {
+            key: FillKey {
+                ts: record.ts,
+                fill_record_id: record.fillRecordId?,
+            },
+            // quote and price precision are equal
+            price: (quote as u128 * 10_u128.pow(base_decimals) / base as u128) as u64,
+            base_asset_amount: base,
+            quote_asset_amount: quote,
+        })
+    }
+}
+
+/// An OHLCV candle of a market
+///
+/// Prices are `PRICE_PRECISION`, `volume_quote` is `QUOTE_PRECISION`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle {
+    pub market_type: MarketType,
+    pub market_index: u16,
+    pub resolution: Resolution,
+    /// Unix timestamp of the candle start
+    pub start: i64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    /// Base amount traded
+    pub volume_base: u64,
+    /// Quote amount traded
+    pub volume_quote: u64,
+    /// Number of fills
+    pub trades: u32,
+    /// The first and last fill (by chain order), deciding `open` and `close`
+    pub open_fill: FillKey,
+    pub close_fill: FillKey,
+    /// IDs of the fills applied so far, only kept on 1m candles to detect repeated fills
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fill_record_ids: Vec<u64>,
+}
+
+impl Candle {
+    /// Create a new candle from its first `fill`
+    pub fn new(
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        fill: &Fill,
+    ) -> Self {
+        Self {
+            market_type,
+            market_index,
+            resolution,
+            start: resolution.start(fill.key.ts),
+            open: fill.price,
+            high: fill.price,
+            low: fill.price,
+            close: fill.price,
+            volume_base: fill.base_asset_amount,
+            volume_quote: fill.quote_asset_amount,
+            trades: 1,
+            open_fill: fill.key,
+            close_fill: fill.key,
+            fill_record_ids: if resolution == Resolution::OneMinute {
+                vec![fill.key.fill_record_id]
+            } else {
+                Default::default()
+            },
+        }
+    }
+
+    /// Apply a `fill` of the candle, in any order
+    pub fn apply(&mut self, fill: &Fill) {
+        if fill.key < self.open_fill {
+            self.open = fill.price;
+            self.open_fill = fill.key;
+        }
+        if fill.key > self.close_fill {
+            self.close = fill.price;
+            self.close_fill = fill.key;
+        }
+        self.high = self.high.max(fill.price);
+        self.low = self.low.min(fill.price);
+        self.volume_base += fill.base_asset_amount;
+        self.volume_quote += fill.quote_asset_amount;
+        self.trades += 1;
+        if self.resolution == Resolution::OneMinute {
+            self.fill_record_ids.push(fill.key.fill_record_id);
+        }
+    }
+}
+
+/// Apply the fill of `record` (if any) to the market's candles
+pub async fn track_candles<T: IndexerBackend>(
+    db: &T,
+    record: &OrderActionRecord,
+    markets: Option<&MarketRegistry>,
+) -> Result<(), DbError> {
+    if record.action != OrderAction::Fill {
+        return Ok(());
+    }
+    let (market_type, market_index) = (record.marketType, record.marketIndex);
+    let Some(decimals) = base_decimals(market_type, market_index, markets) else {
+        warn!("no decimals of {market_type:?} market {market_index}, skipping candles");
+        return Ok(());
+    };
+    let Some(fill) = Fill::from_record(record, decimals) else {
+        return Ok(());
+    };
+
+    // a fill is indexed once per maker/taker account
+    let minute = db
+        .candle(
+            market_type,
+            market_index,
+            Resolution::OneMinute,
+            Resolution::OneMinute.start(fill.key.ts),
+        )
+        .await?;
+    if minute.is_some_and(|c| c.fill_record_ids.contains(&fill.key.fill_record_id)) {
+        debug!("fill {} already in candles", fill.key.fill_record_id);
+        return Ok(());
+    }
+
+    for resolution in Resolution::ALL {
+        let candle = match db
+            .candle(
+                market_type,
+                market_index,
+                resolution,
+                resolution.start(fill.key.ts),
+            )
+            .await?
+        {
+            Some(mut candle) => {
+                candle.apply(&fill);
+                candle
+            }
+            None => Candle::new(market_type, market_index, resolution, &fill),
+        };
+        db.upsert_candle(candle).await?;
+    }
+
+    Ok(())
+}
+
+/// Rebuild the candles of a market covering `ts` from its indexed fills e.g. after a fill was retracted, removing
+/// those left without fills
+pub async fn rebuild_candles<T: IndexerBackend>(
+    db: &T,
+    market_type: MarketType,
+    market_index: u16,
+    ts: i64,
+    markets: Option<&MarketRegistry>,
+) -> Result<(), DbError> {
+    let Some(decimals) = base_decimals(market_type, market_index, markets) else {
+        warn!("no decimals of {market_type:?} market {market_index}, skipping candles");
+        return Ok(());
+    };
+    for resolution in Resolution::ALL {
+        let start = resolution.start(ts);
+        let mut fills = db
+            .fills_between(
+                market_type,
+                market_index,
+                start,
+                start + resolution.seconds(),
+            )
+            .await?
+            .iter()
+            .filter_map(|record| Fill::from_record(&record.record, decimals))
+            .collect::<Vec<_>>();
+        // a fill is indexed once per maker/taker account
+        fills.sort_by_key(|fill| fill.key);
+        fills.dedup_by_key(|fill| fill.key);
+
+        let mut fills = fills.iter();
+        let Some(first) = fills.next() else {
+            db.remove_candle(market_type, market_index, resolution, start)
+                .await?;
+            continue;
+        };
+        let mut candle = Candle::new(market_type, market_index, resolution, first);
+        fills.for_each(|fill| candle.apply(fill));
+        db.upsert_candle(candle).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::MockBackend, test_utils};
+
+    /// A fill of 1 base at `price` ($) at `ts`
+    fn fill(template: &OrderActionRecord, id: u64, ts: i64, price: u64) -> OrderActionRecord {
+        OrderActionRecord {
+            ts,
+            fillRecordId: Some(id),
+            baseAssetAmountFilled: Some(1_000_000_000),
+            quoteAssetAmountFilled: Some(price * 1_000_000),
+            ..template.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_candles() {
+        let db = MockBackend::init("mockdb").await;
+        let template = test_utils::fill();
+
+        // the captured fill: 0.0015 @ $27,684.3
+        track_candles(&db, &template, None).await.unwrap();
+        let start = Resolution::OneMinute.start(template.ts);
+        let captured = db
+            .candle(MarketType::Perp, 1, Resolution::OneMinute, start)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(captured.open, 27_684_300_000);
+        assert_eq!(captured.volume_base, 1_500_000);
+
+        // the day before the captured fill
+        let ts = Resolution::OneDay.start(template.ts) - Resolution::OneDay.seconds();
+        let fills = [
+            fill(&template, 10, ts + 10, 100),
+            fill(&template, 11, ts + 20, 110),
+            fill(&template, 13, ts + 90, 90),
+            // backfilled, before the other fills of the first minute
+            fill(&template, 9, ts + 5, 105),
+            // repeated (indexed for the maker and taker)
+            fill(&template, 11, ts + 20, 110),
+            fill(&template, 12, ts + 30, 120),
+        ];
+        for fill in fills.iter() {
+            track_candles(&db, fill, None).await.unwrap();
+        }
+
+        let minutes = db
+            .candles(MarketType::Perp, 1, Resolution::OneMinute, ts, ts + 120, 10)
+            .await
+            .unwrap();
+        assert_eq!(minutes.len(), 2);
+        let first = &minutes[0];
+        assert_eq!(first.start, ts);
+        assert_eq!(
+            (first.open, first.high, first.low, first.close),
+            (105_000_000, 120_000_000, 100_000_000, 120_000_000)
+        );
+        assert_eq!(first.trades, 4);
+        assert_eq!(first.volume_base, 4_000_000_000);
+        assert_eq!(first.volume_quote, 435_000_000);
+        assert_eq!(minutes[1].start, ts + 60);
+        assert_eq!(minutes[1].open, 90_000_000);
+
+        let five_minutes = db
+            .candles(MarketType::Perp, 1, Resolution::FiveMinutes, ts, ts, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            (
+                five_minutes[0].open,
+                five_minutes[0].low,
+                five_minutes[0].close
+            ),
+            (105_000_000, 90_000_000, 90_000_000)
+        );
+        assert_eq!(five_minutes[0].trades, 5);
+        assert!(five_minutes[0].fill_record_ids.is_empty());
+    }
+}
@@ -1,19 +1,59 @@
 //! Database schema and connection management
-use std::sync::{Mutex, MutexGuard};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
 
 use async_trait::async_trait;
+use futures::TryStreamExt;
 use log::debug;
 use mongodb::{
-    bson::{doc, Bson},
-    options::FindOneAndUpdateOptions,
+    bson::{doc, Bson, Document},
+    options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReplaceOptions},
     Client, Database,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use solana_sdk::{pubkey::Pubkey, signature::Signature};
 
-use crate::types::{OrderActionRecord, OrderRecord};
+use crate::{
+    analytics::AccountStats,
+    candles::{Candle, Resolution},
+    dynamic::{to_bson, DynamicEvent},
+    finality::{PendingTx, ProvisionalTx, RetractedViews},
+    funding::{FundingPayment, FundingRate},
+    insurance::{IfStakeEvent, IfVault},
+    ledger::{LedgerEntry, SpotInterest},
+    lp::LpEvent,
+    orders::{LifecycleStatus, OrderLifecycle},
+    positions::{Position, PositionEvent},
+    precision::NormalizedRecord,
+    realtime::AccountDiff,
+    scheduler::PollStatus,
+    snapshot::AccountSnapshot,
+    swaps::Swap,
+    types::{
+        drift::{
+            events::{OrderActionRecord, OrderRecord},
+            types::{MarketType, OrderAction},
+        },
+        drift_pda,
+    },
+    watchlist::WatchedAccount,
+};
 
 const DB_DATABASE_NAME: &str = "drift";
+/// Collections of derived view entries keyed by the tx they were derived from
+const DERIVED_COLLECTIONS: [&str; 9] = [
+    "position_events",
+    "ledger",
+    "lp_events",
+    "funding_rates",
+    "funding_payments",
+    "spot_interest",
+    "if_stake_events",
+    "if_vaults",
+    "swaps",
+];
 
 #[derive(Debug)]
 pub enum DbError {
@@ -28,18 +68,269 @@ pub enum DbError {
 pub trait IndexerBackend: Send + Sync {
     /// Instantiate the db backend
     async fn init(conn_str: &str) -> Self;
-    /// Return the last indexed tx signature for `account`
-    async fn last_indexed_signature(&self, account: &Pubkey) -> Result<Option<Signature>, DbError>;
-    /// Update the last processed `signature` for `account`
+    /// Return the last indexed tx signature of `program` for `account`
+    async fn last_indexed_signature(
+        &self,
+        program: &Pubkey,
+        account: &Pubkey,
+    ) -> Result<Option<Signature>, DbError>;
+    /// Update the last processed `signature` of `program` for `account`
     async fn update_last_indexed_signature(
         &self,
+        program: &Pubkey,
         account: &Pubkey,
         signature: &Signature,
     ) -> Result<(), DbError>;
-    /// Insert an `OrderActionRecord` into the db
-    async fn insert_order_action_record(&self, record: OrderActionRecord) -> Result<(), DbError>;
-    /// Insert an `OrderRecord` into the db
-    async fn insert_order_record(&self, record: OrderRecord) -> Result<(), DbError>;
+    /// Insert an `OrderActionRecord` with its `normalized` (decimal) fields and labels into the db
+    async fn insert_order_action_record(
+        &self,
+        record: NormalizedRecord<OrderActionRecord>,
+    ) -> Result<(), DbError>;
+    /// Insert an `OrderRecord` with its `normalized` (decimal) fields and labels into the db
+    async fn insert_order_record(
+        &self,
+        record: NormalizedRecord<OrderRecord>,
+    ) -> Result<(), DbError>;
+    /// Return the latest `limit` `OrderActionRecord`s where `account` is the maker or taker (newest first)
+    /// - `finalized` excludes provisional records
+    async fn order_action_records_of(
+        &self,
+        account: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError>;
+    /// Return the latest `limit` `OrderRecord`s of `user` (newest first)
+    /// - `finalized` excludes provisional records
+    async fn order_records_of(
+        &self,
+        user: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderRecord>>, DbError>;
+    /// Return the placement `OrderRecord` of order `order_id` of `user`, if indexed
+    async fn order_record(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Option<NormalizedRecord<OrderRecord>>, DbError>;
+    /// Return the `OrderActionRecord`s acting on order `order_id` of `user` (oldest first)
+    async fn order_actions(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError>;
+    /// Return the fill `OrderActionRecord`s of a market within `from..to` (oldest first)
+    async fn fills_between(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError>;
+    /// Return the state of order `order_id` of `user`, if any
+    async fn order(&self, user: &Pubkey, order_id: u32) -> Result<Option<OrderLifecycle>, DbError>;
+    /// Insert or replace the state of an order
+    async fn upsert_order(&self, order: OrderLifecycle) -> Result<(), DbError>;
+    /// Remove the state of order `order_id` of `user`
+    async fn remove_order(&self, user: &Pubkey, order_id: u32) -> Result<(), DbError>;
+    /// Return the latest `limit` orders of `user`, optionally only those with `status` (newest first)
+    async fn orders_of(
+        &self,
+        user: &Pubkey,
+        status: Option<LifecycleStatus>,
+        limit: usize,
+    ) -> Result<Vec<OrderLifecycle>, DbError>;
+    /// Log a position event, returns false if it was already logged
+    async fn insert_position_event(&self, event: PositionEvent) -> Result<bool, DbError>;
+    /// Return the position events of `user` in perp market `market_index` (chain order)
+    async fn position_events(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<PositionEvent>, DbError>;
+    /// Return all position events (chain order)
+    async fn all_position_events(&self) -> Result<Vec<PositionEvent>, DbError>;
+    /// Return the position of `user` in perp market `market_index`, if any
+    async fn position(&self, user: &Pubkey, market_index: u16)
+        -> Result<Option<Position>, DbError>;
+    /// Return all positions of `user`
+    async fn positions_of(&self, user: &Pubkey) -> Result<Vec<Position>, DbError>;
+    /// Insert or replace a position
+    async fn upsert_position(&self, position: Position) -> Result<(), DbError>;
+    /// Remove the position of `user` in perp market `market_index`
+    async fn remove_position(&self, user: &Pubkey, market_index: u16) -> Result<(), DbError>;
+    /// Remove all positions
+    async fn clear_positions(&self) -> Result<(), DbError>;
+    /// Return the `resolution` candle of a market starting at `start`, if any
+    async fn candle(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
+    ) -> Result<Option<Candle>, DbError>;
+    /// Insert or replace a candle
+    async fn upsert_candle(&self, candle: Candle) -> Result<(), DbError>;
+    /// Remove the `resolution` candle of a market starting at `start`
+    async fn remove_candle(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
+    ) -> Result<(), DbError>;
+    /// Return up to `limit` `resolution` candles of a market starting within `from..=to` (oldest first)
+    async fn candles(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<Candle>, DbError>;
+    /// Mark the event at `log_index` of tx `signature` applied to `account`'s `view`,
+    /// returns false if it was already applied
+    async fn mark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: u32,
+    ) -> Result<bool, DbError>;
+    /// Remove the marks of log `log_index` (all logs if unset) of tx `signature` in `view` for `account`, returns
+    /// the number removed
+    async fn unmark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: Option<u32>,
+    ) -> Result<u64, DbError>;
+    /// Return the stats bucket of `account` in a market starting at `bucket`, if any
+    async fn account_stats(
+        &self,
+        account: &Pubkey,
+        market_type: MarketType,
+        market_index: u16,
+        bucket: i64,
+    ) -> Result<Option<AccountStats>, DbError>;
+    /// Insert or replace a stats bucket
+    async fn upsert_account_stats(&self, stats: AccountStats) -> Result<(), DbError>;
+    /// Return the stats buckets of `account` starting at or after `since` (all if unset)
+    async fn account_stats_of(
+        &self,
+        account: &Pubkey,
+        since: Option<i64>,
+    ) -> Result<Vec<AccountStats>, DbError>;
+    /// Insert or replace a funding rate update (keyed by market and record ID)
+    async fn upsert_funding_rate(&self, rate: FundingRate) -> Result<(), DbError>;
+    /// Return the funding rate updates of perp market `market_index` from `from` to `to` (oldest first)
+    async fn funding_rates(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<FundingRate>, DbError>;
+    /// Insert or replace a funding payment (keyed by tx and log)
+    async fn upsert_funding_payment(&self, payment: FundingPayment) -> Result<(), DbError>;
+    /// Return the funding payments of `user` from `from` to `to`, optionally in one perp market (oldest first)
+    async fn funding_payments(
+        &self,
+        user: &Pubkey,
+        market_index: Option<u16>,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<FundingPayment>, DbError>;
+    /// Return the ledger of `user` in spot market `market_index` (chain order)
+    async fn ledger(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LedgerEntry>, DbError>;
+    /// Insert or replace a ledger entry (keyed by user, tx and log)
+    async fn upsert_ledger_entry(&self, entry: LedgerEntry) -> Result<(), DbError>;
+    /// Return the last ledger entry of `user` in spot market `market_index` at or before `ts`, if any
+    async fn ledger_entry_at(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<LedgerEntry>, DbError>;
+    /// Insert or replace a spot market interest update (keyed by tx)
+    async fn upsert_spot_interest(&self, interest: SpotInterest) -> Result<(), DbError>;
+    /// Return the last interest update of spot market `market_index` at or before `ts`, if any
+    async fn spot_interest_at(
+        &self,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<SpotInterest>, DbError>;
+    /// Insert or replace an insurance fund stake event (keyed by tx and log)
+    async fn upsert_if_stake_event(&self, event: IfStakeEvent) -> Result<(), DbError>;
+    /// Return the stake events of `authority` in spot market `market_index` (chain order)
+    async fn if_stake_events(
+        &self,
+        authority: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<IfStakeEvent>, DbError>;
+    /// Return all (authority, spot market index) with stake events
+    async fn if_stakers(&self) -> Result<Vec<(Pubkey, u16)>, DbError>;
+    /// Insert or replace an insurance vault update (keyed by tx and log)
+    async fn upsert_if_vault(&self, vault: IfVault) -> Result<(), DbError>;
+    /// Return the last insurance vault update of spot market `market_index` at or before `ts`, if any
+    async fn if_vault_at(&self, market_index: u16, ts: i64) -> Result<Option<IfVault>, DbError>;
+    /// Return the insurance vault updates of spot market `market_index` from `from` to `to` (oldest first)
+    async fn if_vaults(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<IfVault>, DbError>;
+    /// Insert or replace a perp LP event (keyed by tx and log)
+    async fn upsert_lp_event(&self, event: LpEvent) -> Result<(), DbError>;
+    /// Return the LP events of `user` in perp market `market_index` (chain order)
+    async fn lp_events(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LpEvent>, DbError>;
+    /// Insert or replace a swap (keyed by tx and log)
+    async fn upsert_swap(&self, swap: Swap) -> Result<(), DbError>;
+    /// Return the latest `limit` swaps of `user` (newest first)
+    async fn swaps(&self, user: &Pubkey, limit: usize) -> Result<Vec<Swap>, DbError>;
+    /// Insert or replace a watchlist account (keyed by pubkey)
+    async fn upsert_watched_account(&self, account: WatchedAccount) -> Result<(), DbError>;
+    /// Remove `pubkey` from the watchlist, returns whether it was watched
+    async fn remove_watched_account(&self, pubkey: &Pubkey) -> Result<bool, DbError>;
+    /// Return the watchlist account `pubkey`, if watched
+    async fn watched_account(&self, pubkey: &Pubkey) -> Result<Option<WatchedAccount>, DbError>;
+    /// Return all watchlist accounts
+    async fn watched_accounts(&self) -> Result<Vec<WatchedAccount>, DbError>;
+    /// Insert or replace the poll status of an account (keyed by pubkey)
+    async fn upsert_poll_status(&self, status: PollStatus) -> Result<(), DbError>;
+    /// Return the poll status of all polled accounts
+    async fn poll_statuses(&self) -> Result<Vec<PollStatus>, DbError>;
+    /// Insert or replace a provisionally indexed tx awaiting finality (keyed by signature)
+    async fn upsert_pending_tx(&self, tx: PendingTx) -> Result<(), DbError>;
+    /// Return the oldest `limit` txs awaiting finality (slot order)
+    async fn pending_txs(&self, limit: usize) -> Result<Vec<PendingTx>, DbError>;
+    /// Remove the pending tx `signature`
+    async fn remove_pending_tx(&self, signature: &str) -> Result<(), DbError>;
+    /// Delete the provisional records and events of tx `signature`, returns the number deleted
+    async fn retract_provisional(&self, signature: &str) -> Result<u64, DbError>;
+    /// Return the provisional records and events of tx `signature`
+    async fn provisional_tx(&self, signature: &str) -> Result<ProvisionalTx, DbError>;
+    /// Delete the derived view entries of tx `signature` (position events, ledger entries, funding, interest, IF,
+    /// LP and swaps), returns the folded views to rebuild
+    async fn retract_derived(&self, signature: &str) -> Result<RetractedViews, DbError>;
+    /// Insert an account state snapshot into the db, replacing any existing snapshot at the same slot
+    async fn insert_account_snapshot(&self, snapshot: AccountSnapshot) -> Result<(), DbError>;
+    /// Return all snapshots of `account` ordered by slot (ascending)
+    async fn account_snapshots(&self, account: &Pubkey) -> Result<Vec<AccountSnapshot>, DbError>;
+    /// Return the latest snapshot of `account`
+    async fn latest_account_snapshot(
+        &self,
+        account: &Pubkey,
+    ) -> Result<Option<AccountSnapshot>, DbError>;
+    /// Insert an account state diff into the db
+    async fn insert_account_diff(&self, diff: AccountDiff) -> Result<(), DbError>;
+    /// Return all diffs of `account` ordered by slot (ascending)
+    async fn account_diffs(&self, account: &Pubkey) -> Result<Vec<AccountDiff>, DbError>;
+    /// Insert an event decoded with a runtime IDL into the db
+    async fn insert_dynamic_event(&self, event: DynamicEvent) -> Result<(), DbError>;
 }
 
 /// MongoDb indexer database client
@@ -61,7 +352,11 @@ impl IndexerBackend for MongoDbClient {
     async fn init(conn_str: &str) -> Self {
         MongoDbClient::new(conn_str).await
     }
-    async fn last_indexed_signature(&self, account: &Pubkey) -> Result<Option<Signature>, DbError> {
+    async fn last_indexed_signature(
+        &self,
+        program: &Pubkey,
+        account: &Pubkey,
+    ) -> Result<Option<Signature>, DbError> {
         let address_bytes = Bson::Array(
             account
                 .to_bytes()
@@ -69,7 +364,7 @@ impl IndexerBackend for MongoDbClient {
                 .map(|d| Bson::Int32(*d as i32))
                 .collect(),
         );
-        let query = doc! { "address": address_bytes };
+        let query = account_query(address_bytes, program);
         let res = self
             .db
             .collection::<Account>("accounts")
@@ -81,6 +376,7 @@ impl IndexerBackend for MongoDbClient {
     }
     async fn update_last_indexed_signature(
         &self,
+        program: &Pubkey,
         account: &Pubkey,
         signature: &Signature,
     ) -> Result<(), DbError> {
@@ -107,86 +403,2047 @@ impl IndexerBackend for MongoDbClient {
         self.db
             .collection::<Account>("accounts")
             .find_one_and_update(
-                doc! { "address": address_bytes },
-                doc! { "$set": { "last_processed_signature": signature_bytes } },
+                account_query(address_bytes, program),
+                // also sets the program of legacy (drift) accounts
+                doc! { "$set": {
+                    "program": to_bson_bytes(program.as_ref()),
+                    "last_processed_signature": signature_bytes,
+                } },
                 FindOneAndUpdateOptions::builder().upsert(true).build(),
             )
             .await
             .map_err(|err| DbError::Insert(err.kind.to_string()))
             .map(|_res| ())
     }
-    async fn insert_order_action_record(&self, record: OrderActionRecord) -> Result<(), DbError> {
+    async fn insert_order_action_record(
+        &self,
+        record: NormalizedRecord<OrderActionRecord>,
+    ) -> Result<(), DbError> {
+        self.insert_normalized("order_action_records", record).await
+    }
+    async fn insert_order_record(
+        &self,
+        record: NormalizedRecord<OrderRecord>,
+    ) -> Result<(), DbError> {
+        self.insert_normalized("order_records", record).await
+    }
+    async fn order_action_records_of(
+        &self,
+        account: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let account = to_bson_bytes(account.as_ref());
+        let mut filter = doc! { "$or": [ { "maker": account.clone() }, { "taker": account } ] };
+        if finalized {
+            filter.insert("provisional", doc! { "$ne": true });
+        }
+        self.find_normalized("order_action_records", filter, limit)
+            .await
+    }
+    async fn order_records_of(
+        &self,
+        user: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderRecord>>, DbError> {
+        let mut filter = doc! { "user": to_bson_bytes(user.as_ref()) };
+        if finalized {
+            filter.insert("provisional", doc! { "$ne": true });
+        }
+        self.find_normalized("order_records", filter, limit).await
+    }
+    async fn order_record(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Option<NormalizedRecord<OrderRecord>>, DbError> {
+        let filter =
+            doc! { "user": to_bson_bytes(user.as_ref()), "order.orderId": order_id as i64 };
+        Ok(self
+            .find_normalized("order_records", filter, 1)
+            .await?
+            .pop())
+    }
+    async fn order_actions(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let user = to_bson_bytes(user.as_ref());
+        let filter = doc! { "$or": [
+            { "taker": user.clone(), "takerOrderId": order_id as i64 },
+            { "maker": user, "makerOrderId": order_id as i64 },
+        ] };
+        let mut records = self
+            .find_normalized("order_action_records", filter, 0)
+            .await?;
+        records.reverse();
+        Ok(records)
+    }
+    async fn fills_between(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let filter = doc! {
+            "marketType": mongodb::bson::to_bson(&market_type)
+                .map_err(|err| DbError::Read(err.to_string()))?,
+            "marketIndex": market_index as i32,
+            "action": mongodb::bson::to_bson(&OrderAction::Fill)
+                .map_err(|err| DbError::Read(err.to_string()))?,
+            "ts": { "$gte": from, "$lt": to },
+        };
+        let mut records = self
+            .find_normalized("order_action_records", filter, 0)
+            .await?;
+        records.reverse();
+        Ok(records)
+    }
+    async fn order(&self, user: &Pubkey, order_id: u32) -> Result<Option<OrderLifecycle>, DbError> {
+        self.db
+            .collection::<OrderLifecycle>("orders")
+            .find_one(
+                doc! { "user": to_bson_bytes(user.as_ref()), "orderId": order_id as i64 },
+                None,
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_order(&self, order: OrderLifecycle) -> Result<(), DbError> {
+        let query = doc! {
+            "user": to_bson_bytes(order.user.as_ref()),
+            "orderId": order.order_id as i64,
+        };
         self.db
-            .collection("order_action_records")
-            .insert_one(record, None)
+            .collection::<OrderLifecycle>("orders")
+            .replace_one(query, order, ReplaceOptions::builder().upsert(true).build())
             .await
             .map_err(|err| DbError::Insert(err.kind.to_string()))
             .map(|_res| ())
     }
-    async fn insert_order_record(&self, record: OrderRecord) -> Result<(), DbError> {
+    async fn remove_order(&self, user: &Pubkey, order_id: u32) -> Result<(), DbError> {
         self.db
-            .collection("order_records")
-            .insert_one(record, None)
+            .collection::<OrderLifecycle>("orders")
+            .delete_one(
+                doc! { "user": to_bson_bytes(user.as_ref()), "orderId": order_id as i64 },
+                None,
+            )
             .await
             .map_err(|err| DbError::Insert(err.kind.to_string()))
             .map(|_res| ())
     }
-}
-
-/// Test backend
-pub struct MockBackend {
-    order_action_records: Mutex<Vec<OrderActionRecord>>,
-    order_records: Mutex<Vec<OrderRecord>>,
-    last_signature: Mutex<Option<Signature>>,
-}
-
-impl MockBackend {
-    pub fn order_records(&self) -> MutexGuard<Vec<OrderRecord>> {
-        self.order_records.lock().unwrap()
-    }
-    pub fn order_action_records(&self) -> MutexGuard<Vec<OrderActionRecord>> {
-        self.order_action_records.lock().unwrap()
+    async fn orders_of(
+        &self,
+        user: &Pubkey,
+        status: Option<LifecycleStatus>,
+        limit: usize,
+    ) -> Result<Vec<OrderLifecycle>, DbError> {
+        let mut filter = doc! { "user": to_bson_bytes(user.as_ref()) };
+        if let Some(status) = status {
+            filter.insert(
+                "status",
+                mongodb::bson::to_bson(&status).map_err(|err| DbError::Read(err.to_string()))?,
+            );
+        }
+        self.db
+            .collection::<OrderLifecycle>("orders")
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "orderId": -1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
     }
-}
-
-#[async_trait]
-impl IndexerBackend for MockBackend {
-    async fn init(_conn_str: &str) -> Self {
-        Self {
-            order_action_records: Default::default(),
-            order_records: Default::default(),
-            last_signature: Default::default(),
+    async fn insert_position_event(&self, event: PositionEvent) -> Result<bool, DbError> {
+        let collection = self.db.collection::<PositionEvent>("position_events");
+        let query = doc! {
+            "signature": event.signature.as_str(),
+            "logIndex": event.log_index,
+            "user": to_bson_bytes(event.user.as_ref()),
+        };
+        let logged = collection
+            .find_one(query, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?;
+        if logged.is_some() {
+            return Ok(false);
         }
+        collection
+            .insert_one(event, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| true)
     }
-    async fn last_indexed_signature(
+    async fn position_events(
         &self,
-        _account: &Pubkey,
-    ) -> Result<Option<Signature>, DbError> {
-        Ok(*self.last_signature.lock().unwrap())
+        user: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<PositionEvent>, DbError> {
+        self.find_position_events(
+            doc! { "user": to_bson_bytes(user.as_ref()), "marketIndex": market_index as i32 },
+        )
+        .await
     }
-    async fn insert_order_action_record(&self, record: OrderActionRecord) -> Result<(), DbError> {
-        let mut records = self.order_action_records.lock().unwrap();
-        records.push(record);
-        Ok(())
+    async fn all_position_events(&self) -> Result<Vec<PositionEvent>, DbError> {
+        self.find_position_events(doc! {}).await
     }
-    async fn insert_order_record(&self, record: OrderRecord) -> Result<(), DbError> {
-        let mut records = self.order_records.lock().unwrap();
-        records.push(record);
-        Ok(())
+    async fn position(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+    ) -> Result<Option<Position>, DbError> {
+        self.db
+            .collection::<Position>("positions")
+            .find_one(
+                doc! { "user": to_bson_bytes(user.as_ref()), "marketIndex": market_index as i32 },
+                None,
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
     }
-    async fn update_last_indexed_signature(
+    async fn positions_of(&self, user: &Pubkey) -> Result<Vec<Position>, DbError> {
+        self.db
+            .collection::<Position>("positions")
+            .find(
+                doc! { "user": to_bson_bytes(user.as_ref()) },
+                FindOptions::builder()
+                    .sort(doc! { "marketIndex": 1 })
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_position(&self, position: Position) -> Result<(), DbError> {
+        let query = doc! {
+            "user": to_bson_bytes(position.user.as_ref()),
+            "marketIndex": position.market_index as i32,
+        };
+        self.db
+            .collection::<Position>("positions")
+            .replace_one(
+                query,
+                position,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn remove_position(&self, user: &Pubkey, market_index: u16) -> Result<(), DbError> {
+        self.db
+            .collection::<Position>("positions")
+            .delete_one(
+                doc! { "user": to_bson_bytes(user.as_ref()), "marketIndex": market_index as i32 },
+                None,
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn clear_positions(&self) -> Result<(), DbError> {
+        self.db
+            .collection::<Position>("positions")
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn candle(
         &self,
-        _account: &Pubkey,
-        signature: &Signature,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
+    ) -> Result<Option<Candle>, DbError> {
+        let mut query = candle_filter(market_type, market_index, resolution)?;
+        query.insert("start", start);
+        self.db
+            .collection::<Candle>("candles")
+            .find_one(query, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_candle(&self, candle: Candle) -> Result<(), DbError> {
+        let mut query = candle_filter(candle.market_type, candle.market_index, candle.resolution)?;
+        query.insert("start", candle.start);
+        self.db
+            .collection::<Candle>("candles")
+            .replace_one(
+                query,
+                candle,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn remove_candle(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
     ) -> Result<(), DbError> {
-        let mut last_signature = self.last_signature.lock().unwrap();
-        *last_signature = Some(*signature);
-        Ok(())
+        let mut query = candle_filter(market_type, market_index, resolution)?;
+        query.insert("start", start);
+        self.db
+            .collection::<Candle>("candles")
+            .delete_one(query, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn candles(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<Candle>, DbError> {
+        let mut filter = candle_filter(market_type, market_index, resolution)?;
+        filter.insert("start", doc! { "$gte": from, "$lte": to });
+        self.db
+            .collection::<Candle>("candles")
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "start": 1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn mark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: u32,
+    ) -> Result<bool, DbError> {
+        let key = doc! {
+            "view": view,
+            "account": to_bson_bytes(account.as_ref()),
+            "signature": signature,
+            "logIndex": log_index,
+        };
+        // upsert reports an insert only if the key did not exist
+        let res = self
+            .db
+            .collection::<Document>("applied_events")
+            .update_one(
+                key.clone(),
+                doc! { "$setOnInsert": key },
+                mongodb::options::UpdateOptions::builder()
+                    .upsert(true)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))?;
+        Ok(res.upserted_id.is_some())
+    }
+    async fn unmark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: Option<u32>,
+    ) -> Result<u64, DbError> {
+        let mut key = doc! {
+            "view": view,
+            "account": to_bson_bytes(account.as_ref()),
+            "signature": signature,
+        };
+        if let Some(log_index) = log_index {
+            key.insert("logIndex", log_index);
+        }
+        self.db
+            .collection::<Document>("applied_events")
+            .delete_many(key, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|res| res.deleted_count)
+    }
+    async fn account_stats(
+        &self,
+        account: &Pubkey,
+        market_type: MarketType,
+        market_index: u16,
+        bucket: i64,
+    ) -> Result<Option<AccountStats>, DbError> {
+        self.db
+            .collection::<AccountStats>("account_stats")
+            .find_one(
+                account_stats_key(account, market_type, market_index, bucket)?,
+                None,
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_account_stats(&self, stats: AccountStats) -> Result<(), DbError> {
+        let query = account_stats_key(
+            &stats.account,
+            stats.market_type,
+            stats.market_index,
+            stats.bucket,
+        )?;
+        self.db
+            .collection::<AccountStats>("account_stats")
+            .replace_one(query, stats, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn account_stats_of(
+        &self,
+        account: &Pubkey,
+        since: Option<i64>,
+    ) -> Result<Vec<AccountStats>, DbError> {
+        let mut filter = doc! { "account": to_bson_bytes(account.as_ref()) };
+        if let Some(since) = since {
+            filter.insert("bucket", doc! { "$gte": since });
+        }
+        self.db
+            .collection::<AccountStats>("account_stats")
+            .find(
+                filter,
+                FindOptions::builder().sort(doc! { "bucket": 1 }).build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_funding_rate(&self, rate: FundingRate) -> Result<(), DbError> {
+        self.db
+            .collection::<FundingRate>("funding_rates")
+            .replace_one(
+                doc! { "marketIndex": rate.market_index as i32, "recordId": rate.record_id as i64 },
+                rate,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn funding_rates(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<FundingRate>, DbError> {
+        self.db
+            .collection::<FundingRate>("funding_rates")
+            .find(
+                doc! {
+                    "marketIndex": market_index as i32,
+                    "ts": { "$gte": from, "$lte": to },
+                },
+                FindOptions::builder()
+                    .sort(doc! { "ts": 1, "recordId": 1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_funding_payment(&self, payment: FundingPayment) -> Result<(), DbError> {
+        let query = doc! {
+            "signature": payment.signature.as_str(),
+            "logIndex": payment.log_index,
+        };
+        self.db
+            .collection::<FundingPayment>("funding_payments")
+            .replace_one(
+                query,
+                payment,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn funding_payments(
+        &self,
+        user: &Pubkey,
+        market_index: Option<u16>,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<FundingPayment>, DbError> {
+        let mut filter = doc! {
+            "user": to_bson_bytes(user.as_ref()),
+            "ts": { "$gte": from, "$lte": to },
+        };
+        if let Some(market_index) = market_index {
+            filter.insert("marketIndex", market_index as i32);
+        }
+        self.db
+            .collection::<FundingPayment>("funding_payments")
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "ts": 1, "slot": 1, "logIndex": 1 })
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn ledger(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LedgerEntry>, DbError> {
+        self.db
+            .collection::<LedgerEntry>("ledger")
+            .find(
+                doc! { "user": to_bson_bytes(user.as_ref()), "marketIndex": market_index as i32 },
+                FindOptions::builder()
+                    .sort(chain_order_sort("depositRecordId", 1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_ledger_entry(&self, entry: LedgerEntry) -> Result<(), DbError> {
+        let query = doc! {
+            "user": to_bson_bytes(entry.user.as_ref()),
+            "signature": entry.signature.as_str(),
+            "logIndex": entry.log_index,
+        };
+        self.db
+            .collection::<LedgerEntry>("ledger")
+            .replace_one(query, entry, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn ledger_entry_at(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<LedgerEntry>, DbError> {
+        self.db
+            .collection::<LedgerEntry>("ledger")
+            .find_one(
+                doc! {
+                    "user": to_bson_bytes(user.as_ref()),
+                    "marketIndex": market_index as i32,
+                    "ts": { "$lte": ts },
+                },
+                FindOneOptions::builder()
+                    .sort(chain_order_sort("depositRecordId", -1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_spot_interest(&self, interest: SpotInterest) -> Result<(), DbError> {
+        let query = doc! {
+            "marketIndex": interest.market_index as i32,
+            "signature": interest.signature.as_str(),
+        };
+        self.db
+            .collection::<SpotInterest>("spot_interest")
+            .replace_one(
+                query,
+                interest,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn spot_interest_at(
+        &self,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<SpotInterest>, DbError> {
+        self.db
+            .collection::<SpotInterest>("spot_interest")
+            .find_one(
+                doc! { "marketIndex": market_index as i32, "ts": { "$lte": ts } },
+                FindOneOptions::builder()
+                    .sort(doc! { "ts": -1, "slot": -1 })
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_if_stake_event(&self, event: IfStakeEvent) -> Result<(), DbError> {
+        let query = doc! { "signature": event.signature.as_str(), "logIndex": event.log_index };
+        self.db
+            .collection::<IfStakeEvent>("if_stake_events")
+            .replace_one(query, event, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn if_stake_events(
+        &self,
+        authority: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<IfStakeEvent>, DbError> {
+        self.db
+            .collection::<IfStakeEvent>("if_stake_events")
+            .find(
+                doc! {
+                    "userAuthority": to_bson_bytes(authority.as_ref()),
+                    "marketIndex": market_index as i32,
+                },
+                FindOptions::builder()
+                    .sort(chain_order_sort("ts", 1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn if_stakers(&self) -> Result<Vec<(Pubkey, u16)>, DbError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Staker {
+            user_authority: Pubkey,
+            market_index: u16,
+        }
+        #[derive(Deserialize)]
+        struct Group {
+            _id: Staker,
+        }
+        let groups: Vec<Document> = self
+            .db
+            .collection::<IfStakeEvent>("if_stake_events")
+            .aggregate(
+                [doc! {
+                    "$group": { "_id": { "userAuthority": "$userAuthority", "marketIndex": "$marketIndex" } }
+                }],
+                None,
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?;
+        groups
+            .into_iter()
+            .map(|group| {
+                mongodb::bson::from_document::<Group>(group)
+                    .map(|g| (g._id.user_authority, g._id.market_index))
+                    .map_err(|err| DbError::Read(err.to_string()))
+            })
+            .collect()
+    }
+    async fn upsert_if_vault(&self, vault: IfVault) -> Result<(), DbError> {
+        let query = doc! { "signature": vault.signature.as_str(), "logIndex": vault.log_index };
+        self.db
+            .collection::<IfVault>("if_vaults")
+            .replace_one(query, vault, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn if_vault_at(&self, market_index: u16, ts: i64) -> Result<Option<IfVault>, DbError> {
+        self.db
+            .collection::<IfVault>("if_vaults")
+            .find_one(
+                doc! { "marketIndex": market_index as i32, "ts": { "$lte": ts } },
+                FindOneOptions::builder()
+                    .sort(chain_order_sort("ts", -1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn if_vaults(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<IfVault>, DbError> {
+        self.db
+            .collection::<IfVault>("if_vaults")
+            .find(
+                doc! { "marketIndex": market_index as i32, "ts": { "$gte": from, "$lte": to } },
+                FindOptions::builder()
+                    .sort(chain_order_sort("ts", 1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_lp_event(&self, event: LpEvent) -> Result<(), DbError> {
+        let query = doc! { "signature": event.signature.as_str(), "logIndex": event.log_index };
+        self.db
+            .collection::<LpEvent>("lp_events")
+            .replace_one(query, event, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn lp_events(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LpEvent>, DbError> {
+        self.db
+            .collection::<LpEvent>("lp_events")
+            .find(
+                doc! { "user": to_bson_bytes(user.as_ref()), "marketIndex": market_index as i32 },
+                FindOptions::builder()
+                    .sort(chain_order_sort("ts", 1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_swap(&self, swap: Swap) -> Result<(), DbError> {
+        let query = doc! { "signature": swap.signature.as_str(), "logIndex": swap.log_index };
+        self.db
+            .collection::<Swap>("swaps")
+            .replace_one(query, swap, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn swaps(&self, user: &Pubkey, limit: usize) -> Result<Vec<Swap>, DbError> {
+        self.db
+            .collection::<Swap>("swaps")
+            .find(
+                doc! { "user": to_bson_bytes(user.as_ref()) },
+                FindOptions::builder()
+                    .sort(doc! { "slot": -1, "signature": -1, "logIndex": -1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_watched_account(&self, account: WatchedAccount) -> Result<(), DbError> {
+        let query = doc! { "pubkey": to_bson_bytes(account.pubkey.as_ref()) };
+        self.db
+            .collection::<WatchedAccount>("watchlist")
+            .replace_one(
+                query,
+                account,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn remove_watched_account(&self, pubkey: &Pubkey) -> Result<bool, DbError> {
+        self.db
+            .collection::<WatchedAccount>("watchlist")
+            .delete_one(doc! { "pubkey": to_bson_bytes(pubkey.as_ref()) }, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|res| res.deleted_count > 0)
+    }
+    async fn watched_account(&self, pubkey: &Pubkey) -> Result<Option<WatchedAccount>, DbError> {
+        self.db
+            .collection::<WatchedAccount>("watchlist")
+            .find_one(doc! { "pubkey": to_bson_bytes(pubkey.as_ref()) }, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn watched_accounts(&self) -> Result<Vec<WatchedAccount>, DbError> {
+        self.db
+            .collection::<WatchedAccount>("watchlist")
+            .find(None, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_poll_status(&self, status: PollStatus) -> Result<(), DbError> {
+        let query = doc! { "pubkey": to_bson_bytes(status.pubkey.as_ref()) };
+        self.db
+            .collection::<PollStatus>("poll_status")
+            .replace_one(
+                query,
+                status,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn poll_statuses(&self) -> Result<Vec<PollStatus>, DbError> {
+        self.db
+            .collection::<PollStatus>("poll_status")
+            .find(None, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn upsert_pending_tx(&self, tx: PendingTx) -> Result<(), DbError> {
+        let query = doc! { "signature": tx.signature.as_str() };
+        self.db
+            .collection::<PendingTx>("pending_txs")
+            .replace_one(query, tx, ReplaceOptions::builder().upsert(true).build())
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn pending_txs(&self, limit: usize) -> Result<Vec<PendingTx>, DbError> {
+        self.db
+            .collection::<PendingTx>("pending_txs")
+            .find(
+                None,
+                FindOptions::builder()
+                    .sort(doc! { "slot": 1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn remove_pending_tx(&self, signature: &str) -> Result<(), DbError> {
+        self.db
+            .collection::<PendingTx>("pending_txs")
+            .delete_one(doc! { "signature": signature }, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn retract_provisional(&self, signature: &str) -> Result<u64, DbError> {
+        let mut deleted = 0;
+        for collection in ["order_action_records", "order_records", "dynamic_events"] {
+            deleted += self
+                .db
+                .collection::<Document>(collection)
+                .delete_many(doc! { "signature": signature, "provisional": true }, None)
+                .await
+                .map_err(|err| DbError::Insert(err.kind.to_string()))?
+                .deleted_count;
+        }
+        Ok(deleted)
+    }
+    async fn provisional_tx(&self, signature: &str) -> Result<ProvisionalTx, DbError> {
+        let filter = doc! { "signature": signature, "provisional": true };
+        let events: Vec<Document> = self
+            .db
+            .collection::<Document>("dynamic_events")
+            .find(filter.clone(), None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?;
+        Ok(ProvisionalTx {
+            order_action_records: self
+                .find_normalized("order_action_records", filter.clone(), 0)
+                .await?,
+            order_records: self.find_normalized("order_records", filter, 0).await?,
+            events: events
+                .into_iter()
+                .filter_map(DynamicEvent::from_document)
+                .collect(),
+        })
+    }
+    async fn retract_derived(&self, signature: &str) -> Result<RetractedViews, DbError> {
+        let filter = doc! { "signature": signature };
+        let mut views = RetractedViews {
+            positions: self
+                .find_all::<PositionEvent>("position_events", filter.clone())
+                .await?
+                .into_iter()
+                .map(|e| (e.user, e.market_index))
+                .collect(),
+            ledgers: self
+                .find_all::<LedgerEntry>("ledger", filter.clone())
+                .await?
+                .into_iter()
+                .map(|e| (e.user, e.market_index))
+                .collect(),
+            lp_histories: self
+                .find_all::<LpEvent>("lp_events", filter.clone())
+                .await?
+                .into_iter()
+                .map(|e| (e.user, e.market_index))
+                .collect(),
+            ..Default::default()
+        };
+        for collection in DERIVED_COLLECTIONS {
+            views.deleted += self
+                .db
+                .collection::<Document>(collection)
+                .delete_many(filter.clone(), None)
+                .await
+                .map_err(|err| DbError::Insert(err.kind.to_string()))?
+                .deleted_count;
+        }
+        views.dedup();
+        Ok(views)
+    }
+    async fn insert_account_snapshot(&self, snapshot: AccountSnapshot) -> Result<(), DbError> {
+        let query = doc! {
+            "pubkey": to_bson_bytes(snapshot.pubkey.as_ref()),
+            "slot": snapshot.slot as i64,
+        };
+        self.db
+            .collection::<AccountSnapshot>("account_snapshots")
+            .replace_one(
+                query,
+                snapshot,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn account_snapshots(&self, account: &Pubkey) -> Result<Vec<AccountSnapshot>, DbError> {
+        self.db
+            .collection::<AccountSnapshot>("account_snapshots")
+            .find(
+                doc! { "pubkey": to_bson_bytes(account.as_ref()) },
+                FindOptions::builder().sort(doc! { "slot": 1 }).build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn latest_account_snapshot(
+        &self,
+        account: &Pubkey,
+    ) -> Result<Option<AccountSnapshot>, DbError> {
+        self.db
+            .collection::<AccountSnapshot>("account_snapshots")
+            .find_one(
+                doc! { "pubkey": to_bson_bytes(account.as_ref()) },
+                FindOneOptions::builder().sort(doc! { "slot": -1 }).build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn insert_account_diff(&self, diff: AccountDiff) -> Result<(), DbError> {
+        self.db
+            .collection("account_diffs")
+            .insert_one(diff, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    async fn account_diffs(&self, account: &Pubkey) -> Result<Vec<AccountDiff>, DbError> {
+        self.db
+            .collection::<AccountDiff>("account_diffs")
+            .find(
+                doc! { "pubkey": to_bson_bytes(account.as_ref()) },
+                FindOptions::builder().sort(doc! { "slot": 1 }).build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    async fn insert_dynamic_event(&self, event: DynamicEvent) -> Result<(), DbError> {
+        self.db
+            .collection("dynamic_events")
+            .insert_one(event.to_document(), None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+}
+
+impl MongoDbClient {
+    /// Insert `record` into `collection` with its `normalized` fields alongside
+    async fn insert_normalized<R: Serialize>(
+        &self,
+        collection: &str,
+        record: NormalizedRecord<R>,
+    ) -> Result<(), DbError> {
+        let mut document = mongodb::bson::to_document(&record.record)
+            .map_err(|err| DbError::Insert(err.to_string()))?;
+        if let Some(ref normalized) = record.normalized {
+            document.insert("normalized", to_bson(normalized));
+        }
+        if !record.labels.is_empty() {
+            document.insert("labels", record.labels);
+        }
+        if let Some(signature) = record.signature {
+            document.insert("signature", signature);
+        }
+        if let Some(slot) = record.slot {
+            document.insert("slot", slot as i64);
+        }
+        if record.provisional {
+            document.insert("provisional", true);
+        }
+        if record.partial {
+            document.insert("partial", true);
+        }
+        self.db
+            .collection::<Document>(collection)
+            .insert_one(document, None)
+            .await
+            .map_err(|err| DbError::Insert(err.kind.to_string()))
+            .map(|_res| ())
+    }
+    /// Return all documents of `collection` matching `filter`
+    async fn find_all<R: DeserializeOwned + Unpin + Send + Sync>(
+        &self,
+        collection: &str,
+        filter: Document,
+    ) -> Result<Vec<R>, DbError> {
+        self.db
+            .collection::<R>(collection)
+            .find(filter, None)
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    /// Return the position events matching `filter` (chain order)
+    async fn find_position_events(&self, filter: Document) -> Result<Vec<PositionEvent>, DbError> {
+        self.db
+            .collection::<PositionEvent>("position_events")
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(chain_order_sort("seq", 1))
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))
+    }
+    /// Return the latest `limit` records of `collection` matching `filter` (newest first)
+    async fn find_normalized<R: DeserializeOwned>(
+        &self,
+        collection: &str,
+        filter: Document,
+        limit: usize,
+    ) -> Result<Vec<NormalizedRecord<R>>, DbError> {
+        let documents: Vec<Document> = self
+            .db
+            .collection::<Document>(collection)
+            .find(
+                filter,
+                FindOptions::builder()
+                    .sort(doc! { "ts": -1 })
+                    .limit(limit as i64)
+                    .build(),
+            )
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|err| DbError::Read(err.kind.to_string()))?;
+
+        documents
+            .into_iter()
+            .map(|mut document| {
+                let normalized = document
+                    .remove("normalized")
+                    .map(|n| n.into_relaxed_extjson());
+                let labels = match document.remove("labels") {
+                    Some(Bson::Array(labels)) => labels
+                        .into_iter()
+                        .filter_map(|l| l.as_str().map(str::to_string))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                let signature = document
+                    .remove("signature")
+                    .and_then(|s| s.as_str().map(str::to_string));
+                let slot = document
+                    .remove("slot")
+                    .and_then(|s| s.as_i64().map(|s| s as u64));
+                let provisional = document
+                    .remove("provisional")
+                    .and_then(|p| p.as_bool())
+                    .unwrap_or_default();
+                let partial = document
+                    .remove("partial")
+                    .and_then(|p| p.as_bool())
+                    .unwrap_or_default();
+                document.remove("_id");
+                Ok(NormalizedRecord {
+                    record: mongodb::bson::from_document(document)
+                        .map_err(|err| DbError::Read(err.to_string()))?,
+                    normalized,
+                    labels,
+                    signature,
+                    slot,
+                    provisional,
+                    partial,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Filter of a market's `resolution` candles
+fn candle_filter(
+    market_type: MarketType,
+    market_index: u16,
+    resolution: Resolution,
+) -> Result<Document, DbError> {
+    Ok(doc! {
+        "marketType": mongodb::bson::to_bson(&market_type)
+            .map_err(|err| DbError::Read(err.to_string()))?,
+        "marketIndex": market_index as i32,
+        "resolution": mongodb::bson::to_bson(&resolution)
+            .map_err(|err| DbError::Read(err.to_string()))?,
+    })
+}
+
+/// Key of an account's stats bucket in a market
+fn account_stats_key(
+    account: &Pubkey,
+    market_type: MarketType,
+    market_index: u16,
+    bucket: i64,
+) -> Result<Document, DbError> {
+    Ok(doc! {
+        "account": to_bson_bytes(account.as_ref()),
+        "marketType": mongodb::bson::to_bson(&market_type)
+            .map_err(|err| DbError::Read(err.to_string()))?,
+        "marketIndex": market_index as i32,
+        "bucket": bucket,
+    })
+}
+
+/// Test backend
+pub struct MockBackend {
+    order_action_records: Mutex<Vec<NormalizedRecord<OrderActionRecord>>>,
+    order_records: Mutex<Vec<NormalizedRecord<OrderRecord>>>,
+    orders: Mutex<Vec<OrderLifecycle>>,
+    position_events: Mutex<Vec<PositionEvent>>,
+    positions: Mutex<Vec<Position>>,
+    candles: Mutex<Vec<Candle>>,
+    applied_events: Mutex<Vec<(String, Pubkey, String, u32)>>,
+    account_stats: Mutex<Vec<AccountStats>>,
+    funding_rates: Mutex<Vec<FundingRate>>,
+    funding_payments: Mutex<Vec<FundingPayment>>,
+    ledger: Mutex<Vec<LedgerEntry>>,
+    spot_interest: Mutex<Vec<SpotInterest>>,
+    if_stake_events: Mutex<Vec<IfStakeEvent>>,
+    if_vaults: Mutex<Vec<IfVault>>,
+    lp_events: Mutex<Vec<LpEvent>>,
+    swaps: Mutex<Vec<Swap>>,
+    watchlist: Mutex<Vec<WatchedAccount>>,
+    pending_txs: Mutex<Vec<PendingTx>>,
+    poll_statuses: Mutex<Vec<PollStatus>>,
+    account_snapshots: Mutex<Vec<AccountSnapshot>>,
+    account_diffs: Mutex<Vec<AccountDiff>>,
+    dynamic_events: Mutex<Vec<DynamicEvent>>,
+    last_signatures: Mutex<HashMap<(Pubkey, Pubkey), Signature>>,
+    yield_reads: bool,
+}
+
+impl MockBackend {
+    /// Yield to the runtime (for a while) after reading derived views, interleaving concurrent read-modify-writes
+    pub fn with_yielding_reads(self) -> Self {
+        Self {
+            yield_reads: true,
+            ..self
+        }
+    }
+    async fn yield_read(&self) {
+        if self.yield_reads {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+    pub fn order_records(&self) -> Vec<OrderRecord> {
+        let records = self.order_records.lock().unwrap();
+        records.iter().map(|r| r.record.clone()).collect()
+    }
+    pub fn order_action_records(&self) -> Vec<OrderActionRecord> {
+        let records = self.order_action_records.lock().unwrap();
+        records.iter().map(|r| r.record.clone()).collect()
+    }
+    pub fn dynamic_events(&self) -> MutexGuard<'_, Vec<DynamicEvent>> {
+        self.dynamic_events.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl IndexerBackend for MockBackend {
+    async fn init(_conn_str: &str) -> Self {
+        Self {
+            order_action_records: Default::default(),
+            order_records: Default::default(),
+            orders: Default::default(),
+            position_events: Default::default(),
+            positions: Default::default(),
+            candles: Default::default(),
+            applied_events: Default::default(),
+            account_stats: Default::default(),
+            funding_rates: Default::default(),
+            funding_payments: Default::default(),
+            ledger: Default::default(),
+            spot_interest: Default::default(),
+            if_stake_events: Default::default(),
+            if_vaults: Default::default(),
+            lp_events: Default::default(),
+            swaps: Default::default(),
+            watchlist: Default::default(),
+            pending_txs: Default::default(),
+            poll_statuses: Default::default(),
+            account_snapshots: Default::default(),
+            account_diffs: Default::default(),
+            dynamic_events: Default::default(),
+            last_signatures: Default::default(),
+            yield_reads: false,
+        }
+    }
+    async fn last_indexed_signature(
+        &self,
+        program: &Pubkey,
+        account: &Pubkey,
+    ) -> Result<Option<Signature>, DbError> {
+        let last_signatures = self.last_signatures.lock().unwrap();
+        Ok(last_signatures.get(&(*program, *account)).copied())
+    }
+    async fn insert_order_action_record(
+        &self,
+        record: NormalizedRecord<OrderActionRecord>,
+    ) -> Result<(), DbError> {
+        let mut records = self.order_action_records.lock().unwrap();
+        records.push(record);
+        Ok(())
+    }
+    async fn insert_order_record(
+        &self,
+        record: NormalizedRecord<OrderRecord>,
+    ) -> Result<(), DbError> {
+        let mut records = self.order_records.lock().unwrap();
+        records.push(record);
+        Ok(())
+    }
+    async fn order_action_records_of(
+        &self,
+        account: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let mut records: Vec<NormalizedRecord<OrderActionRecord>> = self
+            .order_action_records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| {
+                r.record.maker.as_ref() == Some(account) || r.record.taker.as_ref() == Some(account)
+            })
+            .filter(|r| !(finalized && r.provisional))
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| std::cmp::Reverse(r.record.ts));
+        records.truncate(limit);
+        Ok(records)
+    }
+    async fn order_records_of(
+        &self,
+        user: &Pubkey,
+        limit: usize,
+        finalized: bool,
+    ) -> Result<Vec<NormalizedRecord<OrderRecord>>, DbError> {
+        let mut records: Vec<NormalizedRecord<OrderRecord>> = self
+            .order_records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| &r.record.user == user)
+            .filter(|r| !(finalized && r.provisional))
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| std::cmp::Reverse(r.record.ts));
+        records.truncate(limit);
+        Ok(records)
+    }
+    async fn update_last_indexed_signature(
+        &self,
+        program: &Pubkey,
+        account: &Pubkey,
+        signature: &Signature,
+    ) -> Result<(), DbError> {
+        let mut last_signatures = self.last_signatures.lock().unwrap();
+        last_signatures.insert((*program, *account), *signature);
+        Ok(())
+    }
+    async fn order_record(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Option<NormalizedRecord<OrderRecord>>, DbError> {
+        let records = self.order_records.lock().unwrap();
+        Ok(records
+            .iter()
+            .filter(|r| &r.record.user == user && r.record.order.orderId == order_id)
+            .max_by_key(|r| r.record.ts)
+            .cloned())
+    }
+    async fn order_actions(
+        &self,
+        user: &Pubkey,
+        order_id: u32,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let mut records: Vec<NormalizedRecord<OrderActionRecord>> = self
+            .order_action_records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| {
+                let (record, order_id) = (&r.record, Some(order_id));
+                (record.taker.as_ref() == Some(user) && record.takerOrderId == order_id)
+                    || (record.maker.as_ref() == Some(user) && record.makerOrderId == order_id)
+            })
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| r.record.ts);
+        Ok(records)
+    }
+    async fn fills_between(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<NormalizedRecord<OrderActionRecord>>, DbError> {
+        let mut records: Vec<NormalizedRecord<OrderActionRecord>> = self
+            .order_action_records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| {
+                r.record.marketType == market_type
+                    && r.record.marketIndex == market_index
+                    && r.record.action == OrderAction::Fill
+                    && (from..to).contains(&r.record.ts)
+            })
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| r.record.ts);
+        Ok(records)
+    }
+    async fn order(&self, user: &Pubkey, order_id: u32) -> Result<Option<OrderLifecycle>, DbError> {
+        let order = self
+            .orders
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|o| &o.user == user && o.order_id == order_id)
+            .cloned();
+        self.yield_read().await;
+        Ok(order)
+    }
+    async fn upsert_order(&self, order: OrderLifecycle) -> Result<(), DbError> {
+        let mut orders = self.orders.lock().unwrap();
+        orders.retain(|o| !(o.user == order.user && o.order_id == order.order_id));
+        orders.push(order);
+        Ok(())
+    }
+    async fn remove_order(&self, user: &Pubkey, order_id: u32) -> Result<(), DbError> {
+        let mut orders = self.orders.lock().unwrap();
+        orders.retain(|o| !(&o.user == user && o.order_id == order_id));
+        Ok(())
+    }
+    async fn orders_of(
+        &self,
+        user: &Pubkey,
+        status: Option<LifecycleStatus>,
+        limit: usize,
+    ) -> Result<Vec<OrderLifecycle>, DbError> {
+        let mut orders: Vec<OrderLifecycle> = self
+            .orders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|o| &o.user == user && (status.is_none() || status == Some(o.status)))
+            .cloned()
+            .collect();
+        orders.sort_by_key(|o| std::cmp::Reverse(o.order_id));
+        orders.truncate(limit);
+        Ok(orders)
+    }
+    async fn insert_position_event(&self, event: PositionEvent) -> Result<bool, DbError> {
+        let mut events = self.position_events.lock().unwrap();
+        if events.iter().any(|e| {
+            e.signature == event.signature && e.log_index == event.log_index && e.user == event.user
+        }) {
+            return Ok(false);
+        }
+        events.push(event);
+        Ok(true)
+    }
+    async fn position_events(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<PositionEvent>, DbError> {
+        let mut events = self.all_position_events().await?;
+        events.retain(|e| &e.user == user && e.market_index == market_index);
+        Ok(events)
+    }
+    async fn all_position_events(&self) -> Result<Vec<PositionEvent>, DbError> {
+        let mut events = self.position_events.lock().unwrap().clone();
+        events.sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        Ok(events)
+    }
+    async fn position(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+    ) -> Result<Option<Position>, DbError> {
+        let position = self
+            .positions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|p| &p.user == user && p.market_index == market_index)
+            .cloned();
+        self.yield_read().await;
+        Ok(position)
+    }
+    async fn positions_of(&self, user: &Pubkey) -> Result<Vec<Position>, DbError> {
+        let mut positions: Vec<Position> = self
+            .positions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| &p.user == user)
+            .cloned()
+            .collect();
+        positions.sort_by_key(|p| p.market_index);
+        Ok(positions)
+    }
+    async fn upsert_position(&self, position: Position) -> Result<(), DbError> {
+        let mut positions = self.positions.lock().unwrap();
+        positions.retain(|p| !(p.user == position.user && p.market_index == position.market_index));
+        positions.push(position);
+        Ok(())
+    }
+    async fn remove_position(&self, user: &Pubkey, market_index: u16) -> Result<(), DbError> {
+        let mut positions = self.positions.lock().unwrap();
+        positions.retain(|p| !(&p.user == user && p.market_index == market_index));
+        Ok(())
+    }
+    async fn clear_positions(&self) -> Result<(), DbError> {
+        self.positions.lock().unwrap().clear();
+        Ok(())
+    }
+    async fn candle(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
+    ) -> Result<Option<Candle>, DbError> {
+        let candle = self
+            .candles(market_type, market_index, resolution, start, start, 1)
+            .await?
+            .pop();
+        self.yield_read().await;
+        Ok(candle)
+    }
+    async fn upsert_candle(&self, candle: Candle) -> Result<(), DbError> {
+        let mut candles = self.candles.lock().unwrap();
+        candles.retain(|c| {
+            (c.market_type, c.market_index, c.resolution, c.start)
+                != (
+                    candle.market_type,
+                    candle.market_index,
+                    candle.resolution,
+                    candle.start,
+                )
+        });
+        candles.push(candle);
+        Ok(())
+    }
+    async fn remove_candle(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        start: i64,
+    ) -> Result<(), DbError> {
+        let mut candles = self.candles.lock().unwrap();
+        candles.retain(|c| {
+            (c.market_type, c.market_index, c.resolution, c.start)
+                != (market_type, market_index, resolution, start)
+        });
+        Ok(())
+    }
+    async fn candles(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<Candle>, DbError> {
+        let mut candles: Vec<Candle> = self
+            .candles
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| {
+                c.market_type == market_type
+                    && c.market_index == market_index
+                    && c.resolution == resolution
+                    && (from..=to).contains(&c.start)
+            })
+            .cloned()
+            .collect();
+        candles.sort_by_key(|c| c.start);
+        candles.truncate(limit);
+        Ok(candles)
+    }
+    async fn mark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: u32,
+    ) -> Result<bool, DbError> {
+        let mut applied = self.applied_events.lock().unwrap();
+        let key = (view.to_string(), *account, signature.to_string(), log_index);
+        if applied.contains(&key) {
+            return Ok(false);
+        }
+        applied.push(key);
+        Ok(true)
+    }
+    async fn unmark_applied(
+        &self,
+        view: &str,
+        account: &Pubkey,
+        signature: &str,
+        log_index: Option<u32>,
+    ) -> Result<u64, DbError> {
+        let mut applied = self.applied_events.lock().unwrap();
+        let len = applied.len();
+        applied.retain(|(v, a, s, i)| {
+            !(v == view && a == account && s == signature && log_index.is_none_or(|l| l == *i))
+        });
+        Ok((len - applied.len()) as u64)
+    }
+    async fn account_stats(
+        &self,
+        account: &Pubkey,
+        market_type: MarketType,
+        market_index: u16,
+        bucket: i64,
+    ) -> Result<Option<AccountStats>, DbError> {
+        let stats = self
+            .account_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|s| {
+                &s.account == account
+                    && s.market_type == market_type
+                    && s.market_index == market_index
+                    && s.bucket == bucket
+            })
+            .cloned();
+        self.yield_read().await;
+        Ok(stats)
+    }
+    async fn upsert_account_stats(&self, stats: AccountStats) -> Result<(), DbError> {
+        let mut all = self.account_stats.lock().unwrap();
+        all.retain(|s| {
+            (s.account, s.market_type, s.market_index, s.bucket)
+                != (
+                    stats.account,
+                    stats.market_type,
+                    stats.market_index,
+                    stats.bucket,
+                )
+        });
+        all.push(stats);
+        Ok(())
+    }
+    async fn account_stats_of(
+        &self,
+        account: &Pubkey,
+        since: Option<i64>,
+    ) -> Result<Vec<AccountStats>, DbError> {
+        let mut stats: Vec<AccountStats> = self
+            .account_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| &s.account == account && s.bucket >= since.unwrap_or(i64::MIN))
+            .cloned()
+            .collect();
+        stats.sort_by_key(|s| s.bucket);
+        Ok(stats)
+    }
+    async fn upsert_funding_rate(&self, rate: FundingRate) -> Result<(), DbError> {
+        let mut rates = self.funding_rates.lock().unwrap();
+        rates.retain(|r| (r.market_index, r.record_id) != (rate.market_index, rate.record_id));
+        rates.push(rate);
+        Ok(())
+    }
+    async fn funding_rates(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<FundingRate>, DbError> {
+        let mut rates: Vec<FundingRate> = self
+            .funding_rates
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.market_index == market_index && (from..=to).contains(&r.ts))
+            .cloned()
+            .collect();
+        rates.sort_by_key(|r| (r.ts, r.record_id));
+        rates.truncate(limit);
+        Ok(rates)
+    }
+    async fn upsert_funding_payment(&self, payment: FundingPayment) -> Result<(), DbError> {
+        let mut payments = self.funding_payments.lock().unwrap();
+        payments.retain(|p| (&p.signature, p.log_index) != (&payment.signature, payment.log_index));
+        payments.push(payment);
+        Ok(())
+    }
+    async fn funding_payments(
+        &self,
+        user: &Pubkey,
+        market_index: Option<u16>,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<FundingPayment>, DbError> {
+        let mut payments: Vec<FundingPayment> = self
+            .funding_payments
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| {
+                &p.user == user
+                    && (market_index.is_none() || market_index == Some(p.market_index))
+                    && (from..=to).contains(&p.ts)
+            })
+            .cloned()
+            .collect();
+        payments.sort_by_key(|p| (p.ts, p.slot, p.log_index));
+        Ok(payments)
+    }
+    async fn ledger(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LedgerEntry>, DbError> {
+        let mut ledger: Vec<LedgerEntry> = self
+            .ledger
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| &e.user == user && e.market_index == market_index)
+            .cloned()
+            .collect();
+        ledger.sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        self.yield_read().await;
+        Ok(ledger)
+    }
+    async fn upsert_ledger_entry(&self, entry: LedgerEntry) -> Result<(), DbError> {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.retain(|e| e.user != entry.user || e.order_key() != entry.order_key());
+        ledger.push(entry);
+        Ok(())
+    }
+    async fn ledger_entry_at(
+        &self,
+        user: &Pubkey,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<LedgerEntry>, DbError> {
+        let mut ledger = self.ledger(user, market_index).await?;
+        ledger.retain(|e| e.ts <= ts);
+        Ok(ledger.pop())
+    }
+    async fn upsert_spot_interest(&self, interest: SpotInterest) -> Result<(), DbError> {
+        let mut all = self.spot_interest.lock().unwrap();
+        all.retain(|i| {
+            (i.market_index, &i.signature) != (interest.market_index, &interest.signature)
+        });
+        all.push(interest);
+        Ok(())
+    }
+    async fn spot_interest_at(
+        &self,
+        market_index: u16,
+        ts: i64,
+    ) -> Result<Option<SpotInterest>, DbError> {
+        let all = self.spot_interest.lock().unwrap();
+        Ok(all
+            .iter()
+            .filter(|i| i.market_index == market_index && i.ts <= ts)
+            .max_by_key(|i| (i.ts, i.slot))
+            .cloned())
+    }
+    async fn upsert_if_stake_event(&self, event: IfStakeEvent) -> Result<(), DbError> {
+        let mut events = self.if_stake_events.lock().unwrap();
+        events.retain(|e| e.order_key() != event.order_key());
+        events.push(event);
+        Ok(())
+    }
+    async fn if_stake_events(
+        &self,
+        authority: &Pubkey,
+        market_index: u16,
+    ) -> Result<Vec<IfStakeEvent>, DbError> {
+        let mut events: Vec<IfStakeEvent> = self
+            .if_stake_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| &e.user_authority == authority && e.market_index == market_index)
+            .cloned()
+            .collect();
+        events.sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        Ok(events)
+    }
+    async fn if_stakers(&self) -> Result<Vec<(Pubkey, u16)>, DbError> {
+        let mut stakers: Vec<(Pubkey, u16)> = self
+            .if_stake_events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e.user_authority, e.market_index))
+            .collect();
+        stakers.sort();
+        stakers.dedup();
+        Ok(stakers)
+    }
+    async fn upsert_if_vault(&self, vault: IfVault) -> Result<(), DbError> {
+        let mut vaults = self.if_vaults.lock().unwrap();
+        vaults.retain(|v| (&v.signature, v.log_index) != (&vault.signature, vault.log_index));
+        vaults.push(vault);
+        Ok(())
+    }
+    async fn if_vault_at(&self, market_index: u16, ts: i64) -> Result<Option<IfVault>, DbError> {
+        let mut vaults = self.if_vaults(market_index, i64::MIN, ts).await?;
+        Ok(vaults.pop())
+    }
+    async fn if_vaults(
+        &self,
+        market_index: u16,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<IfVault>, DbError> {
+        let mut vaults: Vec<IfVault> = self
+            .if_vaults
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|v| v.market_index == market_index && (from..=to).contains(&v.ts))
+            .cloned()
+            .collect();
+        vaults.sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        Ok(vaults)
+    }
+    async fn upsert_lp_event(&self, event: LpEvent) -> Result<(), DbError> {
+        let mut events = self.lp_events.lock().unwrap();
+        events.retain(|e| e.order_key() != event.order_key());
+        events.push(event);
+        Ok(())
+    }
+    async fn lp_events(&self, user: &Pubkey, market_index: u16) -> Result<Vec<LpEvent>, DbError> {
+        let mut events: Vec<LpEvent> = self
+            .lp_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| &e.user == user && e.market_index == market_index)
+            .cloned()
+            .collect();
+        events.sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        Ok(events)
+    }
+    async fn upsert_swap(&self, swap: Swap) -> Result<(), DbError> {
+        let mut swaps = self.swaps.lock().unwrap();
+        swaps.retain(|s| (&s.signature, s.log_index) != (&swap.signature, swap.log_index));
+        swaps.push(swap);
+        Ok(())
+    }
+    async fn swaps(&self, user: &Pubkey, limit: usize) -> Result<Vec<Swap>, DbError> {
+        let mut swaps: Vec<Swap> = self
+            .swaps
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| &s.user == user)
+            .cloned()
+            .collect();
+        swaps.sort_by(|a, b| {
+            (b.slot, &b.signature, b.log_index).cmp(&(a.slot, &a.signature, a.log_index))
+        });
+        swaps.truncate(limit);
+        Ok(swaps)
+    }
+    async fn upsert_watched_account(&self, account: WatchedAccount) -> Result<(), DbError> {
+        let mut watchlist = self.watchlist.lock().unwrap();
+        watchlist.retain(|a| a.pubkey != account.pubkey);
+        watchlist.push(account);
+        Ok(())
+    }
+    async fn remove_watched_account(&self, pubkey: &Pubkey) -> Result<bool, DbError> {
+        let mut watchlist = self.watchlist.lock().unwrap();
+        let len = watchlist.len();
+        watchlist.retain(|a| &a.pubkey != pubkey);
+        Ok(watchlist.len() < len)
+    }
+    async fn watched_account(&self, pubkey: &Pubkey) -> Result<Option<WatchedAccount>, DbError> {
+        Ok(self
+            .watchlist
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|a| &a.pubkey == pubkey)
+            .cloned())
+    }
+    async fn watched_accounts(&self) -> Result<Vec<WatchedAccount>, DbError> {
+        Ok(self.watchlist.lock().unwrap().clone())
+    }
+    async fn upsert_poll_status(&self, status: PollStatus) -> Result<(), DbError> {
+        let mut statuses = self.poll_statuses.lock().unwrap();
+        statuses.retain(|s| s.pubkey != status.pubkey);
+        statuses.push(status);
+        Ok(())
+    }
+    async fn poll_statuses(&self) -> Result<Vec<PollStatus>, DbError> {
+        Ok(self.poll_statuses.lock().unwrap().clone())
+    }
+    async fn upsert_pending_tx(&self, tx: PendingTx) -> Result<(), DbError> {
+        let mut pending = self.pending_txs.lock().unwrap();
+        pending.retain(|p| p.signature != tx.signature);
+        pending.push(tx);
+        Ok(())
+    }
+    async fn pending_txs(&self, limit: usize) -> Result<Vec<PendingTx>, DbError> {
+        let mut pending = self.pending_txs.lock().unwrap().clone();
+        pending.sort_by_key(|p| p.slot);
+        pending.truncate(limit);
+        Ok(pending)
+    }
+    async fn remove_pending_tx(&self, signature: &str) -> Result<(), DbError> {
+        let mut pending = self.pending_txs.lock().unwrap();
+        pending.retain(|p| p.signature != signature);
+        Ok(())
+    }
+    async fn retract_provisional(&self, signature: &str) -> Result<u64, DbError> {
+        let retracted = |provisional: bool, s: Option<&str>| provisional && s == Some(signature);
+        let mut deleted = 0;
+        let mut records = self.order_action_records.lock().unwrap();
+        let len = records.len();
+        records.retain(|r| !retracted(r.provisional, r.signature.as_deref()));
+        deleted += len - records.len();
+        let mut records = self.order_records.lock().unwrap();
+        let len = records.len();
+        records.retain(|r| !retracted(r.provisional, r.signature.as_deref()));
+        deleted += len - records.len();
+        let mut events = self.dynamic_events.lock().unwrap();
+        let len = events.len();
+        events.retain(|e| !retracted(e.provisional, Some(e.signature.as_str())));
+        deleted += len - events.len();
+        Ok(deleted as u64)
+    }
+    async fn provisional_tx(&self, signature: &str) -> Result<ProvisionalTx, DbError> {
+        let provisional = |provisional: bool, s: Option<&str>| provisional && s == Some(signature);
+        let order_action_records = self.order_action_records.lock().unwrap();
+        let order_records = self.order_records.lock().unwrap();
+        let events = self.dynamic_events.lock().unwrap();
+        Ok(ProvisionalTx {
+            order_action_records: order_action_records
+                .iter()
+                .filter(|r| provisional(r.provisional, r.signature.as_deref()))
+                .cloned()
+                .collect(),
+            order_records: order_records
+                .iter()
+                .filter(|r| provisional(r.provisional, r.signature.as_deref()))
+                .cloned()
+                .collect(),
+            events: events
+                .iter()
+                .filter(|e| provisional(e.provisional, Some(e.signature.as_str())))
+                .cloned()
+                .collect(),
+        })
+    }
+    async fn retract_derived(&self, signature: &str) -> Result<RetractedViews, DbError> {
+        /// Remove the entries of `entries` derived from the tx, returns the removed entries
+        fn retract<E>(entries: &Mutex<Vec<E>>, of_tx: impl Fn(&E) -> bool) -> Vec<E> {
+            let mut entries = entries.lock().unwrap();
+            let (retracted, kept) = entries.drain(..).partition(of_tx);
+            *entries = kept;
+            retracted
+        }
+        let position_events = retract(&self.position_events, |e| e.signature == signature);
+        let ledger = retract(&self.ledger, |e| e.signature == signature);
+        let lp_events = retract(&self.lp_events, |e| e.signature == signature);
+        let deleted = position_events.len()
+            + ledger.len()
+            + lp_events.len()
+            + retract(&self.funding_rates, |e| e.signature == signature).len()
+            + retract(&self.funding_payments, |e| e.signature == signature).len()
+            + retract(&self.spot_interest, |e| e.signature == signature).len()
+            + retract(&self.if_stake_events, |e| e.signature == signature).len()
+            + retract(&self.if_vaults, |e| e.signature == signature).len()
+            + retract(&self.swaps, |e| e.signature == signature).len();
+        let mut views = RetractedViews {
+            deleted: deleted as u64,
+            positions: position_events
+                .iter()
+                .map(|e| (e.user, e.market_index))
+                .collect(),
+            ledgers: ledger.iter().map(|e| (e.user, e.market_index)).collect(),
+            lp_histories: lp_events.iter().map(|e| (e.user, e.market_index)).collect(),
+        };
+        views.dedup();
+        Ok(views)
+    }
+    async fn insert_account_snapshot(&self, snapshot: AccountSnapshot) -> Result<(), DbError> {
+        let mut snapshots = self.account_snapshots.lock().unwrap();
+        snapshots.retain(|s| !(s.pubkey == snapshot.pubkey && s.slot == snapshot.slot));
+        snapshots.push(snapshot);
+        Ok(())
+    }
+    async fn account_snapshots(&self, account: &Pubkey) -> Result<Vec<AccountSnapshot>, DbError> {
+        let mut snapshots: Vec<AccountSnapshot> = self
+            .account_snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| &s.pubkey == account)
+            .cloned()
+            .collect();
+        snapshots.sort_by_key(|s| s.slot);
+        Ok(snapshots)
+    }
+    async fn latest_account_snapshot(
+        &self,
+        account: &Pubkey,
+    ) -> Result<Option<AccountSnapshot>, DbError> {
+        Ok(self
+            .account_snapshots(account)
+            .await?
+            .into_iter()
+            .max_by_key(|s| s.slot))
+    }
+    async fn insert_account_diff(&self, diff: AccountDiff) -> Result<(), DbError> {
+        self.account_diffs.lock().unwrap().push(diff);
+        Ok(())
+    }
+    async fn account_diffs(&self, account: &Pubkey) -> Result<Vec<AccountDiff>, DbError> {
+        let mut diffs: Vec<AccountDiff> = self
+            .account_diffs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| &d.pubkey == account)
+            .cloned()
+            .collect();
+        diffs.sort_by_key(|d| d.slot);
+        Ok(diffs)
+    }
+    async fn insert_dynamic_event(&self, event: DynamicEvent) -> Result<(), DbError> {
+        self.dynamic_events.lock().unwrap().push(event);
+        Ok(())
+    }
+}
+
+/// (De)serializes 128-bit integers as decimal strings, bson has no 128-bit integer type
+pub(crate) mod int_string {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Sort (`order` 1 ascending, -1 descending) of events in `ChainOrder`, `seq` the field of the record's onchain id
+fn chain_order_sort(seq: &str, order: i32) -> Document {
+    doc! { "slot": order, seq: order, "logIndex": order, "signature": order }
+}
+
+/// Convert `bytes` into a bson array matching the serde encoding of fixed size byte arrays e.g. `Pubkey`
+fn to_bson_bytes(bytes: &[u8]) -> Bson {
+    Bson::Array(bytes.iter().map(|d| Bson::Int32(*d as i32)).collect())
+}
+
+/// Return the query of the indexed `address` (serialized bytes) of `program`
+///
+/// Accounts indexed before programs were tracked have no `program`, they are drift accounts
+fn account_query(address: Bson, program: &Pubkey) -> Document {
+    let program_bytes = to_bson_bytes(program.as_ref());
+    if program == &drift_pda() {
+        doc! {
+            "address": address,
+            "$or": [{ "program": program_bytes }, { "program": { "$exists": false } }],
+        }
+    } else {
+        doc! { "address": address, "program": program_bytes }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct Account {
     address: Pubkey,
+    #[serde(default = "drift_pda")]
+    program: Pubkey,
     last_processed_signature: Signature,
 }
@@ -0,0 +1,268 @@
+//! Authority-based account discovery
+//!
+//! Finds the `User` accounts (subaccounts) of a wallet authority: the `UserStats` and `User` PDAs of subaccount
+//! IDs `0..N` are fetched to find those existing, then the authority's txs are watched for `NewUserRecord`s
+//! of subaccounts created later. Found accounts are added to the watchlist
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use log::{debug, info};
+use solana_account_decoder::UiAccountEncoding;
+use solana_rpc_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_rpc_client_api::config::{RpcAccountInfoConfig, RpcTransactionConfig};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{option_serializer::OptionSerializer, UiTransactionEncoding};
+use tokio::select;
+
+use crate::{
+    db::IndexerBackend,
+    snapshot::MAX_ACCOUNTS_PER_REQUEST,
+    types::{
+        drift::{events::DriftEvent, DriftProgram},
+        drift_pda, IndexerError, LogError,
+    },
+    versions::IdlVersions,
+    watchlist::watch_if_absent,
+    RpcClient,
+};
+
+/// Default number of subaccount IDs to probe per authority
+pub const DEFAULT_MAX_SUB_ACCOUNTS: u16 = 8;
+
+/// Return the `UserStats` account (PDA) of `authority`
+pub fn user_stats_pda(authority: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_stats", authority.as_ref()], &drift_pda()).0
+}
+
+/// Return the `User` account (PDA) of `authority`'s subaccount `sub_account_id`
+pub fn user_pda(authority: &Pubkey, sub_account_id: u16) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"user",
+            authority.as_ref(),
+            sub_account_id.to_le_bytes().as_ref(),
+        ],
+        &drift_pda(),
+    )
+    .0
+}
+
+/// Discovers the `User` accounts of wallet authorities
+pub struct AuthorityDiscovery {
+    /// Solana RPC client
+    rpc: Arc<RpcClient>,
+    /// IDL versions to decode `NewUserRecord`s with
+    idl: Arc<IdlVersions<DriftProgram>>,
+    /// Number of subaccount IDs to probe
+    max_sub_accounts: u16,
+}
+
+impl AuthorityDiscovery {
+    /// Create a new `AuthorityDiscovery`
+    pub fn new(rpc: Arc<RpcClient>, idl: Arc<IdlVersions<DriftProgram>>) -> Self {
+        Self {
+            rpc,
+            idl,
+            max_sub_accounts: DEFAULT_MAX_SUB_ACCOUNTS,
+        }
+    }
+
+    /// Probe subaccount IDs `0..max_sub_accounts`
+    pub fn with_max_sub_accounts(mut self, max_sub_accounts: u16) -> Self {
+        self.max_sub_accounts = max_sub_accounts;
+        self
+    }
+
+    /// Add the `User` accounts of `authority` to the watchlist, the existing ones first then those created later
+    /// - `poll_interval` frequency to check the authority's txs for new subaccounts
+    ///
+    /// Accounts are labelled `authority:<authority>`, those already watched are left as is
+    pub async fn run<T: IndexerBackend>(
+        self,
+        db: Arc<T>,
+        authority: &str,
+        poll_interval: Duration,
+    ) -> Result<(), IndexerError> {
+        let authority = Pubkey::try_from(authority).map_err(|_| IndexerError::InvalidPublicKey)?;
+        let labels = vec![format!("authority:{authority}")];
+        // before discovering so subaccounts created meanwhile are not missed
+        let mut last_signature = self.signatures(&authority, None).await?.pop();
+        for user in self.discover(&authority).await? {
+            watch_if_absent(db.as_ref(), user, labels.clone(), None).await?;
+        }
+
+        let mut poll = tokio::time::interval(poll_interval);
+        loop {
+            select! {
+                _ = poll.tick() => {
+                    let signatures = self.signatures(&authority, last_signature).await?;
+                    for signature in signatures.iter() {
+                        for user in self.new_users(&authority, signature).await? {
+                            info!("discovered new User of {authority}: {user}");
+                            watch_if_absent(db.as_ref(), user, labels.clone(), None).await?;
+                        }
+                    }
+                    last_signature = signatures.last().copied().or(last_signature);
+                }
+            }
+        }
+    }
+
+    /// Return the existing `User` accounts of `authority`
+    pub async fn discover(&self, authority: &Pubkey) -> Result<Vec<Pubkey>, IndexerError> {
+        let keys: Vec<Pubkey> = std::iter::once(user_stats_pda(authority))
+            .chain((0..self.max_sub_accounts).map(|id| user_pda(authority, id)))
+            .collect();
+        let mut users = Vec::new();
+        for (chunk_index, chunk) in keys.chunks(MAX_ACCOUNTS_PER_REQUEST).enumerate() {
+            let response = self
+                .rpc
+                .get_multiple_accounts_with_config(
+                    chunk,
+                    RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            for (index, (pubkey, account)) in chunk.iter().zip(response.value).enumerate() {
+                let exists = account.is_some_and(|a| a.owner == drift_pda());
+                if chunk_index == 0 && index == 0 {
+                    if !exists {
+                        info!("no UserStats for authority: {authority}");
+                    }
+                } else if exists {
+                    users.push(*pubkey);
+                }
+            }
+        }
+        info!("discovered {} User(s) of {authority}", users.len());
+
+        Ok(users)
+    }
+
+    /// Return the signatures of `authority`'s txs after `until` (oldest first)
+    async fn signatures(
+        &self,
+        authority: &Pubkey,
+        until: Option<Signature>,
+    ) -> Result<Vec<Signature>, IndexerError> {
+        let results = self
+            .rpc
+            .get_signatures_for_address_with_config(
+                authority,
+                GetConfirmedSignaturesForAddress2Config {
+                    // only the latest when starting
+                    limit: until.is_none().then_some(1),
+                    until,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        Ok(results
+            .iter()
+            .rev()
+            .filter_map(|r| Signature::from_str(r.signature.as_str()).ok())
+            .collect())
+    }
+
+    /// Return the `User` accounts of `authority` created in the tx `signature`
+    async fn new_users(
+        &self,
+        authority: &Pubkey,
+        signature: &Signature,
+    ) -> Result<Vec<Pubkey>, IndexerError> {
+        let tx = self
+            .rpc
+            .get_transaction_with_config(
+                signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    max_supported_transaction_version: Some(0),
+                    commitment: None,
+                },
+            )
+            .await?;
+        let Some(meta) = tx.transaction.meta.filter(|m| m.err.is_none()) else {
+            debug!("skipping failed tx: {signature}");
+            return Ok(Vec::new());
+        };
+        let OptionSerializer::Some(logs) = meta.log_messages else {
+            return Ok(Vec::new());
+        };
+        let mut users = Vec::new();
+        for log in logs.iter() {
+            match self.idl.try_parse_log(log.as_str(), tx.slot) {
+                Ok(Some(DriftEvent::NewUserRecord(record)))
+                    if &record.userAuthority == authority =>
+                {
+                    users.push(record.user)
+                }
+                Ok(_) | Err(LogError::InvalidBase64) => (),
+            }
+        }
+
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use solana_account_decoder::UiAccount;
+    use solana_rpc_client_api::{
+        request::RpcRequest,
+        response::{Response, RpcResponseContext},
+    };
+    use solana_sdk::account::Account;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn discover_sub_accounts() {
+        let authority = Pubkey::new_unique();
+        let account = |owner: Pubkey| {
+            let account = Account {
+                lamports: 1,
+                data: vec![0; 8],
+                owner,
+                executable: false,
+                rent_epoch: 0,
+            };
+            Some(UiAccount::encode(
+                &Pubkey::default(),
+                &account,
+                UiAccountEncoding::Base64,
+                None,
+                None,
+            ))
+        };
+        // UserStats, subaccounts 0 and 2, subaccount 3 is not a drift account
+        let response = Response {
+            context: RpcResponseContext::new(100),
+            value: vec![
+                account(drift_pda()),
+                account(drift_pda()),
+                None,
+                account(drift_pda()),
+                account(Pubkey::new_unique()),
+            ],
+        };
+        let rpc = RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::to_value(response).expect("it serializes"),
+            )]),
+        );
+        let discovery =
+            AuthorityDiscovery::new(Arc::new(rpc), Arc::new(IdlVersions::latest_only()))
+                .with_max_sub_accounts(4);
+
+        assert_eq!(
+            discovery.discover(&authority).await.unwrap(),
+            [user_pda(&authority, 0), user_pda(&authority, 2)]
+        );
+        assert_ne!(user_pda(&authority, 0), user_pda(&authority, 1));
+    }
+}
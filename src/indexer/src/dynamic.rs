@@ -0,0 +1,673 @@
+//! Runtime (dynamic) IDL decoding
+//!
+//! Decodes events, instructions and accounts into a generic value tree using an anchor IDL loaded at runtime,
+//! so a new IDL can be used without rebuilding the typed (`gen_idl_types!`) decoders.
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use anchor_syn::idl::{
+    EnumFields, Idl, IdlEvent, IdlField, IdlInstruction, IdlType, IdlTypeDefinition,
+    IdlTypeDefinitionTy,
+};
+use heck::SnakeCase;
+use mongodb::bson::{doc, Bson, Decimal128, Document};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::types::try_parse_log_data;
+
+/// drift zero-copy accounts i.e. stored with `#[repr(C)]` layout rather than borsh
+pub const DRIFT_ZERO_COPY_ACCOUNTS: &[&str] = &[
+    "InsuranceFundStake",
+    "PerpMarket",
+    "PhoenixV1FulfillmentConfig",
+    "ProtocolIfSharesTransferConfig",
+    "ReferrerName",
+    "SerumV3FulfillmentConfig",
+    "SpotMarket",
+    "State",
+    "User",
+    "UserStats",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The IDL could not be loaded
+    InvalidIdl(String),
+    /// A defined type is missing from the IDL
+    UnknownType(String),
+    /// The data ended before the value was decoded
+    UnexpectedEof,
+    /// The data is not a valid encoding of the type
+    InvalidValue(String),
+}
+
+/// A value decoded with a runtime IDL
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Decoded {
+    /// IDL name of the event, instruction or account e.g. 'OrderActionRecord'
+    pub name: String,
+    /// The decoded fields
+    pub value: Value,
+}
+
+impl Decoded {
+    /// Convert the decoded value into BSON
+    pub fn to_bson(&self) -> Bson {
+        to_bson(&self.value)
+    }
+}
+
+/// An event stored as a generic document and the tx it was emitted in
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicEvent {
+    /// IDL name of the program which emitted the event e.g. 'drift'
+    pub program: String,
+    /// Signature of the tx which emitted the event
+    pub signature: String,
+    /// Slot of the tx
+    pub slot: u64,
+    /// The decoded event
+    pub event: Decoded,
+    /// Decimal values of the event's fixed-point fields (see `Normalizer`)
+    pub normalized: Option<Value>,
+    /// Labels of the account the event was indexed for (see `WatchedAccount`)
+    pub labels: Vec<String>,
+    /// Whether the tx is only confirmed i.e. the event may still be retracted
+    pub provisional: bool,
+    /// Whether the event was decoded from the known prefix of a newer layout i.e. appended fields are missing
+    pub partial: bool,
+}
+
+impl DynamicEvent {
+    /// Convert into a BSON document for storage
+    pub fn to_document(&self) -> Document {
+        let mut document = doc! {
+            "program": self.program.as_str(),
+            "signature": self.signature.as_str(),
+            "slot": self.slot as i64,
+            "name": self.event.name.as_str(),
+            "data": self.event.to_bson(),
+        };
+        if let Some(ref normalized) = self.normalized {
+            document.insert("normalized", to_bson(normalized));
+        }
+        if !self.labels.is_empty() {
+            document.insert("labels", self.labels.clone());
+        }
+        if self.provisional {
+            document.insert("provisional", true);
+        }
+        if self.partial {
+            document.insert("partial", true);
+        }
+        document
+    }
+
+    /// Convert from a stored BSON document, `None` if it is not one
+    pub fn from_document(mut document: Document) -> Option<Self> {
+        let labels = match document.remove("labels") {
+            Some(Bson::Array(labels)) => labels
+                .into_iter()
+                .filter_map(|l| l.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(Self {
+            program: document.get_str("program").ok()?.to_string(),
+            signature: document.get_str("signature").ok()?.to_string(),
+            slot: document.get_i64("slot").ok()? as u64,
+            event: Decoded {
+                name: document.get_str("name").ok()?.to_string(),
+                value: document.remove("data")?.into_relaxed_extjson(),
+            },
+            normalized: document
+                .remove("normalized")
+                .map(Bson::into_relaxed_extjson),
+            labels,
+            provisional: document.get_bool("provisional").unwrap_or_default(),
+            partial: document.get_bool("partial").unwrap_or_default(),
+        })
+    }
+}
+
+/// Memory layout of encoded values
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    /// borsh encoded (events, instructions and regular accounts)
+    Borsh,
+    /// `#[repr(C)]` as cast onchain (zero-copy accounts)
+    C,
+}
+
+/// An anchor IDL loaded at runtime
+pub struct DynamicIdl {
+    /// IDL (program) name e.g. 'drift'
+    name: String,
+    /// IDL version e.g. '2.58.0'
+    version: String,
+    /// Type definitions by name
+    types: HashMap<String, IdlTypeDefinition>,
+    /// Events by discriminant
+    events: HashMap<[u8; 8], IdlEvent>,
+    /// Instructions by discriminant
+    instructions: HashMap<[u8; 8], IdlInstruction>,
+    /// Accounts by discriminant
+    accounts: HashMap<[u8; 8], IdlTypeDefinition>,
+    /// Names of zero-copy accounts
+    zero_copy: HashSet<String>,
+}
+
+impl DynamicIdl {
+    /// Load an anchor IDL from its JSON representation
+    pub fn from_json(json: &str) -> Result<Self, DecodeError> {
+        let idl: Idl =
+            serde_json::from_str(json).map_err(|err| DecodeError::InvalidIdl(err.to_string()))?;
+        Ok(Self::new(idl))
+    }
+
+    /// Load an anchor IDL from a JSON file at `path`
+    pub fn from_file(path: &str) -> Result<Self, DecodeError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| DecodeError::InvalidIdl(err.to_string()))?;
+        Self::from_json(json.as_str())
+    }
+
+    /// Create a new `DynamicIdl` from `idl`, computing the discriminants of its items
+    pub fn new(idl: Idl) -> Self {
+        Self {
+            name: idl.name,
+            version: idl.version,
+            types: idl
+                .types
+                .iter()
+                .chain(idl.accounts.iter())
+                .map(|t| (t.name.clone(), t.clone()))
+                .collect(),
+            events: idl
+                .events
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| (discriminant("event", e.name.as_str()), e))
+                .collect(),
+            instructions: idl
+                .instructions
+                .into_iter()
+                .map(|i| (discriminant("global", i.name.to_snake_case().as_str()), i))
+                .collect(),
+            accounts: idl
+                .accounts
+                .into_iter()
+                .map(|a| (discriminant("account", a.name.as_str()), a))
+                .collect(),
+            zero_copy: Default::default(),
+        }
+    }
+
+    /// Decode the given accounts with their onchain `#[repr(C)]` layout
+    pub fn zero_copy(mut self, accounts: &[&str]) -> Self {
+        self.zero_copy
+            .extend(accounts.iter().map(|a| a.to_string()));
+        self
+    }
+
+    /// Return the IDL (program) name
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Return the IDL version
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
+    /// Try decode an event from raw log string
+    ///
+    /// Returns `None` if the log is not event data or the event is unknown
+    pub fn decode_log(&self, raw: &str) -> Result<Option<Decoded>, DecodeError> {
+        match try_parse_log_data(raw) {
+            Ok(Some(data)) => self.decode_event(data.as_slice()),
+            // not event data e.g. 'Program log: Instruction: FillPerpOrder'
+            Ok(None) | Err(_) => Ok(None),
+        }
+    }
+
+    /// Try decode an event from its data (including discriminant)
+    pub fn decode_event(&self, data: &[u8]) -> Result<Option<Decoded>, DecodeError> {
+        let Some((disc, mut reader)) = Reader::split_discriminant(data) else {
+            return Ok(None);
+        };
+        let Some(event) = self.events.get(&disc) else {
+            return Ok(None);
+        };
+        let mut fields = Map::with_capacity(event.fields.len());
+        for f in &event.fields {
+            fields.insert(f.name.clone(), self.decode_borsh(&f.ty, &mut reader)?);
+        }
+        Ok(Some(Decoded {
+            name: event.name.clone(),
+            value: Value::Object(fields),
+        }))
+    }
+
+    /// Try decode instruction arguments from instruction data (including discriminant)
+    pub fn decode_instruction(&self, data: &[u8]) -> Result<Option<Decoded>, DecodeError> {
+        let Some((disc, mut reader)) = Reader::split_discriminant(data) else {
+            return Ok(None);
+        };
+        let Some(ix) = self.instructions.get(&disc) else {
+            return Ok(None);
+        };
+        Ok(Some(Decoded {
+            name: ix.name.clone(),
+            value: self.decode_fields(&ix.args, &mut reader, Layout::Borsh)?,
+        }))
+    }
+
+    /// Try decode an account from its data (including discriminant)
+    pub fn decode_account(&self, data: &[u8]) -> Result<Option<Decoded>, DecodeError> {
+        let Some((disc, mut reader)) = Reader::split_discriminant(data) else {
+            return Ok(None);
+        };
+        let Some(account) = self.accounts.get(&disc) else {
+            return Ok(None);
+        };
+        let layout = if self.zero_copy.contains(&account.name) {
+            Layout::C
+        } else {
+            Layout::Borsh
+        };
+        Ok(Some(Decoded {
+            name: account.name.clone(),
+            value: self.decode_definition(account, &mut reader, layout)?,
+        }))
+    }
+
+    fn decode_borsh(&self, ty: &IdlType, reader: &mut Reader) -> Result<Value, DecodeError> {
+        self.decode(ty, reader, Layout::Borsh)
+    }
+
+    /// Decode a value of `ty` from `reader`
+    fn decode(
+        &self,
+        ty: &IdlType,
+        reader: &mut Reader,
+        layout: Layout,
+    ) -> Result<Value, DecodeError> {
+        if layout == Layout::C {
+            reader.align(self.align_of(ty)?)?;
+        }
+        let value = match ty {
+            IdlType::Bool => Value::Bool(reader.take::<1>()?[0] != 0),
+            IdlType::U8 => u8::from_le_bytes(reader.take()?).into(),
+            IdlType::I8 => i8::from_le_bytes(reader.take()?).into(),
+            IdlType::U16 => u16::from_le_bytes(reader.take()?).into(),
+            IdlType::I16 => i16::from_le_bytes(reader.take()?).into(),
+            IdlType::U32 => u32::from_le_bytes(reader.take()?).into(),
+            IdlType::I32 => i32::from_le_bytes(reader.take()?).into(),
+            IdlType::F32 => f32::from_le_bytes(reader.take()?).into(),
+            IdlType::U64 => u64::from_le_bytes(reader.take()?).into(),
+            IdlType::I64 => i64::from_le_bytes(reader.take()?).into(),
+            IdlType::F64 => f64::from_le_bytes(reader.take()?).into(),
+            // not representable as JSON numbers
+            IdlType::U128 => u128::from_le_bytes(reader.take()?).to_string().into(),
+            IdlType::I128 => i128::from_le_bytes(reader.take()?).to_string().into(),
+            IdlType::U256 | IdlType::I256 => {
+                let bytes: [u8; 32] = reader.take()?;
+                let hex: String = bytes.iter().rev().map(|b| format!("{b:02x}")).collect();
+                format!("0x{hex}").into()
+            }
+            IdlType::PublicKey => Pubkey::new_from_array(reader.take()?).to_string().into(),
+            IdlType::Array(inner, len) => Value::Array(
+                (0..*len)
+                    .map(|_| self.decode(inner, reader, layout))
+                    .collect::<Result<_, _>>()?,
+            ),
+            IdlType::Defined(name) => {
+                let def = self
+                    .types
+                    .get(name)
+                    .ok_or_else(|| DecodeError::UnknownType(name.clone()))?;
+                self.decode_definition(def, reader, layout)?
+            }
+            IdlType::Option(inner) => match reader.take::<1>()?[0] {
+                0 => Value::Null,
+                1 => self.decode_borsh(inner, reader)?,
+                tag => return Err(DecodeError::InvalidValue(format!("option tag: {tag}"))),
+            },
+            IdlType::Vec(inner) => {
+                let len = reader.len_prefix()?;
+                Value::Array(
+                    (0..len)
+                        .map(|_| self.decode_borsh(inner, reader))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            IdlType::Bytes => {
+                let len = reader.len_prefix()?;
+                Value::Array(
+                    reader
+                        .take_slice(len)?
+                        .iter()
+                        .map(|b| (*b).into())
+                        .collect(),
+                )
+            }
+            IdlType::String => {
+                let len = reader.len_prefix()?;
+                String::from_utf8(reader.take_slice(len)?.to_vec())
+                    .map_err(|err| DecodeError::InvalidValue(err.to_string()))?
+                    .into()
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Decode a value of the user defined type `def` from `reader`
+    fn decode_definition(
+        &self,
+        def: &IdlTypeDefinition,
+        reader: &mut Reader,
+        layout: Layout,
+    ) -> Result<Value, DecodeError> {
+        match def.ty {
+            IdlTypeDefinitionTy::Struct { ref fields } => {
+                let value = self.decode_fields(fields, reader, layout)?;
+                if layout == Layout::C {
+                    // trailing padding
+                    reader.align(self.align_of(&IdlType::Defined(def.name.clone()))?)?;
+                }
+                Ok(value)
+            }
+            IdlTypeDefinitionTy::Enum { ref variants } => {
+                let tag = reader.take::<1>()?[0] as usize;
+                let variant = variants.get(tag).ok_or_else(|| {
+                    DecodeError::InvalidValue(format!("{} variant: {tag}", def.name))
+                })?;
+                let fields = match variant.fields {
+                    None => return Ok(Value::String(variant.name.clone())),
+                    Some(EnumFields::Named(ref fields)) => {
+                        self.decode_fields(fields, reader, Layout::Borsh)?
+                    }
+                    Some(EnumFields::Tuple(ref tys)) => Value::Array(
+                        tys.iter()
+                            .map(|ty| self.decode_borsh(ty, reader))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                };
+                Ok(Value::Object(Map::from_iter([(
+                    variant.name.clone(),
+                    fields,
+                )])))
+            }
+        }
+    }
+
+    /// Decode `fields` into an object
+    fn decode_fields(
+        &self,
+        fields: &[IdlField],
+        reader: &mut Reader,
+        layout: Layout,
+    ) -> Result<Value, DecodeError> {
+        let mut object = Map::with_capacity(fields.len());
+        for f in fields {
+            object.insert(f.name.clone(), self.decode(&f.ty, reader, layout)?);
+        }
+        Ok(Value::Object(object))
+    }
+
+    /// Return the onchain (sbf) alignment of `ty`
+    fn align_of(&self, ty: &IdlType) -> Result<usize, DecodeError> {
+        let align = match ty {
+            IdlType::U16 | IdlType::I16 => 2,
+            IdlType::U32 | IdlType::I32 | IdlType::F32 => 4,
+            // u128 is 8 byte aligned on sbf
+            IdlType::U64
+            | IdlType::I64
+            | IdlType::F64
+            | IdlType::U128
+            | IdlType::I128
+            | IdlType::U256
+            | IdlType::I256 => 8,
+            IdlType::Array(inner, _) => self.align_of(inner)?,
+            IdlType::Defined(name) => {
+                let def = self
+                    .types
+                    .get(name)
+                    .ok_or_else(|| DecodeError::UnknownType(name.clone()))?;
+                match def.ty {
+                    IdlTypeDefinitionTy::Struct { ref fields } => {
+                        let mut align = 1;
+                        for f in fields {
+                            align = align.max(self.align_of(&f.ty)?);
+                        }
+                        align
+                    }
+                    IdlTypeDefinitionTy::Enum { .. } => 1,
+                }
+            }
+            IdlType::Option(_) | IdlType::Vec(_) | IdlType::Bytes | IdlType::String => {
+                return Err(DecodeError::InvalidValue(format!(
+                    "zero copy type has no fixed layout: {ty:?}"
+                )))
+            }
+            _ => 1,
+        };
+
+        Ok(align)
+    }
+}
+
+/// Cursor over encoded data
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Split the discriminant from `data`, returning a reader over the remaining data
+    fn split_discriminant(data: &'a [u8]) -> Option<([u8; 8], Self)> {
+        if data.len() < 8 {
+            return None;
+        }
+        let (disc, data) = data.split_at(8);
+        Some((disc.try_into().unwrap(), Self { data, offset: 0 }))
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .ok_or(DecodeError::UnexpectedEof)?;
+        let bytes = self
+            .data
+            .get(self.offset..end)
+            .ok_or(DecodeError::UnexpectedEof)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take_slice(N)?.try_into().unwrap())
+    }
+
+    /// Read a borsh `u32` length prefix
+    fn len_prefix(&mut self) -> Result<usize, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+
+    /// Skip padding up to the next multiple of `align`
+    ///
+    /// NB: the discriminant is 8 bytes so offsets are aligned as in the original account data
+    fn align(&mut self, align: usize) -> Result<(), DecodeError> {
+        let pad = (align - self.offset % align) % align;
+        self.take_slice(pad).map(|_| ())
+    }
+}
+
+/// Calculate the anchor discriminant of `name` in `namespace` e.g. 'event'
+fn discriminant(namespace: &str, name: &str) -> [u8; 8] {
+    Sha256::digest(format!("{namespace}:{name}").as_bytes())[..8]
+        .try_into()
+        .unwrap()
+}
+
+/// Convert a decoded `value` into BSON
+///
+/// Integers which do not fit an `i64` are stored as `Decimal128` (BSON has no unsigned types), which MongoDB
+/// compares and sorts as a number alongside `Int64` values of the same field
+pub fn to_bson(value: &Value) -> Bson {
+    match value {
+        Value::Null => Bson::Null,
+        Value::Bool(b) => Bson::Boolean(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Bson::Int64(i),
+            (None, Some(u)) => Bson::Decimal128(
+                Decimal128::from_str(u.to_string().as_str()).expect("u64 is a valid decimal"),
+            ),
+            _ => Bson::Double(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => Bson::String(s.clone()),
+        Value::Array(values) => Bson::Array(values.iter().map(to_bson).collect()),
+        Value::Object(fields) => Bson::Document(Document::from_iter(
+            fields.iter().map(|(k, v)| (k.clone(), to_bson(v))),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anchor_lang::{AnchorSerialize, Discriminator};
+
+    use super::*;
+    use crate::drift::accounts::{DriftAccount, PerpMarket, User};
+    use crate::test_utils::{fill, ORDER_ACTION_RECORD};
+
+    fn drift_idl() -> DynamicIdl {
+        DynamicIdl::from_json(include_str!("../../../res/drift-2.58.0.json"))
+            .expect("it loads")
+            .zero_copy(DRIFT_ZERO_COPY_ACCOUNTS)
+    }
+
+    #[test]
+    fn decode_event_matches_typed() {
+        let idl = drift_idl();
+        assert_eq!(idl.version(), "2.58.0");
+
+        let decoded = idl
+            .decode_log(ORDER_ACTION_RECORD)
+            .expect("it decodes")
+            .expect("it is an event");
+        let typed = fill();
+
+        assert_eq!(decoded.name, "OrderActionRecord");
+        let value = &decoded.value;
+        assert_eq!(value["ts"], typed.ts);
+        assert_eq!(value["marketIndex"], typed.marketIndex);
+        assert_eq!(value["action"], "Fill");
+        assert_eq!(value["fillRecordId"], typed.fillRecordId.unwrap());
+        assert_eq!(value["taker"], typed.taker.unwrap().to_string());
+        assert_eq!(value["maker"], typed.maker.unwrap().to_string());
+        assert_eq!(value["oraclePrice"], typed.oraclePrice);
+
+        let bson = decoded.to_bson();
+        let doc = bson.as_document().unwrap();
+        assert_eq!(doc.get_i64("ts").unwrap(), typed.ts);
+        assert_eq!(doc.get_str("action").unwrap(), "Fill");
+
+        assert_eq!(
+            idl.decode_log("Program log: Instruction: FillPerpOrder"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn decode_instruction() {
+        let idl = drift_idl();
+        let mut data = discriminant("global", "initialize_user").to_vec();
+        data.extend_from_slice(&1_u16.to_le_bytes());
+        data.extend_from_slice(&[7_u8; 32]);
+
+        let decoded = idl
+            .decode_instruction(data.as_slice())
+            .unwrap()
+            .expect("it is an instruction");
+        assert_eq!(decoded.name, "initializeUser");
+        assert_eq!(decoded.value["subAccountId"], 1);
+        assert_eq!(decoded.value["name"], Value::from(vec![7_u8; 32]));
+
+        assert_eq!(
+            idl.decode_instruction(&data[..12]),
+            Err(DecodeError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn decode_zero_copy_account_matches_typed() {
+        let idl = drift_idl();
+
+        // PerpMarket has implicit padding and u128 fields
+        let mut data = vec![0_u8; 1216];
+        data[..8].copy_from_slice(&PerpMarket::DISCRIMINATOR);
+        let oracle = Pubkey::new_unique();
+        data[40..72].copy_from_slice(oracle.as_ref());
+        data[256..272].copy_from_slice(&u128::MAX.to_le_bytes());
+        data[1000..1008].copy_from_slice(b"SOL-PERP");
+        data[1160..1162].copy_from_slice(&1_u16.to_le_bytes());
+        data[1162] = 1; // status
+
+        let decoded = idl
+            .decode_account(data.as_slice())
+            .unwrap()
+            .expect("it is an account");
+        assert_eq!(decoded.name, "PerpMarket");
+        assert_eq!(decoded.value["amm"]["oracle"], oracle.to_string());
+        assert_eq!(decoded.value["amm"]["sqrtK"], u128::MAX.to_string());
+        assert_eq!(decoded.value["marketIndex"], 1);
+        assert_eq!(decoded.value["status"], "Active");
+        assert_eq!(decoded.value["name"][0], b'S');
+
+        // all fields and padding of the account consumed
+        let Some((_, mut reader)) = Reader::split_discriminant(data.as_slice()) else {
+            unreachable!();
+        };
+        let def = idl.accounts.get(&PerpMarket::DISCRIMINATOR).unwrap();
+        idl.decode_definition(def, &mut reader, Layout::C).unwrap();
+        assert_eq!(reader.offset, data.len() - 8);
+
+        // User re-encoded by the typed decoder decodes the same
+        let mut user_data = User::DISCRIMINATOR.to_vec();
+        user_data.resize(4376, 0);
+        user_data[8..40].copy_from_slice(oracle.as_ref());
+        let user = DriftAccount::try_from_bytes(user_data.as_slice());
+        let Some(DriftAccount::User(user)) = user else {
+            panic!("typed decode");
+        };
+        let mut encoded = User::DISCRIMINATOR.to_vec();
+        AnchorSerialize::serialize(&user, &mut encoded).unwrap();
+        let decoded = idl.decode_account(encoded.as_slice()).unwrap().unwrap();
+        assert_eq!(decoded.value["authority"], oracle.to_string());
+    }
+
+    #[test]
+    fn to_bson_u64() {
+        assert_eq!(to_bson(&Value::from(7_u64)), Bson::Int64(7));
+        assert_eq!(
+            to_bson(&Value::from(u64::MAX)),
+            Bson::Decimal128(Decimal128::from_str("18446744073709551615").unwrap())
+        );
+    }
+
+    #[test]
+    fn snake_case() {
+        assert_eq!("placePerpOrder".to_snake_case(), "place_perp_order");
+        assert_eq!("initializeUser".to_snake_case(), "initialize_user");
+        assert_eq!("deposit".to_snake_case(), "deposit");
+    }
+}
@@ -0,0 +1,377 @@
+//! Reorg-safe confirmed indexing
+//!
+//! With `--commitment confirmed` txs are indexed once confirmed: their events are stored as raw documents marked
+//! `provisional`, the derived views are updated and the tx is queued in `pending_txs`. Pending txs are checked with
+//! `getSignatureStatuses`, finalized txs are re-indexed at finalized commitment (replacing the provisional documents,
+//! the derived views are idempotent), txs not found once their slot is finalized (skipped slot) are retracted i.e.
+//! their documents deleted and their derived views undone (see `EventRouter::retract`)
+use std::str::FromStr;
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+
+use crate::{
+    db::IndexerBackend,
+    dynamic::DynamicEvent,
+    precision::NormalizedRecord,
+    program::{EventContext, EventRouter},
+    types::{
+        drift::events::{OrderActionRecord, OrderRecord},
+        IndexerError,
+    },
+    ProgramIndexer,
+};
+
+/// Max. signatures per `getSignatureStatuses` request
+const MAX_SIGNATURE_STATUSES: usize = 256;
+
+/// A provisionally indexed tx awaiting finality
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTx {
+    /// Signature of the tx
+    pub signature: String,
+    /// Slot the tx was confirmed in
+    pub slot: u64,
+    /// The indexed accounts the tx was found for
+    pub accounts: Vec<Pubkey>,
+    /// Labels stamped onto the tx's stored events
+    pub labels: Vec<String>,
+}
+
+/// The raw documents of a provisionally indexed tx
+#[derive(Clone, Debug, Default)]
+pub struct ProvisionalTx {
+    pub order_action_records: Vec<NormalizedRecord<OrderActionRecord>>,
+    pub order_records: Vec<NormalizedRecord<OrderRecord>>,
+    /// Events stored as generic documents
+    pub events: Vec<DynamicEvent>,
+}
+
+/// The derived view entries of a retracted tx
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetractedViews {
+    /// Number of entries deleted
+    pub deleted: u64,
+    /// `(user, marketIndex)` of the folded views to rebuild
+    pub positions: Vec<(Pubkey, u16)>,
+    pub ledgers: Vec<(Pubkey, u16)>,
+    pub lp_histories: Vec<(Pubkey, u16)>,
+}
+
+impl RetractedViews {
+    /// Remove repeated views to rebuild
+    pub fn dedup(&mut self) {
+        for views in [
+            &mut self.positions,
+            &mut self.ledgers,
+            &mut self.lp_histories,
+        ] {
+            views.sort();
+            views.dedup();
+        }
+    }
+}
+
+/// Finality of a pending tx
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Finality {
+    /// Not finalized yet
+    Pending,
+    Finalized,
+    /// Not found though its slot is finalized i.e. the slot was skipped
+    Skipped,
+}
+
+impl<P: EventRouter<T>, T: IndexerBackend> ProgramIndexer<P, T> {
+    /// Return the finality of the oldest pending txs (2 RPC requests)
+    pub async fn pending_finality(&self) -> Result<Vec<(PendingTx, Finality)>, IndexerError> {
+        let pending = self.db.pending_txs(MAX_SIGNATURE_STATUSES).await?;
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+        let signatures = pending
+            .iter()
+            .map(|tx| Signature::from_str(tx.signature.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| IndexerError::InvalidSignature)?;
+        // queried first so a tx of a finalized slot not found is known to be skipped
+        let finalized_slot = self
+            .rpc
+            .get_slot_with_commitment(CommitmentConfig::finalized())
+            .await?;
+        let statuses = self
+            .rpc
+            .get_signature_statuses_with_history(signatures.as_slice())
+            .await?
+            .value;
+
+        Ok(pending
+            .into_iter()
+            .zip(statuses)
+            .map(|(tx, status)| {
+                let finality = match status {
+                    Some(status) if status.satisfies_commitment(CommitmentConfig::finalized()) => {
+                        Finality::Finalized
+                    }
+                    // confirmed, not rooted yet
+                    Some(_) => Finality::Pending,
+                    None if tx.slot <= finalized_slot => Finality::Skipped,
+                    None => Finality::Pending,
+                };
+                (tx, finality)
+            })
+            .collect())
+    }
+
+    /// Promote the pending `tx` if finalized (re-indexed at finalized commitment), or retract it if skipped
+    pub async fn finalize(&self, tx: &PendingTx, finality: Finality) -> Result<(), IndexerError> {
+        match finality {
+            Finality::Pending => return Ok(()),
+            Finality::Finalized => {
+                self.db.retract_provisional(tx.signature.as_str()).await?;
+                self.index_transaction_at(
+                    tx.accounts.as_slice(),
+                    tx.labels.as_slice(),
+                    tx.signature.as_str(),
+                    CommitmentConfig::finalized(),
+                )
+                .await?;
+                debug!("finalized tx: {}", tx.signature);
+            }
+            Finality::Skipped => {
+                let ctx = EventContext {
+                    signature: tx.signature.clone(),
+                    slot: tx.slot,
+                    log_index: 0,
+                    accounts: tx.accounts.clone(),
+                    normalizer: self.normalizer.clone(),
+                    alerts: self.alerts.clone(),
+                    instructions: None,
+                    labels: tx.labels.clone(),
+                    provisional: true,
+                    partial: false,
+                    fill_seqs: Default::default(),
+                };
+                let retracted = P::retract(self.db.as_ref(), &ctx).await?;
+                info!(
+                    "retracted {retracted} provisional event(s) of {}, slot {} skipped",
+                    tx.signature, tx.slot
+                );
+            }
+        }
+        self.db.remove_pending_tx(tx.signature.as_str()).await?;
+
+        Ok(())
+    }
+
+    /// Promote or retract the pending txs which reached finality, returns the number finalized
+    pub async fn finalize_pending(&self) -> Result<usize, IndexerError> {
+        let mut finalized = 0;
+        for (tx, finality) in self.pending_finality().await? {
+            if finality != Finality::Pending {
+                self.finalize(&tx, finality).await?;
+                finalized += 1;
+            }
+        }
+        Ok(finalized)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde_json::json;
+    use solana_rpc_client_api::request::RpcRequest;
+    use solana_sdk::{
+        message::{v0, MessageHeader, VersionedMessage},
+        transaction::VersionedTransaction,
+    };
+    use solana_transaction_status::{
+        ConfirmedTransactionWithStatusMeta, TransactionStatusMeta, TransactionWithStatusMeta,
+        UiTransactionEncoding, VersionedTransactionWithStatusMeta,
+    };
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::{ORDER_ACTION_RECORD, SIGNATURE, SLOT},
+        types::drift_pda,
+        AccountStats, Candle, DriftEventIndexer, OrderLifecycle, Position, Resolution, RpcClient,
+        TradingStats,
+    };
+
+    /// A confirmed indexer over `db` whose RPC returns a fill tx, the status `confirmation` of any tx
+    /// (`None` not found) and finalized slot `finalized_slot`
+    fn indexer(
+        db: &Arc<MockBackend>,
+        confirmation: Option<&str>,
+        finalized_slot: u64,
+    ) -> DriftEventIndexer<MockBackend> {
+        let tx = ConfirmedTransactionWithStatusMeta {
+            slot: SLOT,
+            tx_with_meta: TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+                transaction: VersionedTransaction {
+                    message: VersionedMessage::V0(v0::Message {
+                        header: MessageHeader {
+                            num_required_signatures: 1,
+                            ..Default::default()
+                        },
+                        account_keys: vec![drift_pda()],
+                        ..Default::default()
+                    }),
+                    signatures: vec![Signature::new_unique()],
+                },
+                meta: TransactionStatusMeta {
+                    log_messages: Some(vec![
+                        "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH invoke [1]"
+                            .to_string(),
+                        "Program log: Instruction: FillPerpOrder".to_string(),
+                        ORDER_ACTION_RECORD.to_string(),
+                        "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH success".to_string(),
+                    ]),
+                    ..Default::default()
+                },
+            }),
+            block_time: None,
+        }
+        .encode(UiTransactionEncoding::Base64, Some(0))
+        .expect("it encodes");
+        let status = confirmation.map(|confirmation| {
+            json!({
+                "slot": SLOT,
+                // rooted txs have no confirmation count
+                "confirmations": (confirmation != "finalized").then_some(1),
+                "status": { "Ok": null },
+                "err": null,
+                "confirmationStatus": confirmation,
+            })
+        });
+        let rpc = RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([
+                (
+                    RpcRequest::GetTransaction,
+                    serde_json::to_value(tx).expect("it serializes"),
+                ),
+                (
+                    RpcRequest::GetSignatureStatuses,
+                    json!({ "context": { "slot": finalized_slot + 32 }, "value": [status] }),
+                ),
+                (RpcRequest::GetSlot, json!(finalized_slot)),
+            ]),
+        );
+        DriftEventIndexer::new(Arc::clone(db), Arc::new(rpc))
+            .with_commitment(CommitmentConfig::confirmed())
+    }
+
+    /// The derived views of the fill tx: (account stats, maker and taker positions, orders, 1m candle)
+    async fn views(
+        db: &MockBackend,
+        maker: &Pubkey,
+        record: &OrderActionRecord,
+    ) -> (
+        Vec<AccountStats>,
+        Vec<Option<Position>>,
+        Vec<Option<OrderLifecycle>>,
+        Option<Candle>,
+    ) {
+        let taker = record.taker.unwrap();
+        (
+            db.account_stats_of(maker, None).await.unwrap(),
+            vec![
+                db.position(maker, record.marketIndex).await.unwrap(),
+                db.position(&taker, record.marketIndex).await.unwrap(),
+            ],
+            vec![
+                db.order(maker, record.makerOrderId.unwrap()).await.unwrap(),
+                db.order(&taker, record.takerOrderId.unwrap())
+                    .await
+                    .unwrap(),
+            ],
+            db.candle(
+                record.marketType,
+                record.marketIndex,
+                Resolution::OneMinute,
+                Resolution::OneMinute.start(record.ts),
+            )
+            .await
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn promote_and_retract() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let maker = Pubkey::from_str("4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV").unwrap();
+        let labels = vec!["mm".to_string()];
+
+        // confirmed: raw record and derived views, awaiting finality
+        let confirmed = indexer(&db, Some("confirmed"), SLOT - 1);
+        confirmed
+            .index_transaction(&[maker], labels.as_slice(), SIGNATURE)
+            .await
+            .unwrap();
+        let records = db.order_action_records_of(&maker, 10, false).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].provisional);
+        assert!(db
+            .order_action_records_of(&maker, 10, true)
+            .await
+            .unwrap()
+            .is_empty());
+        let record = records[0].record.clone();
+        let provisional = views(&db, &maker, &record).await;
+        assert_eq!(provisional.0.len(), 1);
+        assert!(provisional.0[0].stats.maker_fills > 0);
+        assert!(provisional.1.iter().all(Option::is_some));
+        assert!(provisional.2.iter().all(Option::is_some));
+        assert_eq!(provisional.3.as_ref().unwrap().trades, 1);
+        assert_eq!(confirmed.finalize_pending().await.unwrap(), 0);
+        assert_eq!(db.pending_txs(10).await.unwrap().len(), 1);
+
+        // finalized: promoted, derived views kept as is
+        let finalized = indexer(&db, Some("finalized"), SLOT);
+        assert_eq!(finalized.finalize_pending().await.unwrap(), 1);
+        let records = db.order_action_records_of(&maker, 10, true).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].labels, labels);
+        assert_eq!(views(&db, &maker, &record).await, provisional);
+        assert!(db.pending_txs(10).await.unwrap().is_empty());
+
+        // not found once its slot is finalized: retracted, derived views undone
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let skipped = indexer(&db, None, SLOT - 1);
+        skipped
+            .index_transaction(&[maker], labels.as_slice(), SIGNATURE)
+            .await
+            .unwrap();
+        assert_eq!(views(&db, &maker, &record).await, provisional);
+        assert_eq!(
+            skipped.pending_finality().await.unwrap()[0].1,
+            Finality::Pending
+        );
+        let skipped = indexer(&db, None, SLOT);
+        assert_eq!(skipped.finalize_pending().await.unwrap(), 1);
+        assert!(db
+            .order_action_records_of(&maker, 10, false)
+            .await
+            .unwrap()
+            .is_empty());
+        let (stats, positions, orders, candle) = views(&db, &maker, &record).await;
+        assert!(stats.iter().all(|s| s.stats == TradingStats::default()));
+        assert!(positions.iter().all(Option::is_none));
+        assert!(orders.iter().all(Option::is_none));
+        assert!(candle.is_none());
+        assert!(db.pending_txs(10).await.unwrap().is_empty());
+
+        // re-indexed e.g. landed in a later slot: applied again
+        indexer(&db, Some("confirmed"), SLOT)
+            .index_transaction(&[maker], labels.as_slice(), SIGNATURE)
+            .await
+            .unwrap();
+        assert_eq!(views(&db, &maker, &record).await, provisional);
+    }
+}
@@ -0,0 +1,332 @@
+//! Perp funding rates and payments
+//!
+//! Persists the funding rate updates of perp markets (`FundingRateRecord`) and the funding payments of
+//! users (`FundingPaymentRecord`), documents keep the event's field names so they normalize like the event
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{int_string, DbError, IndexerBackend},
+    precision::{FUNDING_RATE_PRECISION, PRICE_PRECISION},
+    program::EventContext,
+    types::drift::events::{FundingPaymentRecord, FundingRateRecord},
+};
+
+/// Length of a funding period (seconds), drift perp markets update funding hourly
+pub const FUNDING_PERIOD_S: i64 = 60 * 60;
+/// Number of funding periods per (365 day) year
+const PERIODS_PER_YEAR: f64 = (365 * 24 * 60 * 60 / FUNDING_PERIOD_S) as f64;
+
+/// A funding rate update of a perp market
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingRate {
+    pub ts: i64,
+    pub record_id: u64,
+    pub market_index: u16,
+    /// Funding per base paid by longs over the period (`FUNDING_RATE_PRECISION`)
+    pub funding_rate: i64,
+    #[serde(with = "int_string")]
+    pub funding_rate_long: i128,
+    #[serde(with = "int_string")]
+    pub funding_rate_short: i128,
+    #[serde(with = "int_string")]
+    pub cumulative_funding_rate_long: i128,
+    #[serde(with = "int_string")]
+    pub cumulative_funding_rate_short: i128,
+    pub oracle_price_twap: i64,
+    pub mark_price_twap: u64,
+    pub period_revenue: i64,
+    #[serde(with = "int_string")]
+    pub base_asset_amount_with_amm: i128,
+    #[serde(with = "int_string")]
+    pub base_asset_amount_with_unsettled_lp: i128,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+}
+
+impl FundingRate {
+    /// Return the funding rate of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &FundingRateRecord, ctx: &EventContext) -> Self {
+        Self {
+            ts: record.ts,
+            record_id: record.recordId,
+            market_index: record.marketIndex,
+            funding_rate: record.fundingRate,
+            funding_rate_long: record.fundingRateLong,
+            funding_rate_short: record.fundingRateShort,
+            cumulative_funding_rate_long: record.cumulativeFundingRateLong,
+            cumulative_funding_rate_short: record.cumulativeFundingRateShort,
+            oracle_price_twap: record.oraclePriceTwap,
+            mark_price_twap: record.markPriceTwap,
+            period_revenue: record.periodRevenue,
+            base_asset_amount_with_amm: record.baseAssetAmountWithAmm,
+            base_asset_amount_with_unsettled_lp: record.baseAssetAmountWithUnsettledLp,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+        }
+    }
+
+    /// Funding paid by longs over the period as a fraction of the oracle price (TWAP)
+    pub fn period_rate(&self) -> Option<f64> {
+        if self.oracle_price_twap <= 0 {
+            return None;
+        }
+        Some(
+            (self.funding_rate as f64 / FUNDING_RATE_PRECISION as f64)
+                / (self.oracle_price_twap as f64 / PRICE_PRECISION as f64),
+        )
+    }
+
+    /// The period rate annualized (simple, not compounded)
+    pub fn annualized_rate(&self) -> Option<f64> {
+        self.period_rate().map(|rate| rate * PERIODS_PER_YEAR)
+    }
+}
+
+/// A funding rate update and its rates as fractions
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingRateEntry {
+    #[serde(flatten)]
+    pub rate: FundingRate,
+    /// See `FundingRate::period_rate`
+    pub period_rate: Option<f64>,
+    /// See `FundingRate::annualized_rate`
+    pub annualized_rate: Option<f64>,
+}
+
+impl From<FundingRate> for FundingRateEntry {
+    fn from(rate: FundingRate) -> Self {
+        Self {
+            period_rate: rate.period_rate(),
+            annualized_rate: rate.annualized_rate(),
+            rate,
+        }
+    }
+}
+
+/// A funding payment of a user in a perp market
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingPayment {
+    pub ts: i64,
+    pub user_authority: Pubkey,
+    /// The `User` account
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Funding received, negative if paid (`QUOTE_PRECISION`)
+    pub funding_payment: i64,
+    pub base_asset_amount: i64,
+    pub user_last_cumulative_funding: i64,
+    #[serde(with = "int_string")]
+    pub amm_cumulative_funding_long: i128,
+    #[serde(with = "int_string")]
+    pub amm_cumulative_funding_short: i128,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+}
+
+impl FundingPayment {
+    /// Return the funding payment of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &FundingPaymentRecord, ctx: &EventContext) -> Self {
+        Self {
+            ts: record.ts,
+            user_authority: record.userAuthority,
+            user: record.user,
+            market_index: record.marketIndex,
+            funding_payment: record.fundingPayment,
+            base_asset_amount: record.baseAssetAmount,
+            user_last_cumulative_funding: record.userLastCumulativeFunding,
+            amm_cumulative_funding_long: record.ammCumulativeFundingLong,
+            amm_cumulative_funding_short: record.ammCumulativeFundingShort,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+        }
+    }
+}
+
+/// Funding of a user in a perp market over a window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketFunding {
+    pub market_index: u16,
+    /// Net funding received, negative if paid (`QUOTE_PRECISION`)
+    pub funding: i64,
+    /// Number of payments
+    pub payments: u32,
+}
+
+/// Cumulative funding of a user over a window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingSummary {
+    pub user: Pubkey,
+    /// Window start (unix timestamp), unbounded if unset
+    pub from: Option<i64>,
+    /// Window end (unix timestamp), unbounded if unset
+    pub to: Option<i64>,
+    /// Net funding over all markets (`QUOTE_PRECISION`)
+    pub funding: i64,
+    /// Funding by market
+    pub markets: Vec<MarketFunding>,
+}
+
+impl FundingSummary {
+    /// Sum the `payments` of `user` in the window
+    pub fn new(
+        user: Pubkey,
+        from: Option<i64>,
+        to: Option<i64>,
+        payments: &[FundingPayment],
+    ) -> Self {
+        let mut markets = BTreeMap::<u16, MarketFunding>::new();
+        for payment in payments {
+            let market = markets
+                .entry(payment.market_index)
+                .or_insert_with(|| MarketFunding {
+                    market_index: payment.market_index,
+                    funding: 0,
+                    payments: 0,
+                });
+            market.funding += payment.funding_payment;
+            market.payments += 1;
+        }
+
+        Self {
+            user,
+            from,
+            to,
+            funding: markets.values().map(|m| m.funding).sum(),
+            markets: markets.into_values().collect(),
+        }
+    }
+}
+
+/// Return the funding of `user` from `from` to `to` (inclusive), optionally of a single perp market
+pub async fn funding_summary<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: Option<u16>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<FundingSummary, DbError> {
+    let payments = db
+        .funding_payments(
+            &user,
+            market_index,
+            from.unwrap_or(i64::MIN),
+            to.unwrap_or(i64::MAX),
+        )
+        .await?;
+    Ok(FundingSummary::new(user, from, to, payments.as_slice()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::{tx_ctx, SLOT},
+    };
+
+    fn ctx(signature: &str, log_index: u32) -> EventContext {
+        tx_ctx(signature, SLOT, log_index)
+    }
+
+    #[tokio::test]
+    async fn funding_history() {
+        let db = MockBackend::init("mockdb").await;
+
+        // $0.01 per SOL-PERP over the hour at a $20 oracle TWAP
+        let record = FundingRateRecord {
+            ts: 1_700_000_000,
+            recordId: 7,
+            marketIndex: 0,
+            fundingRate: 10_000_000,
+            fundingRateLong: 10_000_000,
+            fundingRateShort: 9_000_000,
+            cumulativeFundingRateLong: 123_456_789_000_000_000_000,
+            cumulativeFundingRateShort: 123_456_789_000_000_000_000,
+            oraclePriceTwap: 20_000_000,
+            markPriceTwap: 20_010_000,
+            periodRevenue: 1_000,
+            baseAssetAmountWithAmm: -5_000_000_000,
+            baseAssetAmountWithUnsettledLp: 0,
+        };
+        let rate = FundingRate::from_record(&record, &ctx("a", 0));
+        // stored twice (indexed for two accounts)
+        db.upsert_funding_rate(rate.clone()).await.unwrap();
+        db.upsert_funding_rate(rate.clone()).await.unwrap();
+        let later = FundingRate {
+            ts: record.ts + FUNDING_PERIOD_S,
+            record_id: 8,
+            ..rate.clone()
+        };
+        db.upsert_funding_rate(later).await.unwrap();
+
+        let rates = db.funding_rates(0, i64::MIN, i64::MAX, 10).await.unwrap();
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0], rate);
+        let entry = FundingRateEntry::from(rates[0].clone());
+        assert_eq!(entry.period_rate, Some(0.0005));
+        assert!((entry.annualized_rate.unwrap() - 4.38).abs() < 1e-9);
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["cumulativeFundingRateLong"], "123456789000000000000");
+        assert_eq!(json["annualizedRate"], entry.annualized_rate.unwrap());
+        assert!(db
+            .funding_rates(0, record.ts + 1, i64::MAX, 10)
+            .await
+            .unwrap()
+            .iter()
+            .all(|r| r.record_id == 8));
+
+        let user = Pubkey::new_unique();
+        let payment = |ts: i64, market_index: u16, funding_payment: i64| FundingPaymentRecord {
+            ts,
+            userAuthority: Pubkey::new_unique(),
+            user,
+            marketIndex: market_index,
+            fundingPayment: funding_payment,
+            baseAssetAmount: 1_000_000_000,
+            userLastCumulativeFunding: 0,
+            ammCumulativeFundingLong: 0,
+            ammCumulativeFundingShort: 0,
+        };
+        let payments = [
+            (payment(100, 0, -10_000), ctx("b", 0)),
+            (payment(100, 1, 4_000), ctx("b", 1)),
+            // repeated
+            (payment(100, 1, 4_000), ctx("b", 1)),
+            (payment(200, 0, -2_500), ctx("c", 0)),
+            (payment(300, 0, 1_000), ctx("d", 0)),
+        ];
+        for (record, ctx) in payments.iter() {
+            db.upsert_funding_payment(FundingPayment::from_record(record, ctx))
+                .await
+                .unwrap();
+        }
+
+        let lifetime = funding_summary(&db, user, None, None, None).await.unwrap();
+        assert_eq!(lifetime.funding, -7_500);
+        assert_eq!(lifetime.markets.len(), 2);
+        assert_eq!(lifetime.markets[0].funding, -11_500);
+        assert_eq!(lifetime.markets[0].payments, 3);
+        assert_eq!(lifetime.markets[1].funding, 4_000);
+
+        let window = funding_summary(&db, user, Some(0), Some(150), Some(300))
+            .await
+            .unwrap();
+        assert_eq!(window.funding, -1_500);
+        assert_eq!(window.markets[0].payments, 2);
+    }
+}
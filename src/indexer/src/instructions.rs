@@ -0,0 +1,124 @@
+//! Transaction instruction lists
+//!
+//! Resolves the programs invoked by a tx (top-level and inner instructions) against its full account list
+//! i.e. the static keys and those loaded from address lookup tables
+use std::str::FromStr;
+
+use solana_sdk::{message::VersionedMessage, pubkey::Pubkey};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, UiInstruction, UiParsedInstruction,
+    UiTransactionStatusMeta,
+};
+
+/// Suffix of the log of a top-level instruction invocation e.g. 'Program <id> invoke [1]'
+const TOP_LEVEL_INVOKE: &str = " invoke [1]";
+
+/// A top-level instruction of a tx
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxInstruction {
+    /// The invoked program
+    pub program: Pubkey,
+    /// Instruction data
+    pub data: Vec<u8>,
+    /// Programs invoked by the instruction (CPI, in invocation order)
+    pub inner: Vec<Pubkey>,
+}
+
+impl TxInstruction {
+    /// Return the programs invoked by the instruction, itself first
+    pub fn programs(&self) -> impl Iterator<Item = &Pubkey> {
+        std::iter::once(&self.program).chain(self.inner.iter())
+    }
+}
+
+/// The instructions of a tx
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxInstructions {
+    /// All accounts of the tx, static keys first then loaded writable and readonly keys
+    pub account_keys: Vec<Pubkey>,
+    /// Top-level instructions (tx order)
+    pub instructions: Vec<TxInstruction>,
+    /// The top-level instruction of each log, `None` for logs outside any instruction
+    log_instructions: Vec<Option<usize>>,
+}
+
+impl TxInstructions {
+    /// Resolve the instructions of the tx `message` with status `meta` (if any)
+    pub fn new(message: &VersionedMessage, meta: Option<&UiTransactionStatusMeta>) -> Self {
+        let mut account_keys = message.static_account_keys().to_vec();
+        if let Some(OptionSerializer::Some(loaded)) = meta.map(|m| &m.loaded_addresses) {
+            account_keys.extend(
+                loaded
+                    .writable
+                    .iter()
+                    .chain(loaded.readonly.iter())
+                    .filter_map(|k| Pubkey::from_str(k).ok()),
+            );
+        }
+        let key = |index: u8| {
+            account_keys
+                .get(index as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+
+        let mut instructions: Vec<TxInstruction> = message
+            .instructions()
+            .iter()
+            .map(|ix| TxInstruction {
+                program: key(ix.program_id_index),
+                data: ix.data.clone(),
+                inner: Vec::new(),
+            })
+            .collect();
+        if let Some(OptionSerializer::Some(inner_instructions)) =
+            meta.map(|m| &m.inner_instructions)
+        {
+            for inner in inner_instructions {
+                let Some(ix) = instructions.get_mut(inner.index as usize) else {
+                    continue;
+                };
+                ix.inner
+                    .extend(inner.instructions.iter().filter_map(|ix| match ix {
+                        UiInstruction::Compiled(ix) => Some(key(ix.program_id_index)),
+                        UiInstruction::Parsed(UiParsedInstruction::Parsed(ix)) => {
+                            Pubkey::from_str(&ix.program_id).ok()
+                        }
+                        UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(ix)) => {
+                            Pubkey::from_str(&ix.program_id).ok()
+                        }
+                    }));
+            }
+        }
+
+        let mut log_instructions = Vec::new();
+        if let Some(OptionSerializer::Some(logs)) = meta.map(|m| &m.log_messages) {
+            let mut current: Option<usize> = None;
+            for log in logs {
+                if log.starts_with("Program ") && log.ends_with(TOP_LEVEL_INVOKE) {
+                    current = Some(current.map_or(0, |i| i + 1));
+                }
+                log_instructions.push(current);
+            }
+        }
+
+        Self {
+            account_keys,
+            instructions,
+            log_instructions,
+        }
+    }
+
+    /// Return whether the tx references `program` in any of its accounts
+    pub fn references(&self, program: &Pubkey) -> bool {
+        self.account_keys.contains(program)
+    }
+
+    /// Return the index of the top-level instruction emitting the log `log_index`, if known
+    pub fn instruction_of_log(&self, log_index: u32) -> Option<usize> {
+        self.log_instructions
+            .get(log_index as usize)
+            .copied()
+            .flatten()
+    }
+}
@@ -0,0 +1,444 @@
+//! Insurance fund vaults and stakes
+//!
+//! `InsuranceFundStakeRecord`s are logged per `(authority, marketIndex)` as `if_stake_events`, each with the
+//! staker's shares after it. Both IF record types update the market's insurance vault (`if_vaults`), whose
+//! timeline values shares (`shares * vault / total shares`) at any point in time, and estimates staking APY
+//! from the share price growth. NB: share rebases are not tracked
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{int_string, DbError, IndexerBackend},
+    program::{ChainOrder, EventContext},
+    snapshot::AccountSnapshot,
+    types::{
+        drift::{
+            accounts::DriftAccount,
+            events::{InsuranceFundRecord, InsuranceFundStakeRecord},
+            types::StakeAction,
+        },
+        drift_pda,
+    },
+};
+
+/// Seconds per (365 day) year
+const YEAR_S: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Return the `InsuranceFundStake` account (PDA) of `authority` in spot market `market_index`
+pub fn if_stake_pda(authority: &Pubkey, market_index: u16) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"insurance_fund_stake",
+            authority.as_ref(),
+            &market_index.to_le_bytes(),
+        ],
+        &drift_pda(),
+    )
+    .0
+}
+
+/// A stake action of an authority in a spot market's insurance fund
+///
+/// Amounts are in the market's token decimals
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IfStakeEvent {
+    pub user_authority: Pubkey,
+    pub market_index: u16,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Unix timestamp of the event
+    pub ts: i64,
+    pub action: StakeAction,
+    pub amount: u64,
+    pub insurance_vault_amount_before: u64,
+    /// The authority's shares before and after the action
+    #[serde(with = "int_string")]
+    pub if_shares_before: u128,
+    #[serde(with = "int_string")]
+    pub if_shares_after: u128,
+    #[serde(with = "int_string")]
+    pub total_if_shares_after: u128,
+    /// Value of the authority's shares after the action
+    pub value_after: u64,
+}
+
+impl IfStakeEvent {
+    /// Return the stake event of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &InsuranceFundStakeRecord, ctx: &EventContext) -> Self {
+        let vault = vault_after(record);
+        Self {
+            user_authority: record.userAuthority,
+            market_index: record.marketIndex,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            ts: record.ts,
+            action: record.action.clone(),
+            amount: record.amount,
+            insurance_vault_amount_before: record.insuranceVaultAmountBefore,
+            if_shares_before: record.ifSharesBefore,
+            if_shares_after: record.ifSharesAfter,
+            total_if_shares_after: record.totalIfSharesAfter,
+            value_after: share_value(record.ifSharesAfter, record.totalIfSharesAfter, vault),
+        }
+    }
+
+    /// Position of the event in chain order, IF records have no onchain id so `ts` orders them within a slot
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.slot,
+            seq: self.ts as u64,
+            log_index: self.log_index,
+            signature: self.signature.as_str(),
+        }
+    }
+}
+
+/// Source of an insurance vault update
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IfVaultSource {
+    /// A stake action (`InsuranceFundStakeRecord`)
+    Stake,
+    /// Revenue settled into or a deficit resolved from the vault (`InsuranceFundRecord`)
+    Revenue,
+}
+
+/// A spot market's insurance vault after an update
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IfVault {
+    pub market_index: u16,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Unix timestamp of the update
+    pub ts: i64,
+    pub source: IfVaultSource,
+    /// Signed change of the vault amount
+    pub amount: i64,
+    /// Vault amount after the update
+    pub vault_amount: u64,
+    /// Total IF shares after the update
+    #[serde(with = "int_string")]
+    pub total_if_shares: u128,
+}
+
+impl IfVault {
+    /// Return the vault update of a stake `record` emitted in the tx `ctx`
+    pub fn from_stake_record(record: &InsuranceFundStakeRecord, ctx: &EventContext) -> Self {
+        let vault_amount = vault_after(record);
+        Self {
+            market_index: record.marketIndex,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            ts: record.ts,
+            source: IfVaultSource::Stake,
+            amount: vault_amount as i64 - record.insuranceVaultAmountBefore as i64,
+            vault_amount,
+            total_if_shares: record.totalIfSharesAfter,
+        }
+    }
+
+    /// Return the vault update of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &InsuranceFundRecord, ctx: &EventContext) -> Self {
+        Self {
+            market_index: record.spotMarketIndex,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            ts: record.ts,
+            source: IfVaultSource::Revenue,
+            amount: record.amount,
+            vault_amount: record
+                .insuranceVaultAmountBefore
+                .saturating_add_signed(record.amount),
+            total_if_shares: record.totalIfSharesAfter,
+        }
+    }
+
+    /// Position of the update in chain order, see `IfStakeEvent::order_key`
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.slot,
+            seq: self.ts as u64,
+            log_index: self.log_index,
+            signature: self.signature.as_str(),
+        }
+    }
+
+    /// Value of one share in vault tokens, unset if there are no shares
+    pub fn share_price(&self) -> Option<f64> {
+        (self.total_if_shares > 0).then(|| self.vault_amount as f64 / self.total_if_shares as f64)
+    }
+}
+
+/// Vault amount after a stake `record`, only stakes and unstakes move tokens
+fn vault_after(record: &InsuranceFundStakeRecord) -> u64 {
+    match record.action {
+        StakeAction::Stake => record.insuranceVaultAmountBefore + record.amount,
+        StakeAction::Unstake => record
+            .insuranceVaultAmountBefore
+            .saturating_sub(record.amount),
+        _ => record.insuranceVaultAmountBefore,
+    }
+}
+
+/// Value of `shares` out of `total_shares` of a vault holding `vault_amount`
+fn share_value(shares: u128, total_shares: u128, vault_amount: u64) -> u64 {
+    if total_shares == 0 {
+        return 0;
+    }
+    (shares * vault_amount as u128 / total_shares) as u64
+}
+
+/// An authority's stake in a spot market's insurance fund
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StakeHistory {
+    pub user_authority: Pubkey,
+    pub market_index: u16,
+    /// The `InsuranceFundStake` account
+    pub stake_account: Pubkey,
+    /// Shares after the last stake event
+    #[serde(with = "int_string")]
+    pub if_shares: u128,
+    /// Value of the shares as of the latest vault update
+    pub value: u64,
+    /// Staked less unstaked amounts
+    pub net_staked: i64,
+    /// Stake events (chain order)
+    pub events: Vec<IfStakeEvent>,
+}
+
+/// Estimated insurance fund staking yield of a spot market over a window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IfApy {
+    pub market_index: u16,
+    /// Unix timestamps of the vault updates bounding the window
+    pub from: i64,
+    pub to: i64,
+    pub share_price_from: f64,
+    pub share_price_to: f64,
+    /// Simple annualized share price growth
+    pub apr: f64,
+    /// Compounded annualized share price growth
+    pub apy: f64,
+}
+
+/// A decoded `InsuranceFundStake` account snapshot
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IfStakeSnapshot {
+    pub pubkey: Pubkey,
+    pub slot: u64,
+    pub authority: Pubkey,
+    pub market_index: u16,
+    #[serde(with = "int_string")]
+    pub if_shares: u128,
+    #[serde(with = "int_string")]
+    pub if_base: u128,
+    #[serde(with = "int_string")]
+    pub last_withdraw_request_shares: u128,
+    pub last_withdraw_request_value: u64,
+    pub last_withdraw_request_ts: i64,
+    pub cost_basis: i64,
+    pub last_valid_ts: i64,
+}
+
+impl IfStakeSnapshot {
+    /// Decode an `InsuranceFundStake` account `snapshot`
+    pub fn decode(snapshot: &AccountSnapshot) -> Option<Self> {
+        let Some(DriftAccount::InsuranceFundStake(stake)) = snapshot.decode() else {
+            return None;
+        };
+        Some(Self {
+            pubkey: snapshot.pubkey,
+            slot: snapshot.slot,
+            authority: stake.authority,
+            market_index: stake.marketIndex,
+            if_shares: stake.ifShares,
+            if_base: stake.ifBase,
+            last_withdraw_request_shares: stake.lastWithdrawRequestShares,
+            last_withdraw_request_value: stake.lastWithdrawRequestValue,
+            last_withdraw_request_ts: stake.lastWithdrawRequestTs,
+            cost_basis: stake.costBasis,
+            last_valid_ts: stake.lastValidTs,
+        })
+    }
+}
+
+/// Log the stake event of `record` and the vault update
+pub async fn track_if_stake<T: IndexerBackend>(
+    db: &T,
+    record: &InsuranceFundStakeRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    db.upsert_if_stake_event(IfStakeEvent::from_record(record, ctx))
+        .await?;
+    db.upsert_if_vault(IfVault::from_stake_record(record, ctx))
+        .await
+}
+
+/// Return the stake history of `authority` in spot market `market_index`, valued as of the latest vault update
+pub async fn stake_history<T: IndexerBackend>(
+    db: &T,
+    authority: Pubkey,
+    market_index: u16,
+) -> Result<StakeHistory, DbError> {
+    let events = db.if_stake_events(&authority, market_index).await?;
+    let if_shares = events.last().map(|e| e.if_shares_after).unwrap_or_default();
+    let value = match db.if_vault_at(market_index, i64::MAX).await? {
+        Some(vault) => share_value(if_shares, vault.total_if_shares, vault.vault_amount),
+        None => 0,
+    };
+    let net_staked = events
+        .iter()
+        .map(|e| match e.action {
+            StakeAction::Stake => e.amount as i64,
+            StakeAction::Unstake => -(e.amount as i64),
+            _ => 0,
+        })
+        .sum();
+
+    Ok(StakeHistory {
+        user_authority: authority,
+        market_index,
+        stake_account: if_stake_pda(&authority, market_index),
+        if_shares,
+        value,
+        net_staked,
+        events,
+    })
+}
+
+/// Estimate the staking yield of spot market `market_index` from the share price growth over the last `days` as of `now`
+///
+/// Returns `None` without two priced vault updates in the window
+pub async fn if_apy<T: IndexerBackend>(
+    db: &T,
+    market_index: u16,
+    days: u32,
+    now: i64,
+) -> Result<Option<IfApy>, DbError> {
+    let since = now - days.max(1) as i64 * 24 * 60 * 60;
+    let start = match db.if_vault_at(market_index, since).await? {
+        Some(vault) => Some(vault),
+        None => db
+            .if_vaults(market_index, since, now)
+            .await?
+            .into_iter()
+            .next(),
+    };
+    let end = db.if_vault_at(market_index, now).await?;
+    let (Some(start), Some(end)) = (start, end) else {
+        return Ok(None);
+    };
+    let (Some(from), Some(to)) = (start.share_price(), end.share_price()) else {
+        return Ok(None);
+    };
+    let elapsed = (end.ts - start.ts) as f64;
+    if elapsed <= 0.0 || from <= 0.0 {
+        return Ok(None);
+    }
+    let growth = to / from;
+
+    Ok(Some(IfApy {
+        market_index,
+        from: start.ts,
+        to: end.ts,
+        share_price_from: from,
+        share_price_to: to,
+        apr: (growth - 1.0) * YEAR_S / elapsed,
+        apy: growth.powf(YEAR_S / elapsed) - 1.0,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::MockBackend, test_utils::ctx};
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    #[tokio::test]
+    async fn insurance_fund_stakes() {
+        let db = MockBackend::init("mockdb").await;
+        let authority = Pubkey::new_unique();
+        let stake = |ts: i64,
+                     action: StakeAction,
+                     amount: u64,
+                     vault_before: u64,
+                     shares: (u128, u128),
+                     total_after: u128| InsuranceFundStakeRecord {
+            ts,
+            userAuthority: authority,
+            action,
+            amount,
+            marketIndex: 0,
+            insuranceVaultAmountBefore: vault_before,
+            ifSharesBefore: shares.0,
+            userIfSharesBefore: shares.0,
+            totalIfSharesBefore: total_after - (shares.1 - shares.0),
+            ifSharesAfter: shares.1,
+            userIfSharesAfter: shares.1,
+            totalIfSharesAfter: total_after,
+        };
+        // stake 1,000 into a vault of 9,000 (9,000 shares)
+        let first = stake(0, StakeAction::Stake, 1_000, 9_000, (0, 1_000), 10_000);
+        track_if_stake(&db, &first, &ctx(1)).await.unwrap();
+        // +5% revenue after 30 days
+        let revenue = InsuranceFundRecord {
+            ts: 30 * DAY,
+            spotMarketIndex: 0,
+            perpMarketIndex: 1,
+            userIfFactor: 0,
+            totalIfFactor: 0,
+            vaultAmountBefore: 0,
+            insuranceVaultAmountBefore: 10_000,
+            totalIfSharesBefore: 10_000,
+            totalIfSharesAfter: 10_000,
+            amount: 500,
+        };
+        db.upsert_if_vault(IfVault::from_record(&revenue, &ctx(2)))
+            .await
+            .unwrap();
+        // request to unstake half of the shares
+        let request = stake(
+            31 * DAY,
+            StakeAction::UnstakeRequest,
+            525,
+            10_500,
+            (1_000, 1_000),
+            10_000,
+        );
+        track_if_stake(&db, &request, &ctx(3)).await.unwrap();
+        // repeated
+        track_if_stake(&db, &request, &ctx(3)).await.unwrap();
+
+        let history = stake_history(&db, authority, 0).await.unwrap();
+        assert_eq!(history.events.len(), 2);
+        assert_eq!(history.events[0].value_after, 1_000);
+        assert_eq!(history.events[1].value_after, 1_050);
+        assert_eq!(history.if_shares, 1_000);
+        assert_eq!(history.value, 1_050);
+        assert_eq!(history.net_staked, 1_000);
+        assert_eq!(history.stake_account, if_stake_pda(&authority, 0));
+
+        let apy = if_apy(&db, 0, 30, 30 * DAY).await.unwrap().unwrap();
+        assert_eq!((apy.from, apy.to), (0, 30 * DAY));
+        assert!((apy.share_price_to - 1.05).abs() < 1e-9);
+        assert!((apy.apr - 0.05 * 365.0 / 30.0).abs() < 1e-9);
+        assert!(apy.apy > apy.apr);
+        assert!(if_apy(&db, 1, 30, 30 * DAY).await.unwrap().is_none());
+    }
+}
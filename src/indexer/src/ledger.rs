@@ -0,0 +1,455 @@
+//! Spot deposit/withdrawal ledger
+//!
+//! `DepositRecord`s are folded per `(user, marketIndex)` in chain order into `ledger` entries carrying the
+//! running token balance. Balances are tracked interest-scaled (as drift does), so interest accrued between
+//! entries is attributed to the entry it is realized in. `SpotInterestRecord`s are kept to accrue interest
+//! on a balance after its last entry (balance-at-time).
+//!
+//! NB: the ledger starts at zero on the first indexed entry of a user
+use log::debug;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{int_string, DbError, IndexerBackend},
+    precision::{SPOT_BALANCE_PRECISION, SPOT_CUMULATIVE_INTEREST_PRECISION},
+    program::{ChainOrder, EventContext},
+    types::drift::{
+        events::{DepositRecord, SpotInterestRecord},
+        types::{DepositDirection, DepositExplanation},
+    },
+};
+
+/// Kind of a ledger entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerKind {
+    Deposit,
+    Withdrawal,
+    /// Deposit transferred from another subaccount of the authority
+    TransferIn,
+    /// Withdrawal transferred to another subaccount of the authority
+    TransferOut,
+    /// Withdrawal exceeding the deposits
+    Borrow,
+}
+
+impl LedgerKind {
+    fn new(direction: &DepositDirection, explanation: &DepositExplanation) -> Self {
+        match (direction, explanation) {
+            (DepositDirection::Deposit, DepositExplanation::Transfer) => Self::TransferIn,
+            (DepositDirection::Deposit, _) => Self::Deposit,
+            (DepositDirection::Withdraw, DepositExplanation::Transfer) => Self::TransferOut,
+            (DepositDirection::Withdraw, DepositExplanation::Borrow) => Self::Borrow,
+            (DepositDirection::Withdraw, DepositExplanation::None) => Self::Withdrawal,
+        }
+    }
+}
+
+/// A deposit or withdrawal of a user in a spot market
+///
+/// Token amounts are in the market's token decimals
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LedgerEntry {
+    /// The `User` account
+    pub user: Pubkey,
+    pub user_authority: Pubkey,
+    pub market_index: u16,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Unix timestamp of the event
+    pub ts: i64,
+    pub deposit_record_id: u64,
+    pub direction: DepositDirection,
+    pub kind: LedgerKind,
+    pub amount: u64,
+    /// The other subaccount of a transfer
+    pub transfer_user: Option<Pubkey>,
+    pub oracle_price: i64,
+    /// The market's cumulative interest at the entry (`SPOT_CUMULATIVE_INTEREST_PRECISION`)
+    #[serde(with = "int_string")]
+    pub cumulative_deposit_interest: u128,
+    #[serde(with = "int_string")]
+    pub cumulative_borrow_interest: u128,
+    /// Interest accrued on the balance since the previous entry, negative if paid on a borrow
+    pub accrued_interest: i64,
+    /// Token balance after the entry, negative if borrowed
+    pub balance: i64,
+    /// `balance` at unit cumulative interest (`SPOT_BALANCE_PRECISION`)
+    #[serde(with = "int_string")]
+    pub scaled_balance: i128,
+}
+
+impl LedgerEntry {
+    /// Return the (unapplied) entry of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &DepositRecord, ctx: &EventContext) -> Self {
+        Self {
+            user: record.user,
+            user_authority: record.userAuthority,
+            market_index: record.marketIndex,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            ts: record.ts,
+            deposit_record_id: record.depositRecordId,
+            direction: record.direction.clone(),
+            kind: LedgerKind::new(&record.direction, &record.explanation),
+            amount: record.amount,
+            transfer_user: record.transferUser,
+            oracle_price: record.oraclePrice,
+            cumulative_deposit_interest: record.marketCumulativeDepositInterest,
+            cumulative_borrow_interest: record.marketCumulativeBorrowInterest,
+            accrued_interest: 0,
+            balance: 0,
+            scaled_balance: 0,
+        }
+    }
+
+    /// Position of the entry in chain order, by `deposit_record_id` within a slot
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.slot,
+            seq: self.deposit_record_id,
+            log_index: self.log_index,
+            signature: self.signature.as_str(),
+        }
+    }
+
+    /// Signed token amount of the entry
+    pub fn delta(&self) -> i64 {
+        match self.direction {
+            DepositDirection::Deposit => self.amount as i64,
+            DepositDirection::Withdraw => -(self.amount as i64),
+        }
+    }
+
+    /// Set the balances of the entry following `previous` (if any)
+    pub fn apply(&mut self, previous: Option<&LedgerEntry>) {
+        let scaled = previous.map(|p| p.scaled_balance).unwrap_or_default();
+        let before = token_amount(
+            scaled,
+            self.cumulative_deposit_interest,
+            self.cumulative_borrow_interest,
+        );
+        self.accrued_interest = before - previous.map(|p| p.balance).unwrap_or_default();
+        self.balance = before + self.delta();
+        self.scaled_balance = scaled_amount(
+            self.balance,
+            self.cumulative_deposit_interest,
+            self.cumulative_borrow_interest,
+        );
+    }
+}
+
+/// A spot market's interest update
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotInterest {
+    pub ts: i64,
+    pub market_index: u16,
+    #[serde(with = "int_string")]
+    pub deposit_balance: u128,
+    #[serde(with = "int_string")]
+    pub cumulative_deposit_interest: u128,
+    #[serde(with = "int_string")]
+    pub borrow_balance: u128,
+    #[serde(with = "int_string")]
+    pub cumulative_borrow_interest: u128,
+    pub optimal_utilization: u32,
+    pub optimal_borrow_rate: u32,
+    pub max_borrow_rate: u32,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+}
+
+impl SpotInterest {
+    /// Return the interest update of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &SpotInterestRecord, ctx: &EventContext) -> Self {
+        Self {
+            ts: record.ts,
+            market_index: record.marketIndex,
+            deposit_balance: record.depositBalance,
+            cumulative_deposit_interest: record.cumulativeDepositInterest,
+            borrow_balance: record.borrowBalance,
+            cumulative_borrow_interest: record.cumulativeBorrowInterest,
+            optimal_utilization: record.optimalUtilization,
+            optimal_borrow_rate: record.optimalBorrowRate,
+            max_borrow_rate: record.maxBorrowRate,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+        }
+    }
+}
+
+/// A user's token balance in a spot market at a point in time
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balance {
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Unix timestamp of the balance
+    pub ts: i64,
+    /// Token balance, negative if borrowed
+    pub balance: i64,
+    /// The last ledger entry at or before `ts`
+    pub entry: LedgerEntry,
+    /// Interest update the balance was accrued to, if newer than `entry`
+    pub interest: Option<SpotInterest>,
+}
+
+/// Return the token amount of an interest-scaled balance
+fn token_amount(scaled: i128, deposit_interest: u128, borrow_interest: u128) -> i64 {
+    let interest = if scaled < 0 {
+        borrow_interest
+    } else {
+        deposit_interest
+    };
+    div_round(
+        scaled * interest as i128,
+        (SPOT_BALANCE_PRECISION * SPOT_CUMULATIVE_INTEREST_PRECISION) as i128,
+    ) as i64
+}
+
+/// Return the interest-scaled balance of a token amount
+fn scaled_amount(amount: i64, deposit_interest: u128, borrow_interest: u128) -> i128 {
+    let interest = if amount < 0 {
+        borrow_interest
+    } else {
+        deposit_interest
+    };
+    if interest == 0 {
+        return 0;
+    }
+    div_round(
+        amount as i128 * (SPOT_BALANCE_PRECISION * SPOT_CUMULATIVE_INTEREST_PRECISION) as i128,
+        interest as i128,
+    )
+}
+
+/// `n / d` rounded to the nearest integer (`d` > 0)
+fn div_round(n: i128, d: i128) -> i128 {
+    let (q, r) = (n / d, n % d);
+    if 2 * r.abs() >= d {
+        q + n.signum()
+    } else {
+        q
+    }
+}
+
+/// Add the entry of `record` to its user's ledger, rebalancing later entries if indexed out of order
+pub async fn track_deposit<T: IndexerBackend>(
+    db: &T,
+    record: &DepositRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    let entry = LedgerEntry::from_record(record, ctx);
+    let mut ledger = db.ledger(&entry.user, entry.market_index).await?;
+    if ledger.iter().any(|e| e.order_key() == entry.order_key()) {
+        debug!("ledger entry already applied: {}", ctx.signature);
+        return Ok(());
+    }
+    let at = ledger.partition_point(|e| e.order_key() < entry.order_key());
+    if at < ledger.len() {
+        debug!("rebalancing ledger {}/{}", entry.user, entry.market_index);
+    }
+    ledger.insert(at, entry);
+    for i in at..ledger.len() {
+        let (applied, rest) = ledger.split_at_mut(i);
+        rest[0].apply(applied.last());
+        db.upsert_ledger_entry(rest[0].clone()).await?;
+    }
+
+    Ok(())
+}
+
+/// Re-fold the ledger of `user` in spot market `market_index` e.g. after entries were retracted
+pub async fn refold_ledger<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: u16,
+) -> Result<(), DbError> {
+    let mut ledger = db.ledger(&user, market_index).await?;
+    for i in 0..ledger.len() {
+        let (applied, rest) = ledger.split_at_mut(i);
+        rest[0].apply(applied.last());
+        db.upsert_ledger_entry(rest[0].clone()).await?;
+    }
+
+    Ok(())
+}
+
+/// Return the balance of `user` in spot market `market_index` at unix timestamp `ts`, if it had any entry by then
+pub async fn balance_at<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: u16,
+    ts: i64,
+) -> Result<Option<Balance>, DbError> {
+    let Some(entry) = db.ledger_entry_at(&user, market_index, ts).await? else {
+        return Ok(None);
+    };
+    let interest = db
+        .spot_interest_at(market_index, ts)
+        .await?
+        .filter(|i| i.ts > entry.ts);
+    let balance = match interest {
+        Some(ref interest) => token_amount(
+            entry.scaled_balance,
+            interest.cumulative_deposit_interest,
+            interest.cumulative_borrow_interest,
+        ),
+        None => entry.balance,
+    };
+
+    Ok(Some(Balance {
+        user,
+        market_index,
+        ts,
+        balance,
+        entry,
+        interest,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::MockBackend, test_utils::ctx};
+
+    /// Cumulative interest of 1.0
+    const ONE: u128 = SPOT_CUMULATIVE_INTEREST_PRECISION;
+
+    #[tokio::test]
+    async fn deposit_ledger() {
+        let db = MockBackend::init("mockdb").await;
+        let user = Pubkey::new_unique();
+        let record = |ts: i64,
+                      direction: DepositDirection,
+                      explanation: DepositExplanation,
+                      amount: u64,
+                      deposit_interest: u128,
+                      borrow_interest: u128| DepositRecord {
+            ts,
+            userAuthority: Pubkey::new_unique(),
+            user,
+            direction,
+            depositRecordId: ts as u64,
+            amount,
+            marketIndex: 1,
+            oraclePrice: 20_000_000,
+            marketDepositBalance: 0,
+            marketWithdrawBalance: 0,
+            marketCumulativeDepositInterest: deposit_interest,
+            marketCumulativeBorrowInterest: borrow_interest,
+            totalDepositsAfter: 0,
+            totalWithdrawsAfter: 0,
+            explanation,
+            transferUser: None,
+        };
+        use DepositDirection::*;
+        use DepositExplanation as E;
+        let records = [
+            (1, record(100, Deposit, E::None, 1_000, ONE, ONE)),
+            // deposit interest +10%
+            (
+                2,
+                record(200, Deposit, E::Transfer, 500, ONE * 11 / 10, ONE),
+            ),
+            // withdraw more than deposited
+            (
+                4,
+                record(
+                    400,
+                    Withdraw,
+                    E::Borrow,
+                    2_000,
+                    ONE * 11 / 10,
+                    ONE * 12 / 10,
+                ),
+            ),
+            // indexed after a later entry
+            (
+                3,
+                record(300, Withdraw, E::None, 100, ONE * 11 / 10, ONE * 12 / 10),
+            ),
+            // repeated
+            (
+                3,
+                record(300, Withdraw, E::None, 100, ONE * 11 / 10, ONE * 12 / 10),
+            ),
+        ];
+        for (slot, record) in records.iter() {
+            track_deposit(&db, record, &ctx(*slot)).await.unwrap();
+        }
+
+        let ledger = db.ledger(&user, 1).await.unwrap();
+        assert_eq!(ledger.len(), 4);
+        assert_eq!(
+            ledger.iter().map(|e| e.kind).collect::<Vec<_>>(),
+            [
+                LedgerKind::Deposit,
+                LedgerKind::TransferIn,
+                LedgerKind::Withdrawal,
+                LedgerKind::Borrow
+            ]
+        );
+        assert_eq!(
+            ledger.iter().map(|e| e.balance).collect::<Vec<_>>(),
+            [1_000, 1_600, 1_500, -500]
+        );
+        assert_eq!(ledger[1].accrued_interest, 100);
+
+        // borrow interest +25% after the last entry
+        db.upsert_spot_interest(SpotInterest {
+            ts: 500,
+            market_index: 1,
+            deposit_balance: 0,
+            cumulative_deposit_interest: ONE * 12 / 10,
+            borrow_balance: 0,
+            cumulative_borrow_interest: ONE * 15 / 10,
+            optimal_utilization: 0,
+            optimal_borrow_rate: 0,
+            max_borrow_rate: 0,
+            slot: 5,
+            signature: "sig5".to_string(),
+        })
+        .await
+        .unwrap();
+        let balance = |ts: i64| balance_at(&db, user, 1, ts);
+        assert!(balance(50).await.unwrap().is_none());
+        assert_eq!(balance(250).await.unwrap().unwrap().balance, 1_600);
+        assert_eq!(balance(450).await.unwrap().unwrap().balance, -500);
+        let latest = balance(600).await.unwrap().unwrap();
+        assert_eq!(latest.balance, -625);
+        assert_eq!(latest.interest.unwrap().ts, 500);
+
+        // same slot, the signatures sort against the record ids
+        let mut later = record(600, Deposit, E::None, 300, ONE * 12 / 10, ONE * 15 / 10);
+        later.depositRecordId = 601;
+        let mut earlier = record(600, Withdraw, E::None, 200, ONE * 12 / 10, ONE * 15 / 10);
+        earlier.depositRecordId = 600;
+        for (record, signature) in [(&later, "a"), (&earlier, "b")] {
+            let ctx = EventContext {
+                signature: signature.to_string(),
+                ..ctx(6)
+            };
+            track_deposit(&db, record, &ctx).await.unwrap();
+        }
+
+        let ledger = db.ledger(&user, 1).await.unwrap();
+        assert_eq!(ledger.len(), 6);
+        assert_eq!(
+            ledger[4..]
+                .iter()
+                .map(|e| e.deposit_record_id)
+                .collect::<Vec<_>>(),
+            [600, 601]
+        );
+    }
+}
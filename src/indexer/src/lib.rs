@@ -1,41 +1,217 @@
 //! Drift account indexer
 //!
-//! Provides a service to poll an account's events on the drift program and persist into storage
-use std::{str::FromStr, sync::Arc, time::Duration};
+//! Provides a service to poll an account's events on the drift program (or any anchor program) and persist into storage
+use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
 
-use futures::{stream::FuturesUnordered, StreamExt};
-use log::{debug, info, warn};
+use log::{debug, warn};
 pub use solana_rpc_client::nonblocking::rpc_client::RpcClient;
 use solana_rpc_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
 use solana_rpc_client_api::{
     config::RpcTransactionConfig, response::RpcConfirmedTransactionStatusWithSignature,
 };
-use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::VersionedTransaction};
+pub use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use solana_sdk::{signature::Signature, transaction::VersionedTransaction};
 use solana_transaction_status::{option_serializer::OptionSerializer, UiTransactionEncoding};
 use tokio::select;
 
+mod alerts;
+pub use alerts::{Alert, AlertConfig, AlertTarget, Alerter, SpikeRule};
+mod analytics;
+pub use analytics::{
+    account_summary, stats_bucket, track_account_stats, untrack_account_stats, AccountStats,
+    MarketStats, StatsSummary, TradingStats, STATS_BUCKET_S,
+};
+mod api;
+pub use api::{ApiError, ApiServer, FinalityQuery, RecordsQuery};
+mod candles;
+pub use candles::{rebuild_candles, track_candles, Candle, Fill, FillKey, Resolution};
 mod db;
 pub use db::{IndexerBackend, MockBackend, MongoDbClient};
+mod discovery;
+pub use discovery::{user_pda, user_stats_pda, AuthorityDiscovery, DEFAULT_MAX_SUB_ACCOUNTS};
+mod dynamic;
+pub use dynamic::{DecodeError, Decoded, DynamicEvent, DynamicIdl, DRIFT_ZERO_COPY_ACCOUNTS};
+mod finality;
+pub use finality::{Finality, PendingTx, ProvisionalTx, RetractedViews};
+mod funding;
+pub use funding::{
+    funding_summary, FundingPayment, FundingRate, FundingRateEntry, FundingSummary, MarketFunding,
+    FUNDING_PERIOD_S,
+};
+mod insurance;
+pub use insurance::{
+    if_apy, if_stake_pda, stake_history, track_if_stake, IfApy, IfStakeEvent, IfStakeSnapshot,
+    IfVault, IfVaultSource, StakeHistory,
+};
+mod instructions;
+pub use instructions::{TxInstruction, TxInstructions};
+mod ledger;
+pub use ledger::{
+    balance_at, refold_ledger, track_deposit, Balance, LedgerEntry, LedgerKind, SpotInterest,
+};
+mod lp;
+pub use lp::{lp_position, refold_lp, track_lp, LpEvent, LpPool, LpPosition};
+mod markets;
+pub use markets::{market_fields, MarketInfo, MarketRef, MarketRegistry};
+mod onchain_idl;
+pub use onchain_idl::{
+    check_onchain_idl, decode_idl_account, fetch_onchain_idl, idl_address, Change, IdlDiff,
+    ItemDiff,
+};
+mod orders;
+pub use orders::{
+    action_orders, rebuild_order, track_order_action, track_order_record, LifecycleStatus,
+    OrderLifecycle, Side,
+};
+mod positions;
+pub use positions::{
+    position_history, rebuild_position, replay_positions, track_positions, Position, PositionEntry,
+    PositionEvent, PositionEventKind,
+};
+mod precision;
+pub use precision::{
+    field_precisions, to_decimal, NormalizedRecord, Normalizer, Precision, AMM_RESERVE_PRECISION,
+    BASE_PRECISION, FUNDING_RATE_PRECISION, PEG_PRECISION, PERCENTAGE_PRECISION, PRICE_PRECISION,
+    QUOTE_PRECISION, SPOT_BALANCE_PRECISION, SPOT_CUMULATIVE_INTEREST_PRECISION,
+};
+mod program;
+pub use program::{
+    store_generic_event, AnchorProgram, ChainOrder, EventContext, EventRouter, RuntimeProgram,
+};
+mod realtime;
+pub use realtime::{AccountDiff, TrackedUser, UserAccountMirror, UserAccountTracker};
+mod scheduler;
+pub use scheduler::{
+    AccountScheduler, PollStatus, RpcBudget, DEFAULT_BATCH_SIZE, DEFAULT_RPC_BUDGET,
+};
+mod snapshot;
+pub use snapshot::{AccountSnapshot, AccountSnapshotter};
+mod swaps;
+pub use swaps::{swap_route, track_swap, venue_name, Swap, SWAP_VENUES};
+#[cfg(test)]
+mod test_utils;
 mod types;
-pub use types::IndexerError;
 use types::*;
+pub use types::{
+    drift,
+    drift::{
+        accounts::{DriftAccount, User},
+        DriftProgram,
+    },
+    IndexerError,
+};
+mod versions;
+pub use versions::{DecodeFn, IdlVersion, IdlVersions, ParsedEvent};
+mod watchlist;
+pub use watchlist::{set_watch_status, watch, watch_if_absent, WatchStatus, WatchedAccount};
 
 /// Number of account txs to request per period
 /// should be balanced with polling interval to respect rate limits
-const MAX_TXS_PER_PERIOD: usize = 3;
+pub(crate) const MAX_TXS_PER_PERIOD: usize = 3;
 
 /// Provides indexing for onchain drift events
-pub struct DriftEventIndexer<T: IndexerBackend> {
+pub type DriftEventIndexer<T> = ProgramIndexer<DriftProgram, T>;
+
+/// Provides indexing for the onchain events of anchor program `P`
+pub struct ProgramIndexer<P: EventRouter<T>, T: IndexerBackend> {
     /// Db client
     db: Arc<T>,
+    /// ID and (IDL) name of the indexed program, `P`'s unless given at runtime
+    program: (Pubkey, String),
     /// Solana RPC client
     rpc: Arc<RpcClient>,
+    /// IDL versions to decode events with
+    idl: Arc<IdlVersions<P>>,
+    /// Runtime IDL to additionally decode and persist all events with
+    dynamic_idl: Option<Arc<DynamicIdl>>,
+    /// IDL names of events not routed as typed records, their bundled layout is stale
+    stale_events: HashSet<String>,
+    /// Converts fixed-point event fields to decimals
+    normalizer: Option<Normalizer>,
+    /// Raises liquidation alerts
+    alerts: Option<Alerter>,
+    /// Labels of the indexed account, stamped onto its stored events
+    labels: Vec<String>,
+    /// Commitment of the indexed txs
+    commitment: CommitmentConfig,
 }
 
-impl<T: IndexerBackend> DriftEventIndexer<T> {
-    /// Create a new `DriftEventIndexer`
+impl<P: EventRouter<T>, T: IndexerBackend> ProgramIndexer<P, T> {
+    /// Create a new `ProgramIndexer` decoding events with the IDL `P` was generated from
     pub fn new(db: Arc<T>, rpc: Arc<RpcClient>) -> Self {
-        Self { db, rpc }
+        Self::with_idl_versions(db, rpc, Arc::new(IdlVersions::latest_only()))
+    }
+
+    /// Create a new `ProgramIndexer` decoding events with the given IDL `versions`
+    pub fn with_idl_versions(db: Arc<T>, rpc: Arc<RpcClient>, idl: Arc<IdlVersions<P>>) -> Self {
+        Self {
+            db,
+            program: (P::program_id(), P::NAME.to_string()),
+            rpc,
+            idl,
+            dynamic_idl: None,
+            stale_events: HashSet::new(),
+            normalizer: None,
+            alerts: None,
+            labels: Vec::new(),
+            commitment: CommitmentConfig::finalized(),
+        }
+    }
+
+    /// Additionally decode all events with the runtime IDL `idl`, persisting them as generic documents
+    pub fn with_dynamic_idl(mut self, idl: Arc<DynamicIdl>) -> Self {
+        self.dynamic_idl = Some(idl);
+        self
+    }
+
+    /// Index program `program_id` (IDL name `name`) e.g. a `RuntimeProgram` decoded by the runtime IDL only
+    pub fn with_program(mut self, program_id: Pubkey, name: &str) -> Self {
+        self.program = (program_id, name.to_string());
+        self
+    }
+
+    /// Return the ID of the indexed program
+    pub fn program_id(&self) -> Pubkey {
+        self.program.0
+    }
+
+    /// Skip typed routing of `events` (IDL names) whose bundled layout differs from the onchain IDL
+    ///
+    /// They are then only stored by the runtime IDL (see `IdlDiff::stale_events`)
+    pub fn with_stale_events(mut self, events: impl IntoIterator<Item = String>) -> Self {
+        self.stale_events = events.into_iter().collect();
+        self
+    }
+
+    /// Persist decimal values of fixed-point event fields alongside the raw values
+    pub fn with_normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Raise liquidation alerts with `alerts`
+    pub fn with_alerts(mut self, alerts: Alerter) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Stamp `labels` onto the stored events
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Index txs at `commitment` (confirmed or finalized)
+    ///
+    /// Events of confirmed txs are stored provisionally until finalized, or retracted if skipped, see `finality`
+    pub fn with_commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Return whether txs are indexed provisionally i.e. before finalized
+    pub fn provisional(&self) -> bool {
+        !self.commitment.is_finalized()
     }
 
     /// Run the indexer for `account`
@@ -45,7 +221,12 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
         let mut poll = tokio::time::interval(poll_interval);
         loop {
             select! {
-                _ = poll.tick() => self.index_account_events(account).await?
+                _ = poll.tick() => {
+                    self.index_account_events(account).await?;
+                    if self.provisional() {
+                        self.finalize_pending().await?;
+                    }
+                }
             }
         }
     }
@@ -53,7 +234,10 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
     /// Index the events for `account`
     async fn index_account_events(&self, account: &Pubkey) -> Result<(), IndexerError> {
         // TODO: can use some cached value to avoid db query
-        let last_signature = self.db.last_indexed_signature(account).await?;
+        let last_signature = self
+            .db
+            .last_indexed_signature(&self.program.0, account)
+            .await?;
 
         let results = self
             .rpc
@@ -62,28 +246,56 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
                 GetConfirmedSignaturesForAddress2Config {
                     limit: Some(MAX_TXS_PER_PERIOD),
                     until: last_signature,
+                    commitment: Some(self.commitment),
                     ..Default::default()
                 },
             )
             .await?;
         debug!("latest signatures: {:?}", results);
-        let mut index_tx_futs = FuturesUnordered::from_iter(results.iter().map(
-            |RpcConfirmedTransactionStatusWithSignature { signature, .. }| {
-                self.index_transaction(account, signature.as_str())
-            },
-        ));
-
-        while let Some(res) = index_tx_futs.next().await {
-            res?;
+        // one at a time, oldest first: the derived views are read-modify-writes
+        for RpcConfirmedTransactionStatusWithSignature { signature, .. } in results.iter().rev() {
+            self.index_transaction(
+                std::slice::from_ref(account),
+                self.labels.as_slice(),
+                signature.as_str(),
+            )
+            .await?;
+        }
+        // newest first
+        if let Some(latest) = results.first() {
+            self.db
+                .update_last_indexed_signature(
+                    &self.program.0,
+                    account,
+                    &Signature::from_str(latest.signature.as_str())
+                        .map_err(|_| IndexerError::InvalidSignature)?,
+                )
+                .await?;
         }
 
         Ok(())
     }
-    /// Index events of the given transaction `signature`, provided the tx interacts with the drift program
-    async fn index_transaction(
+
+    /// Index events of the given transaction `signature`, provided the tx interacts with the program
+    /// - `accounts` the indexed accounts the tx was found for, its events are attributed to all of them
+    /// - `labels` stamped onto the stored events
+    pub(crate) async fn index_transaction(
+        &self,
+        accounts: &[Pubkey],
+        labels: &[String],
+        tx_signature: &str,
+    ) -> Result<(), IndexerError> {
+        self.index_transaction_at(accounts, labels, tx_signature, self.commitment)
+            .await
+    }
+
+    /// Index events of the given transaction `signature` at `commitment`, confirmed txs provisionally
+    async fn index_transaction_at(
         &self,
-        account: &Pubkey,
+        accounts: &[Pubkey],
+        labels: &[String],
         tx_signature: &str,
+        commitment: CommitmentConfig,
     ) -> Result<(), IndexerError> {
         let tx_data = self
             .rpc
@@ -92,21 +304,19 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
                 RpcTransactionConfig {
                     encoding: Some(UiTransactionEncoding::Base64),
                     max_supported_transaction_version: Some(0),
-                    commitment: None, // finalized by default
+                    commitment: Some(commitment),
                 },
             )
             .await?;
 
-        // only interested in txs interacting with the drift program
-        match tx_data.transaction.transaction.decode() {
+        // only interested in txs interacting with the program
+        let instructions = match tx_data.transaction.transaction.decode() {
             Some(VersionedTransaction { message, .. }) => {
-                if !message
-                    .static_account_keys()
-                    .iter()
-                    .any(|k| k == &drift_pda())
-                {
+                let instructions = TxInstructions::new(&message, tx_data.transaction.meta.as_ref());
+                if !instructions.references(&self.program.0) {
                     return Ok(());
                 }
+                instructions
             }
             None => {
                 warn!(
@@ -115,35 +325,92 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
                 );
                 return Ok(());
             }
-        }
-        debug!("drift tx: {:?}", &tx_data.transaction);
+        };
+        debug!("{} tx: {:?}", self.program.1, &tx_data.transaction);
+        let mut ctx = EventContext {
+            signature: tx_signature.to_string(),
+            slot: tx_data.slot,
+            log_index: 0,
+            accounts: accounts.to_vec(),
+            normalizer: self.normalizer.clone(),
+            alerts: self.alerts.clone(),
+            instructions: Some(Arc::new(instructions)),
+            labels: labels.to_vec(),
+            provisional: !commitment.is_finalized(),
+            partial: false,
+            fill_seqs: Default::default(),
+        };
+        // typed events are routed once the whole tx is decoded (see `EventRouter::prepare_tx`)
+        let mut parsed_events = Vec::new();
         if let Some(ref meta) = tx_data.transaction.meta {
             if let OptionSerializer::Some(ref logs) = meta.log_messages {
-                for log in logs {
-                    match try_parse_log(log.as_str()) {
-                        Ok(Some(DriftEvent::OrderActionRecord(record))) => {
-                            info!(
-                                "indexing OrderActionRecord maker={:?}, taker={:?}",
-                                record.maker, record.taker
-                            );
-                            self.db.insert_order_action_record(record).await?;
-                        }
-                        Ok(Some(DriftEvent::OrderRecord(record))) => {
-                            info!("indexing OrderRecord: {:?}", record.user);
-                            self.db.insert_order_record(record).await?;
+                for (log_index, log) in logs.iter().enumerate() {
+                    if let Some(ref dynamic_idl) = self.dynamic_idl {
+                        match dynamic_idl.decode_log(log.as_str()) {
+                            Ok(Some(event)) => {
+                                debug!("indexing dynamic event: {}", event.name);
+                                let normalized = self
+                                    .normalizer
+                                    .as_ref()
+                                    .and_then(|n| n.normalize(event.name.as_str(), &event.value));
+                                self.db
+                                    .insert_dynamic_event(DynamicEvent {
+                                        program: self.program.1.clone(),
+                                        signature: tx_signature.to_string(),
+                                        slot: tx_data.slot,
+                                        event,
+                                        normalized,
+                                        labels: labels.to_vec(),
+                                        provisional: ctx.provisional,
+                                        partial: false,
+                                    })
+                                    .await?;
+                            }
+                            Ok(None) => (),
+                            Err(err) => warn!("failed decoding event with runtime IDL: {err:?}"),
                         }
-                        Ok(Some(unhandled_event)) => {
-                            info!("got unhandled event: {:?}", unhandled_event);
+                    }
+                    match self.idl.parse_log(log.as_str(), tx_data.slot) {
+                        Ok(Some(parsed))
+                            if self.stale_events.contains(P::event_name(&parsed.event)) =>
+                        {
+                            debug!(
+                                "skipping stale typed event: {}",
+                                P::event_name(&parsed.event)
+                            )
                         }
+                        Ok(Some(parsed)) => parsed_events.push((log_index as u32, parsed)),
                         Ok(None) | Err(LogError::InvalidBase64) => (), // this is harmless, indicates log is not an IDL typed event
                     }
                 }
             }
         }
-
-        self.db
-            .update_last_indexed_signature(account, &Signature::from_str(tx_signature).unwrap())
-            .await?;
+        let events = parsed_events
+            .iter()
+            .map(|(_, parsed)| &parsed.event)
+            .collect::<Vec<_>>();
+        P::prepare_tx(events.as_slice(), &mut ctx);
+        for (log_index, ParsedEvent { event, partial }) in parsed_events {
+            if partial {
+                warn!(
+                    "event {} of {tx_signature} has trailing data, indexing its known fields",
+                    P::event_name(&event)
+                );
+            }
+            ctx.log_index = log_index;
+            ctx.partial = partial;
+            P::route(self.db.as_ref(), event, &ctx).await?
+        }
+        if ctx.provisional {
+            self.db
+                .upsert_pending_tx(PendingTx {
+                    signature: tx_signature.to_string(),
+                    slot: tx_data.slot,
+                    accounts: accounts.to_vec(),
+                    labels: labels.to_vec(),
+                })
+                .await?;
+        }
 
         Ok(())
     }
@@ -152,6 +419,10 @@ impl<T: IndexerBackend> DriftEventIndexer<T> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        drift::{events::OrderActionRecord, types::*},
+        test_utils::{ORDER_ACTION_RECORD, SIGNATURE, SLOT},
+    };
     use std::{collections::HashMap, str::FromStr};
 
     use solana_rpc_client_api::{
@@ -171,22 +442,19 @@ mod test {
         env_logger::init();
     }
 
-    #[tokio::test]
-    async fn index_account() {
-        enable_logs();
-
-        let get_signature_for_address_response: Vec<RpcConfirmedTransactionStatusWithSignature> = vec![
-            RpcConfirmedTransactionStatusWithSignature {
-                signature: "3gvGQufckXGHrFDv4dNWEXuXKRMy3NZkKHMyFrAhLoYScaXXTGCp9vq58kWkfyJ8oDYZrz4bTyGayjUy9PKigeLS".to_string(),
-                slot: 196923928_u64,
+    /// An RPC returning a fill tx of the drift program for any account
+    fn mock_rpc() -> RpcClient {
+        let get_signature_for_address_response: Vec<RpcConfirmedTransactionStatusWithSignature> =
+            vec![RpcConfirmedTransactionStatusWithSignature {
+                signature: SIGNATURE.to_string(),
+                slot: SLOT,
                 err: None,
                 memo: None,
                 block_time: None,
                 confirmation_status: None,
-            }
-        ];
+            }];
         let get_transaction_response = ConfirmedTransactionWithStatusMeta {
-            slot: 196923928_u64,
+            slot: SLOT,
             tx_with_meta: TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
                 transaction: VersionedTransaction {
                     message: VersionedMessage::V0(v0::Message {
@@ -207,7 +475,7 @@ mod test {
                         "Program ComputeBudget111111111111111111111111111111 success".to_string(),
                         "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH invoke [1]".to_string(),
                         "Program log: Instruction: FillPerpOrder".to_string(),
-                        "Program log: 4DRDR8LtbQGWwHZkAAAAAAIIAQABAVAItYsox9wC2v+AAz8WXQRRjyHZ0aSDao8VZMh+F12zAd0EAAAAAAAAAYLxCAAAAAAAAWDjFgAAAAAAAbKkeQIAAAAAAaowAAAAAAAAAY/f////////AAAAAe3FfpKhZkk9E4ZlwFSFEmXchAsvmwHVTjGQOBC+69TDAQ8hIQABAAGAhB4AAAAAAAGAhB4AAAAAAAGq2EwDAAAAAAE10NxKUa97dfc1auP2TjQAqOAgggM7dWBcCJ9gI3Fn5AGbdFQAAQEBoNcmAgAAAAABYOMWAAAAAAABsqR5AgAAAABAiupxBgAAAA==".to_string(),
+                        ORDER_ACTION_RECORD.to_string(),
                         "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH consumed 306396 of 400000 compute units".to_string(),
                         "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH success.to_string()".to_string(),
                     ]),
@@ -229,12 +497,16 @@ mod test {
                 serde_json::to_value(get_transaction_response).expect("it serializes"),
             ),
         ]);
-        let mock_rpc =
-            RpcClient::new_mock_with_mocks("http://example.com".to_string(), mock_responses);
+        RpcClient::new_mock_with_mocks("http://example.com".to_string(), mock_responses)
+    }
+
+    #[tokio::test]
+    async fn index_account() {
+        enable_logs();
 
         let indexer = DriftEventIndexer::new(
             Arc::new(MockBackend::init("mockdb").await),
-            Arc::new(mock_rpc),
+            Arc::new(mock_rpc()),
         );
         let account =
             Pubkey::from_str("BTDXiRzG1QBP7bfK4A33RcSP5mmZx8mGJ9YC5maetoD6").expect("valid pubkey");
@@ -244,8 +516,12 @@ mod test {
 
         assert!(res.is_ok());
         assert_eq!(
-            indexer.db.last_indexed_signature(&account).await.unwrap(),
-            Some(Signature::from_str("3gvGQufckXGHrFDv4dNWEXuXKRMy3NZkKHMyFrAhLoYScaXXTGCp9vq58kWkfyJ8oDYZrz4bTyGayjUy9PKigeLS").unwrap())
+            indexer
+                .db
+                .last_indexed_signature(&drift_pda(), &account)
+                .await
+                .unwrap(),
+            Some(Signature::from_str(SIGNATURE).unwrap())
         );
         assert_eq!(
             indexer.db.order_action_records().as_slice(),
@@ -287,4 +563,53 @@ mod test {
             }]
         );
     }
+
+    #[tokio::test]
+    async fn skip_stale_events() {
+        let indexer = DriftEventIndexer::new(
+            Arc::new(MockBackend::init("mockdb").await),
+            Arc::new(mock_rpc()),
+        )
+        .with_dynamic_idl(Arc::new(
+            DynamicIdl::from_json(drift::IDL_JSON).expect("valid IDL"),
+        ))
+        .with_stale_events(["OrderActionRecord".to_string()]);
+        let account =
+            Pubkey::from_str("BTDXiRzG1QBP7bfK4A33RcSP5mmZx8mGJ9YC5maetoD6").expect("valid pubkey");
+
+        indexer.index_account_events(&account).await.unwrap();
+
+        // stored by the runtime IDL only
+        assert!(indexer.db.order_action_records().is_empty());
+        assert_eq!(indexer.db.dynamic_events().len(), 1);
+        assert_eq!(
+            indexer.db.dynamic_events()[0].event.name,
+            "OrderActionRecord"
+        );
+    }
+
+    #[tokio::test]
+    async fn index_runtime_program() {
+        let idl = DynamicIdl::from_json(drift::IDL_JSON).expect("valid IDL");
+        let indexer = ProgramIndexer::<RuntimeProgram, _>::new(
+            Arc::new(MockBackend::init("mockdb").await),
+            Arc::new(mock_rpc()),
+        )
+        .with_program(drift_pda(), idl.name())
+        .with_dynamic_idl(Arc::new(idl));
+        let account =
+            Pubkey::from_str("BTDXiRzG1QBP7bfK4A33RcSP5mmZx8mGJ9YC5maetoD6").expect("valid pubkey");
+
+        indexer.index_account_events(&account).await.unwrap();
+
+        assert!(indexer.db.order_action_records().is_empty());
+        assert_eq!(indexer.db.dynamic_events().len(), 1);
+        assert_eq!(indexer.db.dynamic_events()[0].program, "drift");
+        assert!(indexer
+            .db
+            .last_indexed_signature(&drift_pda(), &account)
+            .await
+            .unwrap()
+            .is_some());
+    }
 }
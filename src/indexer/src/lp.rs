@@ -0,0 +1,312 @@
+//! Perp LP (BAL) positions
+//!
+//! `LPRecord`s are folded per `(user, marketIndex)` in chain order into `lp_events` carrying the user's LP
+//! shares and the cumulative base/quote settled and PnL after each event. Pool-level totals are read from the
+//! latest `PerpMarket` snapshot (see `--snapshot-accounts`)
+use log::debug;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{int_string, DbError, IndexerBackend},
+    program::{ChainOrder, EventContext},
+    snapshot::AccountSnapshot,
+    types::drift::{accounts::DriftAccount, events::LPRecord, types::LPAction},
+};
+
+/// An LP action of a user in a perp market
+///
+/// Shares are `AMM_RESERVE_PRECISION`, base amounts `BASE_PRECISION` and quote amounts `QUOTE_PRECISION`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LpEvent {
+    /// The `User` account
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Unix timestamp of the event
+    pub ts: i64,
+    pub action: LPAction,
+    /// Shares added/removed, or held when settling
+    pub n_shares: u64,
+    /// Base/quote settled into the user's perp position
+    pub delta_base_asset_amount: i64,
+    pub delta_quote_asset_amount: i64,
+    pub pnl: i64,
+    /// LP shares after the event
+    pub lp_shares: u64,
+    /// Cumulative base/quote settled and PnL after the event
+    pub settled_base_asset_amount: i64,
+    pub settled_quote_asset_amount: i64,
+    pub lp_pnl: i64,
+}
+
+impl LpEvent {
+    /// Return the (unapplied) event of `record` emitted in the tx `ctx`
+    pub fn from_record(record: &LPRecord, ctx: &EventContext) -> Self {
+        Self {
+            user: record.user,
+            market_index: record.marketIndex,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            ts: record.ts,
+            action: record.action.clone(),
+            n_shares: record.nShares,
+            delta_base_asset_amount: record.deltaBaseAssetAmount,
+            delta_quote_asset_amount: record.deltaQuoteAssetAmount,
+            pnl: record.pnl,
+            lp_shares: 0,
+            settled_base_asset_amount: 0,
+            settled_quote_asset_amount: 0,
+            lp_pnl: 0,
+        }
+    }
+
+    /// Position of the event in chain order, `LPRecord`s have no onchain id so `ts` orders them within a slot
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.slot,
+            seq: self.ts as u64,
+            log_index: self.log_index,
+            signature: self.signature.as_str(),
+        }
+    }
+
+    /// Set the shares and cumulative amounts of the event following `previous` (if any)
+    pub fn apply(&mut self, previous: Option<&LpEvent>) {
+        let shares = previous.map(|p| p.lp_shares).unwrap_or_default();
+        self.lp_shares = match self.action {
+            LPAction::AddLiquidity => shares + self.n_shares,
+            LPAction::RemoveLiquidity => shares.saturating_sub(self.n_shares),
+            // settling reports the shares held
+            LPAction::SettleLiquidity => self.n_shares,
+        };
+        self.settled_base_asset_amount = previous
+            .map(|p| p.settled_base_asset_amount)
+            .unwrap_or_default()
+            + self.delta_base_asset_amount;
+        self.settled_quote_asset_amount = previous
+            .map(|p| p.settled_quote_asset_amount)
+            .unwrap_or_default()
+            + self.delta_quote_asset_amount;
+        self.lp_pnl = previous.map(|p| p.lp_pnl).unwrap_or_default() + self.pnl;
+    }
+}
+
+/// LP totals of a perp market's AMM as of a `PerpMarket` snapshot
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LpPool {
+    /// The `PerpMarket` account
+    pub pubkey: Pubkey,
+    pub market_index: u16,
+    /// Slot of the snapshot
+    pub slot: u64,
+    /// Shares of all users (`AMM_RESERVE_PRECISION`)
+    #[serde(with = "int_string")]
+    pub user_lp_shares: u128,
+    /// Total shares incl. the protocol's
+    #[serde(with = "int_string")]
+    pub sqrt_k: u128,
+    /// Cumulative base/quote per LP share
+    #[serde(with = "int_string")]
+    pub base_asset_amount_per_lp: i128,
+    #[serde(with = "int_string")]
+    pub quote_asset_amount_per_lp: i128,
+    /// Base amount of the users' unsettled LP positions
+    #[serde(with = "int_string")]
+    pub base_asset_amount_with_unsettled_lp: i128,
+    /// Cumulative fees earned per LP share
+    pub total_fee_earned_per_lp: u64,
+}
+
+impl LpPool {
+    /// Decode the LP totals of a `PerpMarket` account `snapshot`
+    pub fn decode(snapshot: &AccountSnapshot) -> Option<Self> {
+        let Some(DriftAccount::PerpMarket(market)) = snapshot.decode() else {
+            return None;
+        };
+        let amm = market.amm;
+        Some(Self {
+            pubkey: snapshot.pubkey,
+            market_index: market.marketIndex,
+            slot: snapshot.slot,
+            user_lp_shares: amm.userLpShares,
+            sqrt_k: amm.sqrtK,
+            base_asset_amount_per_lp: amm.baseAssetAmountPerLp,
+            quote_asset_amount_per_lp: amm.quoteAssetAmountPerLp,
+            base_asset_amount_with_unsettled_lp: amm.baseAssetAmountWithUnsettledLp,
+            total_fee_earned_per_lp: amm.totalFeeEarnedPerLp,
+        })
+    }
+}
+
+/// A user's LP position in a perp market
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LpPosition {
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Shares, base/quote settled and PnL as of the last event
+    pub lp_shares: u64,
+    pub settled_base_asset_amount: i64,
+    pub settled_quote_asset_amount: i64,
+    pub lp_pnl: i64,
+    /// The market's LP totals, if snapshotted
+    pub pool: Option<LpPool>,
+    /// Fraction of the AMM liquidity (`sqrtK`) provided by the user
+    pub pool_share: Option<f64>,
+    /// LP events (chain order)
+    pub events: Vec<LpEvent>,
+}
+
+impl LpPosition {
+    /// Return the position of `user` from its `events` and the market's `pool`
+    pub fn new(
+        user: Pubkey,
+        market_index: u16,
+        events: Vec<LpEvent>,
+        pool: Option<LpPool>,
+    ) -> Self {
+        let last = events.last();
+        let lp_shares = last.map(|e| e.lp_shares).unwrap_or_default();
+        Self {
+            user,
+            market_index,
+            lp_shares,
+            settled_base_asset_amount: last
+                .map(|e| e.settled_base_asset_amount)
+                .unwrap_or_default(),
+            settled_quote_asset_amount: last
+                .map(|e| e.settled_quote_asset_amount)
+                .unwrap_or_default(),
+            lp_pnl: last.map(|e| e.lp_pnl).unwrap_or_default(),
+            pool_share: pool
+                .as_ref()
+                .filter(|p| p.sqrt_k > 0)
+                .map(|p| lp_shares as f64 / p.sqrt_k as f64),
+            pool,
+            events,
+        }
+    }
+}
+
+/// Add the event of `record` to its user's LP history, re-folding later events if indexed out of order
+pub async fn track_lp<T: IndexerBackend>(
+    db: &T,
+    record: &LPRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    let event = LpEvent::from_record(record, ctx);
+    let mut events = db.lp_events(&event.user, event.market_index).await?;
+    if events.iter().any(|e| e.order_key() == event.order_key()) {
+        debug!("LP event already applied: {}", ctx.signature);
+        return Ok(());
+    }
+    let at = events.partition_point(|e| e.order_key() < event.order_key());
+    if at < events.len() {
+        debug!("re-folding LP events {}/{}", event.user, event.market_index);
+    }
+    events.insert(at, event);
+    for i in at..events.len() {
+        let (applied, rest) = events.split_at_mut(i);
+        rest[0].apply(applied.last());
+        db.upsert_lp_event(rest[0].clone()).await?;
+    }
+
+    Ok(())
+}
+
+/// Re-fold the LP history of `user` in perp market `market_index` e.g. after events were retracted
+pub async fn refold_lp<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: u16,
+) -> Result<(), DbError> {
+    let mut events = db.lp_events(&user, market_index).await?;
+    for i in 0..events.len() {
+        let (applied, rest) = events.split_at_mut(i);
+        rest[0].apply(applied.last());
+        db.upsert_lp_event(rest[0].clone()).await?;
+    }
+
+    Ok(())
+}
+
+/// Return the LP position of `user` in perp market `market_index`
+/// - `market` the `PerpMarket` account, for the pool totals
+pub async fn lp_position<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: u16,
+    market: Option<Pubkey>,
+) -> Result<LpPosition, DbError> {
+    let events = db.lp_events(&user, market_index).await?;
+    let pool = match market {
+        Some(market) => db
+            .latest_account_snapshot(&market)
+            .await?
+            .as_ref()
+            .and_then(LpPool::decode),
+        None => None,
+    };
+    Ok(LpPosition::new(user, market_index, events, pool))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{db::MockBackend, test_utils::ctx};
+
+    #[tokio::test]
+    async fn lp_history() {
+        let db = MockBackend::init("mockdb").await;
+        let user = Pubkey::new_unique();
+        let record = |action: LPAction, n_shares: u64, base: i64, quote: i64, pnl: i64| LPRecord {
+            ts: 1_700_000_000,
+            user,
+            action,
+            nShares: n_shares,
+            marketIndex: 0,
+            deltaBaseAssetAmount: base,
+            deltaQuoteAssetAmount: quote,
+            pnl,
+        };
+        let records = [
+            (1, record(LPAction::AddLiquidity, 1_000, 0, 0, 0)),
+            (2, record(LPAction::SettleLiquidity, 1_000, 100, -2_000, 50)),
+            (
+                4,
+                record(LPAction::RemoveLiquidity, 1_500, -300, 6_500, 120),
+            ),
+            // indexed after a later event
+            (3, record(LPAction::AddLiquidity, 500, 0, 0, 0)),
+            // repeated
+            (3, record(LPAction::AddLiquidity, 500, 0, 0, 0)),
+        ];
+        for (slot, record) in records.iter() {
+            track_lp(&db, record, &ctx(*slot)).await.unwrap();
+        }
+
+        let position = lp_position(&db, user, 0, None).await.unwrap();
+        assert_eq!(
+            position
+                .events
+                .iter()
+                .map(|e| e.lp_shares)
+                .collect::<Vec<_>>(),
+            [1_000, 1_000, 1_500, 0]
+        );
+        assert_eq!(position.lp_shares, 0);
+        assert_eq!(position.settled_base_asset_amount, -200);
+        assert_eq!(position.settled_quote_asset_amount, 4_500);
+        assert_eq!(position.lp_pnl, 170);
+        assert!(position.pool.is_none());
+    }
+}
@@ -0,0 +1,376 @@
+//! drift market metadata
+//!
+//! Events only carry a `marketIndex` (and `marketType`), the registry maps them to the market's symbol,
+//! oracle, decimals etc. as loaded from the onchain `PerpMarket`/`SpotMarket` accounts
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anchor_lang::Discriminator;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use solana_account_decoder::UiAccountEncoding;
+use solana_rpc_client_api::{
+    config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::pubkey::Pubkey;
+use tokio::select;
+
+use crate::{
+    precision::BASE_PRECISION,
+    types::{
+        drift::{
+            accounts::{DriftAccount, PerpMarket, SpotMarket},
+            types::{MarketStatus, MarketType},
+        },
+        drift_pda, IndexerError,
+    },
+    RpcClient,
+};
+
+/// Size of a drift `PerpMarket` account (including discriminant)
+const PERP_MARKET_ACCOUNT_SIZE: u64 = 1_216;
+/// Size of a drift `SpotMarket` account (including discriminant)
+const SPOT_MARKET_ACCOUNT_SIZE: u64 = 776;
+
+/// Metadata of a drift market
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketInfo {
+    pub market_type: MarketType,
+    pub market_index: u16,
+    /// The market account address
+    pub pubkey: Pubkey,
+    /// e.g. 'BTC-PERP', 'SOL'
+    pub symbol: String,
+    pub oracle: Pubkey,
+    /// Decimals of base amounts i.e. token decimals (spot) or `BASE_PRECISION` (perp)
+    pub decimals: u32,
+    /// Min. order price increment (`PRICE_PRECISION`)
+    pub tick_size: u64,
+    /// Min. order size increment (base amount)
+    pub step_size: u64,
+    pub status: MarketStatus,
+}
+
+impl MarketInfo {
+    /// Extract the metadata of a `PerpMarket` account
+    pub fn from_perp_market(pubkey: Pubkey, market: &PerpMarket) -> Self {
+        // copy out of the packed struct
+        let amm = market.amm;
+        Self {
+            market_type: MarketType::Perp,
+            market_index: market.marketIndex,
+            pubkey,
+            symbol: decode_name(&market.name),
+            oracle: amm.oracle,
+            decimals: BASE_PRECISION.ilog10(),
+            tick_size: amm.orderTickSize,
+            step_size: amm.orderStepSize,
+            status: market.status,
+        }
+    }
+
+    /// Extract the metadata of a `SpotMarket` account
+    pub fn from_spot_market(pubkey: Pubkey, market: &SpotMarket) -> Self {
+        Self {
+            market_type: MarketType::Spot,
+            market_index: market.marketIndex,
+            pubkey,
+            symbol: decode_name(&market.name),
+            oracle: market.oracle,
+            decimals: market.decimals,
+            tick_size: market.orderTickSize,
+            step_size: market.orderStepSize,
+            status: market.status,
+        }
+    }
+}
+
+/// Where an event field's market type comes from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarketRef {
+    Perp,
+    Spot,
+    /// The `MarketType` at the given field path
+    Typed(&'static str),
+}
+
+/// Market index fields of drift events: (field path, market type)
+pub fn market_fields(event: &str) -> &'static [(&'static str, MarketRef)] {
+    use MarketRef::*;
+    match event {
+        "OrderActionRecord" | "Candle" => &[("marketIndex", Typed("marketType"))],
+        "OrderRecord" => &[("order.marketIndex", Typed("order.marketType"))],
+        "DepositRecord"
+        | "SpotInterestRecord"
+        | "InsuranceFundStakeRecord"
+        | "LedgerEntry"
+        | "Balance" => &[("marketIndex", Spot)],
+        "FundingPaymentRecord"
+        | "FundingRateRecord"
+        | "CurveRecord"
+        | "LPRecord"
+        | "SettlePnlRecord"
+        | "Position" => &[("marketIndex", Perp)],
+        "InsuranceFundRecord" => &[("spotMarketIndex", Spot), ("perpMarketIndex", Perp)],
+        "SwapRecord" => &[("inMarketIndex", Spot), ("outMarketIndex", Spot)],
+        "LiquidationRecord" => &[
+            ("liquidatePerp.marketIndex", Perp),
+            ("liquidateSpot.assetMarketIndex", Spot),
+            ("liquidateSpot.liabilityMarketIndex", Spot),
+            ("liquidateBorrowForPerpPnl.perpMarketIndex", Perp),
+            ("liquidateBorrowForPerpPnl.liabilityMarketIndex", Spot),
+            ("liquidatePerpPnlForDeposit.perpMarketIndex", Perp),
+            ("liquidatePerpPnlForDeposit.assetMarketIndex", Spot),
+            ("perpBankruptcy.marketIndex", Perp),
+            ("spotBankruptcy.marketIndex", Spot),
+        ],
+        _ => &[],
+    }
+}
+
+/// Return the decimals of base amounts of a market, spot decimals are known once the market is loaded
+pub(crate) fn base_decimals(
+    market_type: MarketType,
+    market_index: u16,
+    markets: Option<&MarketRegistry>,
+) -> Option<u32> {
+    match market_type {
+        MarketType::Perp => Some(BASE_PRECISION.ilog10()),
+        MarketType::Spot => markets?.spot_decimals(market_index),
+    }
+}
+
+/// Live registry of drift markets
+///
+/// Cheap to clone, all clones share the same underlying state
+#[derive(Clone, Debug, Default)]
+pub struct MarketRegistry {
+    /// Perp markets by market index
+    perp: Arc<RwLock<BTreeMap<u16, MarketInfo>>>,
+    /// Spot markets by market index
+    spot: Arc<RwLock<BTreeMap<u16, MarketInfo>>>,
+}
+
+impl MarketRegistry {
+    /// Create a new `MarketRegistry` with all onchain markets
+    pub async fn load(rpc: &RpcClient) -> Result<Self, IndexerError> {
+        let registry = Self::default();
+        registry.refresh(rpc).await?;
+        Ok(registry)
+    }
+
+    /// Keep the registry up to date, reloading all markets every `poll_interval`
+    pub async fn run(
+        self,
+        rpc: Arc<RpcClient>,
+        poll_interval: Duration,
+    ) -> Result<(), IndexerError> {
+        let mut poll = tokio::time::interval(poll_interval);
+        loop {
+            select! {
+                _ = poll.tick() => {
+                    if let Err(err) = self.refresh(&rpc).await {
+                        warn!("failed refreshing markets: {err:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reload all markets from their onchain accounts
+    pub async fn refresh(&self, rpc: &RpcClient) -> Result<(), IndexerError> {
+        let perp_markets = rpc
+            .get_program_accounts_with_config(
+                &drift_pda(),
+                program_accounts_config(PERP_MARKET_ACCOUNT_SIZE, PerpMarket::DISCRIMINATOR),
+            )
+            .await?;
+        let spot_markets = rpc
+            .get_program_accounts_with_config(
+                &drift_pda(),
+                program_accounts_config(SPOT_MARKET_ACCOUNT_SIZE, SpotMarket::DISCRIMINATOR),
+            )
+            .await?;
+        info!(
+            "loaded {} perp and {} spot markets",
+            perp_markets.len(),
+            spot_markets.len()
+        );
+        for (pubkey, account) in perp_markets.into_iter().chain(spot_markets) {
+            match DriftAccount::try_from_bytes(account.data.as_slice()) {
+                Some(DriftAccount::PerpMarket(market)) => {
+                    self.insert(MarketInfo::from_perp_market(pubkey, &market))
+                }
+                Some(DriftAccount::SpotMarket(market)) => {
+                    self.insert(MarketInfo::from_spot_market(pubkey, &market))
+                }
+                _ => warn!("failed decoding market account: {pubkey}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert or replace a market
+    pub fn insert(&self, market: MarketInfo) {
+        debug!("market {}: {:?}", market.symbol, market.status);
+        let markets = match market.market_type {
+            MarketType::Perp => &self.perp,
+            MarketType::Spot => &self.spot,
+        };
+        markets.write().unwrap().insert(market.market_index, market);
+    }
+
+    /// Return the market `market_index` of `market_type`, if known
+    pub fn get(&self, market_type: MarketType, market_index: u16) -> Option<MarketInfo> {
+        let markets = match market_type {
+            MarketType::Perp => &self.perp,
+            MarketType::Spot => &self.spot,
+        };
+        markets.read().unwrap().get(&market_index).cloned()
+    }
+
+    /// Return all known markets (perp then spot, by market index)
+    pub fn all(&self) -> Vec<MarketInfo> {
+        let perp = self.perp.read().unwrap();
+        let spot = self.spot.read().unwrap();
+        perp.values().chain(spot.values()).cloned().collect()
+    }
+
+    /// Return the token decimals of spot market `market_index`, if known
+    pub fn spot_decimals(&self, market_index: u16) -> Option<u32> {
+        self.spot
+            .read()
+            .unwrap()
+            .get(&market_index)
+            .map(|m| m.decimals)
+    }
+
+    /// Return the symbol of the market at `field` of `event`
+    pub fn symbol(&self, event: &Value, field: &str, market: MarketRef) -> Option<String> {
+        let market_index = lookup(event, field)?.as_u64()?;
+        let market_type = match market {
+            MarketRef::Perp => MarketType::Perp,
+            MarketRef::Spot => MarketType::Spot,
+            MarketRef::Typed(path) => serde_json::from_value(lookup(event, path)?.clone()).ok()?,
+        };
+        self.get(market_type, u16::try_from(market_index).ok()?)
+            .map(|m| m.symbol)
+    }
+}
+
+/// Return the field of `value` at the dot separated `path`
+pub(crate) fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+/// Decode a market's (space padded) name
+fn decode_name(name: &[u8; 32]) -> String {
+    String::from_utf8_lossy(name)
+        .trim_end_matches([' ', '\0'])
+        .to_string()
+}
+
+/// Build the query of all markets with account `size` and `discriminator`
+fn program_accounts_config(size: u64, discriminator: [u8; 8]) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::DataSize(size),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, discriminator.to_vec())),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use solana_account_decoder::UiAccount;
+    use solana_rpc_client_api::{request::RpcRequest, response::RpcKeyedAccount};
+    use solana_sdk::account::Account;
+
+    use super::*;
+
+    /// Offset of `name` in a `PerpMarket` account
+    const PERP_MARKET_NAME_OFFSET: usize = 1_000;
+    /// Offset of `marketIndex` in a `PerpMarket` account
+    const PERP_MARKET_INDEX_OFFSET: usize = 1_160;
+    /// Offset of `status` in a `PerpMarket` account
+    const PERP_MARKET_STATUS_OFFSET: usize = 1_162;
+    /// Offset of `amm.oracle` in a `PerpMarket` account
+    const PERP_MARKET_ORACLE_OFFSET: usize = 40;
+
+    fn perp_market_account(name: &str, market_index: u16, oracle: &Pubkey) -> Vec<u8> {
+        let mut data = vec![0_u8; PERP_MARKET_ACCOUNT_SIZE as usize];
+        data[..8].copy_from_slice(&PerpMarket::DISCRIMINATOR);
+        let mut padded = [b' '; 32];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        data[PERP_MARKET_NAME_OFFSET..PERP_MARKET_NAME_OFFSET + 32].copy_from_slice(&padded);
+        data[PERP_MARKET_INDEX_OFFSET..PERP_MARKET_INDEX_OFFSET + 2]
+            .copy_from_slice(&market_index.to_le_bytes());
+        data[PERP_MARKET_STATUS_OFFSET] = 1; // Active
+        data[PERP_MARKET_ORACLE_OFFSET..PERP_MARKET_ORACLE_OFFSET + 32]
+            .copy_from_slice(oracle.as_ref());
+        data
+    }
+
+    #[tokio::test]
+    async fn load_markets() {
+        let oracle = Pubkey::new_unique();
+        let btc_perp = Pubkey::new_unique();
+        let account = Account {
+            lamports: 1,
+            data: perp_market_account("BTC-PERP", 1, &oracle),
+            owner: drift_pda(),
+            executable: false,
+            rent_epoch: 0,
+        };
+        let keyed_accounts = vec![RpcKeyedAccount {
+            pubkey: btc_perp.to_string(),
+            account: UiAccount::encode(&btc_perp, &account, UiAccountEncoding::Base64, None, None),
+        }];
+        let mock_rpc = RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([(
+                RpcRequest::GetProgramAccounts,
+                serde_json::to_value(keyed_accounts).unwrap(),
+            )]),
+        );
+
+        let registry = MarketRegistry::load(&mock_rpc).await.unwrap();
+        // the mock returns the perp market for both (perp and spot) queries
+        assert_eq!(
+            registry.get(MarketType::Perp, 1),
+            Some(MarketInfo {
+                market_type: MarketType::Perp,
+                market_index: 1,
+                pubkey: btc_perp,
+                symbol: "BTC-PERP".to_string(),
+                oracle,
+                decimals: 9,
+                tick_size: 0,
+                step_size: 0,
+                status: MarketStatus::Active,
+            })
+        );
+        assert_eq!(registry.all().len(), 1);
+
+        let fill = json!({ "marketIndex": 1, "marketType": "Perp" });
+        assert_eq!(
+            registry.symbol(&fill, "marketIndex", MarketRef::Typed("marketType")),
+            Some("BTC-PERP".to_string())
+        );
+        assert_eq!(registry.symbol(&fill, "marketIndex", MarketRef::Spot), None);
+    }
+}
@@ -0,0 +1,510 @@
+//! Onchain anchor IDL
+//!
+//! Fetches a program's IDL account and reports how it differs from the IDL compiled in via `gen_idl_types!`
+use std::{collections::HashSet, fmt, io::Read};
+
+use anchor_syn::idl::{EnumFields, Idl, IdlType, IdlTypeDefinition, IdlTypeDefinitionTy};
+use flate2::read::ZlibDecoder;
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    dynamic::DecodeError,
+    types::{drift, drift_pda, IndexerError},
+    RpcClient,
+};
+
+/// Seed of the IDL account address
+const IDL_SEED: &str = "anchor:idl";
+/// Size of the IDL account header: discriminant, authority, data length
+const IDL_HEADER_SIZE: usize = 8 + 32 + 4;
+
+/// Derive the address of `program_id`'s IDL account
+pub fn idl_address(program_id: &Pubkey) -> Pubkey {
+    let (base, _bump) = Pubkey::find_program_address(&[], program_id);
+    Pubkey::create_with_seed(&base, IDL_SEED, program_id).expect("valid seed")
+}
+
+/// Decode the IDL from IDL account `data`
+///
+/// The account holds `authority`, followed by the zlib compressed IDL json (u32 length prefixed)
+pub fn decode_idl_account(data: &[u8]) -> Result<Idl, DecodeError> {
+    if data.len() < IDL_HEADER_SIZE {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    let disc: [u8; 8] = Sha256::digest(b"account:IdlAccount")[..8]
+        .try_into()
+        .unwrap();
+    if data[..8] != disc {
+        return Err(DecodeError::InvalidIdl("not an IDL account".to_string()));
+    }
+    let len = u32::from_le_bytes(data[40..IDL_HEADER_SIZE].try_into().unwrap()) as usize;
+    let compressed = data
+        .get(IDL_HEADER_SIZE..IDL_HEADER_SIZE + len)
+        .ok_or(DecodeError::UnexpectedEof)?;
+
+    let mut json = String::new();
+    ZlibDecoder::new(compressed)
+        .read_to_string(&mut json)
+        .map_err(|err| DecodeError::InvalidIdl(err.to_string()))?;
+    serde_json::from_str(json.as_str()).map_err(|err| DecodeError::InvalidIdl(err.to_string()))
+}
+
+/// Fetch the onchain IDL of `program_id`
+pub async fn fetch_onchain_idl(rpc: &RpcClient, program_id: &Pubkey) -> Result<Idl, IndexerError> {
+    let account = rpc.get_account(&idl_address(program_id)).await?;
+    Ok(decode_idl_account(account.data.as_slice())?)
+}
+
+/// Fetch drift's onchain IDL and diff it against the bundled (latest) IDL
+///
+/// Returns the onchain IDL and the diff
+pub async fn check_onchain_idl(rpc: &RpcClient) -> Result<(Idl, IdlDiff), IndexerError> {
+    let bundled: Idl = serde_json::from_str(drift::IDL_JSON).expect("valid bundled IDL");
+    let onchain = fetch_onchain_idl(rpc, &drift_pda()).await?;
+    let diff = IdlDiff::new(&bundled, &onchain);
+    Ok((onchain, diff))
+}
+
+/// A change to a named item of the IDL e.g. an event, field or enum variant
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The item was added with the given type
+    Added(String, String),
+    /// The item was removed
+    Removed(String),
+    /// The item's type changed (from, to)
+    Changed(String, String, String),
+    /// The fields (or variants) of the item changed
+    Modified(ItemDiff),
+}
+
+/// Changes to the fields (or variants) of an event, account or type
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemDiff {
+    /// Name of the event, account or type
+    pub name: String,
+    /// Fields added, removed or retyped
+    pub changes: Vec<Change>,
+    /// The (common) fields are in a different order
+    pub reordered: bool,
+}
+
+/// Differences between two IDLs which affect decoding
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdlDiff {
+    /// IDL versions (from, to)
+    pub versions: (String, String),
+    /// Events added, removed or changed
+    pub events: Vec<Change>,
+    /// Accounts added, removed or changed
+    pub accounts: Vec<Change>,
+    /// Types added, removed or changed
+    pub types: Vec<Change>,
+    /// Events of `from` which decode differently under `to` i.e. changed, or holding a changed type
+    pub stale_events: Vec<String>,
+}
+
+impl IdlDiff {
+    /// Diff IDL `from` against `to` e.g. bundled vs. onchain
+    pub fn new(from: &Idl, to: &Idl) -> Self {
+        let events = |idl: &Idl| -> Vec<(String, Vec<(String, String)>)> {
+            idl.events
+                .iter()
+                .flatten()
+                .map(|e| {
+                    let fields = e
+                        .fields
+                        .iter()
+                        .map(|f| (f.name.clone(), type_name(&f.ty)))
+                        .collect();
+                    (e.name.clone(), fields)
+                })
+                .collect()
+        };
+        let definitions = |defs: &[IdlTypeDefinition]| -> Vec<(String, Vec<(String, String)>)> {
+            defs.iter()
+                .map(|d| (d.name.clone(), definition_fields(d)))
+                .collect()
+        };
+
+        let mut diff = Self {
+            versions: (from.version.clone(), to.version.clone()),
+            events: diff_items(events(from), events(to)),
+            accounts: diff_items(definitions(&from.accounts), definitions(&to.accounts)),
+            types: diff_items(definitions(&from.types), definitions(&to.types)),
+            stale_events: Vec::new(),
+        };
+
+        // changed types and (transitively) the types holding them
+        let mut stale_types: HashSet<&str> = diff
+            .types
+            .iter()
+            .filter_map(|c| match c {
+                Change::Removed(name) => Some(name.as_str()),
+                Change::Modified(item) => Some(item.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        loop {
+            let stale = from
+                .types
+                .iter()
+                .filter(|d| !stale_types.contains(d.name.as_str()))
+                .filter(|d| definition_types(d).any(|ty| references(ty, &stale_types)))
+                .map(|d| d.name.as_str())
+                .collect::<Vec<_>>();
+            if stale.is_empty() {
+                break;
+            }
+            stale_types.extend(stale);
+        }
+        diff.stale_events = from
+            .events
+            .iter()
+            .flatten()
+            .filter(|e| {
+                diff.events
+                    .iter()
+                    .any(|c| matches!(c, Change::Modified(item) if item.name == e.name))
+                    || e.fields.iter().any(|f| references(&f.ty, &stale_types))
+            })
+            .map(|e| e.name.clone())
+            .collect();
+
+        diff
+    }
+
+    /// True if the IDLs decode identically
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.accounts.is_empty() && self.types.is_empty()
+    }
+}
+
+/// Diff named items and their (named) fields
+fn diff_items(
+    from: Vec<(String, Vec<(String, String)>)>,
+    to: Vec<(String, Vec<(String, String)>)>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (name, from_fields) in from.iter() {
+        match to.iter().find(|(n, _)| n == name) {
+            None => changes.push(Change::Removed(name.clone())),
+            Some((_, to_fields)) if to_fields != from_fields => {
+                let common = |a: &[(String, String)], b: &[(String, String)]| -> Vec<String> {
+                    a.iter()
+                        .filter(|(n, _)| b.iter().any(|(m, _)| m == n))
+                        .map(|(n, _)| n.clone())
+                        .collect()
+                };
+                changes.push(Change::Modified(ItemDiff {
+                    name: name.clone(),
+                    changes: diff_fields(from_fields, to_fields),
+                    reordered: common(from_fields, to_fields) != common(to_fields, from_fields),
+                }));
+            }
+            Some(_) => (),
+        }
+    }
+    for (name, fields) in to.iter() {
+        if !from.iter().any(|(n, _)| n == name) {
+            changes.push(Change::Added(
+                name.clone(),
+                format!("{} fields", fields.len()),
+            ));
+        }
+    }
+
+    changes
+}
+
+/// Diff the (named) fields of an item
+fn diff_fields(from: &[(String, String)], to: &[(String, String)]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (name, ty) in from {
+        match to.iter().find(|(n, _)| n == name) {
+            None => changes.push(Change::Removed(name.clone())),
+            Some((_, to_ty)) if to_ty != ty => {
+                changes.push(Change::Changed(name.clone(), ty.clone(), to_ty.clone()))
+            }
+            Some(_) => (),
+        }
+    }
+    for (name, ty) in to {
+        if !from.iter().any(|(n, _)| n == name) {
+            changes.push(Change::Added(name.clone(), ty.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Return the types of a type definition's fields (or variant fields)
+fn definition_types(def: &IdlTypeDefinition) -> Box<dyn Iterator<Item = &IdlType> + '_> {
+    match def.ty {
+        IdlTypeDefinitionTy::Struct { ref fields } => Box::new(fields.iter().map(|f| &f.ty)),
+        IdlTypeDefinitionTy::Enum { ref variants } => {
+            Box::new(variants.iter().flat_map(|v| match v.fields {
+                None => Vec::new(),
+                Some(EnumFields::Named(ref fields)) => fields.iter().map(|f| &f.ty).collect(),
+                Some(EnumFields::Tuple(ref tys)) => tys.iter().collect(),
+            }))
+        }
+    }
+}
+
+/// True if `ty` is, or holds, one of the defined types `names`
+fn references(ty: &IdlType, names: &HashSet<&str>) -> bool {
+    match ty {
+        IdlType::Defined(name) => names.contains(name.as_str()),
+        IdlType::Option(inner) | IdlType::Vec(inner) | IdlType::Array(inner, _) => {
+            references(inner, names)
+        }
+        _ => false,
+    }
+}
+
+/// Return the named fields (or variants) of a type definition
+fn definition_fields(def: &IdlTypeDefinition) -> Vec<(String, String)> {
+    match def.ty {
+        IdlTypeDefinitionTy::Struct { ref fields } => fields
+            .iter()
+            .map(|f| (f.name.clone(), type_name(&f.ty)))
+            .collect(),
+        IdlTypeDefinitionTy::Enum { ref variants } => variants
+            .iter()
+            .map(|v| {
+                let fields = match v.fields {
+                    None => String::new(),
+                    Some(EnumFields::Named(ref fields)) => format!(
+                        "{{ {} }}",
+                        fields
+                            .iter()
+                            .map(|f| format!("{}: {}", f.name, type_name(&f.ty)))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    Some(EnumFields::Tuple(ref tys)) => format!(
+                        "({})",
+                        tys.iter().map(type_name).collect::<Vec<_>>().join(", ")
+                    ),
+                };
+                (v.name.clone(), format!("variant{fields}"))
+            })
+            .collect(),
+    }
+}
+
+/// Return a readable name of `ty` e.g. 'Option<u64>'
+fn type_name(ty: &IdlType) -> String {
+    match ty {
+        IdlType::Defined(name) => name.clone(),
+        IdlType::Option(inner) => format!("Option<{}>", type_name(inner)),
+        IdlType::Vec(inner) => format!("Vec<{}>", type_name(inner)),
+        IdlType::Array(inner, len) => format!("[{}; {len}]", type_name(inner)),
+        IdlType::PublicKey => "Pubkey".to_string(),
+        IdlType::Bytes => "Vec<u8>".to_string(),
+        other => format!("{other:?}").to_lowercase(),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(name, ty) => write!(f, "+ {name}: {ty}"),
+            Change::Removed(name) => write!(f, "- {name}"),
+            Change::Changed(name, from, to) => write!(f, "~ {name}: {from} -> {to}"),
+            Change::Modified(item) => {
+                write!(f, "~ {}", item.name)?;
+                for (i, change) in item.changes.iter().enumerate() {
+                    write!(f, "{} {change}", if i == 0 { ":" } else { "," })?;
+                }
+                if item.reordered {
+                    write!(f, " (fields reordered)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for IdlDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (from, to) = &self.versions;
+        if self.is_empty() {
+            return writeln!(f, "IDL v{from} and v{to} match");
+        }
+        writeln!(f, "IDL v{from} -> v{to}")?;
+        for (section, changes) in [
+            ("events", &self.events),
+            ("accounts", &self.accounts),
+            ("types", &self.types),
+        ] {
+            if changes.is_empty() {
+                continue;
+            }
+            writeln!(f, "{section}:")?;
+            for change in changes {
+                let Change::Modified(item) = change else {
+                    writeln!(f, "  {change}")?;
+                    continue;
+                };
+                writeln!(f, "  ~ {}", item.name)?;
+                for field_change in &item.changes {
+                    writeln!(f, "      {field_change}")?;
+                }
+                if item.reordered {
+                    writeln!(f, "      fields reordered")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Write};
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use solana_account_decoder::{UiAccount, UiAccountEncoding};
+    use solana_rpc_client_api::{
+        request::RpcRequest,
+        response::{Response, RpcResponseContext},
+    };
+    use solana_sdk::account::Account;
+
+    use super::*;
+
+    /// Build IDL account data holding `idl`
+    fn idl_account_data(idl: &serde_json::Value) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(serde_json::to_vec(idl).unwrap().as_slice())
+            .unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut data = Sha256::digest(b"account:IdlAccount")[..8].to_vec();
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(compressed.as_slice());
+        // accounts are allocated larger than the IDL
+        data.extend_from_slice(&[0_u8; 64]);
+        data
+    }
+
+    /// The bundled IDL with some changes as made by a program upgrade
+    fn upgraded_idl() -> serde_json::Value {
+        let mut idl: serde_json::Value = serde_json::from_str(drift::IDL_JSON).unwrap();
+        idl["version"] = "2.59.0".into();
+        let events = idl["events"].as_array_mut().unwrap();
+        let swap_record = events
+            .iter()
+            .position(|e| e["name"] == "SwapRecord")
+            .unwrap();
+        events.remove(swap_record);
+        let order_record = events
+            .iter_mut()
+            .find(|e| e["name"] == "OrderRecord")
+            .unwrap();
+        order_record["fields"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({ "name": "bitFlags", "type": "u8", "index": false }));
+        let types = idl["types"].as_array_mut().unwrap();
+        let market_type = types
+            .iter_mut()
+            .find(|t| t["name"] == "MarketType")
+            .unwrap();
+        market_type["type"]["variants"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({ "name": "Prediction" }));
+        idl
+    }
+
+    #[test]
+    fn decode_and_diff_idl_account() {
+        let bundled: Idl = serde_json::from_str(drift::IDL_JSON).unwrap();
+        let onchain =
+            decode_idl_account(idl_account_data(&upgraded_idl()).as_slice()).expect("it decodes");
+        assert_eq!(onchain.version, "2.59.0");
+
+        let diff = IdlDiff::new(&bundled, &onchain);
+        assert!(!diff.is_empty());
+        assert!(diff
+            .events
+            .contains(&Change::Removed("SwapRecord".to_string())));
+        assert!(diff.events.contains(&Change::Modified(ItemDiff {
+            name: "OrderRecord".to_string(),
+            changes: vec![Change::Added("bitFlags".to_string(), "u8".to_string())],
+            reordered: false,
+        })));
+        assert_eq!(diff.events.len(), 2);
+        assert!(diff.accounts.is_empty());
+        assert_eq!(diff.types.len(), 1);
+        // changed, or holding the changed `MarketType`
+        assert_eq!(
+            diff.stale_events,
+            vec!["OrderRecord".to_string(), "OrderActionRecord".to_string()]
+        );
+
+        let report = diff.to_string();
+        assert!(report.contains("IDL v2.58.0 -> v2.59.0"));
+        assert!(report.contains("- SwapRecord"));
+        assert!(report.contains("+ bitFlags: u8"));
+        assert_eq!(diff.events[0].to_string(), "~ OrderRecord: + bitFlags: u8");
+        assert!(report.contains("+ Prediction: variant"));
+
+        assert!(IdlDiff::new(&bundled, &bundled).is_empty());
+    }
+
+    #[test]
+    fn decode_idl_account_fails() {
+        let mut data = idl_account_data(&upgraded_idl());
+        assert_eq!(
+            decode_idl_account(&data[..IDL_HEADER_SIZE + 4]),
+            Err(DecodeError::UnexpectedEof)
+        );
+        data[0] = data[0].wrapping_add(1);
+        assert!(matches!(
+            decode_idl_account(data.as_slice()),
+            Err(DecodeError::InvalidIdl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetch_idl() {
+        let account = Account {
+            lamports: 1,
+            data: idl_account_data(&upgraded_idl()),
+            owner: drift_pda(),
+            executable: false,
+            rent_epoch: 0,
+        };
+        let address = idl_address(&drift_pda());
+        let response = Response {
+            context: RpcResponseContext::new(100),
+            value: Some(UiAccount::encode(
+                &address,
+                &account,
+                UiAccountEncoding::Base64,
+                None,
+                None,
+            )),
+        };
+        let rpc = RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                serde_json::to_value(response).unwrap(),
+            )]),
+        );
+
+        let idl = fetch_onchain_idl(&rpc, &drift_pda())
+            .await
+            .expect("it fetches");
+        assert_eq!(idl.version, "2.59.0");
+    }
+}
@@ -0,0 +1,455 @@
+//! Order lifecycle reconstruction
+//!
+//! Joins the `OrderRecord` (placement) and `OrderActionRecord`s (fills, cancels etc.) of each `(user, orderId)`
+//! into the order's current state, persisted as the `orders` view
+use log::debug;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    markets::{base_decimals, MarketRegistry},
+    types::drift::{
+        events::{OrderActionRecord, OrderRecord},
+        types::{MarketType, OrderAction, OrderType, PositionDirection},
+    },
+};
+
+/// Progress of an order
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LifecycleStatus {
+    /// Placed, not filled
+    Open,
+    /// Some but not all of the order is filled
+    PartiallyFilled,
+    /// Completely filled (terminal)
+    Filled,
+    /// Canceled, possibly after partial fills (terminal)
+    Canceled,
+    /// Expired, possibly after partial fills (terminal)
+    Expired,
+}
+
+impl LifecycleStatus {
+    /// True if the order can not progress any further
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Filled | Self::Canceled | Self::Expired)
+    }
+}
+
+/// The reconstructed state of an order
+///
+/// Events may be indexed out of order e.g. a fill before its placement, fields only known from
+/// the placement are unset until it is indexed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderLifecycle {
+    /// The `User` account which placed the order
+    pub user: Pubkey,
+    pub order_id: u32,
+    pub market_index: u16,
+    pub market_type: MarketType,
+    pub status: LifecycleStatus,
+    pub direction: Option<PositionDirection>,
+    pub order_type: Option<OrderType>,
+    /// Limit price (`PRICE_PRECISION`)
+    pub price: Option<u64>,
+    /// Order size (base amount)
+    pub base_asset_amount: Option<u64>,
+    /// Cumulative filled base amount
+    pub filled_base: u64,
+    /// Cumulative filled quote amount (`QUOTE_PRECISION`)
+    pub filled_quote: u64,
+    /// Average fill price (`PRICE_PRECISION`), unset if unfilled or the market decimals are unknown
+    pub avg_fill_price: Option<u64>,
+    /// Fees paid (`QUOTE_PRECISION`), negative for net maker rebates
+    pub fees: i64,
+    /// Fill record IDs applied so far (a fill is indexed for both the maker and taker accounts)
+    pub fill_record_ids: Vec<u64>,
+    /// True if the (trigger) order was triggered
+    pub triggered: bool,
+    /// Unix timestamp of the placement
+    pub placed_ts: Option<i64>,
+    pub first_fill_ts: Option<i64>,
+    pub last_fill_ts: Option<i64>,
+    /// Unix timestamp the order reached a terminal status
+    pub closed_ts: Option<i64>,
+    /// Seconds from placement to complete fill
+    pub time_to_fill: Option<i64>,
+}
+
+/// The side of an order in an `OrderActionRecord`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Taker,
+    Maker,
+}
+
+impl OrderLifecycle {
+    /// Create a new (open) order
+    pub fn new(user: Pubkey, order_id: u32, market_index: u16, market_type: MarketType) -> Self {
+        Self {
+            user,
+            order_id,
+            market_index,
+            market_type,
+            status: LifecycleStatus::Open,
+            direction: None,
+            order_type: None,
+            price: None,
+            base_asset_amount: None,
+            filled_base: 0,
+            filled_quote: 0,
+            avg_fill_price: None,
+            fees: 0,
+            fill_record_ids: Default::default(),
+            triggered: false,
+            placed_ts: None,
+            first_fill_ts: None,
+            last_fill_ts: None,
+            closed_ts: None,
+            time_to_fill: None,
+        }
+    }
+
+    /// Apply the order's placement
+    /// - `base_decimals` decimals of the market's base amounts
+    pub fn apply_order_record(&mut self, record: &OrderRecord, base_decimals: Option<u32>) {
+        let order = &record.order;
+        self.direction = Some(order.direction);
+        self.order_type = Some(order.orderType);
+        self.price = Some(order.price);
+        self.base_asset_amount = Some(order.baseAssetAmount);
+        self.placed_ts = Some(record.ts);
+        self.filled_base = self.filled_base.max(order.baseAssetAmountFilled);
+        self.filled_quote = self.filled_quote.max(order.quoteAssetAmountFilled);
+        self.update(base_decimals);
+    }
+
+    /// Apply an action on the order, as the `side` of `record`
+    /// - `base_decimals` decimals of the market's base amounts
+    pub fn apply_action(
+        &mut self,
+        record: &OrderActionRecord,
+        side: Side,
+        base_decimals: Option<u32>,
+    ) {
+        let (direction, base_asset_amount, cumulative_base, cumulative_quote, fee) = match side {
+            Side::Taker => (
+                record.takerOrderDirection,
+                record.takerOrderBaseAssetAmount,
+                record.takerOrderCumulativeBaseAssetAmountFilled,
+                record.takerOrderCumulativeQuoteAssetAmountFilled,
+                record.takerFee.map(|f| f as i64),
+            ),
+            Side::Maker => (
+                record.makerOrderDirection,
+                record.makerOrderBaseAssetAmount,
+                record.makerOrderCumulativeBaseAssetAmountFilled,
+                record.makerOrderCumulativeQuoteAssetAmountFilled,
+                record.makerFee,
+            ),
+        };
+        if direction.is_some() {
+            self.direction = direction;
+        }
+        if base_asset_amount.is_some() {
+            self.base_asset_amount = base_asset_amount;
+        }
+
+        match record.action {
+            OrderAction::Place => {
+                self.placed_ts.get_or_insert(record.ts);
+            }
+            OrderAction::Fill => {
+                if let Some(fill_record_id) = record.fillRecordId {
+                    if self.fill_record_ids.contains(&fill_record_id) {
+                        debug!(
+                            "order {}/{}: fill {fill_record_id} already applied",
+                            self.user, self.order_id
+                        );
+                        return;
+                    }
+                    self.fill_record_ids.push(fill_record_id);
+                }
+                // cumulative amounts are order independent, fills may be indexed out of order
+                self.filled_base = self.filled_base.max(cumulative_base.unwrap_or_default());
+                self.filled_quote = self.filled_quote.max(cumulative_quote.unwrap_or_default());
+                self.fees += fee.unwrap_or_default();
+                self.first_fill_ts =
+                    Some(self.first_fill_ts.map_or(record.ts, |ts| ts.min(record.ts)));
+                self.last_fill_ts =
+                    Some(self.last_fill_ts.map_or(record.ts, |ts| ts.max(record.ts)));
+            }
+            OrderAction::Trigger => self.triggered = true,
+            OrderAction::Cancel => self.close(LifecycleStatus::Canceled, record.ts),
+            OrderAction::Expire => self.close(LifecycleStatus::Expired, record.ts),
+        }
+        self.update(base_decimals);
+    }
+
+    /// End the order with `status` at `ts`
+    fn close(&mut self, status: LifecycleStatus, ts: i64) {
+        if !self.status.is_terminal() {
+            self.status = status;
+            self.closed_ts = Some(ts);
+        }
+    }
+
+    /// Update the derived fields i.e. status, average fill price and time to fill
+    fn update(&mut self, base_decimals: Option<u32>) {
+        if self.filled_base > 0 {
+            self.avg_fill_price = base_decimals.map(|decimals| {
+                // quote and price precision are equal
+                (self.filled_quote as u128 * 10_u128.pow(decimals) / self.filled_base as u128)
+                    as u64
+            });
+        }
+        if matches!(
+            self.status,
+            LifecycleStatus::Canceled | LifecycleStatus::Expired
+        ) {
+            return;
+        }
+        self.status = match self.base_asset_amount {
+            Some(size) if size > 0 && self.filled_base >= size => LifecycleStatus::Filled,
+            _ if self.filled_base > 0 => LifecycleStatus::PartiallyFilled,
+            _ => LifecycleStatus::Open,
+        };
+        if self.status == LifecycleStatus::Filled {
+            self.closed_ts = self.last_fill_ts;
+            self.time_to_fill = self
+                .placed_ts
+                .zip(self.last_fill_ts)
+                .map(|(placed, filled)| filled - placed);
+        }
+    }
+}
+
+/// Return the orders (user, order ID, side) an `OrderActionRecord` acts on
+pub fn action_orders(record: &OrderActionRecord) -> Vec<(Pubkey, u32, Side)> {
+    let taker = record
+        .taker
+        .zip(record.takerOrderId)
+        .map(|(u, id)| (u, id, Side::Taker));
+    let maker = record
+        .maker
+        .zip(record.makerOrderId)
+        .map(|(u, id)| (u, id, Side::Maker));
+    taker.into_iter().chain(maker).collect()
+}
+
+/// Load the order or create it if unseen
+async fn load_order<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    order_id: u32,
+    market_index: u16,
+    market_type: MarketType,
+) -> Result<OrderLifecycle, DbError> {
+    Ok(db
+        .order(&user, order_id)
+        .await?
+        .unwrap_or_else(|| OrderLifecycle::new(user, order_id, market_index, market_type)))
+}
+
+/// Apply an order placement to the `orders` view
+pub async fn track_order_record<T: IndexerBackend>(
+    db: &T,
+    record: &OrderRecord,
+    markets: Option<&MarketRegistry>,
+) -> Result<(), DbError> {
+    let order = &record.order;
+    let mut lifecycle = load_order(
+        db,
+        record.user,
+        order.orderId,
+        order.marketIndex,
+        order.marketType,
+    )
+    .await?;
+    lifecycle.apply_order_record(
+        record,
+        base_decimals(order.marketType, order.marketIndex, markets),
+    );
+    db.upsert_order(lifecycle).await
+}
+
+/// Apply an order action to the `orders` view
+pub async fn track_order_action<T: IndexerBackend>(
+    db: &T,
+    record: &OrderActionRecord,
+    markets: Option<&MarketRegistry>,
+) -> Result<(), DbError> {
+    let decimals = base_decimals(record.marketType, record.marketIndex, markets);
+    for (user, order_id, side) in action_orders(record) {
+        let mut lifecycle =
+            load_order(db, user, order_id, record.marketIndex, record.marketType).await?;
+        lifecycle.apply_action(record, side, decimals);
+        db.upsert_order(lifecycle).await?;
+    }
+
+    Ok(())
+}
+
+/// Rebuild order `order_id` of `user` from its indexed records e.g. after one was retracted, removing it if none
+/// are left
+pub async fn rebuild_order<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    order_id: u32,
+    markets: Option<&MarketRegistry>,
+) -> Result<(), DbError> {
+    let placement = db.order_record(&user, order_id).await?;
+    let actions = db.order_actions(&user, order_id).await?;
+    let (market_index, market_type) = match (&placement, actions.first()) {
+        (Some(placement), _) => (
+            placement.record.order.marketIndex,
+            placement.record.order.marketType,
+        ),
+        (None, Some(action)) => (action.record.marketIndex, action.record.marketType),
+        (None, None) => return db.remove_order(&user, order_id).await,
+    };
+    let decimals = base_decimals(market_type, market_index, markets);
+
+    let mut lifecycle = OrderLifecycle::new(user, order_id, market_index, market_type);
+    if let Some(placement) = placement {
+        lifecycle.apply_order_record(&placement.record, decimals);
+    }
+    for action in actions.iter() {
+        for (_, _, side) in action_orders(&action.record)
+            .into_iter()
+            .filter(|(u, id, _)| *u == user && *id == order_id)
+        {
+            lifecycle.apply_action(&action.record, side, decimals);
+        }
+    }
+    db.upsert_order(lifecycle).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        drift::types::{Order, OrderActionExplanation, OrderStatus, OrderTriggerCondition},
+        test_utils::fill,
+    };
+
+    fn order_record(user: Pubkey, order_id: u32, base_asset_amount: u64, ts: i64) -> OrderRecord {
+        OrderRecord {
+            ts,
+            user,
+            order: Order {
+                slot: 196923900,
+                price: 27_700_000_000,
+                baseAssetAmount: base_asset_amount,
+                baseAssetAmountFilled: 0,
+                quoteAssetAmountFilled: 0,
+                triggerPrice: 0,
+                auctionStartPrice: 0,
+                auctionEndPrice: 0,
+                maxTs: 0,
+                oraclePriceOffset: 0,
+                orderId: order_id,
+                marketIndex: 1,
+                status: OrderStatus::Open,
+                orderType: OrderType::Limit,
+                marketType: MarketType::Perp,
+                userOrderId: 0,
+                existingPositionDirection: PositionDirection::Long,
+                direction: PositionDirection::Long,
+                reduceOnly: false,
+                postOnly: false,
+                immediateOrCancel: false,
+                triggerCondition: OrderTriggerCondition::Above,
+                auctionDuration: 0,
+                padding: [0; 3],
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn order_lifecycle() {
+        let db = MockBackend::init("mockdb").await;
+        let fill = fill();
+        let taker = Pubkey::from_str("H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG").unwrap();
+        let maker = Pubkey::from_str("4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV").unwrap();
+
+        // the fill is indexed before the placement, and twice (once per maker/taker account)
+        track_order_action(&db, &fill, None).await.unwrap();
+        track_order_action(&db, &fill, None).await.unwrap();
+        track_order_record(
+            &db,
+            &order_record(taker, 2171151, 2_000_000, fill.ts - 3),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let taker_order = db.order(&taker, 2171151).await.unwrap().unwrap();
+        assert_eq!(taker_order.status, LifecycleStatus::Filled);
+        assert_eq!(taker_order.filled_base, 2_000_000);
+        assert_eq!(taker_order.filled_quote, 55_367_850);
+        // $27,683.925
+        assert_eq!(taker_order.avg_fill_price, Some(27_683_925_000));
+        assert_eq!(taker_order.fees, 12458);
+        assert_eq!(taker_order.time_to_fill, Some(3));
+
+        let maker_order = db.order(&maker, 5534875).await.unwrap().unwrap();
+        assert_eq!(maker_order.status, LifecycleStatus::PartiallyFilled);
+        assert_eq!(maker_order.filled_base, 1_500_000);
+        assert_eq!(maker_order.fees, -8305);
+        assert_eq!(maker_order.direction, Some(PositionDirection::Short));
+
+        // cancel the rest of the maker order
+        let cancel = OrderActionRecord {
+            ts: fill.ts + 10,
+            action: OrderAction::Cancel,
+            actionExplanation: OrderActionExplanation::None,
+            filler: None,
+            fillerReward: None,
+            fillRecordId: None,
+            baseAssetAmountFilled: None,
+            quoteAssetAmountFilled: None,
+            takerFee: None,
+            makerFee: None,
+            referrerReward: None,
+            quoteAssetAmountSurplus: None,
+            spotFulfillmentMethodFee: None,
+            taker: Some(maker),
+            takerOrderId: Some(5534875),
+            takerOrderDirection: Some(PositionDirection::Short),
+            takerOrderBaseAssetAmount: Some(36_100_000),
+            takerOrderCumulativeBaseAssetAmountFilled: Some(1_500_000),
+            takerOrderCumulativeQuoteAssetAmountFilled: Some(41_526_450),
+            maker: None,
+            makerOrderId: None,
+            makerOrderDirection: None,
+            makerOrderBaseAssetAmount: None,
+            makerOrderCumulativeBaseAssetAmountFilled: None,
+            makerOrderCumulativeQuoteAssetAmountFilled: None,
+            ..fill
+        };
+        track_order_action(&db, &cancel, None).await.unwrap();
+        let maker_order = db.order(&maker, 5534875).await.unwrap().unwrap();
+        assert_eq!(maker_order.status, LifecycleStatus::Canceled);
+        assert_eq!(maker_order.closed_ts, Some(cancel.ts));
+        assert_eq!(maker_order.filled_base, 1_500_000);
+
+        assert_eq!(
+            db.orders_of(&maker, Some(LifecycleStatus::Canceled), 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(db
+            .orders_of(&maker, Some(LifecycleStatus::Open), 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,739 @@
+//! Perp position and PnL reconstruction
+//!
+//! Position changing events (fills, funding payments, pnl settlements and liquidations) are logged per
+//! `(user, marketIndex)` as `position_events`, the `positions` view is the log folded in chain order
+use std::collections::BTreeMap;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    precision::BASE_PRECISION,
+    program::{ChainOrder, EventContext},
+    types::drift::{
+        events::{
+            DriftEvent, FundingPaymentRecord, LiquidationRecord, OrderActionRecord, SettlePnlRecord,
+        },
+        types::{
+            LiquidationType, MarketType, OrderAction, PositionDirection, SettlePnlExplanation,
+        },
+    },
+};
+
+/// A position changing event of a user in a perp market
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionEvent {
+    /// The `User` account
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Onchain sequence of the tx in the market i.e. its first fill record ID (0 if none)
+    #[serde(default)]
+    pub seq: u64,
+    /// Unix timestamp of the event
+    pub ts: i64,
+    pub kind: PositionEventKind,
+}
+
+/// The change of a `PositionEvent`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PositionEventKind {
+    /// A fill of one of the user's orders (incl. liquidation fills)
+    #[serde(rename_all = "camelCase")]
+    Fill {
+        fill_record_id: u64,
+        /// Signed base amount filled (positive for buys)
+        base_asset_amount: i64,
+        /// Quote amount filled
+        quote_asset_amount: u64,
+        /// Fee paid, negative for maker rebates
+        fee: i64,
+    },
+    /// A funding payment, positive if received
+    #[serde(rename_all = "camelCase")]
+    Funding { funding_payment: i64 },
+    /// A pnl settlement, with the onchain position after settlement
+    #[serde(rename_all = "camelCase")]
+    SettlePnl {
+        pnl: i64,
+        base_asset_amount: i64,
+        quote_entry_amount: i64,
+        settle_price: i64,
+        expired: bool,
+    },
+    /// A perp liquidation of the user, its position change is recorded by the liquidation fill
+    #[serde(rename_all = "camelCase")]
+    Liquidation { liquidation_id: u16, if_fee: u64 },
+}
+
+impl PositionEvent {
+    /// Position in chain order, by the tx's first fill within a slot (txs without fills first) and by log index
+    /// within a tx
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.slot,
+            seq: self.seq,
+            log_index: self.log_index,
+            signature: self.signature.as_str(),
+        }
+    }
+
+    /// Return the position events of a drift `event` emitted in `ctx`
+    pub fn from_event(event: &DriftEvent, ctx: &EventContext) -> Vec<Self> {
+        let new = |user: Pubkey, market_index: u16, ts: i64, kind: PositionEventKind| Self {
+            user,
+            market_index,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            seq: ctx
+                .fill_seqs
+                .get(&market_index)
+                .copied()
+                .unwrap_or_default(),
+            ts,
+            kind,
+        };
+        match event {
+            DriftEvent::OrderActionRecord(record) => fills(record)
+                .into_iter()
+                .map(|(user, kind)| new(user, record.marketIndex, record.ts, kind))
+                .collect(),
+            DriftEvent::FundingPaymentRecord(FundingPaymentRecord {
+                ts,
+                user,
+                marketIndex,
+                fundingPayment,
+                ..
+            }) => vec![new(
+                *user,
+                *marketIndex,
+                *ts,
+                PositionEventKind::Funding {
+                    funding_payment: *fundingPayment,
+                },
+            )],
+            DriftEvent::SettlePnlRecord(SettlePnlRecord {
+                ts,
+                user,
+                marketIndex,
+                pnl,
+                baseAssetAmount,
+                quoteEntryAmount,
+                settlePrice,
+                explanation,
+                ..
+            }) => vec![new(
+                *user,
+                *marketIndex,
+                *ts,
+                PositionEventKind::SettlePnl {
+                    pnl: i64::try_from(*pnl).unwrap_or(if *pnl < 0 { i64::MIN } else { i64::MAX }),
+                    base_asset_amount: *baseAssetAmount,
+                    quote_entry_amount: *quoteEntryAmount,
+                    settle_price: *settlePrice,
+                    expired: *explanation == SettlePnlExplanation::ExpiredPosition,
+                },
+            )],
+            DriftEvent::LiquidationRecord(LiquidationRecord {
+                ts,
+                liquidationType: LiquidationType::LiquidatePerp,
+                user,
+                liquidationId,
+                liquidatePerp,
+                ..
+            }) => vec![new(
+                *user,
+                liquidatePerp.marketIndex,
+                *ts,
+                PositionEventKind::Liquidation {
+                    liquidation_id: *liquidationId,
+                    if_fee: liquidatePerp.ifFee,
+                },
+            )],
+            _ => Default::default(),
+        }
+    }
+}
+
+/// Return the perp fills (user, change) of an `OrderActionRecord`
+fn fills(record: &OrderActionRecord) -> Vec<(Pubkey, PositionEventKind)> {
+    if record.action != OrderAction::Fill || record.marketType != MarketType::Perp {
+        return Default::default();
+    }
+    let (Some(fill_record_id), Some(base), Some(quote)) = (
+        record.fillRecordId,
+        record.baseAssetAmountFilled,
+        record.quoteAssetAmountFilled,
+    ) else {
+        return Default::default();
+    };
+    let sides = [
+        (
+            record.taker,
+            record.takerOrderDirection,
+            record.takerFee.map(|f| f as i64),
+        ),
+        (record.maker, record.makerOrderDirection, record.makerFee),
+    ];
+    sides
+        .into_iter()
+        .filter_map(|(user, direction, fee)| {
+            let user = user?;
+            let Some(direction) = direction else {
+                warn!("fill {fill_record_id} of {user} has no order direction");
+                return None;
+            };
+            let base = match direction {
+                PositionDirection::Long => base as i64,
+                PositionDirection::Short => -(base as i64),
+            };
+            Some((
+                user,
+                PositionEventKind::Fill {
+                    fill_record_id,
+                    base_asset_amount: base,
+                    quote_asset_amount: quote,
+                    fee: fee.unwrap_or_default(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// A user's position and PnL in a perp market
+///
+/// All amounts are `QUOTE_PRECISION` except `base_asset_amount` (`BASE_PRECISION`)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    /// The `User` account
+    pub user: Pubkey,
+    pub market_index: u16,
+    /// Signed base amount, positive if long
+    pub base_asset_amount: i64,
+    /// Quote amount the open position was entered at, negative if long
+    pub quote_entry_amount: i64,
+    /// Average entry price (`PRICE_PRECISION`), unset if flat
+    pub entry_price: Option<u64>,
+    /// PnL of closed base amounts, excl. fees and funding
+    pub realized_pnl: i64,
+    /// Funding paid, negative if received
+    pub funding_paid: i64,
+    /// Fees paid incl. liquidation fees, negative for net maker rebates
+    pub fees: i64,
+    /// PnL settled to the quote spot balance
+    pub settled_pnl: i64,
+    /// Number of liquidations
+    pub liquidations: u32,
+    /// Unix timestamp of the last applied event
+    pub last_ts: i64,
+    /// Slot, onchain sequence, signature and log index of the last applied event
+    pub last_slot: u64,
+    #[serde(default)]
+    pub last_seq: u64,
+    pub last_signature: String,
+    pub last_log_index: u32,
+}
+
+impl Position {
+    /// Create a new (flat) position
+    pub fn new(user: Pubkey, market_index: u16) -> Self {
+        Self {
+            user,
+            market_index,
+            ..Default::default()
+        }
+    }
+
+    /// Position of the last applied event in chain order
+    pub fn order_key(&self) -> ChainOrder<'_> {
+        ChainOrder {
+            slot: self.last_slot,
+            seq: self.last_seq,
+            log_index: self.last_log_index,
+            signature: self.last_signature.as_str(),
+        }
+    }
+
+    /// Realized PnL net of fees and funding
+    pub fn net_pnl(&self) -> i64 {
+        self.realized_pnl - self.fees - self.funding_paid
+    }
+
+    /// Apply the next `event` (in chain order)
+    pub fn apply(&mut self, event: &PositionEvent) {
+        match event.kind {
+            PositionEventKind::Fill {
+                base_asset_amount,
+                quote_asset_amount,
+                fee,
+                ..
+            } => {
+                self.trade(base_asset_amount, quote_asset_amount);
+                self.fees += fee;
+            }
+            PositionEventKind::Funding { funding_payment } => self.funding_paid -= funding_payment,
+            PositionEventKind::SettlePnl {
+                pnl,
+                base_asset_amount,
+                quote_entry_amount,
+                settle_price,
+                expired,
+            } => {
+                if expired && self.base_asset_amount != 0 {
+                    // the market expired, the position is closed at the settle price
+                    let quote = self.base_asset_amount.unsigned_abs() as u128
+                        * settle_price.unsigned_abs() as u128
+                        / BASE_PRECISION;
+                    self.trade(-self.base_asset_amount, quote as u64);
+                }
+                // the onchain position is authoritative e.g. events before indexing started are missing
+                if (self.base_asset_amount, self.quote_entry_amount)
+                    != (base_asset_amount, quote_entry_amount)
+                {
+                    debug!(
+                        "position {}/{}: adopting onchain position base={base_asset_amount}, entry={quote_entry_amount}",
+                        self.user, self.market_index
+                    );
+                    self.base_asset_amount = base_asset_amount;
+                    self.quote_entry_amount = quote_entry_amount;
+                }
+                self.settled_pnl += pnl;
+            }
+            PositionEventKind::Liquidation { if_fee, .. } => {
+                self.fees += if_fee as i64;
+                self.liquidations += 1;
+            }
+        }
+        self.entry_price = match self.base_asset_amount {
+            0 => None,
+            base => Some(
+                (self.quote_entry_amount.unsigned_abs() as u128 * BASE_PRECISION
+                    / base.unsigned_abs() as u128) as u64,
+            ),
+        };
+        self.last_ts = event.ts;
+        self.last_slot = event.slot;
+        self.last_seq = event.seq;
+        self.last_signature = event.signature.clone();
+        self.last_log_index = event.log_index;
+    }
+
+    /// Trade `base` (signed, positive for buys) for `quote`, realizing the PnL of any reduced base amount
+    fn trade(&mut self, base: i64, quote: u64) {
+        if base == 0 {
+            return;
+        }
+        let sign = base.signum();
+        let (base_abs, quote) = (base.unsigned_abs() as i128, quote as i128);
+        if self.base_asset_amount == 0 || self.base_asset_amount.signum() == sign {
+            // increase (buys pay quote)
+            self.base_asset_amount += base;
+            self.quote_entry_amount -= (sign as i128 * quote) as i64;
+            return;
+        }
+
+        // reduce and possibly flip
+        let position_abs = self.base_asset_amount.unsigned_abs() as i128;
+        let closed = base_abs.min(position_abs);
+        let entry_closed = self.quote_entry_amount as i128 * closed / position_abs;
+        let quote_closed = quote * closed / base_abs;
+        self.realized_pnl += (entry_closed - sign as i128 * quote_closed) as i64;
+        self.quote_entry_amount -= entry_closed as i64;
+        self.base_asset_amount += sign * closed as i64;
+        if base_abs > closed {
+            self.base_asset_amount += sign * (base_abs - closed) as i64;
+            self.quote_entry_amount = (-(sign as i128) * (quote - quote_closed)) as i64;
+        }
+    }
+}
+
+/// A `PositionEvent` and the position after it was applied
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PositionEntry {
+    pub event: PositionEvent,
+    pub position: Position,
+}
+
+/// Fold `events` (in chain order) into the position after each event
+pub fn position_history(events: Vec<PositionEvent>) -> Vec<PositionEntry> {
+    let mut positions = BTreeMap::<(Pubkey, u16), Position>::new();
+    events
+        .into_iter()
+        .map(|event| {
+            let position = positions
+                .entry((event.user, event.market_index))
+                .or_insert_with(|| Position::new(event.user, event.market_index));
+            position.apply(&event);
+            PositionEntry {
+                position: position.clone(),
+                event,
+            }
+        })
+        .collect()
+}
+
+/// Log the position events of `event` and update the `positions` view
+pub async fn track_positions<T: IndexerBackend>(
+    db: &T,
+    event: &DriftEvent,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    for event in PositionEvent::from_event(event, ctx) {
+        if !db.insert_position_event(event.clone()).await? {
+            debug!("position event already logged: {event:?}");
+            continue;
+        }
+        let position = db.position(&event.user, event.market_index).await?;
+        let position = match position {
+            Some(mut position) if position.order_key() < event.order_key() => {
+                position.apply(&event);
+                position
+            }
+            Some(_) => {
+                // indexed out of chain order, rebuild from the log
+                debug!("rebuilding position {}/{}", event.user, event.market_index);
+                let events = db.position_events(&event.user, event.market_index).await?;
+                match position_history(events).pop() {
+                    Some(entry) => entry.position,
+                    None => continue,
+                }
+            }
+            None => {
+                let mut position = Position::new(event.user, event.market_index);
+                position.apply(&event);
+                position
+            }
+        };
+        db.upsert_position(position).await?;
+    }
+
+    Ok(())
+}
+
+/// Rebuild the position of `user` in perp market `market_index` from its logged events e.g. after some were
+/// retracted, removing it if none are left
+pub async fn rebuild_position<T: IndexerBackend>(
+    db: &T,
+    user: Pubkey,
+    market_index: u16,
+) -> Result<(), DbError> {
+    let events = db.position_events(&user, market_index).await?;
+    match position_history(events).pop() {
+        Some(entry) => db.upsert_position(entry.position).await,
+        None => db.remove_position(&user, market_index).await,
+    }
+}
+
+/// Rebuild the `positions` view from scratch from the logged position events
+///
+/// Returns the number of positions rebuilt
+pub async fn replay_positions<T: IndexerBackend>(db: &T) -> Result<usize, DbError> {
+    let mut positions = BTreeMap::<(Pubkey, u16), Position>::new();
+    for entry in position_history(db.all_position_events().await?) {
+        positions.insert(
+            (entry.position.user, entry.position.market_index),
+            entry.position,
+        );
+    }
+    db.clear_positions().await?;
+    let count = positions.len();
+    for position in positions.into_values() {
+        db.upsert_position(position).await?;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        drift::types::{
+            LiquidateBorrowForPerpPnlRecord, LiquidatePerpPnlForDepositRecord, LiquidatePerpRecord,
+            LiquidateSpotRecord, PerpBankruptcyRecord, SpotBankruptcyRecord,
+        },
+        program::EventRouter,
+        test_utils::{ctx, fill, tx_ctx},
+        types::drift::DriftProgram,
+    };
+
+    /// Index the position events of tx `signature` at `slot` emitting `events`, in reverse log order
+    async fn index_tx(db: &MockBackend, signature: &str, slot: u64, events: &[DriftEvent]) {
+        let mut ctx = tx_ctx(signature, slot, 0);
+        <DriftProgram as EventRouter<MockBackend>>::prepare_tx(
+            events.iter().collect::<Vec<_>>().as_slice(),
+            &mut ctx,
+        );
+        for (log_index, event) in events.iter().enumerate().rev() {
+            ctx.log_index = log_index as u32;
+            track_positions(db, event, &ctx).await.unwrap();
+        }
+    }
+
+    /// A fill of `base` at `price` ($) where `maker` sells to `taker`, 10bps taker fee and 5bps maker rebate
+    fn trade(
+        template: &OrderActionRecord,
+        id: u64,
+        taker: Pubkey,
+        maker: Pubkey,
+        base: u64,
+        price: u64,
+    ) -> DriftEvent {
+        let quote = base * price / 1_000;
+        DriftEvent::OrderActionRecord(OrderActionRecord {
+            ts: template.ts + id as i64,
+            fillRecordId: Some(id),
+            baseAssetAmountFilled: Some(base),
+            quoteAssetAmountFilled: Some(quote),
+            takerFee: Some(quote / 1_000),
+            makerFee: Some(-((quote / 2_000) as i64)),
+            taker: Some(taker),
+            takerOrderDirection: Some(PositionDirection::Long),
+            maker: Some(maker),
+            makerOrderDirection: Some(PositionDirection::Short),
+            ..template.clone()
+        })
+    }
+
+    fn liquidation(user: Pubkey, liquidator: Pubkey, ts: i64, if_fee: u64) -> DriftEvent {
+        DriftEvent::LiquidationRecord(LiquidationRecord {
+            ts,
+            liquidationType: LiquidationType::LiquidatePerp,
+            user,
+            liquidator,
+            marginRequirement: 0,
+            totalCollateral: 0,
+            marginFreed: 0,
+            liquidationId: 1,
+            bankrupt: false,
+            canceledOrderIds: vec![],
+            liquidatePerp: LiquidatePerpRecord {
+                marketIndex: 1,
+                oraclePrice: 0,
+                baseAssetAmount: 0,
+                quoteAssetAmount: 0,
+                lpShares: 0,
+                fillRecordId: 0,
+                userOrderId: 0,
+                liquidatorOrderId: 0,
+                liquidatorFee: 0,
+                ifFee: if_fee,
+            },
+            liquidateSpot: LiquidateSpotRecord {
+                assetMarketIndex: 0,
+                assetPrice: 0,
+                assetTransfer: 0,
+                liabilityMarketIndex: 0,
+                liabilityPrice: 0,
+                liabilityTransfer: 0,
+                ifFee: 0,
+            },
+            liquidateBorrowForPerpPnl: LiquidateBorrowForPerpPnlRecord {
+                perpMarketIndex: 0,
+                marketOraclePrice: 0,
+                pnlTransfer: 0,
+                liabilityMarketIndex: 0,
+                liabilityPrice: 0,
+                liabilityTransfer: 0,
+            },
+            liquidatePerpPnlForDeposit: LiquidatePerpPnlForDepositRecord {
+                perpMarketIndex: 0,
+                marketOraclePrice: 0,
+                pnlTransfer: 0,
+                assetMarketIndex: 0,
+                assetPrice: 0,
+                assetTransfer: 0,
+            },
+            perpBankruptcy: PerpBankruptcyRecord {
+                marketIndex: 0,
+                pnl: 0,
+                ifPayment: 0,
+                clawbackUser: None,
+                clawbackUserPayment: None,
+                cumulativeFundingRateDelta: 0,
+            },
+            spotBankruptcy: SpotBankruptcyRecord {
+                marketIndex: 0,
+                borrowAmount: 0,
+                ifPayment: 0,
+                cumulativeDepositInterestDelta: 0,
+            },
+        })
+    }
+
+    #[test]
+    fn position_from_captured_fill() {
+        let events =
+            PositionEvent::from_event(&DriftEvent::OrderActionRecord(fill()), &ctx(196923928));
+        let taker = Pubkey::from_str("H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG").unwrap();
+        let maker = Pubkey::from_str("4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV").unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].user, taker);
+        assert_eq!(events[1].user, maker);
+
+        let history = position_history(events);
+        // taker buys 0.0015 BTC for $41.52645
+        let long = &history[0].position;
+        assert_eq!(long.base_asset_amount, 1_500_000);
+        assert_eq!(long.quote_entry_amount, -41_526_450);
+        assert_eq!(long.entry_price, Some(27_684_300_000));
+        assert_eq!(long.fees, 12458);
+        let short = &history[1].position;
+        assert_eq!(short.base_asset_amount, -1_500_000);
+        assert_eq!(short.quote_entry_amount, 41_526_450);
+        assert_eq!(short.fees, -8305);
+    }
+
+    #[tokio::test]
+    async fn position_ledger() {
+        let db = MockBackend::init("mockdb").await;
+        let template = fill();
+        let (user, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ts = template.ts;
+        let funding = |payment: i64| {
+            DriftEvent::FundingPaymentRecord(FundingPaymentRecord {
+                ts,
+                userAuthority: Pubkey::default(),
+                user,
+                marketIndex: 1,
+                fundingPayment: payment,
+                baseAssetAmount: 0,
+                userLastCumulativeFunding: 0,
+                ammCumulativeFundingLong: 0,
+                ammCumulativeFundingShort: 0,
+            })
+        };
+        // (slot, event)
+        let sequence = [
+            // buy 1 @ $20,000, buy 1 @ $30,000 => entry $25,000
+            (1, trade(&template, 1, user, other, 1_000_000_000, 20_000)),
+            (2, trade(&template, 2, user, other, 1_000_000_000, 30_000)),
+            // pay $10 funding
+            (3, funding(-10_000_000)),
+            // sell 3 @ $26,000 => realize 2 * $1,000, flip short 1 @ $26,000
+            (4, trade(&template, 3, other, user, 3_000_000_000, 26_000)),
+            (5, funding(4_000_000)),
+            (6, liquidation(user, other, ts, 5_000_000)),
+        ];
+        // the sequence is indexed out of order, and twice
+        for (slot, event) in sequence.iter().rev().chain(sequence.iter()) {
+            track_positions(&db, event, &ctx(*slot)).await.unwrap();
+        }
+
+        let position = db.position(&user, 1).await.unwrap().unwrap();
+        assert_eq!(position.base_asset_amount, -1_000_000_000);
+        assert_eq!(position.quote_entry_amount, 26_000_000_000);
+        assert_eq!(position.entry_price, Some(26_000_000_000));
+        assert_eq!(position.realized_pnl, 2_000_000_000);
+        assert_eq!(position.funding_paid, 6_000_000);
+        // taker fees (20 + 30) + maker rebate 39 + if fee 5
+        assert_eq!(position.fees, 50_000_000 - 39_000_000 + 5_000_000);
+        assert_eq!(position.liquidations, 1);
+        assert_eq!(position.last_slot, 6);
+        assert_eq!(position.net_pnl(), 2_000_000_000 - 16_000_000 - 6_000_000);
+
+        // the counterparty mirrors the trades
+        let counterparty = db.position(&other, 1).await.unwrap().unwrap();
+        assert_eq!(counterparty.base_asset_amount, 1_000_000_000);
+        assert_eq!(counterparty.realized_pnl, -2_000_000_000);
+
+        // settlement adopts the onchain position
+        let settle = DriftEvent::SettlePnlRecord(SettlePnlRecord {
+            ts,
+            user,
+            marketIndex: 1,
+            pnl: 1_990_000_000,
+            baseAssetAmount: -1_000_000_000,
+            quoteAssetAmountAfter: 0,
+            quoteEntryAmount: 25_000_000_000,
+            settlePrice: 0,
+            explanation: SettlePnlExplanation::None,
+        });
+        track_positions(&db, &settle, &ctx(7)).await.unwrap();
+        let position = db.position(&user, 1).await.unwrap().unwrap();
+        assert_eq!(position.settled_pnl, 1_990_000_000);
+        assert_eq!(position.entry_price, Some(25_000_000_000));
+
+        // txs of one slot apply in fill id order, not signature order
+        let (buyer, seller) = (Pubkey::new_unique(), Pubkey::new_unique());
+        index_tx(
+            &db,
+            "a",
+            8,
+            &[trade(&template, 11, seller, buyer, 1_000_000_000, 30_000)],
+        )
+        .await;
+        index_tx(
+            &db,
+            "b",
+            8,
+            &[trade(&template, 10, buyer, seller, 1_000_000_000, 20_000)],
+        )
+        .await;
+        let events = db.position_events(&buyer, 1).await.unwrap();
+        assert_eq!(events.iter().map(|e| e.seq).collect::<Vec<_>>(), [10, 11]);
+        let position = db.position(&buyer, 1).await.unwrap().unwrap();
+        assert_eq!(
+            (position.last_seq, position.last_signature.as_str()),
+            (11, "a")
+        );
+        // bought @ $20,000, then sold @ $30,000
+        assert_eq!(position.base_asset_amount, 0);
+        assert_eq!(position.realized_pnl, 10_000_000_000);
+
+        // events of a tx apply in log order: a fill then the settlement of the filled position
+        let trader = Pubkey::new_unique();
+        let settle = DriftEvent::SettlePnlRecord(SettlePnlRecord {
+            ts,
+            user: trader,
+            marketIndex: 1,
+            pnl: 0,
+            baseAssetAmount: 1_000_000_000,
+            quoteAssetAmountAfter: 0,
+            quoteEntryAmount: -20_000_000_000,
+            settlePrice: 0,
+            explanation: SettlePnlExplanation::None,
+        });
+        index_tx(
+            &db,
+            "c",
+            9,
+            &[
+                trade(&template, 20, trader, seller, 1_000_000_000, 20_000),
+                settle,
+            ],
+        )
+        .await;
+        let events = db.position_events(&trader, 1).await.unwrap();
+        assert!(matches!(
+            events.iter().map(|e| &e.kind).collect::<Vec<_>>()[..],
+            [
+                PositionEventKind::Fill { .. },
+                PositionEventKind::SettlePnl { .. }
+            ]
+        ));
+        let position = db.position(&trader, 1).await.unwrap().unwrap();
+        assert_eq!(position.base_asset_amount, 1_000_000_000);
+        assert_eq!(position.entry_price, Some(20_000_000_000));
+        assert_eq!(position_history(events).pop().unwrap().position, position);
+
+        // replaying from scratch yields the same ledger
+        let positions = db.positions_of(&user).await.unwrap();
+        assert_eq!(replay_positions(&db).await.unwrap(), 5);
+        assert_eq!(db.positions_of(&user).await.unwrap(), positions);
+    }
+}
@@ -0,0 +1,581 @@
+//! Fixed-point precision of drift event fields
+//!
+//! drift stores amounts as integers with an implied precision e.g. an `oraclePrice` of `27681000000`
+//! is $27,681 at `PRICE_PRECISION`. Event fields are annotated with their precision so they can be
+//! stored (and served) as decimals alongside the raw values.
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    markets::{lookup, market_fields, MarketRegistry},
+    types::drift::types::MarketType,
+};
+
+/// Precision of prices e.g. `oraclePrice`
+pub const PRICE_PRECISION: u128 = 1_000_000;
+/// Precision of quote (USDC) amounts e.g. `quoteAssetAmountFilled`
+pub const QUOTE_PRECISION: u128 = 1_000_000;
+/// Precision of perp base amounts e.g. `baseAssetAmountFilled`
+pub const BASE_PRECISION: u128 = 1_000_000_000;
+/// Precision of AMM reserves and LP shares
+pub const AMM_RESERVE_PRECISION: u128 = 1_000_000_000;
+/// Precision of the AMM peg multiplier
+pub const PEG_PRECISION: u128 = 1_000_000;
+/// Precision of funding rates (`PRICE_PRECISION` * `FUNDING_RATE_BUFFER`)
+pub const FUNDING_RATE_PRECISION: u128 = 1_000_000_000;
+/// Precision of spot market balances (scaled by cumulative interest)
+pub const SPOT_BALANCE_PRECISION: u128 = 1_000_000_000;
+/// Precision of spot market cumulative interest
+pub const SPOT_CUMULATIVE_INTEREST_PRECISION: u128 = 10_000_000_000;
+/// Precision of percentages e.g. utilization, borrow rates and IF factors
+pub const PERCENTAGE_PRECISION: u128 = 1_000_000;
+
+/// The precision of an event field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    Price,
+    Quote,
+    Base,
+    AmmReserve,
+    Peg,
+    FundingRate,
+    SpotBalance,
+    SpotCumulativeInterest,
+    Percentage,
+    /// Token amount of the spot market whose index is at the given field path
+    Token(&'static str),
+    /// Base amount of the market whose `marketIndex`/`marketType` are under the given path
+    /// i.e. `BASE_PRECISION` for perp markets and token decimals for spot markets
+    MarketBase(&'static str),
+}
+
+impl Precision {
+    /// Return the number of decimals of the field in `event`
+    /// - `spot_decimals` token decimals by spot market index
+    pub fn decimals(
+        &self,
+        event: &Value,
+        spot_decimals: impl Fn(u16) -> Option<u32>,
+    ) -> Option<u32> {
+        let decimals_of = |precision: u128| Some(precision.ilog10());
+        match self {
+            Self::Price => decimals_of(PRICE_PRECISION),
+            Self::Quote => decimals_of(QUOTE_PRECISION),
+            Self::Base => decimals_of(BASE_PRECISION),
+            Self::AmmReserve => decimals_of(AMM_RESERVE_PRECISION),
+            Self::Peg => decimals_of(PEG_PRECISION),
+            Self::FundingRate => decimals_of(FUNDING_RATE_PRECISION),
+            Self::SpotBalance => decimals_of(SPOT_BALANCE_PRECISION),
+            Self::SpotCumulativeInterest => decimals_of(SPOT_CUMULATIVE_INTEREST_PRECISION),
+            Self::Percentage => decimals_of(PERCENTAGE_PRECISION),
+            Self::Token(market_index) => {
+                let market_index = lookup(event, market_index)?.as_u64()?;
+                spot_decimals(u16::try_from(market_index).ok()?)
+            }
+            Self::MarketBase(prefix) => {
+                let market = if prefix.is_empty() {
+                    event
+                } else {
+                    lookup(event, prefix)?
+                };
+                let market_type: MarketType =
+                    serde_json::from_value(market.get("marketType")?.clone()).ok()?;
+                match market_type {
+                    MarketType::Perp => decimals_of(BASE_PRECISION),
+                    MarketType::Spot => {
+                        let market_index = market.get("marketIndex")?.as_u64()?;
+                        spot_decimals(u16::try_from(market_index).ok()?)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Annotated fields of drift events: (field path, precision)
+///
+/// Field paths are dot separated for fields of nested types e.g. 'order.price'
+pub fn field_precisions(event: &str) -> &'static [(&'static str, Precision)] {
+    use Precision::*;
+    match event {
+        "OrderActionRecord" => &[
+            ("fillerReward", Quote),
+            ("baseAssetAmountFilled", MarketBase("")),
+            ("quoteAssetAmountFilled", Quote),
+            ("takerFee", Quote),
+            ("makerFee", Quote),
+            ("referrerReward", Quote),
+            ("quoteAssetAmountSurplus", Quote),
+            ("spotFulfillmentMethodFee", Quote),
+            ("takerOrderBaseAssetAmount", MarketBase("")),
+            ("takerOrderCumulativeBaseAssetAmountFilled", MarketBase("")),
+            ("takerOrderCumulativeQuoteAssetAmountFilled", Quote),
+            ("makerOrderBaseAssetAmount", MarketBase("")),
+            ("makerOrderCumulativeBaseAssetAmountFilled", MarketBase("")),
+            ("makerOrderCumulativeQuoteAssetAmountFilled", Quote),
+            ("oraclePrice", Price),
+        ],
+        "OrderRecord" => &[
+            ("order.price", Price),
+            ("order.baseAssetAmount", MarketBase("order")),
+            ("order.baseAssetAmountFilled", MarketBase("order")),
+            ("order.quoteAssetAmountFilled", Quote),
+            ("order.triggerPrice", Price),
+            ("order.auctionStartPrice", Price),
+            ("order.auctionEndPrice", Price),
+            ("order.oraclePriceOffset", Price),
+        ],
+        "Position" => &[
+            ("baseAssetAmount", Base),
+            ("quoteEntryAmount", Quote),
+            ("entryPrice", Price),
+            ("realizedPnl", Quote),
+            ("fundingPaid", Quote),
+            ("fees", Quote),
+            ("settledPnl", Quote),
+        ],
+        "Candle" => &[
+            ("open", Price),
+            ("high", Price),
+            ("low", Price),
+            ("close", Price),
+            ("volumeBase", MarketBase("")),
+            ("volumeQuote", Quote),
+        ],
+        "DepositRecord" => &[
+            ("amount", Token("marketIndex")),
+            ("oraclePrice", Price),
+            ("marketDepositBalance", SpotBalance),
+            ("marketWithdrawBalance", SpotBalance),
+            ("marketCumulativeDepositInterest", SpotCumulativeInterest),
+            ("marketCumulativeBorrowInterest", SpotCumulativeInterest),
+            ("totalDepositsAfter", Token("marketIndex")),
+            ("totalWithdrawsAfter", Token("marketIndex")),
+        ],
+        "LedgerEntry" => &[
+            ("amount", Token("marketIndex")),
+            ("oraclePrice", Price),
+            ("cumulativeDepositInterest", SpotCumulativeInterest),
+            ("cumulativeBorrowInterest", SpotCumulativeInterest),
+            ("accruedInterest", Token("marketIndex")),
+            ("balance", Token("marketIndex")),
+        ],
+        "Balance" => &[("balance", Token("marketIndex"))],
+        "SpotInterestRecord" => &[
+            ("depositBalance", SpotBalance),
+            ("cumulativeDepositInterest", SpotCumulativeInterest),
+            ("borrowBalance", SpotBalance),
+            ("cumulativeBorrowInterest", SpotCumulativeInterest),
+            ("optimalUtilization", Percentage),
+            ("optimalBorrowRate", Percentage),
+            ("maxBorrowRate", Percentage),
+        ],
+        "FundingPaymentRecord" => &[
+            ("fundingPayment", Quote),
+            ("baseAssetAmount", Base),
+            ("userLastCumulativeFunding", FundingRate),
+            ("ammCumulativeFundingLong", FundingRate),
+            ("ammCumulativeFundingShort", FundingRate),
+        ],
+        "FundingRateRecord" => &[
+            ("fundingRate", FundingRate),
+            ("fundingRateLong", FundingRate),
+            ("fundingRateShort", FundingRate),
+            ("cumulativeFundingRateLong", FundingRate),
+            ("cumulativeFundingRateShort", FundingRate),
+            ("oraclePriceTwap", Price),
+            ("markPriceTwap", Price),
+            ("periodRevenue", Quote),
+            ("baseAssetAmountWithAmm", Base),
+            ("baseAssetAmountWithUnsettledLp", Base),
+        ],
+        "CurveRecord" => &[
+            ("pegMultiplierBefore", Peg),
+            ("baseAssetReserveBefore", AmmReserve),
+            ("quoteAssetReserveBefore", AmmReserve),
+            ("sqrtKBefore", AmmReserve),
+            ("pegMultiplierAfter", Peg),
+            ("baseAssetReserveAfter", AmmReserve),
+            ("quoteAssetReserveAfter", AmmReserve),
+            ("sqrtKAfter", AmmReserve),
+            ("baseAssetAmountLong", Base),
+            ("baseAssetAmountShort", Base),
+            ("baseAssetAmountWithAmm", Base),
+            ("totalFee", Quote),
+            ("totalFeeMinusDistributions", Quote),
+            ("adjustmentCost", Quote),
+            ("oraclePrice", Price),
+        ],
+        "LPRecord" => &[
+            ("nShares", AmmReserve),
+            ("deltaBaseAssetAmount", Base),
+            ("deltaQuoteAssetAmount", Quote),
+            ("pnl", Quote),
+        ],
+        "LiquidationRecord" => &[
+            ("marginRequirement", Quote),
+            ("totalCollateral", Quote),
+            ("marginFreed", Quote),
+            ("liquidatePerp.oraclePrice", Price),
+            ("liquidatePerp.baseAssetAmount", Base),
+            ("liquidatePerp.quoteAssetAmount", Quote),
+            ("liquidatePerp.lpShares", AmmReserve),
+            ("liquidatePerp.liquidatorFee", Quote),
+            ("liquidatePerp.ifFee", Quote),
+            ("liquidateSpot.assetPrice", Price),
+            (
+                "liquidateSpot.assetTransfer",
+                Token("liquidateSpot.assetMarketIndex"),
+            ),
+            ("liquidateSpot.liabilityPrice", Price),
+            (
+                "liquidateSpot.liabilityTransfer",
+                Token("liquidateSpot.liabilityMarketIndex"),
+            ),
+            (
+                "liquidateSpot.ifFee",
+                Token("liquidateSpot.liabilityMarketIndex"),
+            ),
+            ("liquidateBorrowForPerpPnl.marketOraclePrice", Price),
+            ("liquidateBorrowForPerpPnl.pnlTransfer", Quote),
+            ("liquidateBorrowForPerpPnl.liabilityPrice", Price),
+            (
+                "liquidateBorrowForPerpPnl.liabilityTransfer",
+                Token("liquidateBorrowForPerpPnl.liabilityMarketIndex"),
+            ),
+            ("liquidatePerpPnlForDeposit.marketOraclePrice", Price),
+            ("liquidatePerpPnlForDeposit.pnlTransfer", Quote),
+            ("liquidatePerpPnlForDeposit.assetPrice", Price),
+            (
+                "liquidatePerpPnlForDeposit.assetTransfer",
+                Token("liquidatePerpPnlForDeposit.assetMarketIndex"),
+            ),
+            ("perpBankruptcy.pnl", Quote),
+            ("perpBankruptcy.ifPayment", Quote),
+            ("perpBankruptcy.clawbackUserPayment", Quote),
+            ("perpBankruptcy.cumulativeFundingRateDelta", FundingRate),
+            (
+                "spotBankruptcy.borrowAmount",
+                Token("spotBankruptcy.marketIndex"),
+            ),
+            (
+                "spotBankruptcy.ifPayment",
+                Token("spotBankruptcy.marketIndex"),
+            ),
+            (
+                "spotBankruptcy.cumulativeDepositInterestDelta",
+                SpotCumulativeInterest,
+            ),
+        ],
+        "SettlePnlRecord" => &[
+            ("pnl", Quote),
+            ("baseAssetAmount", Base),
+            ("quoteAssetAmountAfter", Quote),
+            ("quoteEntryAmount", Quote),
+            ("settlePrice", Price),
+        ],
+        "InsuranceFundRecord" => &[
+            ("userIfFactor", Percentage),
+            ("totalIfFactor", Percentage),
+            ("vaultAmountBefore", Token("spotMarketIndex")),
+            ("insuranceVaultAmountBefore", Token("spotMarketIndex")),
+            ("totalIfSharesBefore", Token("spotMarketIndex")),
+            ("totalIfSharesAfter", Token("spotMarketIndex")),
+            ("amount", Token("spotMarketIndex")),
+        ],
+        "InsuranceFundStakeRecord" => &[
+            ("amount", Token("marketIndex")),
+            ("insuranceVaultAmountBefore", Token("marketIndex")),
+            ("ifSharesBefore", Token("marketIndex")),
+            ("userIfSharesBefore", Token("marketIndex")),
+            ("totalIfSharesBefore", Token("marketIndex")),
+            ("ifSharesAfter", Token("marketIndex")),
+            ("userIfSharesAfter", Token("marketIndex")),
+            ("totalIfSharesAfter", Token("marketIndex")),
+        ],
+        "SwapRecord" => &[
+            ("amountOut", Token("outMarketIndex")),
+            ("amountIn", Token("inMarketIndex")),
+            ("outOraclePrice", Price),
+            ("inOraclePrice", Price),
+            ("fee", Token("outMarketIndex")),
+        ],
+        _ => &[],
+    }
+}
+
+/// A stored event and its normalized (human-readable) fields
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NormalizedRecord<T> {
+    /// The event as emitted (raw integer fields)
+    #[serde(flatten)]
+    pub record: T,
+    /// Decimal values and market symbols of the annotated fields, nested like the event
+    pub normalized: Option<Value>,
+    /// Labels of the account the event was indexed for (see `WatchedAccount`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Signature of the tx which emitted the event, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Slot of the tx, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// Whether the tx is only confirmed i.e. the event may still be retracted (see `--commitment`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub provisional: bool,
+    /// Whether the event was decoded from the known prefix of a newer layout i.e. appended fields are missing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+/// Converts drift event fields to human-readable values
+/// i.e. fixed-point fields to decimals and market indexes to market symbols
+///
+/// Cheap to clone, all clones share the same market registry
+#[derive(Clone, Debug, Default)]
+pub struct Normalizer {
+    /// Market metadata (symbols and spot token decimals)
+    markets: MarketRegistry,
+}
+
+impl Normalizer {
+    /// Create a new `Normalizer` with metadata from `markets`
+    pub fn new(markets: MarketRegistry) -> Self {
+        Self { markets }
+    }
+
+    /// Return the market registry
+    pub fn markets(&self) -> &MarketRegistry {
+        &self.markets
+    }
+
+    /// Return the normalized values of the annotated fields of `event` (named `name`)
+    ///
+    /// Fields which are unset (`None`) or of unknown precision/market (e.g. an unknown spot market) are omitted.
+    /// Returns `None` if `name` has no annotated fields.
+    pub fn normalize(&self, name: &str, event: &Value) -> Option<Value> {
+        let fields = field_precisions(name);
+        let markets = market_fields(name);
+        if fields.is_empty() && markets.is_empty() {
+            return None;
+        }
+        let mut normalized = Map::new();
+        for (path, precision) in fields {
+            if !is_active(name, event, path) {
+                continue;
+            }
+            let Some(raw) = lookup(event, path).and_then(raw_integer) else {
+                continue;
+            };
+            let Some(decimals) = precision.decimals(event, |i| self.markets.spot_decimals(i))
+            else {
+                debug!("{name}.{path}: unknown precision {precision:?}");
+                continue;
+            };
+            insert_path(
+                &mut normalized,
+                path,
+                Value::from(to_decimal(raw, decimals)),
+            );
+        }
+        for (path, market) in markets {
+            if !is_active(name, event, path) {
+                continue;
+            }
+            match self.markets.symbol(event, path, *market) {
+                Some(symbol) => insert_path(&mut normalized, path, Value::from(symbol)),
+                None => debug!("{name}.{path}: unknown market"),
+            }
+        }
+
+        Some(Value::Object(normalized))
+    }
+
+    /// Return the normalized values of the annotated fields of the typed event `event` (named `name`)
+    pub fn normalize_event<E: Serialize>(&self, name: &str, event: &E) -> Option<Value> {
+        match serde_json::to_value(event) {
+            Ok(value) => self.normalize(name, &value),
+            Err(err) => {
+                warn!("failed serializing {name}: {err:?}");
+                None
+            }
+        }
+    }
+}
+
+/// Convert the fixed-point `raw` with `decimals` to a decimal
+pub fn to_decimal(raw: i128, decimals: u32) -> f64 {
+    raw as f64 / 10_f64.powi(decimals as i32)
+}
+
+/// True if the field at `path` applies to `event` (named `name`)
+///
+/// A `LiquidationRecord` holds a record of each liquidation type, only the one of its `liquidationType` is set
+fn is_active(name: &str, event: &Value, path: &str) -> bool {
+    if name != "LiquidationRecord" {
+        return true;
+    }
+    let Some((record, _)) = path.split_once('.') else {
+        return true;
+    };
+    let Some(liquidation_type) = event.get("liquidationType").and_then(Value::as_str) else {
+        return true;
+    };
+    let mut chars = liquidation_type.chars();
+    chars.next().is_some_and(|c| {
+        record.starts_with(c.to_ascii_lowercase()) && record[1..] == *chars.as_str()
+    })
+}
+
+/// Insert `value` into `map` at the dot separated `path`, creating intermediate objects
+fn insert_path(map: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((key, rest)) => {
+            let inner = map.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(inner) = inner {
+                insert_path(inner, rest, value);
+            }
+        }
+        None => {
+            map.insert(path.to_string(), value);
+        }
+    }
+}
+
+/// Read an integer field, either a number or a string (e.g. `u128` values of runtime decoded events)
+fn raw_integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from)),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::{drift::types::MarketStatus, markets::MarketInfo, test_utils::fill};
+
+    #[test]
+    fn normalize_perp_fill() {
+        let record = fill();
+        let normalized = Normalizer::default()
+            .normalize_event("OrderActionRecord", &record)
+            .unwrap();
+
+        assert_eq!(normalized["oraclePrice"], 27_681.0);
+        assert_eq!(normalized["baseAssetAmountFilled"], 0.0015);
+        assert_eq!(normalized["quoteAssetAmountFilled"], 41.52645);
+        assert_eq!(normalized["takerFee"], 0.012458);
+        assert_eq!(normalized["makerFee"], -0.008305);
+        assert_eq!(normalized["fillerReward"], 0.001245);
+        // unset fields are omitted
+        assert!(normalized.get("referrerReward").is_none());
+    }
+
+    fn market(
+        market_type: MarketType,
+        market_index: u16,
+        symbol: &str,
+        decimals: u32,
+    ) -> MarketInfo {
+        MarketInfo {
+            market_type,
+            market_index,
+            pubkey: Pubkey::new_unique(),
+            symbol: symbol.to_string(),
+            oracle: Pubkey::new_unique(),
+            decimals,
+            tick_size: 100,
+            step_size: 1_000_000,
+            status: MarketStatus::Active,
+        }
+    }
+
+    #[test]
+    fn normalize_spot_amounts() {
+        let markets = MarketRegistry::default();
+        markets.insert(market(MarketType::Spot, 0, "USDC", 6));
+        markets.insert(market(MarketType::Spot, 1, "SOL", 9));
+        let normalizer = Normalizer::new(markets);
+
+        let order = json!({
+            "user": "4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV",
+            "order": {
+                "price": 21_500_000_u64,
+                "baseAssetAmount": 2_500_000_000_u64,
+                "baseAssetAmountFilled": 0,
+                "quoteAssetAmountFilled": 0,
+                "marketIndex": 1,
+                "marketType": "Spot",
+            }
+        });
+        assert_eq!(
+            normalizer.normalize("OrderRecord", &order).unwrap(),
+            json!({
+                "order": {
+                    "price": 21.5,
+                    "baseAssetAmount": 2.5,
+                    "baseAssetAmountFilled": 0.0,
+                    "quoteAssetAmountFilled": 0.0,
+                    "marketIndex": "SOL",
+                }
+            })
+        );
+
+        // u128 fields of runtime decoded events are strings, spot market 2 decimals are unknown
+        let deposit = json!({
+            "amount": 1_000_000_u64,
+            "marketIndex": 2,
+            "oraclePrice": 1_000_000,
+            "marketCumulativeDepositInterest": "10400000000",
+        });
+        assert_eq!(
+            normalizer.normalize("DepositRecord", &deposit).unwrap(),
+            json!({
+                "oraclePrice": 1.0,
+                "marketCumulativeDepositInterest": 1.04,
+            })
+        );
+
+        assert!(normalizer.normalize("NewUserRecord", &json!({})).is_none());
+    }
+
+    #[test]
+    fn normalize_liquidation() {
+        let markets = MarketRegistry::default();
+        markets.insert(market(MarketType::Perp, 1, "BTC-PERP", 9));
+        markets.insert(market(MarketType::Spot, 0, "USDC", 6));
+        let normalizer = Normalizer::new(markets);
+
+        // only the record of the liquidation type is set, the others are zeroed
+        let liquidation = json!({
+            "liquidationType": "LiquidatePerp",
+            "marginFreed": 5_000_000,
+            "liquidatePerp": { "marketIndex": 1, "oraclePrice": 27_681_000_000_u64, "baseAssetAmount": -1_000_000 },
+            "liquidateSpot": { "assetMarketIndex": 0, "assetPrice": 0 },
+        });
+        assert_eq!(
+            normalizer
+                .normalize("LiquidationRecord", &liquidation)
+                .unwrap(),
+            json!({
+                "marginFreed": 5.0,
+                "liquidatePerp": {
+                    "marketIndex": "BTC-PERP",
+                    "oraclePrice": 27_681.0,
+                    "baseAssetAmount": -0.001,
+                },
+            })
+        );
+    }
+}
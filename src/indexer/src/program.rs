@@ -0,0 +1,441 @@
+//! Anchor program descriptors
+//!
+//! Describes how to index an anchor program: its ID, how to decode its events and where to persist them.
+//! Descriptors are generated by `gen_idl_types!(.., program(crate::AnchorProgram))`
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    alerts::Alerter,
+    analytics::{track_account_stats, untrack_account_stats},
+    candles::{rebuild_candles, track_candles},
+    db::IndexerBackend,
+    dynamic::{Decoded, DynamicEvent},
+    funding::{FundingPayment, FundingRate},
+    instructions::TxInstructions,
+    insurance::{track_if_stake, IfVault},
+    ledger::{refold_ledger, track_deposit, SpotInterest},
+    lp::{refold_lp, track_lp},
+    markets::MarketRegistry,
+    orders::{action_orders, rebuild_order, track_order_action, track_order_record},
+    positions::{rebuild_position, track_positions},
+    precision::{NormalizedRecord, Normalizer},
+    swaps::track_swap,
+    types::{
+        drift::{
+            events::{DriftEvent, LiquidationRecord, OrderActionRecord},
+            types::{MarketType, OrderAction},
+            DriftProgram,
+        },
+        IndexerError,
+    },
+};
+
+/// An anchor program and its (IDL generated) event types
+pub trait AnchorProgram: Send + Sync + 'static {
+    /// The outer event type of the program
+    type Event: Debug + Serialize + Send + Sync;
+    /// IDL name of the program e.g. 'drift'
+    const NAME: &'static str;
+    /// Version of the IDL the event types were generated from
+    const IDL_VERSION: &'static str;
+    /// The program ID
+    fn program_id() -> Pubkey;
+    /// Deserialize an event from its discriminant and data (excluding discriminant)
+    fn decode_event(disc: [u8; 8], data: &mut &[u8]) -> Option<Self::Event>;
+    /// The IDL name of `event`'s type
+    fn event_name(event: &Self::Event) -> &'static str;
+}
+
+/// An anchor program without generated types, its events are decoded by the runtime IDL only
+///
+/// Index it with `ProgramIndexer::with_program` and `ProgramIndexer::with_dynamic_idl`
+pub struct RuntimeProgram;
+
+impl AnchorProgram for RuntimeProgram {
+    type Event = ();
+    const NAME: &'static str = "runtime";
+    const IDL_VERSION: &'static str = "";
+    fn program_id() -> Pubkey {
+        Pubkey::default()
+    }
+    fn decode_event(_disc: [u8; 8], _data: &mut &[u8]) -> Option<Self::Event> {
+        None
+    }
+    fn event_name(_event: &Self::Event) -> &'static str {
+        ""
+    }
+}
+
+impl<T: IndexerBackend> EventRouter<T> for RuntimeProgram {}
+
+/// The tx an event was emitted in
+#[derive(Clone, Debug)]
+pub struct EventContext {
+    /// Signature of the tx
+    pub signature: String,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// The indexed accounts the tx was found for
+    pub accounts: Vec<Pubkey>,
+    /// Converts fixed-point event fields to decimals, if configured
+    pub normalizer: Option<Normalizer>,
+    /// Raises liquidation alerts, if configured
+    pub alerts: Option<Alerter>,
+    /// The instructions of the tx, if resolved
+    pub instructions: Option<Arc<TxInstructions>>,
+    /// Labels of the indexed account, stamped onto stored events
+    pub labels: Vec<String>,
+    /// Whether the tx is only confirmed, its events are then stored as raw documents until finalized
+    pub provisional: bool,
+    /// Whether the event was decoded from the known prefix of a newer layout (see `ParsedEvent`)
+    pub partial: bool,
+    /// First fill record ID of the tx by perp market, orders the tx's position events within a slot
+    pub fill_seqs: BTreeMap<u16, u64>,
+}
+
+impl EventContext {
+    /// Return the decimal values of `event`'s (named `name`) fixed-point fields, if any
+    pub fn normalize<E: Serialize>(&self, name: &str, event: &E) -> Option<Value> {
+        self.normalizer.as_ref()?.normalize_event(name, event)
+    }
+
+    /// Return `event` (named `name`) with its decimal values and the account's labels
+    pub fn normalized_record<E: Serialize>(&self, name: &str, event: E) -> NormalizedRecord<E> {
+        NormalizedRecord {
+            normalized: self.normalize(name, &event),
+            record: event,
+            labels: self.labels.clone(),
+            signature: Some(self.signature.clone()),
+            slot: Some(self.slot),
+            provisional: self.provisional,
+            partial: self.partial,
+        }
+    }
+
+    /// Return the market registry, if configured
+    pub fn markets(&self) -> Option<&MarketRegistry> {
+        self.normalizer.as_ref().map(Normalizer::markets)
+    }
+}
+
+/// Position of an event in chain order
+///
+/// The execution order of a slot's txs is not known from their signatures, within a slot txs are ordered by a
+/// monotonic onchain id (`seq`) e.g. `depositRecordId`, equal for all events of a tx or ascending in log order. The
+/// log index orders the events of a tx and the signature keeps keys of distinct events unique
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChainOrder<'a> {
+    pub slot: u64,
+    pub seq: u64,
+    pub log_index: u32,
+    pub signature: &'a str,
+}
+
+/// Routes a program's events to the backend
+///
+/// By default events are persisted as generic documents (see `IndexerBackend::insert_dynamic_event`),
+/// programs with dedicated tables override `route`
+#[async_trait]
+pub trait EventRouter<T: IndexerBackend>: AnchorProgram {
+    /// Prepare the context of a tx from all of its `events`, before they are routed
+    fn prepare_tx(_events: &[&Self::Event], _ctx: &mut EventContext) {}
+
+    /// Persist `event` emitted in the tx `ctx`
+    async fn route(db: &T, event: Self::Event, ctx: &EventContext) -> Result<(), IndexerError> {
+        store_generic_event::<Self, T>(db, &event, ctx).await
+    }
+
+    /// Undo the provisionally indexed tx `ctx` (its slot was skipped), returns the number of documents deleted
+    async fn retract(db: &T, ctx: &EventContext) -> Result<u64, IndexerError> {
+        Ok(db.retract_provisional(ctx.signature.as_str()).await?)
+    }
+}
+
+/// Persist `event` of program `P` as a generic document
+pub async fn store_generic_event<P: AnchorProgram + ?Sized, T: IndexerBackend>(
+    db: &T,
+    event: &P::Event,
+    ctx: &EventContext,
+) -> Result<(), IndexerError> {
+    let name = P::event_name(event);
+    let value = match serde_json::to_value(event) {
+        Ok(value) => value,
+        Err(err) => {
+            warn!("failed serializing {} event {name}: {err:?}", P::NAME);
+            return Ok(());
+        }
+    };
+    let normalized = ctx
+        .normalizer
+        .as_ref()
+        .and_then(|n| n.normalize(name, &value));
+    db.insert_dynamic_event(DynamicEvent {
+        program: P::NAME.to_string(),
+        signature: ctx.signature.clone(),
+        slot: ctx.slot,
+        event: Decoded {
+            name: name.to_string(),
+            value,
+        },
+        normalized,
+        labels: ctx.labels.clone(),
+        provisional: ctx.provisional,
+        partial: ctx.partial,
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl<T: IndexerBackend> EventRouter<T> for DriftProgram {
+    fn prepare_tx(events: &[&DriftEvent], ctx: &mut EventContext) {
+        for event in events {
+            if let DriftEvent::OrderActionRecord(OrderActionRecord {
+                action: OrderAction::Fill,
+                marketType: MarketType::Perp,
+                marketIndex,
+                fillRecordId: Some(fill_record_id),
+                ..
+            }) = event
+            {
+                let seq = ctx.fill_seqs.entry(*marketIndex).or_insert(*fill_record_id);
+                *seq = (*seq).min(*fill_record_id);
+            }
+        }
+    }
+
+    async fn route(db: &T, event: DriftEvent, ctx: &EventContext) -> Result<(), IndexerError> {
+        // kept raw to undo the derived views if retracted, the records are stored raw regardless
+        if ctx.provisional
+            && !matches!(
+                event,
+                DriftEvent::OrderActionRecord(_) | DriftEvent::OrderRecord(_)
+            )
+        {
+            store_generic_event::<DriftProgram, T>(db, &event, ctx).await?;
+        }
+        track_positions(db, &event, ctx).await?;
+        match event {
+            DriftEvent::OrderActionRecord(record) => {
+                info!(
+                    "indexing OrderActionRecord maker={:?}, taker={:?}",
+                    record.maker, record.taker
+                );
+                track_order_action(db, &record, ctx.markets()).await?;
+                track_candles(db, &record, ctx.markets()).await?;
+                track_account_stats(db, &record, ctx).await?;
+                db.insert_order_action_record(ctx.normalized_record("OrderActionRecord", record))
+                    .await?;
+            }
+            DriftEvent::OrderRecord(record) => {
+                info!("indexing OrderRecord: {:?}", record.user);
+                track_order_record(db, &record, ctx.markets()).await?;
+                db.insert_order_record(ctx.normalized_record("OrderRecord", record))
+                    .await?;
+            }
+            DriftEvent::FundingRateRecord(record) => {
+                info!("indexing FundingRateRecord: {}", record.marketIndex);
+                db.upsert_funding_rate(FundingRate::from_record(&record, ctx))
+                    .await?;
+            }
+            DriftEvent::FundingPaymentRecord(record) => {
+                info!("indexing FundingPaymentRecord: {:?}", record.user);
+                db.upsert_funding_payment(FundingPayment::from_record(&record, ctx))
+                    .await?;
+            }
+            DriftEvent::DepositRecord(record) => {
+                info!("indexing DepositRecord: {:?}", record.user);
+                track_deposit(db, &record, ctx).await?;
+            }
+            DriftEvent::SpotInterestRecord(record) => {
+                info!("indexing SpotInterestRecord: {}", record.marketIndex);
+                db.upsert_spot_interest(SpotInterest::from_record(&record, ctx))
+                    .await?;
+            }
+            DriftEvent::InsuranceFundStakeRecord(record) => {
+                info!(
+                    "indexing InsuranceFundStakeRecord: {:?}",
+                    record.userAuthority
+                );
+                track_if_stake(db, &record, ctx).await?;
+            }
+            DriftEvent::InsuranceFundRecord(record) => {
+                info!("indexing InsuranceFundRecord: {}", record.spotMarketIndex);
+                db.upsert_if_vault(IfVault::from_record(&record, ctx))
+                    .await?;
+            }
+            DriftEvent::LPRecord(record) => {
+                info!("indexing LPRecord: {:?}", record.user);
+                track_lp(db, &record, ctx).await?;
+            }
+            DriftEvent::SwapRecord(record) => {
+                info!("indexing SwapRecord: {:?}", record.user);
+                track_swap(db, &record, ctx).await?;
+            }
+            DriftEvent::LiquidationRecord(record) => {
+                info!("indexing LiquidationRecord: {:?}", record.user);
+                if let Some(ref alerts) = ctx.alerts {
+                    alerts.on_liquidation(db, &record, ctx.markets()).await?;
+                }
+            }
+            unhandled_event => {
+                info!("got unhandled event: {:?}", unhandled_event);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn retract(db: &T, ctx: &EventContext) -> Result<u64, IndexerError> {
+        let signature = ctx.signature.as_str();
+        let tx = db.provisional_tx(signature).await?;
+        let mut deleted = db.retract_provisional(signature).await?;
+        let mut views = db.retract_derived(signature).await?;
+        deleted += views.deleted;
+
+        // rebuilt from what is left
+        views.dedup();
+        for (user, market_index) in views.positions {
+            rebuild_position(db, user, market_index).await?;
+        }
+        for (user, market_index) in views.ledgers {
+            refold_ledger(db, user, market_index).await?;
+        }
+        for (user, market_index) in views.lp_histories {
+            refold_lp(db, user, market_index).await?;
+        }
+        // the tx may have been indexed repeatedly
+        let mut records = Vec::<OrderActionRecord>::new();
+        for record in tx.order_action_records {
+            if !records.contains(&record.record) {
+                records.push(record.record);
+            }
+        }
+        untrack_account_stats(db, records.as_slice(), ctx).await?;
+        let mut orders = tx
+            .order_records
+            .iter()
+            .map(|r| (r.record.user, r.record.order.orderId))
+            .chain(records.iter().flat_map(|r| {
+                action_orders(r)
+                    .into_iter()
+                    .map(|(user, order_id, _)| (user, order_id))
+            }))
+            .collect::<Vec<_>>();
+        orders.sort();
+        orders.dedup();
+        for (user, order_id) in orders {
+            rebuild_order(db, user, order_id, ctx.markets()).await?;
+        }
+        for record in records.iter().filter(|r| r.action == OrderAction::Fill) {
+            rebuild_candles(
+                db,
+                record.marketType,
+                record.marketIndex,
+                record.ts,
+                ctx.markets(),
+            )
+            .await?;
+        }
+        if let Some(ref alerts) = ctx.alerts {
+            for event in tx
+                .events
+                .iter()
+                .filter(|e| e.event.name == "LiquidationRecord")
+            {
+                match serde_json::from_value::<LiquidationRecord>(event.event.value.clone()) {
+                    Ok(record) => {
+                        alerts
+                            .on_retracted_liquidation(db, &record, ctx.markets())
+                            .await?;
+                    }
+                    Err(err) => warn!("failed decoding retracted LiquidationRecord: {err:?}"),
+                }
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::{tx_ctx, ORDER_ACTION_RECORD, SIGNATURE, SLOT},
+        versions::IdlVersions,
+    };
+
+    /// A program without dedicated tables i.e. uses the default routing
+    struct GenericProgram;
+
+    impl AnchorProgram for GenericProgram {
+        type Event = DriftEvent;
+        const NAME: &'static str = "generic";
+        const IDL_VERSION: &'static str = DriftProgram::IDL_VERSION;
+        fn program_id() -> Pubkey {
+            DriftProgram::program_id()
+        }
+        fn decode_event(disc: [u8; 8], data: &mut &[u8]) -> Option<Self::Event> {
+            DriftProgram::decode_event(disc, data)
+        }
+        fn event_name(event: &Self::Event) -> &'static str {
+            DriftProgram::event_name(event)
+        }
+    }
+
+    impl<T: IndexerBackend> EventRouter<T> for GenericProgram {}
+
+    fn ctx() -> EventContext {
+        EventContext {
+            normalizer: Some(Normalizer::default()),
+            ..tx_ctx(SIGNATURE, SLOT, 0)
+        }
+    }
+
+    #[tokio::test]
+    async fn route_events() {
+        let db = MockBackend::init("mockdb").await;
+
+        let event = IdlVersions::<GenericProgram>::latest_only()
+            .try_parse_log(ORDER_ACTION_RECORD, 0)
+            .unwrap()
+            .expect("it decodes");
+        GenericProgram::route(&db, event, &ctx()).await.unwrap();
+        {
+            let events = db.dynamic_events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].program, "generic");
+            assert_eq!(events[0].slot, 196923928);
+            assert_eq!(events[0].event.name, "OrderActionRecord");
+            assert_eq!(events[0].event.value["marketIndex"], 1);
+            assert_eq!(events[0].normalized.as_ref().unwrap()["takerFee"], 0.012458);
+        }
+        assert!(db.order_action_records().is_empty());
+
+        let event = IdlVersions::<DriftProgram>::default()
+            .try_parse_log(ORDER_ACTION_RECORD, 0)
+            .unwrap()
+            .expect("it decodes");
+        DriftProgram::route(&db, event, &ctx()).await.unwrap();
+        assert_eq!(db.order_action_records().len(), 1);
+        assert_eq!(db.dynamic_events().len(), 1);
+
+        let taker = db.order_action_records()[0].taker.unwrap();
+        let stored = db.order_action_records_of(&taker, 10, false).await.unwrap();
+        assert_eq!(
+            stored[0].normalized.as_ref().unwrap()["oraclePrice"],
+            27_681.0
+        );
+    }
+}
@@ -0,0 +1,438 @@
+//! Realtime drift `User` account tracking
+//!
+//! Maintains a live in-memory mirror of drift `User` accounts via websocket `programSubscribe`
+//! and persists the changes of each update into storage
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anchor_lang::Discriminator;
+use futures::{stream::select_all, StreamExt};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_account_decoder::UiAccountEncoding;
+use solana_pubsub_client::nonblocking::pubsub_client::PubsubClient;
+use solana_rpc_client_api::{
+    config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    filter::{Memcmp, RpcFilterType},
+    request::RpcRequest,
+    response::{OptionalContext, RpcKeyedAccount},
+};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+use crate::{
+    alerts::Alerter,
+    db::IndexerBackend,
+    types::{
+        drift::accounts::{DriftAccount, User},
+        drift_pda, IndexerError,
+    },
+    RpcClient,
+};
+
+/// Size of a drift `User` account (including discriminant)
+const USER_ACCOUNT_SIZE: u64 = 4_376;
+/// Offset of the `authority` field in a drift `User` account
+const USER_AUTHORITY_OFFSET: usize = 8;
+
+/// The changed fields of an account update
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    /// The account address
+    pub pubkey: Pubkey,
+    /// Slot the update was observed at
+    pub slot: u64,
+    /// New values of fields which changed, keyed by IDL field name
+    /// (all fields on the first observation of an account)
+    pub changes: BTreeMap<String, serde_json::Value>,
+}
+
+/// A tracked `User` account state
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedUser {
+    /// Slot the state was observed at
+    pub slot: u64,
+    /// The account state
+    pub user: User,
+}
+
+/// Live view of tracked drift `User` accounts
+///
+/// Cheap to clone, all clones share the same underlying state
+#[derive(Clone, Default)]
+pub struct UserAccountMirror {
+    inner: Arc<RwLock<HashMap<Pubkey, TrackedUser>>>,
+}
+
+impl UserAccountMirror {
+    /// Return the current state of the `User` account at `pubkey`
+    pub fn get(&self, pubkey: &Pubkey) -> Option<TrackedUser> {
+        self.inner.read().unwrap().get(pubkey).cloned()
+    }
+    /// Return all tracked `User` accounts owned by `authority`
+    pub fn by_authority(&self, authority: &Pubkey) -> Vec<(Pubkey, TrackedUser)> {
+        self.inner
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, tracked)| &tracked.user.authority == authority)
+            .map(|(pubkey, tracked)| (*pubkey, tracked.clone()))
+            .collect()
+    }
+    /// Return the addresses of all tracked `User` accounts
+    pub fn pubkeys(&self) -> Vec<Pubkey> {
+        self.inner.read().unwrap().keys().copied().collect()
+    }
+    /// Number of tracked accounts
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().len()
+    }
+    /// True if no accounts are tracked
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().unwrap().is_empty()
+    }
+    /// Apply an account update observed at `slot`
+    ///
+    /// Returns the diff from the previous state, or `None` if the update is stale or unchanged
+    fn update(&self, pubkey: Pubkey, slot: u64, user: User) -> Option<AccountDiff> {
+        let mut inner = self.inner.write().unwrap();
+        let previous = inner.get(&pubkey);
+        if let Some(previous) = previous {
+            if previous.slot > slot || previous.user == user {
+                return None;
+            }
+        }
+        let changes = diff_fields(previous.map(|p| &p.user), &user);
+        inner.insert(pubkey, TrackedUser { slot, user });
+
+        Some(AccountDiff {
+            pubkey,
+            slot,
+            changes,
+        })
+    }
+}
+
+/// Return the top-level fields of `next` which differ from `previous`
+fn diff_fields(previous: Option<&User>, next: &User) -> BTreeMap<String, serde_json::Value> {
+    let next = match serde_json::to_value(next) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return Default::default(),
+    };
+    let previous = match previous.map(serde_json::to_value) {
+        Some(Ok(serde_json::Value::Object(fields))) => fields,
+        _ => Default::default(),
+    };
+
+    next.into_iter()
+        .filter(|(name, value)| previous.get(name) != Some(value))
+        .collect()
+}
+
+/// Decode a drift `User` from raw account data
+fn decode_user(data: &[u8]) -> Option<User> {
+    match DriftAccount::try_from_bytes(data) {
+        Some(DriftAccount::User(user)) => Some(user),
+        _ => None,
+    }
+}
+
+/// Provides realtime tracking of drift `User` accounts
+pub struct UserAccountTracker<T: IndexerBackend> {
+    /// Db client
+    db: Arc<T>,
+    /// Solana RPC client
+    rpc: Arc<RpcClient>,
+    /// Solana RPC websocket endpoint
+    ws_url: String,
+    /// Live account states
+    mirror: UserAccountMirror,
+    /// Raises alerts on accounts entering liquidation
+    alerts: Option<Alerter>,
+}
+
+impl<T: IndexerBackend> UserAccountTracker<T> {
+    /// Create a new `UserAccountTracker`
+    pub fn new(db: Arc<T>, rpc: Arc<RpcClient>, ws_url: String) -> Self {
+        Self {
+            db,
+            rpc,
+            ws_url,
+            mirror: Default::default(),
+            alerts: None,
+        }
+    }
+
+    /// Raise alerts when tracked accounts enter liquidation or bankruptcy
+    pub fn with_alerts(mut self, alerts: Alerter) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Return a handle to the live account states
+    pub fn mirror(&self) -> UserAccountMirror {
+        self.mirror.clone()
+    }
+
+    /// Run the tracker
+    /// - `authorities` track only `User` accounts of these authorities, tracks all `User` accounts if empty
+    pub async fn run(self, authorities: &[String]) -> Result<(), IndexerError> {
+        let authorities = authorities
+            .iter()
+            .map(|a| Pubkey::try_from(a.as_str()))
+            .collect::<Result<Vec<Pubkey>, _>>()
+            .map_err(|_| IndexerError::InvalidPublicKey)?;
+        let configs: Vec<RpcProgramAccountsConfig> = if authorities.is_empty() {
+            vec![program_accounts_config(None)]
+        } else {
+            authorities
+                .iter()
+                .map(|a| program_accounts_config(Some(a)))
+                .collect()
+        };
+
+        // subscribe before seeding so no updates are missed in between
+        let pubsub = PubsubClient::new(self.ws_url.as_str()).await?;
+        let mut subscriptions = Vec::with_capacity(configs.len());
+        for config in configs.iter() {
+            let (stream, _unsubscribe) = pubsub
+                .program_subscribe(&drift_pda(), Some(config.clone()))
+                .await?;
+            subscriptions.push(stream);
+        }
+        self.seed(configs.as_slice()).await?;
+
+        let mut notifications = select_all(subscriptions);
+        while let Some(notification) = notifications.next().await {
+            let slot = notification.context.slot;
+            let keyed_account = notification.value;
+            let Ok(pubkey) = Pubkey::from_str(keyed_account.pubkey.as_str()) else {
+                warn!("invalid pubkey: {}", keyed_account.pubkey);
+                continue;
+            };
+            let Some(user) = keyed_account
+                .account
+                .decode::<solana_sdk::account::Account>()
+                .and_then(|a| decode_user(a.data.as_slice()))
+            else {
+                warn!("failed decoding User account: {pubkey}");
+                continue;
+            };
+            self.apply(pubkey, slot, user).await?;
+        }
+
+        warn!("account subscription closed");
+        Ok(())
+    }
+
+    /// Load the current state of all tracked accounts
+    ///
+    /// Accounts are labelled with the slot of the `getProgramAccounts` response context so they order correctly
+    /// against websocket updates
+    async fn seed(&self, configs: &[RpcProgramAccountsConfig]) -> Result<(), IndexerError> {
+        for config in configs {
+            let mut config = config.clone();
+            config.with_context = Some(true);
+            let (slot, accounts) = match self
+                .rpc
+                .send::<OptionalContext<Vec<RpcKeyedAccount>>>(
+                    RpcRequest::GetProgramAccounts,
+                    json!([drift_pda().to_string(), config]),
+                )
+                .await?
+            {
+                OptionalContext::Context(response) => (response.context.slot, response.value),
+                // RPC without context support
+                OptionalContext::NoContext(accounts) => (self.rpc.get_slot().await?, accounts),
+            };
+            info!("seeding {} User accounts @ {slot}", accounts.len());
+            for keyed_account in accounts {
+                let Ok(pubkey) = Pubkey::from_str(keyed_account.pubkey.as_str()) else {
+                    warn!("invalid pubkey: {}", keyed_account.pubkey);
+                    continue;
+                };
+                match keyed_account
+                    .account
+                    .decode::<Account>()
+                    .and_then(|account| decode_user(account.data.as_slice()))
+                {
+                    Some(user) => self.apply(pubkey, slot, user).await?,
+                    None => warn!("failed decoding User account: {pubkey}"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply an account update to the mirror and persist any changes
+    async fn apply(&self, pubkey: Pubkey, slot: u64, user: User) -> Result<(), IndexerError> {
+        if let Some(ref alerts) = self.alerts {
+            alerts.on_user(pubkey, slot, &user);
+        }
+        if let Some(diff) = self.mirror.update(pubkey, slot, user) {
+            debug!("User account update: {pubkey} @ {slot}");
+            self.db.insert_account_diff(diff).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Build the `User` account query, optionally filtered by `authority`
+fn program_accounts_config(authority: Option<&Pubkey>) -> RpcProgramAccountsConfig {
+    let mut filters = vec![
+        RpcFilterType::DataSize(USER_ACCOUNT_SIZE),
+        RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, User::DISCRIMINATOR.to_vec())),
+    ];
+    if let Some(authority) = authority {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            USER_AUTHORITY_OFFSET,
+            authority.to_bytes().to_vec(),
+        )));
+    }
+
+    RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::SinkExt;
+    use serde_json::Value;
+    use solana_account_decoder::UiAccount;
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+    use crate::db::MockBackend;
+
+    fn user_account(authority: &Pubkey, next_order_id: u8) -> Account {
+        let mut data = vec![0_u8; USER_ACCOUNT_SIZE as usize];
+        data[..8].copy_from_slice(&User::DISCRIMINATOR);
+        data[USER_AUTHORITY_OFFSET..USER_AUTHORITY_OFFSET + 32].copy_from_slice(authority.as_ref());
+        // `nextOrderId`
+        data[4_336] = next_order_id;
+        Account {
+            lamports: 1,
+            data,
+            owner: drift_pda(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn keyed_account(pubkey: &Pubkey, account: &Account) -> RpcKeyedAccount {
+        RpcKeyedAccount {
+            pubkey: pubkey.to_string(),
+            account: UiAccount::encode(pubkey, account, UiAccountEncoding::Base64, None, None),
+        }
+    }
+
+    /// Start a local pubsub server which emits `notifications` for every `programSubscribe`
+    async fn mock_pubsub_server(notifications: Vec<(u64, RpcKeyedAccount)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut subscription_id = 0_u64;
+            while let Some(Ok(msg)) = ws.next().await {
+                let Message::Text(text) = msg else {
+                    continue;
+                };
+                let request: Value = serde_json::from_str(text.as_str()).unwrap();
+                let method = request["method"].as_str().unwrap();
+                let result = if method == "getVersion" {
+                    json!({ "solana-core": "1.16.1" })
+                } else {
+                    subscription_id += 1;
+                    json!(subscription_id)
+                };
+                let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+                ws.send(Message::Text(response.to_string())).await.unwrap();
+
+                if method == "programSubscribe" {
+                    for (slot, keyed_account) in notifications.iter() {
+                        let notification = json!({
+                            "jsonrpc": "2.0",
+                            "method": "programNotification",
+                            "params": {
+                                "result": { "context": { "slot": slot }, "value": keyed_account },
+                                "subscription": subscription_id,
+                            }
+                        });
+                        ws.send(Message::Text(notification.to_string()))
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        });
+
+        format!("ws://{addr}")
+    }
+
+    #[tokio::test]
+    async fn track_user_accounts() {
+        let authority = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+
+        let mock_rpc = RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([(
+                RpcRequest::GetProgramAccounts,
+                json!({
+                    "context": { "slot": 100 },
+                    "value": [keyed_account(&user, &user_account(&authority, 1))],
+                }),
+            )]),
+        );
+        let ws_url = mock_pubsub_server(vec![
+            // stale update is ignored
+            (99, keyed_account(&user, &user_account(&authority, 5))),
+            (101, keyed_account(&user, &user_account(&authority, 2))),
+        ])
+        .await;
+
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let tracker = UserAccountTracker::new(Arc::clone(&db), Arc::new(mock_rpc), ws_url);
+        let mirror = tracker.mirror();
+        tokio::spawn(async move { tracker.run(&[authority.to_string()]).await });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mirror.get(&user).map(|u| u.slot) != Some(101) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("mirror updated");
+
+        let tracked = mirror.get(&user).unwrap();
+        assert_eq!({ tracked.user.nextOrderId }, 2);
+        assert_eq!(mirror.by_authority(&authority).len(), 1);
+        assert!(mirror.by_authority(&Pubkey::new_unique()).is_empty());
+
+        let diffs = db.account_diffs(&user).await.unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].slot, 100);
+        assert!(diffs[0].changes.contains_key("authority"));
+        assert_eq!(diffs[1].slot, 101);
+        assert_eq!(
+            diffs[1].changes,
+            BTreeMap::from([("nextOrderId".to_string(), json!(2))])
+        );
+    }
+}
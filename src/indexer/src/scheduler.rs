@@ -0,0 +1,837 @@
+//! Shared account polling
+//!
+//! One `AccountScheduler` polls all active watchlist accounts under a global RPC budget (requests/s). Each round
+//! the most due accounts, by recent activity and time waited, have their signatures queried in one JSON-RPC batch,
+//! accounts with more new signatures than a page are paged back to their last indexed signature.
+//! Txs found for several accounts are fetched once and their events attributed to all of them. A round's txs are
+//! indexed one at a time, oldest first, as the derived views (positions, stats, ..) are read-modify-writes.
+//!
+//! Each account's poll interval adapts to its activity: reset to the min. when new signatures are found, doubled
+//! (up to the max.) when not. The effective intervals are stored in `poll_status`
+//!
+//! When indexing confirmed txs, pending txs are promoted or retracted once final, within the same budget
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_rpc_client_api::{
+    client_error::Error as ClientError, config::RpcSignaturesForAddressConfig,
+    response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use tokio::{
+    select,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::IndexerBackend,
+    program::EventRouter,
+    types::{drift::DriftProgram, IndexerError},
+    watchlist::WatchStatus,
+    Finality, ProgramIndexer, MAX_TXS_PER_PERIOD,
+};
+
+/// Default RPC requests per second shared by the polled accounts
+pub const DEFAULT_RPC_BUDGET: u32 = 10;
+/// Default number of accounts whose signatures are queried per round (one JSON-RPC batch)
+pub const DEFAULT_BATCH_SIZE: usize = 20;
+/// Default min./max. poll interval of an account
+const DEFAULT_POLL_INTERVAL: (Duration, Duration) =
+    (Duration::from_secs(3), Duration::from_secs(60));
+/// How often to start a polling round
+const ROUND_INTERVAL: Duration = Duration::from_millis(500);
+/// Weight of an account's previous activity per poll
+const ACTIVITY_DECAY: f64 = 0.5;
+/// How often to check the finality of provisionally indexed txs
+const FINALIZE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Token bucket limiting RPC requests per second
+pub struct RpcBudget {
+    /// Requests per second, also the burst size
+    rate: f64,
+    /// Requests available, negative when overdrawn by a large batch
+    tokens: f64,
+    /// Time `tokens` was last refilled
+    refilled: Instant,
+}
+
+impl RpcBudget {
+    /// Create a new `RpcBudget` of `requests_per_second`
+    pub fn new(requests_per_second: u32) -> Self {
+        let rate = requests_per_second.max(1) as f64;
+        Self {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Wait until `requests` can be made
+    pub async fn acquire(&mut self, requests: usize) {
+        self.refill();
+        let requests = requests as f64;
+        if self.tokens < requests {
+            let wait = (requests - self.tokens.max(0.0).min(requests)) / self.rate;
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+            self.refill();
+        }
+        self.tokens -= requests;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+    }
+}
+
+/// Polling status of a watched account
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PollStatus {
+    /// The account address
+    pub pubkey: Pubkey,
+    /// Effective poll interval (ms)
+    pub interval_ms: u64,
+    /// Unix timestamp of the last poll
+    pub polled_at: i64,
+    /// New signatures found by the last poll
+    pub new_signatures: u32,
+}
+
+/// Polling state of a watched account
+#[derive(Clone, Debug, Default)]
+struct PolledAccount {
+    /// Labels stamped onto the account's stored events
+    labels: Vec<String>,
+    /// The last indexed tx signature
+    last_signature: Option<Signature>,
+    /// Time of the last poll
+    polled: Option<Instant>,
+    /// Current poll interval
+    interval: Duration,
+    /// Decaying count of the new txs found per poll
+    activity: f64,
+}
+
+impl PolledAccount {
+    /// Return the polling priority of the account at `now`, `None` if polled within its interval
+    ///
+    /// Priority grows with the time waited (relative to the interval) so quiet accounts are not starved by
+    /// active ones
+    fn priority(&self, now: Instant) -> Option<f64> {
+        let Some(polled) = self.polled else {
+            return Some(f64::INFINITY);
+        };
+        let waited = now.duration_since(polled);
+        (waited >= self.interval).then_some(
+            waited.as_secs_f64() / self.interval.as_secs_f64().max(f64::EPSILON)
+                * (1.0 + self.activity),
+        )
+    }
+
+    /// Adapt the poll interval to a poll finding `new_signatures`, within `(min, max)`
+    fn adapt(&mut self, new_signatures: usize, (min, max): (Duration, Duration)) {
+        self.activity = self.activity * ACTIVITY_DECAY + new_signatures as f64;
+        self.interval = if new_signatures > 0 {
+            min
+        } else {
+            (self.interval * 2).clamp(min, max)
+        };
+    }
+}
+
+/// Polls the events of all active accounts of the watchlist
+pub struct AccountScheduler<T: IndexerBackend, P: EventRouter<T> = DriftProgram> {
+    /// Db client
+    db: Arc<T>,
+    /// Indexes the txs found
+    indexer: ProgramIndexer<P, T>,
+    /// HTTP client for batched RPC requests
+    http: reqwest::Client,
+    /// Limits RPC requests, may be shared with the schedulers of other programs
+    budget: Arc<Mutex<RpcBudget>>,
+    /// Max. accounts queried per round
+    batch_size: usize,
+    /// Min./max. poll interval of an account
+    poll_interval: (Duration, Duration),
+    /// The polled accounts
+    accounts: HashMap<Pubkey, PolledAccount>,
+}
+
+impl<T: IndexerBackend, P: EventRouter<T>> AccountScheduler<T, P> {
+    /// Create a new `AccountScheduler` indexing the txs found with `indexer`
+    pub fn new(db: Arc<T>, indexer: ProgramIndexer<P, T>) -> Self {
+        Self {
+            db,
+            indexer,
+            http: reqwest::Client::new(),
+            budget: Arc::new(Mutex::new(RpcBudget::new(DEFAULT_RPC_BUDGET))),
+            batch_size: DEFAULT_BATCH_SIZE,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            accounts: Default::default(),
+        }
+    }
+
+    /// Make at most `requests_per_second` RPC requests
+    pub fn with_rpc_budget(mut self, requests_per_second: u32) -> Self {
+        self.budget = Arc::new(Mutex::new(RpcBudget::new(requests_per_second)));
+        self
+    }
+
+    /// Share `budget` with other schedulers e.g. those of other programs
+    pub fn with_shared_rpc_budget(mut self, budget: Arc<Mutex<RpcBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Return the RPC budget, to share it with other schedulers
+    pub fn rpc_budget(&self) -> Arc<Mutex<RpcBudget>> {
+        Arc::clone(&self.budget)
+    }
+
+    /// Query the signatures of at most `batch_size` accounts per round
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Poll each account every `min`..`max` depending on its activity
+    pub fn with_poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.poll_interval = (min, max.max(min));
+        self
+    }
+
+    /// Run the scheduler
+    /// - `sync_interval` frequency to apply watchlist changes
+    pub async fn run(mut self, sync_interval: Duration) -> Result<(), IndexerError> {
+        let mut sync = tokio::time::interval(sync_interval);
+        let mut round = tokio::time::interval(ROUND_INTERVAL);
+        let mut finalize = tokio::time::interval(FINALIZE_INTERVAL);
+        loop {
+            select! {
+                _ = sync.tick() => self.sync().await?,
+                _ = round.tick() => {
+                    if let Err(err) = self.poll().await {
+                        warn!("polling round failed: {err:?}");
+                    }
+                }
+                _ = finalize.tick(), if self.indexer.provisional() => {
+                    if let Err(err) = self.finalize().await {
+                        warn!("finalizing pending txs failed: {err:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Promote or retract the provisionally indexed txs which reached finality, returns the number finalized
+    pub async fn finalize(&mut self) -> Result<usize, IndexerError> {
+        // finalized slot and signature statuses
+        self.budget.lock().await.acquire(2).await;
+        let final_txs: Vec<_> = self
+            .indexer
+            .pending_finality()
+            .await?
+            .into_iter()
+            .filter(|(_, finality)| *finality != Finality::Pending)
+            .collect();
+        // finalized txs are fetched again
+        self.budget
+            .lock()
+            .await
+            .acquire(
+                final_txs
+                    .iter()
+                    .filter(|(_, finality)| *finality == Finality::Finalized)
+                    .count(),
+            )
+            .await;
+        for (tx, finality) in final_txs.iter() {
+            self.indexer.finalize(tx, *finality).await?;
+        }
+        Ok(final_txs.len())
+    }
+
+    /// Return the polled accounts
+    pub fn accounts(&self) -> Vec<Pubkey> {
+        self.accounts.keys().copied().collect()
+    }
+
+    /// Add/remove polled accounts to match the watchlist
+    pub async fn sync(&mut self) -> Result<(), IndexerError> {
+        let active: HashMap<Pubkey, Vec<String>> = self
+            .db
+            .watched_accounts()
+            .await?
+            .into_iter()
+            .filter(|a| {
+                a.status == WatchStatus::Active && a.program_id() == self.indexer.program_id()
+            })
+            .map(|a| (a.pubkey, a.labels))
+            .collect();
+
+        self.accounts.retain(|pubkey, _| {
+            let keep = active.contains_key(pubkey);
+            if !keep {
+                info!("stopped polling: {pubkey}");
+            }
+            keep
+        });
+        for (pubkey, labels) in active {
+            if let Some(account) = self.accounts.get_mut(&pubkey) {
+                account.labels = labels;
+                continue;
+            }
+            info!("polling: {pubkey} {labels:?}");
+            let last_signature = self
+                .db
+                .last_indexed_signature(&self.indexer.program_id(), &pubkey)
+                .await?;
+            self.accounts.insert(
+                pubkey,
+                PolledAccount {
+                    labels,
+                    last_signature,
+                    interval: self.poll_interval.0,
+                    ..Default::default()
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Poll the most due accounts (at most `batch_size`), returns the number of txs indexed
+    pub async fn poll(&mut self) -> Result<usize, IndexerError> {
+        let now = Instant::now();
+        let mut due: Vec<(f64, Pubkey)> = self
+            .accounts
+            .iter()
+            .filter_map(|(pubkey, a)| Some((a.priority(now)?, *pubkey)))
+            .collect();
+        if due.is_empty() {
+            return Ok(0);
+        }
+        due.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let accounts: Vec<Pubkey> = due
+            .into_iter()
+            .take(self.batch_size)
+            .map(|(_, pubkey)| pubkey)
+            .collect();
+
+        self.budget.lock().await.acquire(accounts.len()).await;
+        let results = self.signatures(accounts.as_slice()).await?;
+        // the slot and position (in its first account's signatures) of each tx and the accounts it was found for
+        let mut txs = HashMap::<String, (u64, usize, Vec<Pubkey>)>::new();
+        let mut found = Vec::<(Pubkey, Vec<String>)>::new();
+        let polled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        for (pubkey, signatures) in accounts.iter().zip(results) {
+            let account = self.accounts.get_mut(pubkey).expect("polled account");
+            account.polled = Some(now);
+            let Some(signatures) = signatures else {
+                continue;
+            };
+            let interval = account.interval;
+            account.adapt(signatures.len(), self.poll_interval);
+            if account.interval != interval {
+                debug!("poll interval of {pubkey}: {:?}", account.interval);
+            }
+            self.db
+                .upsert_poll_status(PollStatus {
+                    pubkey: *pubkey,
+                    interval_ms: account.interval.as_millis() as u64,
+                    polled_at,
+                    new_signatures: signatures.len() as u32,
+                })
+                .await?;
+            for (i, status) in signatures.iter().enumerate() {
+                txs.entry(status.signature.clone())
+                    .or_insert_with(|| (status.slot, i, Vec::new()))
+                    .2
+                    .push(*pubkey);
+            }
+            // newest first
+            found.push((
+                *pubkey,
+                signatures.into_iter().map(|s| s.signature).collect(),
+            ));
+        }
+        if txs.is_empty() {
+            return Ok(0);
+        }
+        debug!(
+            "fetching {} txs found for {} accounts",
+            txs.len(),
+            found.len()
+        );
+
+        self.budget.lock().await.acquire(txs.len()).await;
+        let mut txs: Vec<_> = txs
+            .into_iter()
+            .map(|(signature, (slot, i, accounts))| {
+                let mut labels = Vec::<String>::new();
+                for label in accounts.iter().flat_map(|a| self.accounts[a].labels.iter()) {
+                    if !labels.contains(label) {
+                        labels.push(label.clone());
+                    }
+                }
+                (signature, (slot, i), accounts, labels)
+            })
+            .collect();
+        // oldest first, signatures are newest first
+        txs.sort_by_key(|(_, (slot, i), _, _)| (*slot, Reverse(*i)));
+        let mut failed = HashSet::<&String>::new();
+        for (signature, _, accounts, labels) in txs.iter() {
+            if let Err(err) = self
+                .indexer
+                .index_transaction(accounts.as_slice(), labels.as_slice(), signature.as_str())
+                .await
+            {
+                warn!("failed indexing tx {signature}: {err:?}");
+                failed.insert(signature);
+            }
+        }
+
+        // accounts with a failed tx are retried from their last indexed signature
+        for (pubkey, signatures) in found {
+            if signatures.iter().any(|s| failed.contains(s)) {
+                continue;
+            }
+            let Some(latest) = signatures.first() else {
+                continue;
+            };
+            let latest =
+                Signature::from_str(latest.as_str()).map_err(|_| IndexerError::InvalidSignature)?;
+            self.db
+                .update_last_indexed_signature(&self.indexer.program_id(), &pubkey, &latest)
+                .await?;
+            if let Some(account) = self.accounts.get_mut(&pubkey) {
+                account.last_signature = Some(latest);
+            }
+        }
+
+        Ok(txs.len() - failed.len())
+    }
+
+    /// Return the latest signatures of each of `accounts` since its last indexed signature (newest first),
+    /// queried in JSON-RPC batches. `None` for accounts whose query failed
+    ///
+    /// Accounts with a full page of signatures are paged back until their last indexed signature so txs are not
+    /// skipped while an account is backed off, new accounts only index their latest page
+    async fn signatures(
+        &self,
+        accounts: &[Pubkey],
+    ) -> Result<Vec<Option<Vec<RpcConfirmedTransactionStatusWithSignature>>>, IndexerError> {
+        let queries: Vec<(Pubkey, Option<String>)> = accounts.iter().map(|a| (*a, None)).collect();
+        let mut results = self.query_signatures(queries.as_slice()).await?;
+        // (account index, oldest signature found) of the accounts with more pages
+        let more = |id: usize, page: &[RpcConfirmedTransactionStatusWithSignature]| {
+            let full = page.len() >= MAX_TXS_PER_PERIOD;
+            let oldest = page.last().filter(|_| full)?;
+            self.accounts[&accounts[id]]
+                .last_signature
+                .map(|_| (id, oldest.signature.clone()))
+        };
+        let mut pending: Vec<(usize, String)> = results
+            .iter()
+            .enumerate()
+            .filter_map(|(id, page)| more(id, page.as_deref()?))
+            .collect();
+        while !pending.is_empty() {
+            debug!("paging signatures of {} accounts", pending.len());
+            self.budget.lock().await.acquire(pending.len()).await;
+            let queries: Vec<(Pubkey, Option<String>)> = pending
+                .iter()
+                .map(|(id, before)| (accounts[*id], Some(before.clone())))
+                .collect();
+            let pages = self.query_signatures(queries.as_slice()).await?;
+            let mut next = Vec::new();
+            for ((id, _), page) in pending.into_iter().zip(pages) {
+                // a failed page fails the account's poll, it is retried from its last indexed signature
+                let Some(page) = page else {
+                    results[id] = None;
+                    continue;
+                };
+                next.extend(more(id, page.as_slice()));
+                if let Some(signatures) = results[id].as_mut() {
+                    signatures.extend(page);
+                }
+            }
+            pending = next;
+        }
+
+        Ok(results)
+    }
+
+    /// Query the signatures of each `(account, before)` since the account's last indexed signature (newest first)
+    /// in one JSON-RPC batch. `None` for accounts whose query failed
+    async fn query_signatures(
+        &self,
+        queries: &[(Pubkey, Option<String>)],
+    ) -> Result<Vec<Option<Vec<RpcConfirmedTransactionStatusWithSignature>>>, IndexerError> {
+        let requests: Vec<Value> = queries
+            .iter()
+            .enumerate()
+            .map(|(id, (pubkey, before))| {
+                let config = RpcSignaturesForAddressConfig {
+                    before: before.clone(),
+                    until: self.accounts[pubkey].last_signature.map(|s| s.to_string()),
+                    limit: Some(MAX_TXS_PER_PERIOD),
+                    commitment: Some(self.indexer.commitment),
+                    ..Default::default()
+                };
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "getSignaturesForAddress",
+                    "params": [pubkey.to_string(), config],
+                })
+            })
+            .collect();
+        let responses: Vec<Value> = self
+            .http
+            .post(self.indexer.rpc.url())
+            .json(&requests)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(ClientError::from)?
+            .json()
+            .await
+            .map_err(ClientError::from)?;
+
+        let mut results = vec![None; queries.len()];
+        for response in responses {
+            let Some(id) = response["id"].as_u64().map(|id| id as usize) else {
+                warn!("unexpected RPC response: {response}");
+                continue;
+            };
+            let Some((pubkey, _)) = queries.get(id) else {
+                continue;
+            };
+            match serde_json::from_value(response["result"].clone()) {
+                Ok(signatures) => results[id] = Some(signatures),
+                Err(_) => warn!(
+                    "failed querying signatures of {pubkey}: {}",
+                    response["error"]
+                ),
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, routing::post, Json, Router};
+    use solana_sdk::{
+        message::{v0, MessageHeader, VersionedMessage},
+        transaction::VersionedTransaction,
+    };
+    use solana_transaction_status::{
+        ConfirmedTransactionWithStatusMeta, TransactionStatusMeta, TransactionWithStatusMeta,
+        UiTransactionEncoding, VersionedTransactionWithStatusMeta,
+    };
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::{ORDER_ACTION_RECORD, SIGNATURE, SLOT},
+        types::drift_pda,
+        watchlist::watch,
+        DriftEventIndexer, RpcClient,
+    };
+
+    /// Mock RPC state: the tx returned, the signatures of any account (newest first) and request counts
+    /// (signature batches, txs)
+    struct MockRpc {
+        tx: Value,
+        signatures: Vec<String>,
+        batches: AtomicUsize,
+        txs: AtomicUsize,
+    }
+
+    async fn handle(State(rpc): State<Arc<MockRpc>>, Json(request): Json<Value>) -> Json<Value> {
+        let respond = |request: &Value| {
+            let result = match request["method"].as_str().unwrap() {
+                "getSignaturesForAddress" => {
+                    let config = &request["params"][1];
+                    let position = |key: &str| {
+                        let signature = config[key].as_str()?;
+                        rpc.signatures.iter().position(|s| s == signature)
+                    };
+                    let from = position("before").map_or(0, |i| i + 1);
+                    let to = position("until").unwrap_or(rpc.signatures.len());
+                    let limit = config["limit"].as_u64().unwrap() as usize;
+                    Value::Array(
+                        rpc.signatures[from..to.max(from)]
+                            .iter()
+                            .take(limit)
+                            .map(|signature| {
+                                json!({
+                                    "signature": signature,
+                                    "slot": SLOT,
+                                    "err": null,
+                                    "memo": null,
+                                    "blockTime": null,
+                                    "confirmationStatus": null,
+                                })
+                            })
+                            .collect(),
+                    )
+                }
+                "getTransaction" => {
+                    rpc.txs.fetch_add(1, Ordering::SeqCst);
+                    rpc.tx.clone()
+                }
+                _ => json!({ "solana-core": "1.16.1" }),
+            };
+            json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+        };
+        Json(match request {
+            Value::Array(requests) => {
+                rpc.batches.fetch_add(1, Ordering::SeqCst);
+                Value::Array(requests.iter().map(respond).collect())
+            }
+            request => respond(&request),
+        })
+    }
+
+    /// Start a local RPC server returning a fill between `taker` and `maker` for any account
+    async fn mock_rpc_server(rpc: Arc<MockRpc>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", post(handle)).with_state(rpc);
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        format!("http://{addr}")
+    }
+
+    /// Mock RPC state returning a fill for any of `signatures` (newest first)
+    fn mock_rpc(signatures: Vec<String>) -> Arc<MockRpc> {
+        let tx = ConfirmedTransactionWithStatusMeta {
+            slot: SLOT,
+            tx_with_meta: TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+                transaction: VersionedTransaction {
+                    message: VersionedMessage::V0(v0::Message {
+                        header: MessageHeader {
+                            num_required_signatures: 1,
+                            ..Default::default()
+                        },
+                        account_keys: vec![drift_pda()],
+                        ..Default::default()
+                    }),
+                    signatures: vec![Signature::new_unique()],
+                },
+                meta: TransactionStatusMeta {
+                    log_messages: Some(vec![
+                        "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH invoke [1]"
+                            .to_string(),
+                        "Program log: Instruction: FillPerpOrder".to_string(),
+                        ORDER_ACTION_RECORD.to_string(),
+                        "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH success".to_string(),
+                    ]),
+                    ..Default::default()
+                },
+            }),
+            block_time: None,
+        }
+        .encode(UiTransactionEncoding::Base64, Some(0))
+        .expect("it encodes");
+        Arc::new(MockRpc {
+            tx: serde_json::to_value(tx).unwrap(),
+            signatures,
+            batches: AtomicUsize::new(0),
+            txs: AtomicUsize::new(0),
+        })
+    }
+
+    #[tokio::test]
+    async fn poll_shared_txs() {
+        let mock = mock_rpc(vec![SIGNATURE.to_string()]);
+        let rpc = Arc::new(RpcClient::new(mock_rpc_server(Arc::clone(&mock)).await));
+
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let taker = Pubkey::from_str("H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG").unwrap();
+        let maker = Pubkey::from_str("4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV").unwrap();
+        let quiet = Pubkey::new_unique();
+        watch(db.as_ref(), taker, vec!["mm".to_string()], None)
+            .await
+            .unwrap();
+        watch(db.as_ref(), maker, vec!["desk".to_string()], None)
+            .await
+            .unwrap();
+        watch(db.as_ref(), quiet, Vec::new(), None).await.unwrap();
+        // indexed for another program, not polled by the drift scheduler
+        let vault = Pubkey::new_unique();
+        watch(db.as_ref(), vault, Vec::new(), Some(Pubkey::new_unique()))
+            .await
+            .unwrap();
+
+        let mut scheduler = AccountScheduler::new(
+            Arc::clone(&db),
+            DriftEventIndexer::new(Arc::clone(&db), rpc),
+        )
+        .with_rpc_budget(100)
+        .with_poll_interval(Duration::from_secs(60), Duration::from_secs(600));
+        scheduler.sync().await.unwrap();
+        assert_eq!(scheduler.accounts().len(), 3);
+        assert!(!scheduler.accounts().contains(&vault));
+
+        // one signature batch, the tx found for all accounts is fetched once
+        assert_eq!(scheduler.poll().await.unwrap(), 1);
+        assert_eq!(mock.batches.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.txs.load(Ordering::SeqCst), 1);
+        // none due
+        assert_eq!(scheduler.poll().await.unwrap(), 0);
+        assert_eq!(mock.batches.load(Ordering::SeqCst), 1);
+
+        // stored once with the labels of all accounts, stats attributed to each
+        let records = db.order_action_records_of(&maker, 10, false).await.unwrap();
+        assert_eq!(records.len(), 1);
+        let mut labels = records[0].labels.clone();
+        labels.sort();
+        assert_eq!(labels, ["desk", "mm"]);
+        for account in [taker, maker, quiet] {
+            assert_eq!(
+                db.last_indexed_signature(&drift_pda(), &account)
+                    .await
+                    .unwrap(),
+                Some(Signature::from_str(SIGNATURE).unwrap())
+            );
+        }
+        assert_eq!(db.account_stats_of(&taker, None).await.unwrap().len(), 1);
+        assert_eq!(db.account_stats_of(&maker, None).await.unwrap().len(), 1);
+        assert!(db.account_stats_of(&quiet, None).await.unwrap().is_empty());
+
+        // new signatures found, polled at the min. interval
+        let statuses = db.poll_statuses().await.unwrap();
+        assert_eq!(statuses.len(), 3);
+        assert!(statuses
+            .iter()
+            .all(|s| s.interval_ms == 60_000 && s.new_signatures == 1));
+    }
+
+    #[tokio::test]
+    async fn poll_pages_signatures() {
+        // more new signatures than a page, newest first
+        let last = Signature::new_unique();
+        let new: Vec<String> = (0..MAX_TXS_PER_PERIOD * 2 + 1)
+            .map(|_| Signature::new_unique().to_string())
+            .collect();
+        let mock = mock_rpc(new.iter().cloned().chain([last.to_string()]).collect());
+        let rpc = Arc::new(RpcClient::new(mock_rpc_server(Arc::clone(&mock)).await));
+
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let account = Pubkey::new_unique();
+        watch(db.as_ref(), account, Vec::new(), None).await.unwrap();
+        db.update_last_indexed_signature(&drift_pda(), &account, &last)
+            .await
+            .unwrap();
+
+        let mut scheduler = AccountScheduler::new(
+            Arc::clone(&db),
+            DriftEventIndexer::new(Arc::clone(&db), rpc),
+        )
+        .with_rpc_budget(100);
+        scheduler.sync().await.unwrap();
+
+        // all new txs are indexed, paged back to the last indexed signature
+        assert_eq!(scheduler.poll().await.unwrap(), new.len());
+        assert_eq!(mock.batches.load(Ordering::SeqCst), 3);
+        assert_eq!(mock.txs.load(Ordering::SeqCst), new.len());
+        assert_eq!(
+            db.last_indexed_signature(&drift_pda(), &account)
+                .await
+                .unwrap(),
+            Some(Signature::from_str(new[0].as_str()).unwrap())
+        );
+        let statuses = db.poll_statuses().await.unwrap();
+        assert_eq!(statuses[0].new_signatures, new.len() as u32);
+    }
+
+    #[tokio::test]
+    async fn poll_applies_txs_in_order() {
+        // two fills of the taker in one round
+        let signatures: Vec<String> = (0..2)
+            .map(|_| Signature::new_unique().to_string())
+            .collect();
+        let mock = mock_rpc(signatures);
+        let rpc = Arc::new(RpcClient::new(mock_rpc_server(Arc::clone(&mock)).await));
+
+        // the derived views' reads yield, interleaving txs indexed concurrently
+        let db = Arc::new(MockBackend::init("mockdb").await.with_yielding_reads());
+        let taker = Pubkey::from_str("H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG").unwrap();
+        watch(db.as_ref(), taker, Vec::new(), None).await.unwrap();
+
+        let mut scheduler = AccountScheduler::new(
+            Arc::clone(&db),
+            DriftEventIndexer::new(Arc::clone(&db), rpc),
+        )
+        .with_rpc_budget(100);
+        scheduler.sync().await.unwrap();
+        assert_eq!(scheduler.poll().await.unwrap(), 2);
+
+        // no update is lost
+        let stats = db.account_stats_of(&taker, None).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].stats.taker_fills, 2);
+        let positions = db.positions_of(&taker).await.unwrap();
+        assert_eq!(positions[0].base_asset_amount, 2 * 1_500_000);
+    }
+
+    #[test]
+    fn adaptive_interval() {
+        let bounds = (Duration::from_secs(2), Duration::from_secs(10));
+        let mut account = PolledAccount {
+            interval: bounds.0,
+            ..Default::default()
+        };
+        let now = Instant::now();
+        assert_eq!(account.priority(now), Some(f64::INFINITY));
+
+        // backs off exponentially while idle
+        let intervals: Vec<u64> = (0..4)
+            .map(|_| {
+                account.adapt(0, bounds);
+                account.interval.as_secs()
+            })
+            .collect();
+        assert_eq!(intervals, [4, 8, 10, 10]);
+        account.polled = Some(now);
+        assert_eq!(account.priority(now + Duration::from_secs(9)), None);
+        assert!(account.priority(now + Duration::from_secs(10)).is_some());
+
+        // resets when active
+        account.adapt(3, bounds);
+        assert_eq!(account.interval, bounds.0);
+        // active accounts are preferred at the same relative wait
+        let idle = PolledAccount {
+            polled: Some(now),
+            interval: bounds.0,
+            ..Default::default()
+        };
+        let later = now + Duration::from_secs(4);
+        assert!(account.priority(later) > idle.priority(later));
+    }
+}
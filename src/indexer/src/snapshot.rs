@@ -0,0 +1,404 @@
+//! Drift account state snapshots
+//!
+//! Periodically fetches drift program accounts and persists a versioned copy whenever the account data changes,
+//! optionally including the `InsuranceFundStake` accounts of all indexed stakers. Snapshots store the decoded
+//! account so the history of positions, orders and balances can be queried e.g.
+//! `{ "account.perpPositions.marketIndex": 0 }`
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use log::{debug, info, warn};
+use mongodb::bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccountEncoding;
+use solana_rpc_client_api::config::RpcAccountInfoConfig;
+use solana_sdk::{hash::Hash, pubkey::Pubkey};
+use tokio::select;
+
+use crate::{
+    db::IndexerBackend,
+    dynamic::to_bson,
+    insurance::if_stake_pda,
+    types::{drift::accounts::DriftAccount, drift_pda, IndexerError},
+    RpcClient,
+};
+
+/// Max. number of accounts per `getMultipleAccounts` request (RPC enforced)
+pub(crate) const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// Account types whose raw data is always stored, derived views decode it (see `LpPool`, `IfStakeSnapshot`)
+const RAW_DATA_ACCOUNTS: &[&str] = &["PerpMarket", "InsuranceFundStake"];
+
+/// A drift account's state as of `slot`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    /// The account address
+    pub pubkey: Pubkey,
+    /// Slot the account data was observed at
+    pub slot: u64,
+    /// IDL name of the account type e.g. 'User'
+    pub kind: String,
+    /// The decoded account fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<Document>,
+    /// Hash of the raw account data, used to skip unchanged accounts
+    #[serde(default)]
+    pub hash: String,
+    /// Raw account data (including discriminant), omitted with `--snapshot-decoded-only` unless a derived view reads it
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u8>>,
+}
+
+impl AccountSnapshot {
+    /// Decode the snapshot data into its drift account type, `None` if the raw data was not stored
+    pub fn decode(&self) -> Option<DriftAccount> {
+        DriftAccount::try_from_bytes(self.data.as_deref()?)
+    }
+
+    /// Return the hash of the snapshotted account data
+    fn data_hash(&self) -> Option<Hash> {
+        match self.data {
+            // snapshots stored before hashes were
+            Some(ref data) if self.hash.is_empty() => Some(solana_sdk::hash::hash(data)),
+            _ => Hash::from_str(self.hash.as_str()).ok(),
+        }
+    }
+}
+
+/// Provides snapshotting of drift account states
+pub struct AccountSnapshotter<T: IndexerBackend> {
+    /// Db client
+    db: Arc<T>,
+    /// Solana RPC client
+    rpc: Arc<RpcClient>,
+    /// Hash of the last persisted data per account, used to skip unchanged accounts
+    last_hashes: HashMap<Pubkey, Hash>,
+    /// Also snapshot the `InsuranceFundStake` accounts of stakers with indexed stake events
+    if_stakes: bool,
+    /// Also store the raw data of accounts not read by derived views
+    raw_data: bool,
+}
+
+impl<T: IndexerBackend> AccountSnapshotter<T> {
+    /// Create a new `AccountSnapshotter`
+    pub fn new(db: Arc<T>, rpc: Arc<RpcClient>) -> Self {
+        Self {
+            db,
+            rpc,
+            last_hashes: Default::default(),
+            if_stakes: false,
+            raw_data: true,
+        }
+    }
+
+    /// Store only the decoded accounts, not their raw data
+    ///
+    /// The raw data of `PerpMarket` and `InsuranceFundStake` accounts is still stored for the LP and IF stake views
+    pub fn decoded_only(mut self) -> Self {
+        self.raw_data = false;
+        self
+    }
+
+    /// Also snapshot the `InsuranceFundStake` accounts of stakers with indexed stake events
+    pub fn with_if_stakes(mut self) -> Self {
+        self.if_stakes = true;
+        self
+    }
+
+    /// Run the snapshotter for `accounts`
+    /// - `poll_interval` frequency to fetch account states
+    pub async fn run(
+        mut self,
+        accounts: &[String],
+        poll_interval: Duration,
+    ) -> Result<(), IndexerError> {
+        let accounts = accounts
+            .iter()
+            .map(|a| Pubkey::try_from(a.as_str()))
+            .collect::<Result<Vec<Pubkey>, _>>()
+            .map_err(|_| IndexerError::InvalidPublicKey)?;
+        let mut poll = tokio::time::interval(poll_interval);
+        loop {
+            select! {
+                _ = poll.tick() => {
+                    let accounts = self.with_discovered(accounts.as_slice()).await?;
+                    self.snapshot_accounts(accounts.as_slice()).await?
+                }
+            }
+        }
+    }
+
+    /// Return `accounts` and the `InsuranceFundStake` accounts of indexed stakers (if enabled)
+    async fn with_discovered(&self, accounts: &[Pubkey]) -> Result<Vec<Pubkey>, IndexerError> {
+        let mut accounts = accounts.to_vec();
+        if self.if_stakes {
+            for (authority, market_index) in self.db.if_stakers().await? {
+                accounts.push(if_stake_pda(&authority, market_index));
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Fetch `accounts` and persist a snapshot of any which changed since last observed
+    async fn snapshot_accounts(&mut self, accounts: &[Pubkey]) -> Result<(), IndexerError> {
+        for chunk in accounts.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+            let response = self
+                .rpc
+                .get_multiple_accounts_with_config(
+                    chunk,
+                    RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            let slot = response.context.slot;
+
+            for (pubkey, account) in chunk.iter().zip(response.value) {
+                let Some(account) = account else {
+                    warn!("account not found: {pubkey}");
+                    continue;
+                };
+                if account.owner != drift_pda() {
+                    warn!("account not owned by drift: {pubkey}");
+                    continue;
+                }
+                let Some(decoded) = DriftAccount::try_from_bytes(account.data.as_slice()) else {
+                    warn!("account has unknown discriminant: {pubkey}");
+                    continue;
+                };
+
+                let hash = solana_sdk::hash::hash(account.data.as_slice());
+                if self.last_hash(pubkey).await? == Some(hash) {
+                    debug!("account unchanged: {pubkey}");
+                    continue;
+                }
+                // via json so u64 values beyond i64 are stored as `Decimal128`, like the runtime IDL's
+                let fields = match serde_json::to_value(&decoded) {
+                    Ok(value) => match to_bson(&value) {
+                        Bson::Document(document) => Some(document),
+                        _ => None,
+                    },
+                    Err(err) => {
+                        warn!("failed serializing account {pubkey}: {err:?}");
+                        None
+                    }
+                };
+                let raw_data = self.raw_data || RAW_DATA_ACCOUNTS.contains(&decoded.name());
+
+                info!("snapshot {} account: {pubkey} @ {slot}", decoded.name());
+                self.db
+                    .insert_account_snapshot(AccountSnapshot {
+                        pubkey: *pubkey,
+                        slot,
+                        kind: decoded.name().to_string(),
+                        account: fields,
+                        hash: hash.to_string(),
+                        data: raw_data.then_some(account.data),
+                    })
+                    .await?;
+                self.last_hashes.insert(*pubkey, hash);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the hash of the last persisted data of `pubkey`, read from its latest stored snapshot once
+    async fn last_hash(&mut self, pubkey: &Pubkey) -> Result<Option<Hash>, IndexerError> {
+        if let Some(hash) = self.last_hashes.get(pubkey) {
+            return Ok(Some(*hash));
+        }
+        let stored = self
+            .db
+            .latest_account_snapshot(pubkey)
+            .await?
+            .and_then(|snapshot| snapshot.data_hash());
+        if let Some(hash) = stored {
+            self.last_hashes.insert(*pubkey, hash);
+        }
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use anchor_lang::Discriminator;
+    use solana_account_decoder::UiAccount;
+    use solana_rpc_client_api::{
+        request::RpcRequest,
+        response::{Response, RpcResponseContext},
+    };
+    use solana_sdk::account::Account;
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        drift::accounts::{PerpMarket, User},
+        lp::LpPool,
+    };
+
+    /// Size of the `User` account data (excluding discriminant)
+    const USER_SIZE: usize = 4_368;
+    /// Size of the `PerpMarket` account data (excluding discriminant)
+    const PERP_MARKET_SIZE: usize = 1_208;
+
+    fn mock_rpc(slot: u64, accounts: &[(Pubkey, Account)]) -> RpcClient {
+        let response = Response {
+            context: RpcResponseContext::new(slot),
+            value: accounts
+                .iter()
+                .map(|(pubkey, account)| {
+                    Some(UiAccount::encode(
+                        pubkey,
+                        account,
+                        UiAccountEncoding::Base64,
+                        None,
+                        None,
+                    ))
+                })
+                .collect::<Vec<Option<UiAccount>>>(),
+        };
+        RpcClient::new_mock_with_mocks(
+            "http://example.com".to_string(),
+            HashMap::from([(
+                RpcRequest::GetMultipleAccounts,
+                serde_json::to_value(response).expect("it serializes"),
+            )]),
+        )
+    }
+
+    fn user_account() -> Account {
+        let mut data = User::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&[0_u8; USER_SIZE]);
+        Account {
+            lamports: 1,
+            data,
+            owner: drift_pda(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn changed_account() -> Account {
+        let mut changed = user_account();
+        changed.data[8] = 1;
+        changed
+    }
+
+    #[tokio::test]
+    async fn snapshot_accounts() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let user = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let mut unknown_account = user_account();
+        unknown_account.data[0] = unknown_account.data[0].wrapping_add(1);
+
+        let mut snapshotter = AccountSnapshotter::new(
+            Arc::clone(&db),
+            Arc::new(mock_rpc(
+                100,
+                &[(user, user_account()), (unknown, unknown_account)],
+            )),
+        );
+        snapshotter
+            .snapshot_accounts(&[user, unknown])
+            .await
+            .expect("it snapshots");
+
+        let snapshots = db.account_snapshots(&user).await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].slot, 100);
+        assert_eq!(snapshots[0].kind, "User");
+        assert!(matches!(snapshots[0].decode(), Some(DriftAccount::User(_))));
+        let account = snapshots[0].account.as_ref().expect("it decodes");
+        assert_eq!(
+            account.get_array("authority").unwrap(),
+            &vec![Bson::Int64(0); 32]
+        );
+        assert_eq!(
+            account.get_array("perpPositions").unwrap().len(),
+            8,
+            "positions are queryable"
+        );
+        assert!(db.account_snapshots(&unknown).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn snapshot_accounts_skips_unchanged() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let user = Pubkey::new_unique();
+
+        let mut snapshotter = AccountSnapshotter::new(
+            Arc::clone(&db),
+            Arc::new(mock_rpc(100, &[(user, user_account())])),
+        );
+        snapshotter.snapshot_accounts(&[user]).await.unwrap();
+        snapshotter.rpc = Arc::new(mock_rpc(101, &[(user, user_account())]));
+        snapshotter.snapshot_accounts(&[user]).await.unwrap();
+
+        snapshotter.rpc = Arc::new(mock_rpc(102, &[(user, changed_account())]));
+        snapshotter.snapshot_accounts(&[user]).await.unwrap();
+
+        let slots: Vec<u64> = db
+            .account_snapshots(&user)
+            .await
+            .unwrap()
+            .iter()
+            .map(|s| s.slot)
+            .collect();
+        assert_eq!(slots, vec![100, 102]);
+
+        // restarted, unchanged since the stored snapshot
+        let mut restarted = AccountSnapshotter::new(
+            Arc::clone(&db),
+            Arc::new(mock_rpc(103, &[(user, changed_account())])),
+        )
+        .decoded_only();
+        restarted.snapshot_accounts(&[user]).await.unwrap();
+        assert_eq!(db.account_snapshots(&user).await.unwrap().len(), 2);
+
+        restarted.rpc = Arc::new(mock_rpc(104, &[(user, user_account())]));
+        restarted.snapshot_accounts(&[user]).await.unwrap();
+        let latest = db.latest_account_snapshot(&user).await.unwrap().unwrap();
+        assert_eq!(latest.slot, 104);
+        assert!(latest.data.is_none() && latest.account.is_some());
+    }
+
+    #[tokio::test]
+    async fn decoded_only_keeps_derived_view_data() {
+        let db = Arc::new(MockBackend::init("mockdb").await);
+        let market = Pubkey::new_unique();
+        let mut data = PerpMarket::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&[0_u8; PERP_MARKET_SIZE]);
+        // amm.sqrtK, above u64::MAX
+        data[256..272].copy_from_slice(&(u64::MAX as u128 + 1).to_le_bytes());
+        let account = Account {
+            data,
+            ..user_account()
+        };
+
+        let mut snapshotter = AccountSnapshotter::new(
+            Arc::clone(&db),
+            Arc::new(mock_rpc(100, &[(market, account)])),
+        )
+        .decoded_only();
+        snapshotter.snapshot_accounts(&[market]).await.unwrap();
+
+        let snapshot = db.latest_account_snapshot(&market).await.unwrap().unwrap();
+        assert!(snapshot.data.is_some());
+        let pool = LpPool::decode(&snapshot).expect("it decodes");
+        assert_eq!(pool.sqrt_k, u64::MAX as u128 + 1);
+        assert_eq!(
+            snapshot
+                .account
+                .as_ref()
+                .unwrap()
+                .get_document("amm")
+                .unwrap()
+                .get_str("sqrtK"),
+            Ok("18446744073709551616")
+        );
+    }
+}
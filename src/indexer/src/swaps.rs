@@ -0,0 +1,297 @@
+//! Spot swaps (`beginSwap`/`endSwap`)
+//!
+//! `SwapRecord`s are stored in `swaps` with the route of the swap: the programs invoked by the instructions
+//! between its `beginSwap` and `endSwap` (incl. their CPIs) e.g. a Jupiter route through Phoenix and Orca
+use serde::{Deserialize, Serialize};
+use solana_sdk::{compute_budget, pubkey, pubkey::Pubkey, system_program};
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    instructions::TxInstructions,
+    program::EventContext,
+    types::{
+        drift::{events::SwapRecord, instructions::DriftInstruction},
+        drift_pda,
+    },
+};
+
+/// Known swap venues (program, name)
+pub const SWAP_VENUES: &[(Pubkey, &str)] = &[
+    (
+        pubkey!("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4"),
+        "Jupiter v6",
+    ),
+    (
+        pubkey!("JUP4Fb2cqiRUcaTHdrPC8h2gNsA2ETXiPDD33WcGuJB"),
+        "Jupiter v4",
+    ),
+    (
+        pubkey!("PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY"),
+        "Phoenix",
+    ),
+    (
+        pubkey!("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX"),
+        "OpenBook",
+    ),
+    (
+        pubkey!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"),
+        "Serum v3",
+    ),
+    (
+        pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc"),
+        "Orca Whirlpool",
+    ),
+    (
+        pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8"),
+        "Raydium AMM",
+    ),
+    (
+        pubkey!("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK"),
+        "Raydium CLMM",
+    ),
+];
+
+/// Programs moving the swapped tokens rather than routing the swap
+const INFRA_PROGRAMS: &[Pubkey] = &[
+    pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"),
+    pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb"),
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"),
+    system_program::ID,
+    compute_budget::ID,
+];
+
+/// Return the name of the swap venue `program`, if known
+pub fn venue_name(program: &Pubkey) -> Option<&'static str> {
+    SWAP_VENUES
+        .iter()
+        .find(|(venue, _)| venue == program)
+        .map(|(_, name)| *name)
+}
+
+/// A swap of a user's spot deposits
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Swap {
+    pub ts: i64,
+    /// The `User` account
+    pub user: Pubkey,
+    pub amount_out: u64,
+    pub amount_in: u64,
+    pub out_market_index: u16,
+    pub in_market_index: u16,
+    pub out_oracle_price: i64,
+    pub in_oracle_price: i64,
+    pub fee: u64,
+    /// Slot of the tx
+    pub slot: u64,
+    /// Signature of the tx
+    pub signature: String,
+    /// Index of the event's log in the tx
+    pub log_index: u32,
+    /// Programs the swap routed through (invocation order)
+    pub route: Vec<Pubkey>,
+    /// Names of the known venues of the route e.g. 'Jupiter v6'
+    pub venues: Vec<String>,
+}
+
+impl Swap {
+    /// Return the swap of `record` emitted in the tx `ctx`, routed through `route`
+    pub fn from_record(record: &SwapRecord, ctx: &EventContext, route: Vec<Pubkey>) -> Self {
+        Self {
+            ts: record.ts,
+            user: record.user,
+            amount_out: record.amountOut,
+            amount_in: record.amountIn,
+            out_market_index: record.outMarketIndex,
+            in_market_index: record.inMarketIndex,
+            out_oracle_price: record.outOraclePrice,
+            in_oracle_price: record.inOraclePrice,
+            fee: record.fee,
+            slot: ctx.slot,
+            signature: ctx.signature.clone(),
+            log_index: ctx.log_index,
+            venues: route
+                .iter()
+                .filter_map(venue_name)
+                .map(str::to_string)
+                .collect(),
+            route,
+        }
+    }
+}
+
+/// Return the programs routing the swap whose `SwapRecord` is logged at `log_index` of the tx
+///
+/// These are the programs invoked between the swap's `beginSwap` and `endSwap` instructions, or by any
+/// instruction of the tx if the swap instructions are not top-level (e.g. invoked via CPI)
+pub fn swap_route(tx: &TxInstructions, log_index: u32) -> Vec<Pubkey> {
+    let is_swap_ix = |index: usize, begin: bool| {
+        tx.instructions.get(index).is_some_and(|ix| {
+            ix.program == drift_pda()
+                && matches!(
+                    (DriftInstruction::try_from_bytes(ix.data.as_slice()), begin),
+                    (Some(DriftInstruction::BeginSwap(_)), true)
+                        | (Some(DriftInstruction::EndSwap(_)), false)
+                )
+        })
+    };
+    let range = match tx.instruction_of_log(log_index) {
+        Some(end) if is_swap_ix(end, false) => {
+            match (0..end).rev().find(|i| is_swap_ix(*i, true)) {
+                Some(begin) => begin + 1..end,
+                None => 0..tx.instructions.len(),
+            }
+        }
+        _ => 0..tx.instructions.len(),
+    };
+
+    let mut route = Vec::<Pubkey>::new();
+    let programs = tx.instructions[range].iter().flat_map(|ix| ix.programs());
+    for program in programs.filter(|p| **p != drift_pda() && !INFRA_PROGRAMS.contains(p)) {
+        if !route.contains(program) {
+            route.push(*program);
+        }
+    }
+    route
+}
+
+/// Store the swap of `record` with its route through the tx's instructions (if known)
+pub async fn track_swap<T: IndexerBackend>(
+    db: &T,
+    record: &SwapRecord,
+    ctx: &EventContext,
+) -> Result<(), DbError> {
+    let route = ctx
+        .instructions
+        .as_ref()
+        .map(|tx| swap_route(tx, ctx.log_index))
+        .unwrap_or_default();
+    db.upsert_swap(Swap::from_record(record, ctx, route)).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use anchor_lang::{AnchorSerialize, Discriminator};
+    use solana_sdk::{
+        instruction::CompiledInstruction,
+        message::{v0, MessageHeader, VersionedMessage},
+    };
+    use solana_transaction_status::{
+        InnerInstruction, InnerInstructions, TransactionStatusMeta, UiTransactionStatusMeta,
+    };
+
+    use super::*;
+    use crate::{
+        db::MockBackend,
+        test_utils::tx_ctx,
+        types::drift::instructions::{BeginSwap, EndSwap},
+    };
+
+    fn ix_data<T: AnchorSerialize + Discriminator>(ix: T) -> Vec<u8> {
+        let mut data = T::DISCRIMINATOR.to_vec();
+        data.extend(ix.try_to_vec().unwrap());
+        data
+    }
+
+    #[tokio::test]
+    async fn swap_routes() {
+        let db = MockBackend::init("mockdb").await;
+        let jupiter = SWAP_VENUES[0].0;
+        let whirlpool = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+        let token = INFRA_PROGRAMS[0];
+        let unknown = Pubkey::new_unique();
+        // compute budget, beginSwap, jupiter route, endSwap
+        let message = VersionedMessage::V0(v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                ..Default::default()
+            },
+            account_keys: vec![compute_budget::ID, drift_pda(), jupiter],
+            instructions: vec![
+                CompiledInstruction::new_from_raw_parts(0, vec![], vec![]),
+                CompiledInstruction::new_from_raw_parts(
+                    1,
+                    ix_data(BeginSwap {
+                        inMarketIndex: 1,
+                        outMarketIndex: 0,
+                        amountIn: 1_000_000_000,
+                    }),
+                    vec![],
+                ),
+                CompiledInstruction::new_from_raw_parts(2, vec![], vec![]),
+                CompiledInstruction::new_from_raw_parts(
+                    1,
+                    ix_data(EndSwap {
+                        inMarketIndex: 1,
+                        outMarketIndex: 0,
+                        limitPrice: None,
+                        reduceOnly: None,
+                    }),
+                    vec![],
+                ),
+            ],
+            ..Default::default()
+        });
+        let inner = |program_id_index: u8| InnerInstruction {
+            instruction: CompiledInstruction::new_from_raw_parts(program_id_index, vec![], vec![]),
+            stack_height: Some(2),
+        };
+        let mut meta = TransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 2,
+                // whirlpool and an unknown venue, both loaded from a lookup table
+                instructions: vec![inner(3), inner(5), inner(4), inner(5)],
+            }]),
+            log_messages: Some(
+                [
+                    "Program ComputeBudget111111111111111111111111111111 invoke [1]",
+                    "Program ComputeBudget111111111111111111111111111111 success",
+                    "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH invoke [1]",
+                    "Program log: Instruction: BeginSwap",
+                    "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH success",
+                    "Program JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4 invoke [1]",
+                    "Program whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc invoke [2]",
+                    "Program JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4 success",
+                    "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH invoke [1]",
+                    "Program log: Instruction: EndSwap",
+                    "Program data: <SwapRecord>",
+                    "Program dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH success",
+                ]
+                .map(str::to_string)
+                .to_vec(),
+            ),
+            ..Default::default()
+        };
+        meta.loaded_addresses.writable = vec![whirlpool, unknown];
+        meta.loaded_addresses.readonly = vec![token];
+        let tx = TxInstructions::new(&message, Some(&UiTransactionStatusMeta::from(meta)));
+        assert!(tx.references(&unknown));
+        assert_eq!(tx.instruction_of_log(10), Some(3));
+
+        let record = SwapRecord {
+            ts: 1_700_000_000,
+            user: Pubkey::new_unique(),
+            amountOut: 20_000_000,
+            amountIn: 1_000_000_000,
+            outMarketIndex: 0,
+            inMarketIndex: 1,
+            outOraclePrice: 1_000_000,
+            inOraclePrice: 20_000_000,
+            fee: 0,
+        };
+        let ctx = EventContext {
+            instructions: Some(Arc::new(tx)),
+            ..tx_ctx("a", 1, 10)
+        };
+        track_swap(&db, &record, &ctx).await.unwrap();
+        // repeated
+        track_swap(&db, &record, &ctx).await.unwrap();
+
+        let swaps = db.swaps(&record.user, 10).await.unwrap();
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].route, [jupiter, whirlpool, unknown]);
+        assert_eq!(swaps[0].venues, ["Jupiter v6", "Orca Whirlpool"]);
+    }
+}
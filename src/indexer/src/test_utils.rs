@@ -0,0 +1,48 @@
+//! Shared test fixtures
+use crate::{
+    program::EventContext,
+    types::drift::events::{DriftEvent, OrderActionRecord},
+    versions::IdlVersions,
+};
+
+/// Signature of the captured mainnet fill tx
+pub const SIGNATURE: &str =
+    "3gvGQufckXGHrFDv4dNWEXuXKRMy3NZkKHMyFrAhLoYScaXXTGCp9vq58kWkfyJ8oDYZrz4bTyGayjUy9PKigeLS";
+/// Slot of the captured mainnet fill tx
+pub const SLOT: u64 = 196923928;
+/// The `OrderActionRecord` log of the captured fill tx: 0.0015 @ $27,684.3 in perp market 1, maker
+/// 4d5KsDvVn25So6EqM6KhgJyyUbG11SaBjzDRL1FqzmRV, taker H1AHngDKHCSZe4Xsw7Yk4SV5RP9agaaDhQmwTjRzhXFG
+pub const ORDER_ACTION_RECORD: &str = "Program log: 4DRDR8LtbQGWwHZkAAAAAAIIAQABAVAItYsox9wC2v+AAz8WXQRRjyHZ0aSDao8VZMh+F12zAd0EAAAAAAAAAYLxCAAAAAAAAWDjFgAAAAAAAbKkeQIAAAAAAaowAAAAAAAAAY/f////////AAAAAe3FfpKhZkk9E4ZlwFSFEmXchAsvmwHVTjGQOBC+69TDAQ8hIQABAAGAhB4AAAAAAAGAhB4AAAAAAAGq2EwDAAAAAAE10NxKUa97dfc1auP2TjQAqOAgggM7dWBcCJ9gI3Fn5AGbdFQAAQEBoNcmAgAAAAABYOMWAAAAAAABsqR5AgAAAABAiupxBgAAAA==";
+
+/// The captured fill
+pub fn fill() -> OrderActionRecord {
+    let Some(DriftEvent::OrderActionRecord(record)) = IdlVersions::default()
+        .try_parse_log(ORDER_ACTION_RECORD, 0)
+        .unwrap()
+    else {
+        panic!("it decodes");
+    };
+    record
+}
+
+/// Context of log `log_index` of tx `signature` at `slot`, without indexed accounts
+pub fn tx_ctx(signature: &str, slot: u64, log_index: u32) -> EventContext {
+    EventContext {
+        signature: signature.to_string(),
+        slot,
+        log_index,
+        accounts: Vec::new(),
+        normalizer: None,
+        alerts: None,
+        instructions: None,
+        labels: Vec::new(),
+        provisional: false,
+        partial: false,
+        fill_seqs: Default::default(),
+    }
+}
+
+/// Context of the first log of a tx at `slot` (signed 'sig<slot>')
+pub fn ctx(slot: u64) -> EventContext {
+    tx_ctx(format!("sig{slot}").as_str(), slot, 0)
+}
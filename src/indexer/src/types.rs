@@ -1,37 +1,63 @@
 //! Indexer types
-use std::{cell::OnceCell, str::FromStr};
-
 use anchor_lang::prelude::*;
 use serde::{Deserialize, Serialize};
+use solana_pubsub_client::nonblocking::pubsub_client::PubsubClientError;
 use solana_rpc_client_api::client_error::Error;
 use solana_sdk::pubkey::Pubkey;
 
 use idl_gen::gen_idl_types;
 
-use crate::db::DbError;
+use crate::{db::DbError, dynamic::DecodeError};
 
-const DRIFT_PDA: &str = "dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH";
-const DRIFT_PK: OnceCell<Pubkey> = OnceCell::new();
 const PROGRAM_LOG: &str = "Program log: ";
 const PROGRAM_DATA: &str = "Program data: ";
 
 /// Get the drift PDA
 #[inline]
 pub fn drift_pda() -> Pubkey {
-    *DRIFT_PK.get_or_init(|| Pubkey::from_str(DRIFT_PDA).unwrap())
+    drift::PROGRAM_ID
 }
 
-// TODO: the onchain IDL may change, need to regen if so
 declare_id!("dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH");
-gen_idl_types!("../../res/drift-2.58.0.json");
+
+/// Types of the drift IDL v2.58.0
+/// NB: modules of other IDL versions are registered in `versions`
+pub mod v2_58_0 {
+    use super::*;
+
+    gen_idl_types!(
+        "../../res/drift-2.58.0.json",
+        zero_copy(
+            InsuranceFundStake,
+            PerpMarket,
+            PhoenixV1FulfillmentConfig,
+            ProtocolIfSharesTransferConfig,
+            ReferrerName,
+            SerumV3FulfillmentConfig,
+            SpotMarket,
+            State,
+            User,
+            UserStats
+        ),
+        program(crate::AnchorProgram),
+        program_id("dRiftyHA39MWEi3m9aunc5MzRF1JYuBsbn6VPcn33UH")
+    );
+}
+// the latest IDL version
+pub use v2_58_0::drift;
 
 #[derive(Debug)]
 pub enum IndexerError {
     Rpc(Error),
+    PubSub(PubsubClientError),
     Db(DbError),
     InvalidSignature,
     InvalidPublicKey,
     LogParse(LogError),
+    /// The IDL could not be loaded or decoded
+    Idl(DecodeError),
+    /// The API server failed
+    Api(String),
 }
 
 #[derive(Debug, PartialEq)]
@@ -46,21 +72,33 @@ impl From<LogError> for IndexerError {
     }
 }
 
+impl From<DecodeError> for IndexerError {
+    fn from(err: DecodeError) -> Self {
+        Self::Idl(err)
+    }
+}
+
 impl From<DbError> for IndexerError {
     fn from(err: DbError) -> Self {
         Self::Db(err)
     }
 }
 
+impl From<PubsubClientError> for IndexerError {
+    fn from(err: PubsubClientError) -> Self {
+        Self::PubSub(err)
+    }
+}
+
 impl From<Error> for IndexerError {
     fn from(err: Error) -> Self {
         Self::Rpc(err)
     }
 }
 
-/// Try deserialize a drift event type from raw log string
+/// Try extract the (borsh) event data from raw log string
 /// https://github.com/coral-xyz/anchor/blob/9d947cb26b693e85e1fd26072bb046ff8f95bdcf/client/src/lib.rs#L552
-pub(crate) fn try_parse_log(raw: &str) -> std::result::Result<Option<DriftEvent>, LogError> {
+pub(crate) fn try_parse_log_data(raw: &str) -> std::result::Result<Option<Vec<u8>>, LogError> {
     // Log emitted from the current program.
     if let Some(log) = raw
         .strip_prefix(PROGRAM_LOG)
@@ -68,11 +106,10 @@ pub(crate) fn try_parse_log(raw: &str) -> std::result::Result<Option<DriftEvent>
     {
         let borsh_bytes =
             anchor_lang::__private::base64::decode(log).map_err(|_| LogError::InvalidBase64)?;
-        let (sig, mut data) = borsh_bytes.split_at(8);
-        Ok(DriftEvent::from_discriminant(
-            sig.try_into().unwrap(),
-            &mut data,
-        ))
+        if borsh_bytes.len() < 8 {
+            return Ok(None);
+        }
+        Ok(Some(borsh_bytes))
     } else {
         Ok(None)
     }
@@ -80,18 +117,58 @@ pub(crate) fn try_parse_log(raw: &str) -> std::result::Result<Option<DriftEvent>
 
 #[cfg(test)]
 mod test {
+    use anchor_lang::Discriminator;
+
     use super::*;
+    use crate::{
+        drift::{accounts::*, events::*, types::*},
+        test_utils::ORDER_ACTION_RECORD,
+        versions::IdlVersions,
+    };
+
+    fn try_parse_log(raw: &str) -> std::result::Result<Option<DriftEvent>, LogError> {
+        IdlVersions::default().try_parse_log(raw, 0)
+    }
 
     #[test]
     fn deserialize_order_action_record() {
         // https://explorer.solana.com/tx/3gvGQufckXGHrFDv4dNWEXuXKRMy3NZkKHMyFrAhLoYScaXXTGCp9vq58kWkfyJ8oDYZrz4bTyGayjUy9PKigeLS#ix-3
-        let raw = "Program log: 4DRDR8LtbQGWwHZkAAAAAAIIAQABAVAItYsox9wC2v+AAz8WXQRRjyHZ0aSDao8VZMh+F12zAd0EAAAAAAAAAYLxCAAAAAAAAWDjFgAAAAAAAbKkeQIAAAAAAaowAAAAAAAAAY/f////////AAAAAe3FfpKhZkk9E4ZlwFSFEmXchAsvmwHVTjGQOBC+69TDAQ8hIQABAAGAhB4AAAAAAAGAhB4AAAAAAAGq2EwDAAAAAAE10NxKUa97dfc1auP2TjQAqOAgggM7dWBcCJ9gI3Fn5AGbdFQAAQEBoNcmAgAAAAABYOMWAAAAAAABsqR5AgAAAABAiupxBgAAAA==";
-        let res = try_parse_log(raw).expect("it deserializes");
+        let res = try_parse_log(ORDER_ACTION_RECORD).expect("it deserializes");
         dbg!(&res);
-        match res {
-            Some(DriftEvent::OrderActionRecord(_)) => assert!(true),
-            _ => assert!(false),
-        }
+        assert!(matches!(res, Some(DriftEvent::OrderActionRecord(_))));
+    }
+
+    #[test]
+    fn namespaced_modules() {
+        use crate::drift::{errors::DriftError, instructions::*};
+
+        assert_eq!(drift::PROGRAM_ID, ID);
+        assert_eq!(drift::IDL_VERSION, "2.58.0");
+
+        // initializeUser(subAccountId: u16, name: [u8; 32])
+        let mut data = InitializeUser::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&1_u16.to_le_bytes());
+        data.extend_from_slice(&[7_u8; 32]);
+        let ix = DriftInstruction::try_from_bytes(data.as_slice()).expect("it deserializes");
+        assert_eq!(ix.name(), "initializeUser");
+        assert_eq!(
+            ix,
+            DriftInstruction::InitializeUser(InitializeUser {
+                subAccountId: 1,
+                name: [7_u8; 32],
+            })
+        );
+
+        assert_eq!(
+            DriftError::from_code(6000),
+            Some(DriftError::InvalidSpotMarketAuthority)
+        );
+        assert_eq!(DriftError::InvalidSpotMarketAuthority as u32, 6000);
+        assert_eq!(
+            DriftError::InvalidSpotMarketAuthority.to_string(),
+            "Invalid Spot Market Authority"
+        );
+        assert!(DriftError::from_code(1).is_none());
     }
 
     #[test]
@@ -104,4 +181,266 @@ mod test {
         let res = try_parse_log(raw);
         assert!(res.is_err());
     }
+
+    /// Build raw account data of `size` (including discriminant) with `fields` written at their offsets
+    fn account_data(disc: [u8; 8], size: usize, fields: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0_u8; size];
+        data[..8].copy_from_slice(&disc);
+        for (offset, bytes) in fields {
+            data[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        data
+    }
+
+    #[test]
+    fn zero_copy_account_layouts() {
+        // drift `SIZE` constants (excluding discriminant)
+        assert_eq!(std::mem::size_of::<User>(), 4_368);
+        assert_eq!(std::mem::size_of::<UserStats>(), 232);
+        assert_eq!(std::mem::size_of::<PerpMarket>(), 1_208);
+        assert_eq!(std::mem::size_of::<SpotMarket>(), 768);
+        assert_eq!(std::mem::size_of::<State>(), 984);
+        assert_eq!(User::DISCRIMINATOR, [159, 117, 95, 227, 239, 151, 58, 236]);
+        assert_eq!(
+            PerpMarket::DISCRIMINATOR,
+            [10, 223, 12, 44, 107, 245, 55, 247]
+        );
+    }
+
+    /// Load a captured mainnet account fixture, see `scripts/capture-fixtures.sh`
+    fn mainnet_account(name: &str) -> (Pubkey, Vec<u8>) {
+        let path = format!(
+            "{}/../../res/fixtures/accounts/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let fixture: serde_json::Value = serde_json::from_str(
+            std::fs::read_to_string(path.as_str())
+                .unwrap_or_else(|_| panic!("fixture {path} captured"))
+                .as_str(),
+        )
+        .expect("valid fixture");
+        let pubkey = Pubkey::try_from(fixture["pubkey"].as_str().unwrap()).unwrap();
+        let account: solana_account_decoder::UiAccount =
+            serde_json::from_value(fixture["account"].clone()).expect("valid account");
+        let account: solana_sdk::account::Account = account.decode().expect("base64 account");
+        assert_eq!(account.owner, drift_pda());
+        (pubkey, account.data)
+    }
+
+    fn pda(seeds: &[&[u8]]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(seeds, &drift_pda())
+    }
+
+    #[test]
+    #[ignore = "needs captured mainnet fixtures, see scripts/capture-fixtures.sh"]
+    fn deserialize_mainnet_accounts() {
+        let (pubkey, data) = mainnet_account("state");
+        let Some(DriftAccount::State(state)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(pubkey, pda(&[b"drift_state"]).0);
+        let (signer, nonce) = pda(&[b"drift_signer"]);
+        assert_eq!(state.signer, signer);
+        assert_eq!(state.signerNonce, nonce);
+        assert!(state.numberOfMarkets > 0 && state.numberOfSpotMarkets > 0);
+
+        let (pubkey, data) = mainnet_account("perp_market_0");
+        let Some(DriftAccount::PerpMarket(market)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(pubkey, pda(&[b"perp_market", &0_u16.to_le_bytes()]).0);
+        assert_eq!(market.pubkey, pubkey);
+        assert_eq!({ market.marketIndex }, 0);
+        assert_eq!(&market.name[..8], b"SOL-PERP");
+        assert_eq!({ market.quoteSpotMarketIndex }, 0);
+
+        let (pubkey, data) = mainnet_account("spot_market_0");
+        let Some(DriftAccount::SpotMarket(market)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(pubkey, pda(&[b"spot_market", &0_u16.to_le_bytes()]).0);
+        assert_eq!(market.pubkey, pubkey);
+        assert_eq!({ market.marketIndex }, 0);
+        assert_eq!(&market.name[..4], b"USDC");
+        assert_eq!(
+            market.mint,
+            Pubkey::try_from("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap()
+        );
+        assert_eq!({ market.decimals }, 6);
+
+        let (pubkey, data) = mainnet_account("user");
+        let Some(DriftAccount::User(user)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(
+            crate::discovery::user_pda(&user.authority, user.subAccountId),
+            pubkey
+        );
+        let orders = user.orders;
+        assert_eq!(
+            orders
+                .iter()
+                .filter(|o| o.status == OrderStatus::Open)
+                .count(),
+            user.openOrders as usize
+        );
+    }
+
+    #[test]
+    fn deserialize_perp_market() {
+        let oracle = Pubkey::new_unique();
+        let mut name = [b' '; 32];
+        name[..8].copy_from_slice(b"SOL-PERP");
+        let data = account_data(
+            PerpMarket::DISCRIMINATOR,
+            1_216,
+            &[
+                (40, oracle.as_ref()),
+                (256, &123_456_789_u128.to_le_bytes()),
+                (1_000, name.as_slice()),
+                (1_160, &2_u16.to_le_bytes()),
+                (1_162, &[1]),
+                (1_164, &[3]),
+            ],
+        );
+
+        let Some(DriftAccount::PerpMarket(market)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(market.amm.oracle, oracle);
+        assert_eq!({ market.amm.sqrtK }, 123_456_789);
+        assert_eq!(&market.name[..8], b"SOL-PERP");
+        assert_eq!({ market.marketIndex }, 2);
+        assert_eq!(market.status, MarketStatus::Active);
+        assert_eq!(market.contractTier, ContractTier::Speculative);
+        assert_eq!(
+            PerpMarket::try_deserialize(&mut data.as_slice()).expect("it deserializes"),
+            market
+        );
+        // trailing padding is part of the layout
+        assert_eq!(market.try_to_vec().unwrap().as_slice(), &data[8..]);
+
+        // 128-bit fields are stored as decimal strings
+        let document = mongodb::bson::to_document(&market).expect("it serializes");
+        assert_eq!(
+            document.get_document("amm").unwrap().get_str("sqrtK"),
+            Ok("123456789")
+        );
+        assert_eq!(
+            mongodb::bson::from_document::<PerpMarket>(document).expect("it deserializes"),
+            market
+        );
+    }
+
+    #[test]
+    fn serialize_int128_fields() {
+        let above_u64 = u64::MAX as u128 + 1;
+        let record = InsuranceFundStakeRecord {
+            ts: 1,
+            userAuthority: Pubkey::new_unique(),
+            action: StakeAction::Stake,
+            amount: 2,
+            marketIndex: 0,
+            insuranceVaultAmountBefore: 3,
+            ifSharesBefore: above_u64,
+            userIfSharesBefore: 0,
+            totalIfSharesBefore: u128::MAX,
+            ifSharesAfter: above_u64 + 1,
+            userIfSharesAfter: 0,
+            totalIfSharesAfter: u128::MAX,
+        };
+        let document = mongodb::bson::to_document(&record).expect("it serializes");
+        assert_eq!(
+            document.get_str("ifSharesBefore"),
+            Ok("18446744073709551616")
+        );
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["totalIfSharesAfter"], u128::MAX.to_string());
+        assert_eq!(
+            mongodb::bson::from_document::<InsuranceFundStakeRecord>(document)
+                .expect("it deserializes"),
+            record
+        );
+        assert_eq!(
+            serde_json::from_value::<InsuranceFundStakeRecord>(json).expect("it deserializes"),
+            record
+        );
+
+        // option
+        let bankruptcy = PerpBankruptcyRecord {
+            marketIndex: 0,
+            pnl: -(above_u64 as i128),
+            ifPayment: 0,
+            clawbackUser: None,
+            clawbackUserPayment: Some(above_u64),
+            cumulativeFundingRateDelta: 0,
+        };
+        let json = serde_json::to_value(&bankruptcy).unwrap();
+        assert_eq!(json["clawbackUserPayment"], "18446744073709551616");
+        assert_eq!(json["pnl"], "-18446744073709551616");
+        assert_eq!(
+            serde_json::from_value::<PerpBankruptcyRecord>(json).unwrap(),
+            bankruptcy
+        );
+        let none = PerpBankruptcyRecord {
+            clawbackUserPayment: None,
+            ..bankruptcy
+        };
+        let document = mongodb::bson::to_document(&none).expect("it serializes");
+        assert_eq!(
+            mongodb::bson::from_document::<PerpBankruptcyRecord>(document).unwrap(),
+            none
+        );
+
+        // array
+        let size = 8 + std::mem::size_of::<ProtocolIfSharesTransferConfig>();
+        let data = account_data(
+            ProtocolIfSharesTransferConfig::DISCRIMINATOR,
+            size,
+            &[(8 + 168, &above_u64.to_le_bytes())],
+        );
+        let config = ProtocolIfSharesTransferConfig::try_deserialize(&mut data.as_slice())
+            .expect("it deserializes");
+        let document = mongodb::bson::to_document(&config).expect("it serializes");
+        assert_eq!(
+            document.get_array("padding").unwrap()[0].as_str(),
+            Some("18446744073709551616")
+        );
+        assert_eq!(
+            mongodb::bson::from_document::<ProtocolIfSharesTransferConfig>(document).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn deserialize_user_orders() {
+        let authority = Pubkey::new_unique();
+        let data = account_data(
+            User::DISCRIMINATOR,
+            4_376,
+            &[
+                (8, authority.as_ref()),
+                (1_200, &27_681_000_000_u64.to_le_bytes()),
+                (1_268, &2_171_151_u32.to_le_bytes()),
+                (1_274, &[1]),
+                (4_336, &2_171_152_u32.to_le_bytes()),
+            ],
+        );
+
+        let Some(DriftAccount::User(user)) = DriftAccount::try_from_bytes(&data) else {
+            panic!("it deserializes");
+        };
+        assert_eq!(user.authority, authority);
+        let orders = user.orders;
+        assert_eq!({ orders[0].price }, 27_681_000_000);
+        assert_eq!({ orders[0].orderId }, 2_171_151);
+        assert_eq!(orders[0].status, OrderStatus::Open);
+        assert_eq!(orders[1].status, OrderStatus::Init);
+        assert_eq!({ user.nextOrderId }, 2_171_152);
+
+        // invalid enum variant
+        let mut invalid = data.clone();
+        invalid[1_274] = 99;
+        assert!(DriftAccount::try_from_bytes(&invalid).is_none());
+    }
 }
@@ -0,0 +1,270 @@
+//! Multi-version IDL event decoding
+//!
+//! A program (and its IDL) changes across upgrades, events must be decoded with the IDL version active at their slot
+use log::debug;
+
+use crate::{
+    program::AnchorProgram,
+    types::{drift::DriftProgram, try_parse_log_data, v2_58_0, LogError},
+};
+
+/// Decodes an event from its discriminant and data (excluding discriminant) into the latest event type of `P`
+pub type DecodeFn<P> = fn([u8; 8], &mut &[u8]) -> Option<<P as AnchorProgram>::Event>;
+
+/// A program IDL version and the slot from which it is active
+pub struct IdlVersion<P: AnchorProgram> {
+    /// IDL version e.g. '2.58.0'
+    pub version: &'static str,
+    /// First slot the version is active (inclusive)
+    pub from_slot: u64,
+    /// Decode an event with this version's layout
+    pub decode: DecodeFn<P>,
+}
+
+impl<P: AnchorProgram> Clone for IdlVersion<P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: AnchorProgram> Copy for IdlVersion<P> {}
+
+/// Known drift IDL versions, ordered oldest to newest
+///
+/// To support another version generate its types into a module in `types` and add it here with the slot of the
+/// program upgrade which deployed it (see `scripts/fetch-idl.sh`), older versions provide a `decode` which upgrades
+/// their events into the latest types
+const KNOWN_VERSIONS: &[IdlVersion<DriftProgram>] = &[IdlVersion {
+    version: v2_58_0::drift::IDL_VERSION,
+    from_slot: 0,
+    decode: v2_58_0::drift::events::DriftEvent::from_discriminant,
+}];
+
+/// Selects the IDL version to decode events with by slot
+pub struct IdlVersions<P: AnchorProgram = DriftProgram> {
+    /// Versions ordered by `from_slot` (ascending)
+    versions: Vec<IdlVersion<P>>,
+}
+
+impl<P: AnchorProgram> Clone for IdlVersions<P> {
+    fn clone(&self) -> Self {
+        Self {
+            versions: self.versions.clone(),
+        }
+    }
+}
+
+impl Default for IdlVersions<DriftProgram> {
+    fn default() -> Self {
+        Self::new(KNOWN_VERSIONS.to_vec())
+    }
+}
+
+impl<P: AnchorProgram> IdlVersions<P> {
+    /// Create an `IdlVersions` with only the IDL `P` was generated from
+    pub fn latest_only() -> Self {
+        Self::new(vec![IdlVersion {
+            version: P::IDL_VERSION,
+            from_slot: 0,
+            decode: P::decode_event,
+        }])
+    }
+
+    /// Create a new `IdlVersions` from `versions`
+    pub fn new(mut versions: Vec<IdlVersion<P>>) -> Self {
+        assert!(!versions.is_empty(), "at least one IDL version");
+        versions.sort_by_key(|v| v.from_slot);
+        Self { versions }
+    }
+
+    /// Return the version active at `slot`
+    pub fn active(&self, slot: u64) -> &IdlVersion<P> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|v| v.from_slot <= slot)
+            .unwrap_or(&self.versions[0])
+    }
+
+    /// Return the latest version
+    pub fn latest(&self) -> &IdlVersion<P> {
+        self.versions.last().unwrap()
+    }
+
+    /// Try deserialize a drift event from raw log string emitted at `slot`
+    ///
+    /// Events decoded only from a known prefix are returned too, see `parse_log`
+    pub fn try_parse_log(&self, raw: &str, slot: u64) -> Result<Option<P::Event>, LogError> {
+        Ok(self.parse_log(raw, slot)?.map(|parsed| parsed.event))
+    }
+
+    /// Try deserialize a drift event from raw log string emitted at `slot`
+    ///
+    /// Decodes with the version active at `slot`, falling back to other versions (newest first)
+    /// if the event layout does not match e.g. the upgrade slot is imprecise.
+    /// If no version consumes all data e.g. an upgrade appended fields, the event is decoded from the known
+    /// prefix and marked `partial`
+    pub fn parse_log(
+        &self,
+        raw: &str,
+        slot: u64,
+    ) -> Result<Option<ParsedEvent<P::Event>>, LogError> {
+        let Some(borsh_bytes) = try_parse_log_data(raw)? else {
+            return Ok(None);
+        };
+        let (sig, data) = borsh_bytes.split_at(8);
+        let disc: [u8; 8] = sig.try_into().unwrap();
+
+        let active = self.active(slot);
+        let candidates = std::iter::once(active).chain(
+            self.versions
+                .iter()
+                .rev()
+                .filter(|v| v.version != active.version),
+        );
+        let mut partial = None;
+        for version in candidates {
+            let mut remaining = data;
+            match (version.decode)(disc, &mut remaining) {
+                // the layout matches only if all data is consumed
+                Some(event) if remaining.is_empty() => {
+                    if version.version != active.version {
+                        debug!(
+                            "decoded event @ {slot} with fallback IDL: {}",
+                            version.version
+                        );
+                    }
+                    return Ok(Some(ParsedEvent {
+                        event,
+                        partial: false,
+                    }));
+                }
+                // trailing data e.g. a newer IDL appended fields
+                Some(event) if partial.is_none() => {
+                    partial = Some((version.version, remaining.len(), event));
+                }
+                _ => (),
+            }
+        }
+
+        Ok(partial.map(|(version, trailing, event)| {
+            debug!("decoded event @ {slot} from a prefix with IDL {version}, {trailing} trailing bytes");
+            ParsedEvent {
+                event,
+                partial: true,
+            }
+        }))
+    }
+}
+
+/// An event decoded from a log
+#[derive(Debug, PartialEq)]
+pub struct ParsedEvent<E> {
+    /// The decoded event
+    pub event: E,
+    /// Whether the log had trailing data i.e. the event was decoded from the known prefix of a newer layout
+    pub partial: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{drift::events::DriftEvent, test_utils::ORDER_ACTION_RECORD};
+
+    /// A 'previous' version whose layout has an extra trailing field
+    fn decode_with_extra_field(disc: [u8; 8], data: &mut &[u8]) -> Option<DriftEvent> {
+        let event = DriftEvent::from_discriminant(disc, data)?;
+        if data.len() < 8 {
+            return None;
+        }
+        *data = &data[8..];
+        Some(event)
+    }
+
+    /// A 'previous' version which fails to decode anything
+    fn decode_nothing(_disc: [u8; 8], _data: &mut &[u8]) -> Option<DriftEvent> {
+        None
+    }
+
+    #[test]
+    fn select_version_by_slot() {
+        let versions = IdlVersions::<DriftProgram>::new(vec![
+            IdlVersion {
+                version: "2.58.0",
+                from_slot: 200,
+                decode: DriftEvent::from_discriminant,
+            },
+            IdlVersion {
+                version: "2.57.0",
+                from_slot: 100,
+                decode: decode_nothing,
+            },
+        ]);
+        assert_eq!(versions.active(50).version, "2.57.0");
+        assert_eq!(versions.active(100).version, "2.57.0");
+        assert_eq!(versions.active(199).version, "2.57.0");
+        assert_eq!(versions.active(200).version, "2.58.0");
+        assert_eq!(versions.latest().version, "2.58.0");
+    }
+
+    #[test]
+    fn parse_log_falls_back_to_matching_layout() {
+        let versions = IdlVersions::<DriftProgram>::new(vec![
+            IdlVersion {
+                version: "2.57.0",
+                from_slot: 0,
+                decode: decode_with_extra_field,
+            },
+            IdlVersion {
+                version: "2.58.0",
+                from_slot: 200,
+                decode: DriftEvent::from_discriminant,
+            },
+        ]);
+
+        // active version fails to consume the data (short), falls back to the latest
+        let res = versions.try_parse_log(ORDER_ACTION_RECORD, 100).unwrap();
+        assert!(matches!(res, Some(DriftEvent::OrderActionRecord(_))));
+
+        let res = versions.try_parse_log(ORDER_ACTION_RECORD, 300).unwrap();
+        assert!(matches!(res, Some(DriftEvent::OrderActionRecord(_))));
+
+        let res = versions
+            .try_parse_log("Program log: Instruction: FillPerpOrder", 300)
+            .unwrap_err();
+        assert_eq!(res, LogError::InvalidBase64);
+    }
+
+    #[test]
+    fn parse_log_partial_layout() {
+        // only a version with a shorter layout is known e.g. IDL not updated after an upgrade
+        let versions = IdlVersions::<DriftProgram>::new(vec![IdlVersion {
+            version: "2.58.0",
+            from_slot: 0,
+            decode: DriftEvent::from_discriminant,
+        }]);
+        let mut borsh_bytes = anchor_lang::__private::base64::decode(
+            ORDER_ACTION_RECORD.strip_prefix("Program log: ").unwrap(),
+        )
+        .unwrap();
+        borsh_bytes.extend_from_slice(&[1, 2, 3, 4]);
+        let raw = format!(
+            "Program data: {}",
+            anchor_lang::__private::base64::encode(borsh_bytes)
+        );
+
+        // decoded from the known prefix
+        let res = versions
+            .parse_log(raw.as_str(), 0)
+            .unwrap()
+            .expect("it decodes");
+        assert!(res.partial);
+        assert!(matches!(res.event, DriftEvent::OrderActionRecord(_)));
+
+        let res = versions
+            .parse_log(ORDER_ACTION_RECORD, 0)
+            .unwrap()
+            .expect("it decodes");
+        assert!(!res.partial);
+    }
+}
@@ -0,0 +1,133 @@
+//! Watchlist of monitored accounts
+//!
+//! The accounts to index are persisted in the backend (`watchlist`) so they can be added, removed, paused and
+//! resumed while running (admin API or `indexer watchlist`). Each account is indexed for one program (drift by
+//! default), the `AccountScheduler` of that program polls it, stamping the account's labels onto its stored events
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    db::{DbError, IndexerBackend},
+    types::drift_pda,
+};
+
+/// Whether a watched account is indexed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchStatus {
+    Active,
+    Paused,
+}
+
+/// An account on the watchlist
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedAccount {
+    /// The account address
+    pub pubkey: Pubkey,
+    pub status: WatchStatus,
+    /// Labels stamped onto the account's stored events e.g. 'market-maker'
+    pub labels: Vec<String>,
+    /// The program whose txs are indexed for the account, drift if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<Pubkey>,
+}
+
+impl WatchedAccount {
+    /// Return the ID of the program indexed for the account
+    pub fn program_id(&self) -> Pubkey {
+        self.program.unwrap_or_else(drift_pda)
+    }
+}
+
+/// Add `pubkey` to the watchlist (active), or replace the labels and program of a watched account keeping its
+/// status
+/// - `program` the program indexed for the account, drift if unset
+pub async fn watch<T: IndexerBackend>(
+    db: &T,
+    pubkey: Pubkey,
+    labels: Vec<String>,
+    program: Option<Pubkey>,
+) -> Result<WatchedAccount, DbError> {
+    let status = db
+        .watched_account(&pubkey)
+        .await?
+        .map(|a| a.status)
+        .unwrap_or(WatchStatus::Active);
+    let account = WatchedAccount {
+        pubkey,
+        status,
+        labels,
+        program,
+    };
+    db.upsert_watched_account(account.clone()).await?;
+    Ok(account)
+}
+
+/// Add `pubkey` to the watchlist (active) unless already watched, returns whether it was added
+/// - `program` the program indexed for the account, drift if unset
+pub async fn watch_if_absent<T: IndexerBackend>(
+    db: &T,
+    pubkey: Pubkey,
+    labels: Vec<String>,
+    program: Option<Pubkey>,
+) -> Result<bool, DbError> {
+    if db.watched_account(&pubkey).await?.is_some() {
+        return Ok(false);
+    }
+    db.upsert_watched_account(WatchedAccount {
+        pubkey,
+        status: WatchStatus::Active,
+        labels,
+        program,
+    })
+    .await?;
+    Ok(true)
+}
+
+/// Set the status of watched account `pubkey`, returns the account if watched
+pub async fn set_watch_status<T: IndexerBackend>(
+    db: &T,
+    pubkey: &Pubkey,
+    status: WatchStatus,
+) -> Result<Option<WatchedAccount>, DbError> {
+    let Some(mut account) = db.watched_account(pubkey).await? else {
+        return Ok(None);
+    };
+    account.status = status;
+    db.upsert_watched_account(account.clone()).await?;
+    Ok(Some(account))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::MockBackend;
+
+    #[tokio::test]
+    async fn watchlist_status() {
+        let db = MockBackend::init("mockdb").await;
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        watch(&db, a, vec!["mm".to_string()], None).await.unwrap();
+        assert!(watch_if_absent(&db, b, Vec::new(), None).await.unwrap());
+        assert!(!watch_if_absent(&db, b, Vec::new(), None).await.unwrap());
+
+        set_watch_status(&db, &a, WatchStatus::Paused)
+            .await
+            .unwrap();
+        // relabelling keeps the status
+        let relabelled = watch(&db, a, vec!["desk".to_string()], None).await.unwrap();
+        assert_eq!(relabelled.status, WatchStatus::Paused);
+        assert_eq!(
+            db.watched_account(&a).await.unwrap().unwrap().labels,
+            ["desk"]
+        );
+
+        assert!(db.remove_watched_account(&b).await.unwrap());
+        assert!(!db.remove_watched_account(&b).await.unwrap());
+        assert!(set_watch_status(&db, &b, WatchStatus::Active)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(db.watched_accounts().await.unwrap(), [relabelled]);
+    }
+}
@@ -1,39 +1,183 @@
 //! Drift indexer entrypoint
 use std::{
+    collections::HashMap,
     env::{self},
+    net::SocketAddr,
     sync::Arc,
     time::Duration,
 };
 
 use clap::Parser;
 use futures_util::future::select_all;
-use log::info;
+use log::{error, info, warn};
 use tokio::task::JoinHandle;
 
 use drift_indexer_backend::{
-    DriftEventIndexer, IndexerBackend, IndexerError, MongoDbClient, RpcClient,
+    check_onchain_idl, replay_positions, set_watch_status, watch, watch_if_absent,
+    AccountScheduler, AccountSnapshotter, Alerter, ApiServer, AuthorityDiscovery, CommitmentConfig,
+    DriftEventIndexer, DynamicIdl, EventRouter, IdlVersions, IndexerBackend, IndexerError,
+    MarketRegistry, MongoDbClient, Normalizer, ProgramIndexer, Pubkey, RpcClient, RuntimeProgram,
+    UserAccountTracker, WatchStatus, DEFAULT_BATCH_SIZE, DEFAULT_MAX_SUB_ACCOUNTS,
+    DEFAULT_RPC_BUDGET, DRIFT_ZERO_COPY_ACCOUNTS,
 };
 
 /// Solana mainnet RPC URL
 const SOLANA_MAINNET_RPC: &str = "https://api.mainnet-beta.solana.com";
 /// How frequently to poll for events (seconds)
 const DEFAULT_POLL_INTERVAL_S: u64 = 3;
+/// Max. poll interval of idle accounts (seconds)
+const DEFAULT_MAX_POLL_INTERVAL_S: u64 = 60;
+/// How frequently to snapshot account states (seconds)
+const DEFAULT_SNAPSHOT_INTERVAL_S: u64 = 60;
+/// How frequently to refresh market metadata (seconds)
+const DEFAULT_MARKETS_INTERVAL_S: u64 = 300;
+/// How frequently to apply watchlist changes (seconds)
+const DEFAULT_WATCHLIST_INTERVAL_S: u64 = 5;
 
 /// Drift account indexing service 🏎️
 #[derive(Parser, Debug)]
 struct CliArgs {
-    /// List of accounts to monitor
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// List of accounts to monitor (added to the watchlist)
     #[clap(long, use_value_delimiter = true, value_delimiter = ',')]
     accounts: Vec<String>,
+    /// Wallet authorities whose User accounts (subaccounts) to monitor
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',')]
+    authorities: Vec<String>,
+    /// Number of subaccount IDs to probe per authority
+    #[clap(long, default_value_t = DEFAULT_MAX_SUB_ACCOUNTS)]
+    max_subaccounts: u16,
     /// Db connection string
     #[clap(long)]
     db: Option<String>,
     /// Solana RPC endpoint
     #[clap(long)]
     rpc: Option<String>,
-    /// Polling interval (seconds)
+    /// Solana RPC websocket endpoint
+    #[clap(long)]
+    ws: Option<String>,
+    /// Polling interval (seconds), the min. for watched accounts
     #[clap(long, default_value_t = DEFAULT_POLL_INTERVAL_S)]
     poll: u64,
+    /// Max. polling interval of idle watched accounts (seconds)
+    #[clap(long, default_value_t = DEFAULT_MAX_POLL_INTERVAL_S)]
+    poll_max: u64,
+    /// RPC requests per second shared by the polled accounts
+    #[clap(long, default_value_t = DEFAULT_RPC_BUDGET)]
+    rpc_budget: u32,
+    /// Max. accounts whose signatures are queried per JSON-RPC batch
+    #[clap(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    rpc_batch: usize,
+    /// Index txs once: confirmed (provisional)|finalized
+    #[clap(long, value_enum, default_value_t = Commitment::Finalized, hide_possible_values = true)]
+    commitment: Commitment,
+    /// List of program accounts to snapshot
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',')]
+    snapshot_accounts: Vec<String>,
+    /// Also snapshot the InsuranceFundStake accounts of indexed stakers
+    #[clap(long)]
+    snapshot_if_stakes: bool,
+    /// Snapshot interval (seconds)
+    #[clap(long, default_value_t = DEFAULT_SNAPSHOT_INTERVAL_S)]
+    snapshot_poll: u64,
+    /// Store only decoded snapshots, without the raw account data
+    #[clap(long)]
+    snapshot_decoded_only: bool,
+    /// Track User accounts in realtime
+    #[clap(long)]
+    track_users: bool,
+    /// Only track User accounts of these authorities
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',')]
+    track_authorities: Vec<String>,
+    /// Also decode all events with this IDL (json)
+    #[clap(long)]
+    idl: Option<String>,
+    /// Also index this anchor program, its events decoded with the given IDL (json) e.g. <PROGRAM_ID>=res/vault.json
+    #[clap(long, value_parser = parse_program)]
+    program: Vec<(Pubkey, String)>,
+    /// Diff the onchain IDL against the bundled IDL and exit (1 if they differ, 2 if not fetched)
+    #[clap(long)]
+    check_idl: bool,
+    /// On IDL mismatch: warn|refuse|fallback
+    #[clap(long, value_enum, default_value_t = IdlMismatch::Warn, hide_possible_values = true)]
+    idl_mismatch: IdlMismatch,
+    /// Serve the query API on this address e.g. 0.0.0.0:8080
+    #[clap(long)]
+    api: Option<SocketAddr>,
+    /// Serve the admin (watchlist) API on this address e.g. 127.0.0.1:8081
+    #[clap(long)]
+    admin_api: Option<SocketAddr>,
+    /// Watchlist sync interval (seconds)
+    #[clap(long, default_value_t = DEFAULT_WATCHLIST_INTERVAL_S)]
+    watchlist_poll: u64,
+    /// Market refresh interval (seconds)
+    #[clap(long, default_value_t = DEFAULT_MARKETS_INTERVAL_S)]
+    markets_poll: u64,
+    /// Liquidation alert rules (json)
+    #[clap(long)]
+    alerts: Option<String>,
+    /// Rebuild positions from the logged events and exit
+    #[clap(long)]
+    replay_positions: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Manage the watchlist of monitored accounts (applied by the running service)
+    #[clap(subcommand)]
+    Watchlist(WatchlistCommand),
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum WatchlistCommand {
+    /// List the watched accounts and their poll intervals
+    List,
+    /// Watch an account, or replace its labels
+    Add {
+        account: Pubkey,
+        /// Labels stamped onto the account's stored events
+        #[clap(long, use_value_delimiter = true, value_delimiter = ',')]
+        labels: Vec<String>,
+        /// Index the account's txs of this program (see `--program`) instead of drift
+        #[clap(long)]
+        program: Option<Pubkey>,
+    },
+    /// Stop watching an account
+    Remove { account: Pubkey },
+    /// Stop indexing an account, keeping it on the watchlist
+    Pause { account: Pubkey },
+    /// Resume indexing a paused account
+    Resume { account: Pubkey },
+}
+
+/// Action when the onchain IDL differs from the bundled IDL
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum IdlMismatch {
+    /// Log the differences and continue
+    Warn,
+    /// Exit without indexing
+    Refuse,
+    /// Also decode all events with the onchain IDL (unless `--idl` is given), changed events are only stored
+    /// by it
+    Fallback,
+}
+
+/// Commitment of the indexed txs
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Commitment {
+    /// Store events provisionally until finalized, retracted if their slot is skipped
+    Confirmed,
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
 }
 
 #[tokio::main]
@@ -43,12 +187,16 @@ async fn main() {
     let args = CliArgs::parse();
     let mut db_conn_str = args.db;
     let mut rpc_url = args.rpc;
+    let mut ws_url = args.ws;
     // env vars have priority of cli args
     for (k, v) in env::vars() {
         match k.as_str() {
             "INDEXER_SOLANA_RPC_URL" => {
                 rpc_url.replace(v);
             }
+            "INDEXER_SOLANA_WS_URL" => {
+                ws_url.replace(v);
+            }
             "INDEXER_DB_CONN_STR" => {
                 db_conn_str.replace(v);
             }
@@ -56,35 +204,341 @@ async fn main() {
         }
     }
     let rpc_url = rpc_url.unwrap_or_else(|| SOLANA_MAINNET_RPC.to_string());
+    let ws_url = ws_url.unwrap_or_else(|| rpc_url.replacen("http", "ws", 1));
     let db_conn_str = db_conn_str.unwrap_or_default();
-    info!("using: db: {db_conn_str}, rpc: {rpc_url}");
+    info!("using: db: {db_conn_str}, rpc: {rpc_url}, ws: {ws_url}");
 
     let rpc_client = Arc::new(RpcClient::new(rpc_url));
+    // read-only, before connecting to the db
+    if args.check_idl {
+        match check_onchain_idl(&rpc_client).await {
+            Ok((_, diff)) => {
+                print!("{diff}");
+                std::process::exit(if diff.is_empty() { 0 } else { 1 });
+            }
+            Err(err) => {
+                eprintln!("failed fetching onchain IDL: {err:?}");
+                std::process::exit(2);
+            }
+        }
+    }
     let db_client = Arc::new(MongoDbClient::init(db_conn_str.as_str()).await);
     let poll = Duration::from_secs(args.poll);
 
-    select_all(
-        args.accounts
-            .into_iter()
-            .map(|acc| spawn_indexer(acc, Arc::clone(&db_client), Arc::clone(&rpc_client), poll)),
+    if let Some(Command::Watchlist(command)) = args.command {
+        if let Err(err) = manage_watchlist(db_client.as_ref(), command).await {
+            error!("failed updating watchlist: {err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.replay_positions {
+        match replay_positions(db_client.as_ref()).await {
+            Ok(count) => info!("rebuilt {count} positions"),
+            Err(err) => {
+                error!("failed replaying positions: {err:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    for account in args.accounts.iter() {
+        let pubkey = Pubkey::try_from(account.as_str()).expect("valid pubkey");
+        if watch_if_absent(db_client.as_ref(), pubkey, Vec::new(), None)
+            .await
+            .expect("watchlist updated")
+        {
+            info!("added to watchlist: {account}");
+        }
+    }
+
+    let idl_versions = Arc::new(IdlVersions::default());
+    let mut dynamic_idl = args.idl.map(|path| {
+        DynamicIdl::from_file(path.as_str())
+            .expect("valid IDL")
+            .zero_copy(DRIFT_ZERO_COPY_ACCOUNTS)
+    });
+
+    let mut stale_events = Vec::new();
+    match check_onchain_idl(&rpc_client).await {
+        Ok((_, diff)) if diff.is_empty() => info!("onchain IDL matches bundled IDL"),
+        Ok((onchain, diff)) => {
+            warn!("onchain IDL differs from bundled IDL\n{diff}");
+            match args.idl_mismatch {
+                IdlMismatch::Warn => (),
+                IdlMismatch::Refuse => {
+                    error!("refusing to index, onchain IDL differs (--idl-mismatch refuse)");
+                    std::process::exit(1);
+                }
+                IdlMismatch::Fallback => {
+                    stale_events = diff.stale_events;
+                    if dynamic_idl.is_none() {
+                        dynamic_idl
+                            .replace(DynamicIdl::new(onchain).zero_copy(DRIFT_ZERO_COPY_ACCOUNTS));
+                    }
+                }
+            }
+        }
+        Err(err) => warn!("failed fetching onchain IDL: {err:?}"),
+    }
+    let dynamic_idl = dynamic_idl.map(|idl| {
+        info!("decoding events with runtime IDL v{}", idl.version());
+        Arc::new(idl)
+    });
+
+    let markets = MarketRegistry::load(&rpc_client)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("failed loading markets: {err:?}");
+            MarketRegistry::default()
+        });
+    let normalizer = Normalizer::new(markets.clone());
+    let alerts = args
+        .alerts
+        .map(|path| Alerter::from_file(path.as_str()).expect("valid alert rules"));
+
+    let mut indexer = DriftEventIndexer::with_idl_versions(
+        Arc::clone(&db_client),
+        Arc::clone(&rpc_client),
+        Arc::clone(&idl_versions),
     )
-    .await
-    .0
-    .unwrap()
-    .unwrap();
+    .with_normalizer(normalizer.clone())
+    .with_commitment(args.commitment.into())
+    .with_stale_events(stale_events);
+    if let Some(dynamic_idl) = dynamic_idl {
+        indexer = indexer.with_dynamic_idl(dynamic_idl);
+    }
+    if let Some(alerts) = alerts.clone() {
+        indexer = indexer.with_alerts(alerts);
+    }
+    let scheduler = AccountScheduler::new(Arc::clone(&db_client), indexer)
+        .with_rpc_budget(args.rpc_budget)
+        .with_batch_size(args.rpc_batch)
+        .with_poll_interval(poll, Duration::from_secs(args.poll_max));
+    // the schedulers of all programs share one RPC budget
+    let rpc_budget = scheduler.rpc_budget();
+    let mut tasks: Vec<JoinHandle<Result<(), IndexerError>>> = vec![spawn_scheduler(
+        scheduler,
+        Duration::from_secs(args.watchlist_poll),
+    )];
+    for (program, idl_path) in args.program {
+        let idl = DynamicIdl::from_file(idl_path.as_str()).expect("valid IDL");
+        info!(
+            "indexing program {program} ({} v{})",
+            idl.name(),
+            idl.version()
+        );
+        let indexer = ProgramIndexer::<RuntimeProgram, _>::new(
+            Arc::clone(&db_client),
+            Arc::clone(&rpc_client),
+        )
+        .with_program(program, idl.name())
+        .with_dynamic_idl(Arc::new(idl))
+        .with_commitment(args.commitment.into());
+        tasks.push(spawn_scheduler(
+            AccountScheduler::new(Arc::clone(&db_client), indexer)
+                .with_shared_rpc_budget(Arc::clone(&rpc_budget))
+                .with_batch_size(args.rpc_batch)
+                .with_poll_interval(poll, Duration::from_secs(args.poll_max)),
+            Duration::from_secs(args.watchlist_poll),
+        ));
+    }
+    for authority in args.authorities {
+        let discovery = AuthorityDiscovery::new(Arc::clone(&rpc_client), Arc::clone(&idl_versions))
+            .with_max_sub_accounts(args.max_subaccounts);
+        tasks.push(spawn_discovery(
+            authority,
+            discovery,
+            Arc::clone(&db_client),
+            poll,
+        ));
+    }
+    if !args.snapshot_accounts.is_empty() || args.snapshot_if_stakes {
+        let mut snapshotter =
+            AccountSnapshotter::new(Arc::clone(&db_client), Arc::clone(&rpc_client));
+        if args.snapshot_if_stakes {
+            snapshotter = snapshotter.with_if_stakes();
+        }
+        if args.snapshot_decoded_only {
+            snapshotter = snapshotter.decoded_only();
+        }
+        tasks.push(spawn_snapshotter(
+            args.snapshot_accounts,
+            snapshotter,
+            Duration::from_secs(args.snapshot_poll),
+        ));
+    }
+
+    if args.track_users || !args.track_authorities.is_empty() {
+        tasks.push(spawn_tracker(
+            args.track_authorities,
+            Arc::clone(&db_client),
+            Arc::clone(&rpc_client),
+            ws_url,
+            alerts,
+        ));
+    }
+
+    tasks.push(spawn_market_registry(
+        markets,
+        Arc::clone(&rpc_client),
+        Duration::from_secs(args.markets_poll),
+    ));
+    if let Some(addr) = args.api {
+        tasks.push(spawn_api(addr, Arc::clone(&db_client), normalizer.clone()));
+    }
+    if let Some(addr) = args.admin_api {
+        tasks.push(spawn_admin_api(addr, Arc::clone(&db_client), normalizer));
+    }
+
+    select_all(tasks).await.0.unwrap().unwrap();
+}
+
+/// Parse a `--program` argument: <PROGRAM_ID>=<IDL>
+fn parse_program(arg: &str) -> Result<(Pubkey, String), String> {
+    let (program, idl) = arg
+        .split_once('=')
+        .ok_or_else(|| "expected <PROGRAM_ID>=<IDL>".to_string())?;
+    let program =
+        Pubkey::try_from(program).map_err(|_| format!("invalid program ID: {program}"))?;
+    Ok((program, idl.to_string()))
+}
+
+/// Apply the watchlist `command`
+async fn manage_watchlist<T: IndexerBackend>(
+    db: &T,
+    command: WatchlistCommand,
+) -> Result<(), IndexerError> {
+    match command {
+        WatchlistCommand::List => {
+            let intervals: HashMap<Pubkey, u64> = db
+                .poll_statuses()
+                .await?
+                .into_iter()
+                .map(|s| (s.pubkey, s.interval_ms))
+                .collect();
+            for account in db.watched_accounts().await? {
+                let interval = match intervals.get(&account.pubkey) {
+                    Some(ms) => format!("{}s", *ms as f64 / 1_000.0),
+                    None => "-".to_string(),
+                };
+                println!(
+                    "{} {:?} program:{} poll:{interval} {}",
+                    account.pubkey,
+                    account.status,
+                    account.program_id(),
+                    account.labels.join(",")
+                );
+            }
+        }
+        WatchlistCommand::Add {
+            account,
+            labels,
+            program,
+        } => {
+            let account = watch(db, account, labels, program).await?;
+            println!(
+                "watching {} {:?} program:{}",
+                account.pubkey,
+                account.labels,
+                account.program_id()
+            );
+        }
+        WatchlistCommand::Remove { account } => {
+            if !db.remove_watched_account(&account).await? {
+                println!("not watched: {account}");
+            }
+        }
+        WatchlistCommand::Pause { ref account } | WatchlistCommand::Resume { ref account } => {
+            let status = match command {
+                WatchlistCommand::Pause { .. } => WatchStatus::Paused,
+                _ => WatchStatus::Active,
+            };
+            if set_watch_status(db, account, status).await?.is_none() {
+                println!("not watched: {account}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawn the `scheduler` thread polling the active watchlist accounts for its program
+fn spawn_scheduler<T: IndexerBackend + 'static, P: EventRouter<T>>(
+    scheduler: AccountScheduler<T, P>,
+    sync: Duration,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning watchlist scheduler");
+    tokio::spawn(async move { scheduler.run(sync).await })
 }
 
-/// Spawn an indexer thread for `account`
-fn spawn_indexer<T: IndexerBackend + 'static>(
-    account: String,
+/// Spawn a `discovery` thread adding the subaccounts of `authority` to the watchlist
+fn spawn_discovery<T: IndexerBackend + 'static>(
+    authority: String,
+    discovery: AuthorityDiscovery,
     db: Arc<T>,
-    rpc: Arc<RpcClient>,
     poll: Duration,
 ) -> JoinHandle<Result<(), IndexerError>> {
-    info!("spawning indexer for: {}", account);
+    info!("spawning account discovery for: {authority}");
+    tokio::spawn(async move { discovery.run(db, authority.as_str(), poll).await })
+}
+
+/// Spawn a `snapshotter` thread for `accounts`
+fn spawn_snapshotter<T: IndexerBackend + 'static>(
+    accounts: Vec<String>,
+    snapshotter: AccountSnapshotter<T>,
+    poll: Duration,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning snapshotter for: {:?}", accounts);
+    tokio::spawn(async move { snapshotter.run(accounts.as_slice(), poll).await })
+}
+
+/// Spawn a realtime User account tracker thread for `authorities` (all Users if empty)
+fn spawn_tracker<T: IndexerBackend + 'static>(
+    authorities: Vec<String>,
+    db: Arc<T>,
+    rpc: Arc<RpcClient>,
+    ws_url: String,
+    alerts: Option<Alerter>,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning User tracker for: {:?}", authorities);
     tokio::spawn(async move {
-        DriftEventIndexer::new(db, rpc)
-            .run(account.as_str(), poll)
-            .await
+        let mut tracker = UserAccountTracker::new(db, rpc, ws_url);
+        if let Some(alerts) = alerts {
+            tracker = tracker.with_alerts(alerts);
+        }
+        tracker.run(authorities.as_slice()).await
     })
 }
+
+/// Spawn the query API server thread on `addr`
+fn spawn_api<T: IndexerBackend + 'static>(
+    addr: SocketAddr,
+    db: Arc<T>,
+    normalizer: Normalizer,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning API server on: {addr}");
+    tokio::spawn(async move { ApiServer::new(db, normalizer).run(addr).await })
+}
+
+/// Spawn the admin API server thread on `addr`
+fn spawn_admin_api<T: IndexerBackend + 'static>(
+    addr: SocketAddr,
+    db: Arc<T>,
+    normalizer: Normalizer,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning admin API server on: {addr}");
+    tokio::spawn(async move { ApiServer::new(db, normalizer).run_admin(addr).await })
+}
+
+/// Spawn a thread refreshing the market registry
+fn spawn_market_registry(
+    markets: MarketRegistry,
+    rpc: Arc<RpcClient>,
+    poll: Duration,
+) -> JoinHandle<Result<(), IndexerError>> {
+    info!("spawning market registry refresh");
+    tokio::spawn(async move { markets.run(rpc, poll).await })
+}